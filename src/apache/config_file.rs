@@ -1,12 +1,24 @@
-use std::{error::Error, fmt::Display, fs::File};
-
-use crate::{configuration_file::ConfigurationFile, domain::Domain};
+use std::{
+    error::Error,
+    fmt::Display,
+    fs::{self, File},
+    path::Path,
+};
+
+use crate::{
+    backup::{Backup, Operation},
+    configuration_file::ConfigurationFile,
+    domain::Domain,
+    error::QicertError,
+    layout::Server,
+};
 
 #[derive(Debug)]
 pub enum ConfigError {
     FileSaving,
     InvalidPath,
     FileExists,
+    Including,
 }
 
 impl Display for ConfigError {
@@ -18,6 +30,7 @@ impl Display for ConfigError {
                 "An invalid path was given for an Apache configuration file"
             ),
             Self::FileExists => write!(f, "Apache configuration file already exists"),
+            Self::Including => write!(f, "The main Apache configuration could not be updated"),
         }
     }
 }
@@ -25,31 +38,114 @@ impl Display for ConfigError {
 impl Error for ConfigError {}
 
 impl ConfigFile {
-    pub fn chown_to_www(domain: &Domain) -> Result<(), ConfigError> {
+    pub fn chown_to_www(domain: &Domain) -> Result<(), QicertError> {
         Self::_chown_to_www(domain, ConfigError::FileSaving)
     }
 
-    pub fn create(domain: &Domain) -> Result<File, ConfigError> {
+    pub fn create(domain: &Domain) -> Result<File, QicertError> {
         Self::_create(domain, ConfigError::FileExists, ConfigError::FileSaving)
     }
 
-    pub fn create_backup(domain: &Domain) -> Result<(), ConfigError> {
-        Self::_create_backup(domain, ConfigError::FileSaving)
+    pub fn create_backup(domain: &Domain, operation: Operation) -> Result<Backup, QicertError> {
+        Self::_create_backup(domain, operation)
+    }
+
+    pub fn restore(domain: &Domain, existed: bool) -> Result<(), QicertError> {
+        Self::_restore(domain, existed, ConfigError::FileSaving)
+    }
+
+    pub fn remove_domain(domain: &Domain) -> Result<(), QicertError> {
+        Self::_remove_domain(domain, ConfigError::FileSaving)
+    }
+
+    pub fn read(domain: &Domain, file: &mut File) -> Result<String, QicertError> {
+        Self::_read(domain, file, ConfigError::InvalidPath)
+    }
+
+    pub fn write_block(domain: &Domain, file: &mut File, block: &str) -> Result<(), QicertError> {
+        Self::_write(domain, file, block, ConfigError::FileSaving)
     }
 
-    pub fn append(domain: &Domain) -> Result<File, ConfigError> {
+    pub fn reset(domain: &Domain, file: &mut File, content: &str) -> Result<(), QicertError> {
+        Self::_reset(domain, file, content, ConfigError::FileSaving)
+    }
+
+    pub fn write_region(domain: &Domain, file: &mut File, body: &str) -> Result<(), QicertError> {
+        Self::_write_region(domain, file, body, ConfigError::FileSaving)
+    }
+
+    pub fn clear_stale_challenge(
+        domain: &Domain,
+        file: &mut File,
+        content: &mut String,
+    ) -> Result<bool, QicertError> {
+        Self::_clear_stale_challenge(domain, file, content, ConfigError::FileSaving)
+    }
+
+    pub fn append(domain: &Domain) -> Result<File, QicertError> {
         Self::_append(domain, ConfigError::InvalidPath)
     }
+
+    pub fn include_directive() -> String {
+        format!(
+            "IncludeOptional {}/*.conf",
+            Self::sites_enabled_path().display()
+        )
+    }
+
+    pub fn has_include<S: AsRef<str>>(main: S) -> bool {
+        let directive = Self::include_directive();
+
+        main.as_ref().lines().map(str::trim).any(|l| l == directive)
+    }
+
+    /// Makes sure `main`, the main configuration, includes the directory managed by
+    /// qicert. Virtual hosts may come last, so the line is appended.
+    pub fn ensure_include(main: &Path) -> Result<(), QicertError> {
+        use std::io::Write;
+
+        let content = fs::read_to_string(main)
+            .map_err(|e| QicertError::io(ConfigError::Including, main, e))?;
+
+        if Self::has_include(&content) {
+            return Ok(());
+        }
+
+        fs::OpenOptions::new()
+            .append(true)
+            .open(main)
+            .and_then(|mut file| writeln!(file, "\n{}", Self::include_directive()))
+            .map_err(|e| QicertError::io(ConfigError::Including, main, e))?;
+
+        Ok(())
+    }
 }
 
 pub struct ConfigFile;
 
-impl<'a> ConfigurationFile<'a> for ConfigFile {
-    const SITES_AVAILABLE: &'a str = "/etc/apache2/sites-available";
+impl ConfigurationFile for ConfigFile {
+    const SERVER: Server = Server::Apache;
 
     fn server_name(domain: &Domain) -> String {
         format!("ServerName {domain}")
     }
+
+    fn split_blocks(haystack: &str) -> Vec<&str> {
+        const END: &str = "</VirtualHost>";
+
+        let mut blocks: Vec<&str> = haystack.split_inclusive(END).collect();
+
+        // Keep text after the last virtual host together with it.
+        if blocks.len() > 1 && !blocks[blocks.len() - 1].contains(END) {
+            let last = blocks.pop().unwrap_or_default();
+            let previous = blocks.pop().unwrap_or_default();
+            let start = haystack.len() - last.len() - previous.len();
+
+            blocks.push(&haystack[start..]);
+        }
+
+        blocks
+    }
 }
 
 #[cfg(test)]
@@ -61,6 +157,19 @@ mod test {
     use crate::apache::config_file::ConfigFile;
 
     use crate::configuration_file::ConfigurationFile;
+    use crate::test_support::debian_layout;
+
+    #[test]
+    fn include_is_found_uncommented_only() {
+        debian_layout(ConfigFile::SERVER);
+
+        assert!(ConfigFile::has_include(
+            "ServerRoot \"/etc/httpd\"\n  IncludeOptional /etc/apache2/sites-available/*.conf\n"
+        ));
+        assert!(!ConfigFile::has_include(
+            "# IncludeOptional /etc/apache2/sites-available/*.conf\n"
+        ));
+    }
 
     #[test]
     fn find_domain_without_subdomain_file() {
@@ -148,6 +257,8 @@ mod test {
 
     #[test]
     fn config_file_path_without_subdomain() {
+        debian_layout(ConfigFile::SERVER);
+
         let domain = Domain::new_unchecked("example", "com", None);
 
         let expected = PathBuf::from("/etc/apache2/sites-available/example.com.conf");
@@ -159,6 +270,8 @@ mod test {
 
     #[test]
     fn config_file_path_with_subdomain() {
+        debian_layout(ConfigFile::SERVER);
+
         let domain = Domain::new_unchecked("example", "com", Some("www"));
 
         let expected = PathBuf::from("/etc/apache2/sites-available/example.com.conf");
@@ -169,24 +282,61 @@ mod test {
     }
 
     #[test]
-    fn backup_file_path() {
+    fn backup_dir_path() {
+        debian_layout(ConfigFile::SERVER);
+
         let domain = Domain::new_unchecked("example", "com", None);
 
-        let expected = PathBuf::from("/etc/apache2/sites-available/example.com.conf.bak");
+        let expected =
+            PathBuf::from("/var/lib/qicert/backups/etc-apache2-sites-available-example.com.conf");
 
-        let backup_path = ConfigFile::backup_path(&domain);
+        let backup_dir = ConfigFile::backup_dir(&domain);
 
-        assert_eq!(backup_path, expected);
+        assert_eq!(backup_dir, expected);
     }
 
     #[test]
-    fn backup_file_path_with_subdomain() {
+    fn backup_dir_path_with_subdomain() {
+        debian_layout(ConfigFile::SERVER);
+
         let domain = Domain::new_unchecked("example", "com", Some("www"));
 
-        let expected = PathBuf::from("/etc/apache2/sites-available/example.com.conf.bak");
+        let expected =
+            PathBuf::from("/var/lib/qicert/backups/etc-apache2-sites-available-example.com.conf");
 
-        let backup_path = ConfigFile::backup_path(&domain);
+        let backup_dir = ConfigFile::backup_dir(&domain);
 
-        assert_eq!(backup_path, expected);
+        assert_eq!(backup_dir, expected);
+    }
+
+    #[test]
+    fn remove_domain_keeps_other_virtual_hosts() {
+        let domain = Domain::new("example", "com", Some("www")).unwrap();
+
+        let haystack = "
+    <VirtualHost *:80>
+        ServerName example.com
+        Redirect permanent / https://example.com/
+    </VirtualHost>
+    <VirtualHost *:80>
+        ServerName www.example.com
+        Redirect permanent / https://www.example.com/
+    </VirtualHost>
+    <VirtualHost *:443>
+        ServerName www.example.com
+        DocumentRoot /var/www/www.example.com/public
+    </VirtualHost>
+";
+
+        let expected = "
+    <VirtualHost *:80>
+        ServerName example.com
+        Redirect permanent / https://example.com/
+    </VirtualHost>";
+
+        assert_eq!(
+            ConfigFile::remove_domain_from_str(haystack, &domain),
+            expected
+        );
     }
 }
@@ -1,139 +1,319 @@
-use std::{
-    error::Error,
-    fs::File,
-    io::{Read, Write},
-};
+use std::{fs::File, path::PathBuf};
 
 use crate::{
-    apache::config_file::ConfigError, certer::Certer, configuration_file::ConfigurationFile,
-    domain::Domain, webroot::WebRoot, webserver::WebServer,
+    apache::config_file::ConfigError,
+    audit::Audit,
+    backup::Operation,
+    certer::{CertBotError, Certer},
+    configuration_file::ConfigurationFile,
+    domain::Domain,
+    error::QicertError,
+    listen::Listen,
+    profile::Profile,
+    report::{Action, Report},
+    webroot::WebRoot,
+    webserver::WebServer,
 };
 
-use super::{config_file::ConfigFile, http_config::HttpConfig, Apache};
+use super::{config_file::ConfigFile, http_config::HttpConfig, Apache, ApacheError};
 
 pub struct Configurator;
 
 impl Configurator {
-    pub fn create(domain: &Domain) -> Result<(), Box<dyn Error>> {
+    pub fn create(domain: &Domain, profile: &Profile, listen: &Listen) -> Result<(), QicertError> {
         let mut file = Self::create_file(domain)?;
-        Self::add_well_known(&mut file, domain)?;
+        Self::add_well_known(&mut file, domain, listen)?;
+
+        if profile.uses_default_webroot(domain) {
+            Self::create_webroot(domain)?;
+        }
 
-        WebRoot::create_and_set_chown(domain)?;
+        Self::enable_site(domain)?;
 
-        Apache::enable_site(domain)?;
-        Apache::reload()?;
+        if Certer::uses_webroot() {
+            Self::reload()?;
+        }
 
-        Certer::run(domain)?;
+        Self::certify(domain)?;
 
-        ConfigFile::truncate_file(&mut file)?;
-        Self::add_redirect_and_https(&mut file, domain)?;
+        ConfigFile::reset(domain, &mut file, "")?;
+        Self::add_redirect_and_https(&mut file, domain, profile, listen)?;
 
-        Apache::reload()?;
+        Self::reload()?;
 
         Ok(())
     }
 
-    fn create_file(domain: &Domain) -> Result<File, Box<dyn Error>> {
+    fn create_file(domain: &Domain) -> Result<File, QicertError> {
         if ConfigFile::file_exists(domain) {
             return Err(ConfigError::InvalidPath)?;
         }
 
-        let file = ConfigFile::create(domain)?;
+        let path = ConfigFile::file_path(domain);
+
+        let file = Report::step(
+            Action::CreateConfig,
+            Some(&path),
+            ConfigFile::create(domain),
+        )?;
 
         ConfigFile::chown_to_www(domain)?;
 
         Ok(file)
     }
 
-    fn add_well_known(file: &mut File, domain: &Domain) -> Result<(), Box<dyn Error>> {
-        let server_block = HttpConfig::http_well_known(domain);
+    fn add_well_known(
+        file: &mut File,
+        domain: &Domain,
+        listen: &Listen,
+    ) -> Result<(), QicertError> {
+        // Nothing to serve when the built-in responder answers the challenge.
+        if !Certer::uses_webroot() {
+            return Ok(());
+        }
+
+        let server_block =
+            ConfigFile::mark_challenge(domain, &HttpConfig::http_well_known(domain, listen));
 
-        writeln!(file, "{server_block}")?;
+        let written = ConfigFile::write_block(domain, file, &server_block);
+
+        Report::step(
+            Action::WriteConfig,
+            Some(&ConfigFile::file_path(domain)),
+            written,
+        )?;
 
         Ok(())
     }
 
-    fn add_redirect(file: &mut File, domain: &Domain) -> Result<(), Box<dyn Error>> {
-        let redirect_block = HttpConfig::http_redirect(domain);
+    /// Both blocks go in one region, the part of the file qicert owns for the hostname.
+    fn add_redirect_and_https(
+        file: &mut File,
+        domain: &Domain,
+        profile: &Profile,
+        listen: &Listen,
+    ) -> Result<(), QicertError> {
+        let redirect_block = HttpConfig::http_redirect(domain, listen);
+        let https_block = HttpConfig::https_content(domain, profile, listen);
+
+        ConfigFile::write_region(domain, file, &format!("{redirect_block}\n{https_block}"))?;
 
-        writeln!(file, "{redirect_block}")?;
+        Report::done(Action::WriteConfig, Some(&ConfigFile::file_path(domain)));
 
         Ok(())
     }
 
-    fn add_https(file: &mut File, domain: &Domain) -> Result<(), Box<dyn Error>> {
-        let https_block = HttpConfig::https_content(domain);
+    fn create_webroot(domain: &Domain) -> Result<(), QicertError> {
+        let created = WebRoot::create_and_set_chown(domain, &Apache::layout().owner());
 
-        writeln!(file, "{https_block}")?;
+        Report::step(
+            Action::Webroot,
+            Some(&WebRoot::build_pathbuf(domain)),
+            created,
+        )?;
 
         Ok(())
     }
 
-    fn add_redirect_and_https(file: &mut File, domain: &Domain) -> Result<(), Box<dyn Error>> {
-        Self::add_redirect(file, domain)?;
+    fn enable_site(domain: &Domain) -> Result<(), QicertError> {
+        Report::step(Action::Link, None, Apache::enable_site(domain))
+    }
 
-        Self::add_https(file, domain)?;
+    fn reload() -> Result<(), QicertError> {
+        let reloaded = Apache::reload();
 
-        Ok(())
+        Audit::reload(&reloaded);
+
+        Report::step(Action::Reload, None, reloaded)
     }
 
-    fn panic_if_missing_apache_or_certbot() {
-        if !Self::are_apache_and_certbot_installed() {
-            std::panic::set_hook(Box::new(|_| {
-                println!("Apache or Certbot are missing. Shutting down.");
-            }));
+    fn certify(domain: &Domain) -> Result<(), QicertError> {
+        let issued = Certer::run(domain);
 
-            panic!()
-        }
+        Audit::certificate(&issued);
+
+        Report::step(Action::Certificate, None, issued)
     }
 
-    pub fn append_or_create(domain: &Domain) -> Result<(), Box<dyn Error>> {
-        Self::panic_if_missing_apache_or_certbot();
+    fn ensure_apache_and_certbot_installed() -> Result<(), QicertError> {
+        if !Apache::is_installed() {
+            return Err(ApacheError::NotInstalled)?;
+        }
 
-        if !ConfigFile::file_exists(domain) {
-            return Self::create(domain);
+        if !Certer::is_installed() {
+            return Err(CertBotError::NotInstalled)?;
         }
 
-        Self::append(domain)
+        Ok(())
     }
 
-    fn append(domain: &Domain) -> Result<(), Box<dyn Error>> {
-        ConfigFile::create_backup(domain)?;
+    /// Files whose changes go to the audit log.
+    fn audited_files(domain: &Domain) -> Vec<PathBuf> {
+        vec![ConfigFile::file_path(domain)]
+    }
 
-        let mut file = ConfigFile::append(domain)?;
+    pub fn append_or_create(
+        domain: &Domain,
+        profile: &Profile,
+        listen: &Listen,
+    ) -> Result<(), QicertError> {
+        Audit::run(
+            Operation::Provision,
+            Apache::SERVER,
+            domain,
+            &Self::audited_files(domain),
+            || Self::_append_or_create(domain, profile, listen),
+        )
+    }
+
+    /// Rewrites the virtual hosts of a site that already has its certificate.
+    pub fn update(domain: &Domain, profile: &Profile, listen: &Listen) -> Result<(), QicertError> {
+        Audit::run(
+            Operation::Update,
+            Apache::SERVER,
+            domain,
+            &Self::audited_files(domain),
+            || Self::_update(domain, profile, listen),
+        )
+    }
 
-        let content_backup = {
-            let mut tmp = String::new();
+    pub fn remove(domain: &Domain) -> Result<(), QicertError> {
+        Audit::run(
+            Operation::Remove,
+            Apache::SERVER,
+            domain,
+            &Self::audited_files(domain),
+            || Self::_remove(domain),
+        )
+    }
+
+    fn _append_or_create(
+        domain: &Domain,
+        profile: &Profile,
+        listen: &Listen,
+    ) -> Result<(), QicertError> {
+        Self::ensure_apache_and_certbot_installed()?;
 
-            file.read_to_string(&mut tmp)?;
+        let existed = ConfigFile::file_exists(domain);
 
-            tmp
+        if existed {
+            let backup = ConfigFile::create_backup(domain, Operation::Provision);
+
+            Report::step(
+                Action::Backup,
+                Some(&ConfigFile::backup_dir(domain)),
+                backup,
+            )?;
+        }
+
+        let result = match existed {
+            true => Self::append(domain, profile, listen),
+            false => Self::create(domain, profile, listen),
         };
 
+        result.or_else(|cause| Self::rollback(domain, existed, cause))
+    }
+
+    /// Puts the configuration file back the way it was before a failed run.
+    fn rollback(domain: &Domain, existed: bool, cause: QicertError) -> Result<(), QicertError> {
+        let path = ConfigFile::file_path(domain);
+
+        match Report::step(
+            Action::Rollback,
+            Some(&path),
+            ConfigFile::restore(domain, existed),
+        ) {
+            Ok(_) => Err(cause),
+            Err(e) => Err(QicertError::rollback(cause, e)),
+        }
+    }
+
+    /// Drops the challenge block an interrupted run left behind. Tells whether that
+    /// run got as far as the certificate, leaving only the configuration to write.
+    fn clear_interrupted_run(
+        domain: &Domain,
+        file: &mut File,
+        content: &mut String,
+    ) -> Result<bool, QicertError> {
+        if !ConfigFile::clear_stale_challenge(domain, file, content)? {
+            return Ok(false);
+        }
+
+        Report::say(format!(
+            "Removed the challenge block an interrupted run left for {}",
+            domain.unicode()
+        ));
+
+        let issued = Certer::has_certificate(domain);
+
+        if issued {
+            Report::say(format!(
+                "Certificate for {} already issued, resuming",
+                domain.unicode()
+            ));
+        }
+
+        Ok(issued)
+    }
+
+    fn append(domain: &Domain, profile: &Profile, listen: &Listen) -> Result<(), QicertError> {
+        let mut file = ConfigFile::append(domain)?;
+
+        let mut content_backup = ConfigFile::read(domain, &mut file)?;
+
+        let resume = Self::clear_interrupted_run(domain, &mut file, &mut content_backup)?;
+
         if !ConfigFile::find_domain_in_str(content_backup.as_str(), domain) {
-            Self::add_well_known(&mut file, domain)?;
-            match WebRoot::create_and_set_chown(domain) {
-                Ok(_) => println!("Webroot created for {domain}"),
-                Err(e) => println!("{e} error for {domain}"),
-            };
+            if !resume {
+                Self::add_well_known(&mut file, domain, listen)?;
+                if profile.uses_default_webroot(domain) {
+                    match Self::create_webroot(domain) {
+                        Ok(_) => Report::say(format!("Webroot created for {}", domain.unicode())),
+                        Err(e) => Report::say(format!("{e} error for {}", domain.unicode())),
+                    };
+                }
+            }
+
+            Self::enable_site(domain)?;
 
-            Apache::enable_site(domain)?;
-            Apache::reload()?;
+            if !resume {
+                if Certer::uses_webroot() {
+                    Self::reload()?;
+                }
 
-            Certer::run(domain)?;
+                Self::certify(domain)?;
+            }
 
-            ConfigFile::truncate_file(&mut file)?;
-            file.write_all(content_backup.as_bytes())?;
+            ConfigFile::reset(domain, &mut file, &content_backup)?;
 
-            Self::add_redirect_and_https(&mut file, domain)?;
+            Self::add_redirect_and_https(&mut file, domain, profile, listen)?;
 
-            Apache::reload()?;
+            Self::reload()?;
         }
 
         Ok(())
     }
 
-    pub fn are_apache_and_certbot_installed() -> bool {
-        Apache::is_installed() && Certer::is_installed()
+    fn _update(domain: &Domain, profile: &Profile, listen: &Listen) -> Result<(), QicertError> {
+        ConfigFile::create_backup(domain, Operation::Update)?;
+        ConfigFile::remove_domain(domain)?;
+
+        let mut file = ConfigFile::append(domain)?;
+
+        Self::add_redirect_and_https(&mut file, domain, profile, listen)?;
+
+        Self::enable_site(domain)?;
+        Self::reload()?;
+
+        Ok(())
+    }
+
+    fn _remove(domain: &Domain) -> Result<(), QicertError> {
+        ConfigFile::create_backup(domain, Operation::Remove)?;
+        ConfigFile::remove_domain(domain)?;
+
+        Self::reload()?;
+
+        Ok(())
     }
 }
@@ -1,44 +1,114 @@
-use crate::{domain::Domain, webroot::WebRoot};
+use crate::{domain::Domain, listen::Listen, profile::Profile, settings::Settings};
 
 pub struct HttpConfig;
 
 impl HttpConfig {
-    pub fn http_well_known(domain: &Domain) -> String {
+    /// The `<VirtualHost>` addresses for `port`, `*` unless addresses are given.
+    fn addresses(listen: &Listen, port: u16) -> String {
+        if listen.addresses().is_empty() {
+            return format!("*:{port}");
+        }
+
+        listen
+            .addresses()
+            .iter()
+            .map(|address| format!("{}{port}", Listen::host(address)))
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    /// The `Listen` directives for `port`, nothing for 80 and 443 which the main
+    /// configuration already has. Each is guarded by a `Define` so that sites sharing
+    /// the port do not bind it twice.
+    fn listen_directives(listen: &Listen, port: u16, protocol: &str) -> String {
+        if port == Listen::HTTP_PORT || port == Listen::HTTPS_PORT {
+            return String::new();
+        }
+
+        let sockets = match listen.addresses() {
+            [] => vec![port.to_string()],
+            addresses => addresses
+                .iter()
+                .map(|address| format!("{}{port}", Listen::host(address)))
+                .collect(),
+        };
+
+        sockets
+            .iter()
+            .map(|socket| {
+                let name = socket.replace(|c: char| !c.is_ascii_alphanumeric(), "_");
+
+                format!(
+                    "
+    <IfDefine !qicert_listen_{name}>
+        Define qicert_listen_{name}
+        Listen {socket} {protocol}
+    </IfDefine>"
+                )
+            })
+            .collect()
+    }
+
+    pub fn http_well_known(domain: &Domain, listen: &Listen) -> String {
+        let challenge_root = Settings::current().challenge_root().display();
+
         let server_name = format!("ServerName {}", domain);
 
+        let addresses = Self::addresses(listen, listen.http_port());
+        let directives = Self::listen_directives(listen, listen.http_port(), "http");
+
         format!(
-            "
-    <VirtualHost *:80>
+            "{directives}
+    <VirtualHost {addresses}>
         ServerAdmin webmaster@localhost
         {server_name}
-        DocumentRoot /var/www/.well-known/challenge
+        DocumentRoot {challenge_root}
         ErrorLog ${{APACHE_LOG_DIR}}/error.log
         CustomLog ${{APACHE_LOG_DIR}}/access.log combined
     </VirtualHost>"
         )
     }
 
-    pub fn http_redirect(domain: &Domain) -> String {
+    pub fn http_redirect(domain: &Domain, listen: &Listen) -> String {
         let server_name = format!("ServerName {}", domain);
 
+        let addresses = Self::addresses(listen, listen.http_port());
+        let port = listen.https_port_suffix();
+        let directives = Self::listen_directives(listen, listen.http_port(), "http");
+
         format!(
-            "
-    <VirtualHost *:80>
+            "{directives}
+    <VirtualHost {addresses}>
         {server_name}
-        Redirect permanent / https://{domain}/
+        Redirect permanent / https://{domain}{port}/
     </VirtualHost>"
         )
     }
 
-    pub fn https_content(domain: &Domain) -> String {
+    fn site_content(profile: &Profile) -> String {
+        match profile {
+            Profile::Static(root) => format!("DocumentRoot {}", root.display()),
+            Profile::Proxy(upstream) => format!(
+                "ProxyPreserveHost On
+        ProxyPass / http://{upstream}/
+        ProxyPassReverse / http://{upstream}/"
+            ),
+        }
+    }
+
+    /// Apache offers HTTP/2 whether or not it was asked for.
+    pub fn https_content(domain: &Domain, profile: &Profile, listen: &Listen) -> String {
         let server_name = format!("ServerName {}", domain);
-        let root = WebRoot::build_path_string(domain);
+        let site = Self::site_content(profile);
+
+        let addresses = Self::addresses(listen, listen.https_port());
+        let directives = Self::listen_directives(listen, listen.https_port(), "https");
 
         format!(
-            "
-    <VirtualHost *:443>
+            "{directives}
+    <VirtualHost {addresses}>
         {server_name}
-        DocumentRoot {root}
+        {site}
         Protocols h2 http/1.1
         SSLCertificateFile /etc/letsencrypt/live/{domain}/fullchain.pem
         SSLCertificateKeyFile /etc/letsencrypt/live/{domain}/privkey.pem
@@ -49,7 +119,7 @@ impl HttpConfig {
 
 #[cfg(test)]
 mod test {
-    use crate::domain::Domain;
+    use crate::{domain::Domain, listen::Listen};
 
     #[test]
     fn http_well_known() {
@@ -63,9 +133,10 @@ mod test {
     </VirtualHost>"
             .to_string();
 
-        let domain = crate::Domain::new("example", "com", None).unwrap();
+        let domain = crate::domain::Domain::new("example", "com", None).unwrap();
 
-        let http_config = crate::apache::http_config::HttpConfig::http_well_known(&domain);
+        let http_config =
+            crate::apache::http_config::HttpConfig::http_well_known(&domain, &Listen::default());
 
         assert_eq!(http_config, expected);
     }
@@ -84,7 +155,8 @@ mod test {
 
         let domain = Domain::new("example", "com", Some("test")).unwrap();
 
-        let http_config = crate::apache::http_config::HttpConfig::http_well_known(&domain);
+        let http_config =
+            crate::apache::http_config::HttpConfig::http_well_known(&domain, &Listen::default());
 
         assert_eq!(http_config, expected);
     }
@@ -103,7 +175,8 @@ mod test {
 
         let domain = Domain::new("example", "com", Some("test1.staging1")).unwrap();
 
-        let http_config = crate::apache::http_config::HttpConfig::http_well_known(&domain);
+        let http_config =
+            crate::apache::http_config::HttpConfig::http_well_known(&domain, &Listen::default());
 
         assert_eq!(http_config, expected);
     }
@@ -119,7 +192,8 @@ mod test {
 
         let domain = Domain::new("example", "com", None).unwrap();
 
-        let http_config = crate::apache::http_config::HttpConfig::http_redirect(&domain);
+        let http_config =
+            crate::apache::http_config::HttpConfig::http_redirect(&domain, &Listen::default());
 
         assert_eq!(http_config, expected);
     }
@@ -135,7 +209,8 @@ mod test {
 
         let domain = Domain::new("example", "com", Some("test")).unwrap();
 
-        let http_config = crate::apache::http_config::HttpConfig::http_redirect(&domain);
+        let http_config =
+            crate::apache::http_config::HttpConfig::http_redirect(&domain, &Listen::default());
 
         assert_eq!(http_config, expected);
     }
@@ -151,7 +226,8 @@ mod test {
 
         let domain = Domain::new("example", "com", Some("test1.staging1")).unwrap();
 
-        let http_config = crate::apache::http_config::HttpConfig::http_redirect(&domain);
+        let http_config =
+            crate::apache::http_config::HttpConfig::http_redirect(&domain, &Listen::default());
 
         assert_eq!(
             http_config, expected,
@@ -173,7 +249,11 @@ mod test {
 
         let domain = Domain::new("example", "com", None).unwrap();
 
-        let http_config = crate::apache::http_config::HttpConfig::https_content(&domain);
+        let http_config = crate::apache::http_config::HttpConfig::https_content(
+            &domain,
+            &crate::profile::Profile::default_for(&domain),
+            &Listen::default(),
+        );
 
         assert_eq!(http_config, expected);
     }
@@ -192,7 +272,11 @@ mod test {
 
         let domain = Domain::new("example", "com", Some("test")).unwrap();
 
-        let http_config = crate::apache::http_config::HttpConfig::https_content(&domain);
+        let http_config = crate::apache::http_config::HttpConfig::https_content(
+            &domain,
+            &crate::profile::Profile::default_for(&domain),
+            &Listen::default(),
+        );
 
         assert_eq!(http_config, expected);
     }
@@ -211,8 +295,73 @@ mod test {
 
         let domain = Domain::new("example", "com", Some("test1.staging1")).unwrap();
 
-        let http_config = crate::apache::http_config::HttpConfig::https_content(&domain);
+        let http_config = crate::apache::http_config::HttpConfig::https_content(
+            &domain,
+            &crate::profile::Profile::default_for(&domain),
+            &Listen::default(),
+        );
+
+        assert_eq!(http_config, expected);
+    }
+
+    #[test]
+    fn https_block_proxy() {
+        let expected = "
+    <VirtualHost *:443>
+        ServerName app.example.com
+        ProxyPreserveHost On
+        ProxyPass / http://127.0.0.1:3000/
+        ProxyPassReverse / http://127.0.0.1:3000/
+        Protocols h2 http/1.1
+        SSLCertificateFile /etc/letsencrypt/live/app.example.com/fullchain.pem
+        SSLCertificateKeyFile /etc/letsencrypt/live/app.example.com/privkey.pem
+    </VirtualHost>";
+
+        let domain = Domain::new("example", "com", Some("app")).unwrap();
+        let profile = crate::profile::Profile::Proxy("127.0.0.1:3000".parse().unwrap());
+
+        let http_config = crate::apache::http_config::HttpConfig::https_content(
+            &domain,
+            &profile,
+            &Listen::default(),
+        );
 
         assert_eq!(http_config, expected);
     }
+
+    #[test]
+    fn virtual_hosts_on_given_addresses_and_ports() {
+        let domain = Domain::new("example", "com", Some("www")).unwrap();
+
+        let listen = Listen::default()
+            .with_address("192.0.2.10".parse().unwrap())
+            .with_address("2001:db8::10".parse().unwrap())
+            .with_http_port(8080)
+            .with_https_port(8443);
+
+        let https = crate::apache::http_config::HttpConfig::https_content(
+            &domain,
+            &crate::profile::Profile::default_for(&domain),
+            &listen,
+        );
+
+        assert!(https.starts_with(
+            "
+    <IfDefine !qicert_listen_192_0_2_10_8443>
+        Define qicert_listen_192_0_2_10_8443
+        Listen 192.0.2.10:8443 https
+    </IfDefine>
+    <IfDefine !qicert_listen__2001_db8__10__8443>
+        Define qicert_listen__2001_db8__10__8443
+        Listen [2001:db8::10]:8443 https
+    </IfDefine>
+    <VirtualHost 192.0.2.10:8443 [2001:db8::10]:8443>"
+        ));
+
+        let redirect = crate::apache::http_config::HttpConfig::http_redirect(&domain, &listen);
+
+        assert!(redirect.contains("Listen 192.0.2.10:8080 http\n"));
+        assert!(redirect.contains("<VirtualHost 192.0.2.10:8080 [2001:db8::10]:8080>"));
+        assert!(redirect.contains("Redirect permanent / https://www.example.com:8443/"));
+    }
 }
@@ -1,12 +1,19 @@
-use std::process::{Child, Command};
+use std::process::Command;
 use std::{error::Error, fmt::Display};
 
 use crate::domain::Domain;
-use crate::webserver::WebServer;
+use crate::{
+    configuration_file::ConfigurationFile,
+    error::{run_command, QicertError},
+    layout::{Enable, Server},
+    webserver::WebServer,
+};
+
+use self::config_file::ConfigFile;
 
 pub(crate) mod config_file;
 pub(crate) mod configurator;
-pub(crate) mod http_config;
+pub mod http_config;
 
 #[derive(Debug, Copy, Clone)]
 pub enum ApacheError {
@@ -29,31 +36,64 @@ impl Display for ApacheError {
 
 pub struct Apache;
 
-impl<'a> WebServer<'a> for Apache {
-    const WEBSERVER_SBIN_PATH: &'a str = "/usr/sbin/apache2";
-    const BINARY_NAME: &'a str = "apache2";
+impl WebServer for Apache {
+    const SERVER: Server = Server::Apache;
 }
 
 impl Apache {
-    const SITE_ENABLE_COMMAND: &str = "a2ensite";
+    /// The configurator reloads before the first configuration test, a missing Apache
+    /// has to fail as such and not as a reload that did not work.
+    pub fn reload() -> Result<(), QicertError> {
+        if !Self::is_installed() {
+            return Err(ApacheError::NotInstalled)?;
+        }
 
-    pub fn reload() -> Result<(), ApacheError> {
         Self::_reload(ApacheError::CannotReload)
     }
 
-    pub fn enable_site(domain: &Domain) -> Result<(), ApacheError> {
-        let domain = format!("{}.{}", domain.get_name(), domain.get_tld());
+    /// `apachectl` sets up the environment Debian's `apache2` needs to parse its
+    /// configuration, and sits next to the binary everywhere.
+    pub fn check() -> Result<(), QicertError> {
+        let apachectl = Self::layout().binary().with_file_name("apachectl");
 
-        let output = Command::new(Self::SITE_ENABLE_COMMAND)
-            .arg(&domain)
-            .spawn()
-            .and_then(Child::wait_with_output)
-            .map_err(|_| ApacheError::BadConfiguration)?;
+        run_command(
+            Command::new(apachectl).arg("-t"),
+            ApacheError::BadConfiguration,
+        )?;
 
-        if !output.status.success() {
-            return Err(ApacheError::BadConfiguration);
+        Ok(())
+    }
+
+    pub fn check_and_reload() -> Result<(), QicertError> {
+        if !Self::is_installed() {
+            return Err(ApacheError::NotInstalled)?;
         }
 
+        Self::check()?;
+        Self::reload()?;
+
+        Ok(())
+    }
+
+    /// Debian enables each site with its helper and Arch needs the directory included
+    /// once, elsewhere `conf.d` is always loaded.
+    pub fn enable_site(domain: &Domain) -> Result<(), QicertError> {
+        let layout = Self::layout();
+
+        let site_enable_command = match layout.enable() {
+            Enable::Command(command) => command,
+            Enable::Include(main) => return ConfigFile::ensure_include(main),
+            _ => return Ok(()),
+        };
+
+        let file_name = ConfigFile::file_name(domain);
+        let site = file_name.trim_end_matches(".conf");
+
+        run_command(
+            Command::new(site_enable_command).arg(site),
+            ApacheError::BadConfiguration,
+        )?;
+
         Ok(())
     }
 }
@@ -0,0 +1,512 @@
+use std::{
+    cell::RefCell,
+    error::Error,
+    fmt::Display,
+    fs::{self, File, OpenOptions},
+    io::{self, BufRead, BufReader, Read, Seek, SeekFrom, Write},
+    os::unix::{
+        fs::{MetadataExt, OpenOptionsExt},
+        net::UnixDatagram,
+    },
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::{
+    backup::Operation,
+    domain::Domain,
+    error::QicertError,
+    layout::Server,
+    report::{Report, StepResult},
+    settings::Settings,
+    timestamp::Timestamp,
+};
+
+#[derive(Debug)]
+pub enum AuditError {
+    NotWritten,
+    Unreadable,
+    Broken { line: usize },
+}
+
+impl Display for AuditError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::NotWritten => write!(f, "The audit log could not be written"),
+            Self::Unreadable => write!(f, "The audit log could not be read"),
+            Self::Broken { line } => write!(
+                f,
+                "The audit log was altered: line {line} does not follow the line before it"
+            ),
+        }
+    }
+}
+
+impl Error for AuditError {}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event {
+    /// A file whose content changed, with the SHA-256 before and after. `None` when
+    /// the file did not exist.
+    File {
+        path: PathBuf,
+        before: Option<String>,
+        after: Option<String>,
+    },
+    Certificate,
+    Reload,
+    /// End of the operation.
+    Finished,
+}
+
+/// One line of the audit log.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Entry {
+    time: String,
+    sudo_user: Option<String>,
+    uid: Option<u32>,
+    pid: u32,
+    command: Vec<String>,
+    operation: Operation,
+    server: Server,
+    hostname: String,
+    #[serde(flatten)]
+    event: Event,
+    result: StepResult,
+    error: Option<String>,
+    /// SHA-256 of the previous line, which makes editing or dropping a line in the
+    /// middle of the log show.
+    prev: String,
+}
+
+impl Entry {
+    pub fn time(&self) -> &str {
+        &self.time
+    }
+
+    /// Who ran qicert through sudo, as sudo tells.
+    pub fn sudo_user(&self) -> Option<&str> {
+        self.sudo_user.as_deref()
+    }
+
+    /// Effective uid of the run.
+    pub fn uid(&self) -> Option<u32> {
+        self.uid
+    }
+
+    pub fn command(&self) -> &[String] {
+        &self.command
+    }
+
+    pub fn operation(&self) -> Operation {
+        self.operation
+    }
+
+    pub fn server(&self) -> Server {
+        self.server
+    }
+
+    pub fn hostname(&self) -> &str {
+        &self.hostname
+    }
+
+    pub fn event(&self) -> &Event {
+        &self.event
+    }
+
+    pub fn result(&self) -> StepResult {
+        self.result
+    }
+
+    pub fn error(&self) -> Option<&str> {
+        self.error.as_deref()
+    }
+}
+
+impl Display for Entry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let who = match (&self.sudo_user, self.uid) {
+            (Some(user), _) => user.clone(),
+            (None, Some(uid)) => format!("uid {uid}"),
+            (None, None) => String::from("unknown"),
+        };
+
+        let short = |hash: &Option<String>| match hash {
+            Some(hash) => hash.chars().take(12).collect(),
+            None => String::from("none"),
+        };
+
+        let event = match &self.event {
+            Event::File {
+                path,
+                before,
+                after,
+            } => format!(
+                "wrote {} {} -> {}",
+                path.display(),
+                short(before),
+                short(after)
+            ),
+            Event::Certificate => String::from("certificate"),
+            Event::Reload => format!("reload {:?}", self.server).to_lowercase(),
+            Event::Finished => format!("{} finished", self.operation),
+        };
+
+        write!(f, "{}  {:<12}{:<32}{event}", self.time, who, self.hostname)?;
+
+        if self.result == StepResult::Failed {
+            write!(f, " FAILED")?;
+
+            if let Some(error) = &self.error {
+                write!(f, ": {}", error.lines().next().unwrap_or_default())?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Scope {
+    operation: Operation,
+    server: Server,
+    hostname: String,
+}
+
+thread_local! {
+    static SCOPE: RefCell<Option<Scope>> = const { RefCell::new(None) };
+}
+
+/// Append-only record of what qicert changed, kept as JSON lines in the `audit_log`
+/// setting and, with `audit_syslog`, sent to syslog or journald as well.
+pub struct Audit;
+
+impl Audit {
+    const GENESIS: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+    const SYSLOG: &str = "/dev/log";
+    /// authpriv.info
+    const SYSLOG_PRIORITY: u8 = 10 * 8 + 6;
+    const CHUNK: u64 = 4096;
+
+    /// Runs `body` as one audited operation: the certificates and reloads it reports,
+    /// the files of `files` it changed and how it ended are logged.
+    pub fn run<T, F>(
+        operation: Operation,
+        server: Server,
+        domain: &Domain,
+        files: &[PathBuf],
+        body: F,
+    ) -> Result<T, QicertError>
+    where
+        F: FnOnce() -> Result<T, QicertError>,
+    {
+        let before = files.iter().map(|f| Self::hash_file(f)).collect::<Vec<_>>();
+
+        let outer = SCOPE.replace(Some(Scope {
+            operation,
+            server,
+            hostname: domain.to_string(),
+        }));
+
+        let result = body();
+
+        for (path, before) in files.iter().zip(before) {
+            let after = Self::hash_file(path);
+
+            if before != after {
+                let event = Event::File {
+                    path: path.clone(),
+                    before,
+                    after,
+                };
+
+                Self::log::<()>(event, &Ok(()));
+            }
+        }
+
+        Self::log(Event::Finished, &result);
+
+        SCOPE.set(outer);
+
+        result
+    }
+
+    pub fn certificate<T>(result: &Result<T, QicertError>) {
+        Self::log(Event::Certificate, result);
+    }
+
+    pub fn reload<T>(result: &Result<T, QicertError>) {
+        Self::log(Event::Reload, result);
+    }
+
+    /// Outside of `run` there is nothing to log. A log that can not be written does not
+    /// undo what was already done, it is only reported.
+    fn log<T>(event: Event, result: &Result<T, QicertError>) {
+        let Some(scope) = SCOPE.with_borrow(Clone::clone) else {
+            return;
+        };
+
+        let (result, error) = match result {
+            Ok(_) => (StepResult::Done, None),
+            Err(e) => (StepResult::Failed, Some(e.chain())),
+        };
+
+        let entry = Entry {
+            time: Timestamp::now().rfc3339(),
+            sudo_user: std::env::var("SUDO_USER").ok(),
+            uid: fs::metadata("/proc/self").ok().map(|m| m.uid()),
+            pid: std::process::id(),
+            command: std::env::args().collect(),
+            operation: scope.operation,
+            server: scope.server,
+            hostname: scope.hostname,
+            event,
+            result,
+            error,
+            prev: String::new(),
+        };
+
+        let settings = Settings::current();
+
+        match Self::append(settings.audit_log(), entry) {
+            Ok(line) if settings.audit_syslog() => {
+                if let Err(e) = Self::send_to_syslog(Path::new(Self::SYSLOG), &line) {
+                    Report::say(format!("Audit entry not sent to syslog: {e}"));
+                }
+            }
+            Ok(_) => {}
+            Err(e) => Report::say(format!("Audit entry not written: {}", e.chain())),
+        }
+    }
+
+    pub(crate) fn sha256(bytes: &[u8]) -> String {
+        Sha256::digest(bytes)
+            .iter()
+            .map(|b| format!("{b:02x}"))
+            .collect()
+    }
+
+    fn hash_file(path: &Path) -> Option<String> {
+        fs::read(path).ok().map(|content| Self::sha256(&content))
+    }
+
+    /// Chains `entry` to the last line and appends it, returning the line written. The
+    /// log stays locked in between so that concurrent runs do not fork the chain.
+    fn append(path: &Path, mut entry: Entry) -> Result<String, QicertError> {
+        let not_written = |e| QicertError::io(AuditError::NotWritten, path, e);
+
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)
+                .map_err(|e| QicertError::io(AuditError::NotWritten, parent, e))?;
+        }
+
+        let mut file = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .mode(0o600)
+            .open(path)
+            .map_err(not_written)?;
+
+        file.lock().map_err(not_written)?;
+
+        entry.prev = match Self::last_line(&mut file).map_err(not_written)? {
+            Some(line) => Self::sha256(&line),
+            None => Self::GENESIS.to_string(),
+        };
+
+        let line = serde_json::to_string(&entry)
+            .map_err(|e| QicertError::io(AuditError::NotWritten, path, io::Error::other(e)))?;
+
+        writeln!(file, "{line}").map_err(not_written)?;
+
+        Ok(line)
+    }
+
+    fn last_line(file: &mut File) -> io::Result<Option<Vec<u8>>> {
+        let mut pos = file.seek(SeekFrom::End(0))?;
+        let mut tail = Vec::new();
+
+        while pos > 0 {
+            let chunk = Self::CHUNK.min(pos);
+            pos -= chunk;
+
+            let mut buf = vec![0; chunk as usize];
+
+            file.seek(SeekFrom::Start(pos))?;
+            file.read_exact(&mut buf)?;
+
+            buf.extend_from_slice(&tail);
+            tail = buf;
+
+            let content = tail.strip_suffix(b"\n").unwrap_or(&tail);
+
+            if let Some(i) = content.iter().rposition(|&b| b == b'\n') {
+                return Ok(Some(content[i + 1..].to_vec()));
+            }
+        }
+
+        let content = tail.strip_suffix(b"\n").unwrap_or(&tail);
+
+        Ok((!content.is_empty()).then(|| content.to_vec()))
+    }
+
+    fn send_to_syslog(socket: &Path, line: &str) -> io::Result<()> {
+        let message = format!(
+            "<{}>qicert[{}]: {line}",
+            Self::SYSLOG_PRIORITY,
+            std::process::id()
+        );
+
+        UnixDatagram::unbound()?.send_to(message.as_bytes(), socket)?;
+
+        Ok(())
+    }
+
+    /// Every entry of the log at `path`, oldest first, after checking that each line
+    /// carries the hash of the one before it.
+    pub fn read(path: &Path) -> Result<Vec<Entry>, QicertError> {
+        let file = match File::open(path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(QicertError::io(AuditError::Unreadable, path, e)),
+        };
+
+        let mut entries = Vec::new();
+        let mut prev = Self::GENESIS.to_string();
+
+        for (i, line) in BufReader::new(file).split(b'\n').enumerate() {
+            let line = line.map_err(|e| QicertError::io(AuditError::Unreadable, path, e))?;
+
+            let entry: Entry = serde_json::from_slice(&line)
+                .map_err(|_| QicertError::failed(AuditError::Broken { line: i + 1 }))?;
+
+            if entry.prev != prev {
+                return Err(AuditError::Broken { line: i + 1 })?;
+            }
+
+            prev = Self::sha256(&line);
+
+            entries.push(entry);
+        }
+
+        Ok(entries)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_support::scratch;
+
+    fn entry(event: Event) -> Entry {
+        Entry {
+            time: Timestamp::now().rfc3339(),
+            sudo_user: Some(String::from("alice")),
+            uid: Some(0),
+            pid: 1,
+            command: vec![String::from("qicert"), String::from("nginx")],
+            operation: Operation::Provision,
+            server: Server::Nginx,
+            hostname: String::from("example.com"),
+            event,
+            result: StepResult::Done,
+            error: None,
+            prev: String::new(),
+        }
+    }
+
+    #[test]
+    fn hashes_as_sha256() {
+        assert_eq!(
+            Audit::sha256(b"abc"),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+    }
+
+    #[test]
+    fn lines_are_chained() {
+        let dir = scratch("audit-chain");
+        let path = dir.join("audit.jsonl");
+
+        Audit::append(&path, entry(Event::Certificate)).unwrap();
+        Audit::append(&path, entry(Event::Reload)).unwrap();
+        Audit::append(&path, entry(Event::Finished)).unwrap();
+
+        let entries = Audit::read(&path).unwrap();
+
+        assert_eq!(entries.len(), 3);
+        assert_eq!(entries[0].prev, Audit::GENESIS);
+        assert_eq!(entries[2].event(), &Event::Finished);
+
+        let content = fs::read_to_string(&path).unwrap();
+        let lines = content.lines().collect::<Vec<_>>();
+
+        assert_eq!(entries[1].prev, Audit::sha256(lines[0].as_bytes()));
+    }
+
+    #[test]
+    fn edited_line_breaks_the_chain() {
+        let dir = scratch("audit-edited");
+        let path = dir.join("audit.jsonl");
+
+        Audit::append(&path, entry(Event::Certificate)).unwrap();
+        Audit::append(&path, entry(Event::Reload)).unwrap();
+
+        let content = fs::read_to_string(&path).unwrap();
+
+        fs::write(&path, content.replacen("alice", "mallory", 1)).unwrap();
+
+        assert!(matches!(
+            Audit::read(&path).unwrap_err().downcast_ref(),
+            Some(AuditError::Broken { line: 2 })
+        ));
+    }
+
+    #[test]
+    fn last_line_spans_chunks() {
+        let dir = scratch("audit-chunks");
+        let path = dir.join("audit.jsonl");
+
+        let long = "x".repeat(Audit::CHUNK as usize * 2 + 10);
+
+        fs::write(&path, format!("first\n{long}\n")).unwrap();
+
+        let mut file = File::open(&path).unwrap();
+
+        assert_eq!(
+            Audit::last_line(&mut file).unwrap(),
+            Some(long.into_bytes())
+        );
+
+        fs::write(&path, "").unwrap();
+
+        let mut file = File::open(&path).unwrap();
+
+        assert_eq!(Audit::last_line(&mut file).unwrap(), None);
+    }
+
+    #[test]
+    fn entries_reach_the_syslog_socket() {
+        let dir = scratch("audit-syslog");
+        let socket = dir.join("log");
+
+        let listener = UnixDatagram::bind(&socket).unwrap();
+
+        Audit::send_to_syslog(&socket, "{\"event\":\"reload\"}").unwrap();
+
+        let mut buf = [0; 256];
+        let len = listener.recv(&mut buf).unwrap();
+
+        assert_eq!(
+            String::from_utf8_lossy(&buf[..len]),
+            format!(
+                "<86>qicert[{}]: {{\"event\":\"reload\"}}",
+                std::process::id()
+            )
+        );
+    }
+}
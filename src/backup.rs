@@ -0,0 +1,497 @@
+use std::{
+    error::Error,
+    fmt::Display,
+    fs,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    apache::Apache,
+    audit::Audit,
+    caddy::{Caddy, CaddyReload},
+    domain::Domain,
+    error::QicertError,
+    haproxy::HaProxy,
+    layout::Server,
+    lighttpd::Lighttpd,
+    lock::{Lock, Mode},
+    nginx::Nginx,
+    report::{Action, Report},
+    settings::Settings,
+    timestamp::Timestamp,
+};
+
+#[derive(Debug)]
+pub enum BackupError {
+    NotSaved,
+    Unreadable,
+    NotFound(String),
+    NotRestored,
+}
+
+impl Display for BackupError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::NotSaved => write!(f, "The backup could not be saved"),
+            Self::Unreadable => write!(f, "The backup history could not be read"),
+            Self::NotFound(id) => write!(f, "There is no backup {id}"),
+            Self::NotRestored => write!(f, "The backup could not be put back"),
+        }
+    }
+}
+
+impl Error for BackupError {}
+
+/// What qicert was about to do to the file when the backup was taken.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Operation {
+    Provision,
+    Update,
+    Remove,
+    Restore,
+    Repair,
+    Upgrade,
+}
+
+impl Display for Operation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Provision => write!(f, "provision"),
+            Self::Update => write!(f, "update"),
+            Self::Remove => write!(f, "remove"),
+            Self::Restore => write!(f, "restore"),
+            Self::Repair => write!(f, "repair"),
+            Self::Upgrade => write!(f, "upgrade"),
+        }
+    }
+}
+
+/// Kept next to each backup as `<timestamp>.toml`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Metadata {
+    version: String,
+    operation: Operation,
+    hostname: String,
+    server: Server,
+    file: PathBuf,
+}
+
+impl Metadata {
+    /// qicert version that took the backup.
+    pub fn version(&self) -> &str {
+        &self.version
+    }
+
+    pub fn operation(&self) -> Operation {
+        self.operation
+    }
+
+    pub fn hostname(&self) -> &str {
+        &self.hostname
+    }
+
+    pub fn server(&self) -> Server {
+        self.server
+    }
+
+    /// The configuration file the backup is a copy of.
+    pub fn file(&self) -> &Path {
+        &self.file
+    }
+}
+
+/// One copy of a configuration file, identified by `<file>/<timestamp>`.
+#[derive(Debug, Clone)]
+pub struct Backup {
+    id: String,
+    path: PathBuf,
+    metadata: Metadata,
+}
+
+impl Backup {
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    /// Where the copy is stored.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn metadata(&self) -> &Metadata {
+        &self.metadata
+    }
+}
+
+/// History of the configuration files qicert changed, outside of the server's
+/// configuration directory so that no include glob picks the copies up. Each file gets
+/// its own directory, named like its lock, holding the newest `keep` copies.
+#[derive(Debug, Clone)]
+pub struct Backups {
+    root: PathBuf,
+    keep: usize,
+}
+
+impl Backups {
+    const METADATA: &str = ".toml";
+
+    pub fn new<P: Into<PathBuf>>(root: P, keep: usize) -> Self {
+        Self {
+            root: root.into(),
+            keep,
+        }
+    }
+
+    /// The history in the `backup_dir` setting, pruned to `backup_keep` copies.
+    pub fn current() -> Self {
+        let settings = Settings::current();
+
+        Self::new(settings.backup_dir(), settings.backup_keep())
+    }
+
+    /// `/etc/nginx/sites-available/example.com.conf` is kept in
+    /// `<root>/etc-nginx-sites-available-example.com.conf/`.
+    pub fn dir_for(&self, file: &Path) -> PathBuf {
+        let name = file
+            .to_string_lossy()
+            .trim_start_matches('/')
+            .replace('/', "-");
+
+        self.root.join(name)
+    }
+
+    /// Copies `file` into the history and drops the copies beyond `keep`.
+    pub fn save(
+        &self,
+        file: &Path,
+        server: Server,
+        domain: &Domain,
+        operation: Operation,
+    ) -> Result<Backup, QicertError> {
+        let dir = self.dir_for(file);
+
+        fs::create_dir_all(&dir).map_err(|e| QicertError::io(BackupError::NotSaved, &dir, e))?;
+
+        // Backups taken within the same millisecond are spread over the next ones, so
+        // that names stay unique and in order.
+        let mut time = SystemTime::now();
+        let mut path = dir.join(Timestamp::new(time).compact());
+
+        while path.exists() {
+            time += Duration::from_millis(1);
+            path = dir.join(Timestamp::new(time).compact());
+        }
+
+        let metadata = Metadata {
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            operation,
+            hostname: domain.to_string(),
+            server,
+            file: file.to_path_buf(),
+        };
+
+        let serialized =
+            toml::to_string(&metadata).map_err(|_| QicertError::failed(BackupError::NotSaved))?;
+
+        fs::copy(file, &path).map_err(|e| QicertError::io(BackupError::NotSaved, &path, e))?;
+
+        let metadata_path = Self::metadata_path(&path);
+
+        fs::write(&metadata_path, serialized)
+            .map_err(|e| QicertError::io(BackupError::NotSaved, metadata_path, e))?;
+
+        let backup = Self::load(&dir, &path)?;
+
+        self.prune(&dir)?;
+
+        Ok(backup)
+    }
+
+    fn metadata_path(path: &Path) -> PathBuf {
+        let mut metadata_path = path.as_os_str().to_owned();
+
+        metadata_path.push(Self::METADATA);
+
+        PathBuf::from(metadata_path)
+    }
+
+    fn is_metadata(path: &Path) -> bool {
+        path.to_string_lossy().ends_with(Self::METADATA)
+    }
+
+    fn load(dir: &Path, path: &Path) -> Result<Backup, QicertError> {
+        let metadata_path = Self::metadata_path(path);
+
+        let content = fs::read_to_string(&metadata_path)
+            .map_err(|e| QicertError::io(BackupError::Unreadable, &metadata_path, e))?;
+
+        let metadata = toml::from_str(&content).map_err(|e| {
+            QicertError::io(
+                BackupError::Unreadable,
+                &metadata_path,
+                std::io::Error::other(e.message().to_string()),
+            )
+        })?;
+
+        let name = |p: &Path| {
+            p.file_name()
+                .map(|n| n.to_string_lossy().into_owned())
+                .unwrap_or_default()
+        };
+
+        Ok(Backup {
+            id: format!("{}/{}", name(dir), name(path)),
+            path: path.to_path_buf(),
+            metadata,
+        })
+    }
+
+    /// Copies in `dir`, oldest first.
+    fn in_dir(dir: &Path) -> Result<Vec<Backup>, QicertError> {
+        let entries = match fs::read_dir(dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(QicertError::io(BackupError::Unreadable, dir, e)),
+        };
+
+        let mut copies = entries
+            .filter_map(Result::ok)
+            .map(|entry| entry.path())
+            .filter(|path| !Self::is_metadata(path))
+            .collect::<Vec<_>>();
+
+        copies.sort();
+
+        copies.iter().map(|path| Self::load(dir, path)).collect()
+    }
+
+    /// Every copy in the history, grouped by file and oldest first.
+    pub fn list(&self) -> Result<Vec<Backup>, QicertError> {
+        let entries = match fs::read_dir(&self.root) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(QicertError::io(BackupError::Unreadable, &self.root, e)),
+        };
+
+        let mut dirs = entries
+            .filter_map(Result::ok)
+            .map(|entry| entry.path())
+            .filter(|path| path.is_dir())
+            .collect::<Vec<_>>();
+
+        dirs.sort();
+
+        let mut backups = Vec::new();
+
+        for dir in dirs {
+            backups.extend(Self::in_dir(&dir)?);
+        }
+
+        Ok(backups)
+    }
+
+    /// Copies of `file`, oldest first.
+    pub fn of(&self, file: &Path) -> Result<Vec<Backup>, QicertError> {
+        Self::in_dir(&self.dir_for(file))
+    }
+
+    pub fn latest(&self, file: &Path) -> Result<Option<Backup>, QicertError> {
+        Ok(self.of(file)?.pop())
+    }
+
+    pub fn find(&self, id: &str) -> Result<Backup, QicertError> {
+        let not_found = || QicertError::failed(BackupError::NotFound(id.to_string()));
+
+        // Anything but `<file>/<timestamp>` could reach outside of the history.
+        let Some((file, timestamp)) = id.split_once('/') else {
+            return Err(not_found());
+        };
+
+        let valid = |part: &str| !part.is_empty() && part != "." && part != "..";
+
+        if !valid(file) || !valid(timestamp) || timestamp.contains('/') {
+            return Err(not_found());
+        }
+
+        let dir = self.root.join(file);
+        let path = dir.join(timestamp);
+
+        if !path.is_file() || Self::is_metadata(&path) {
+            return Err(not_found());
+        }
+
+        Self::load(&dir, &path)
+    }
+
+    fn prune(&self, dir: &Path) -> Result<(), QicertError> {
+        let copies = Self::in_dir(dir)?;
+
+        let excess = copies.len().saturating_sub(self.keep);
+
+        for backup in &copies[..excess] {
+            let metadata_path = Self::metadata_path(&backup.path);
+
+            fs::remove_file(&backup.path)
+                .and_then(|_| fs::remove_file(&metadata_path))
+                .map_err(|e| QicertError::io(BackupError::NotSaved, &backup.path, e))?;
+        }
+
+        Ok(())
+    }
+
+    /// Puts the backup `id` back in place and reloads its server once the configuration
+    /// passes the server's test. What the file held until then is saved first, and put
+    /// back if the test or the reload fail.
+    pub fn restore(&self, id: &str, caddy_reload: &CaddyReload) -> Result<Backup, QicertError> {
+        let backup = self.find(id)?;
+
+        let Metadata {
+            server,
+            hostname,
+            file,
+            ..
+        } = backup.metadata();
+
+        let domain = Domain::from_hostname(hostname)?;
+
+        let _global = Lock::global(Mode::Shared)?;
+        let _file = Lock::config_file(file)?;
+
+        Audit::run(
+            Operation::Restore,
+            *server,
+            &domain,
+            std::slice::from_ref(file),
+            || self.put_back(&backup, &domain, caddy_reload),
+        )?;
+
+        Ok(backup)
+    }
+
+    fn put_back(
+        &self,
+        backup: &Backup,
+        domain: &Domain,
+        caddy_reload: &CaddyReload,
+    ) -> Result<(), QicertError> {
+        let Metadata { server, file, .. } = backup.metadata();
+
+        // Read before saving the current file, which may prune this very copy.
+        let content = fs::read(backup.path())
+            .map_err(|e| QicertError::io(BackupError::Unreadable, backup.path(), e))?;
+
+        let previous = match file.exists() {
+            true => Some(Report::step(
+                Action::Backup,
+                Some(&self.dir_for(file)),
+                self.save(file, *server, domain, Operation::Restore),
+            )?),
+            false => None,
+        };
+
+        let written = fs::write(file, &content)
+            .map_err(|e| QicertError::io(BackupError::NotRestored, file, e));
+
+        Report::step(Action::WriteConfig, Some(file), written)?;
+
+        let reloaded = Self::check_and_reload(*server, caddy_reload);
+
+        Audit::reload(&reloaded);
+
+        let Err(cause) = Report::step(Action::Reload, None, reloaded) else {
+            return Ok(());
+        };
+
+        let undone = match &previous {
+            Some(previous) => fs::copy(previous.path(), file).map(|_| ()),
+            None => fs::remove_file(file),
+        };
+
+        let undone = undone.map_err(|e| QicertError::io(BackupError::NotRestored, file, e));
+
+        match Report::step(Action::Rollback, Some(file), undone) {
+            Ok(_) => Err(cause),
+            Err(e) => Err(QicertError::rollback(cause, e)),
+        }
+    }
+
+    fn check_and_reload(server: Server, caddy_reload: &CaddyReload) -> Result<(), QicertError> {
+        match server {
+            Server::Apache => Apache::check_and_reload(),
+            Server::Caddy => Caddy::check_and_reload(caddy_reload),
+            Server::Haproxy => HaProxy::check_and_reload(),
+            Server::Lighttpd => Lighttpd::check_and_reload(),
+            Server::Nginx => Nginx::check_and_reload(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_support::scratch;
+
+    #[test]
+    fn dir_follows_file_path() {
+        let backups = Backups::new("/var/lib/qicert/backups", 10);
+
+        assert_eq!(
+            backups.dir_for(Path::new("/etc/nginx/sites-available/example.com.conf")),
+            PathBuf::from("/var/lib/qicert/backups/etc-nginx-sites-available-example.com.conf")
+        );
+    }
+
+    #[test]
+    fn keeps_the_newest_copies_with_their_metadata() {
+        let dir = scratch("backup-keep");
+        let file = dir.join("example.com.conf");
+        let backups = Backups::new(dir.join("backups"), 2);
+        let domain = Domain::new_unchecked("example", "com", Some("www"));
+
+        for version in 1..=3 {
+            fs::write(&file, format!("version {version}")).unwrap();
+
+            backups
+                .save(&file, Server::Nginx, &domain, Operation::Update)
+                .unwrap();
+        }
+
+        let kept = backups.of(&file).unwrap();
+
+        assert_eq!(kept.len(), 2);
+        assert_eq!(fs::read_to_string(kept[0].path()).unwrap(), "version 2");
+        assert_eq!(fs::read_to_string(kept[1].path()).unwrap(), "version 3");
+
+        let latest = backups.latest(&file).unwrap().unwrap();
+
+        assert_eq!(latest.id(), kept[1].id());
+        assert_eq!(latest.metadata().hostname(), "www.example.com");
+        assert_eq!(latest.metadata().server(), Server::Nginx);
+        assert_eq!(latest.metadata().operation(), Operation::Update);
+        assert_eq!(latest.metadata().file(), file);
+        assert_eq!(latest.metadata().version(), env!("CARGO_PKG_VERSION"));
+
+        assert_eq!(backups.find(latest.id()).unwrap().path(), latest.path());
+        assert_eq!(backups.list().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn find_stays_inside_the_history() {
+        let dir = scratch("backup-find");
+        let backups = Backups::new(dir.join("backups"), 10);
+
+        for id in ["", "x", "../x", "x/..", "x/../../etc/passwd", "x/y.toml"] {
+            assert!(
+                matches!(
+                    backups.find(id).unwrap_err().downcast_ref(),
+                    Some(BackupError::NotFound(_))
+                ),
+                "id: {id}"
+            );
+        }
+    }
+}
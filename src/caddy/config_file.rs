@@ -0,0 +1,218 @@
+use std::{error::Error, fmt::Display, fs::File};
+
+use crate::{
+    backup::{Backup, Operation},
+    configuration_file::ConfigurationFile,
+    domain::Domain,
+    error::QicertError,
+    layout::Server,
+};
+
+#[derive(Debug)]
+pub enum ConfigError {
+    FileSaving,
+    InvalidPath,
+    FileExists,
+    MissingImport,
+}
+
+impl Display for ConfigError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::FileSaving => write!(f, "Caddy configuration file could not be written to disk"),
+            Self::InvalidPath => write!(
+                f,
+                "An invalid path was given for a Caddy configuration file"
+            ),
+            Self::FileExists => write!(f, "Caddy configuration file already exists"),
+            Self::MissingImport => write!(f, "The main Caddyfile could not be updated"),
+        }
+    }
+}
+
+impl Error for ConfigError {}
+
+pub struct ConfigFile;
+
+impl ConfigurationFile for ConfigFile {
+    const SERVER: Server = Server::Caddy;
+
+    fn server_name(domain: &Domain) -> String {
+        format!("://{domain} {{")
+    }
+}
+
+impl ConfigFile {
+    const CADDYFILE: &str = "/etc/caddy/Caddyfile";
+
+    pub fn caddyfile_path() -> &'static str {
+        Self::CADDYFILE
+    }
+
+    pub fn import_directive() -> String {
+        format!("import {}/*.conf", Self::sites_enabled_path().display())
+    }
+
+    pub fn has_import<S: AsRef<str>>(caddyfile: S) -> bool {
+        let directive = Self::import_directive();
+
+        caddyfile
+            .as_ref()
+            .lines()
+            .map(str::trim)
+            .any(|l| l == directive)
+    }
+
+    /// Makes sure the main Caddyfile imports the directory managed by qicert.
+    pub fn ensure_import() -> Result<(), QicertError> {
+        use std::io::Write;
+
+        let content = std::fs::read_to_string(Self::CADDYFILE).unwrap_or_default();
+
+        if Self::has_import(&content) {
+            return Ok(());
+        }
+
+        let sites_enabled = Self::sites_enabled_path();
+
+        std::fs::create_dir_all(&sites_enabled)
+            .map_err(|e| QicertError::io(ConfigError::MissingImport, sites_enabled, e))?;
+
+        std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(Self::CADDYFILE)
+            .and_then(|mut file| writeln!(file, "\n{}", Self::import_directive()))
+            .map_err(|e| QicertError::io(ConfigError::MissingImport, Self::CADDYFILE, e))?;
+
+        Ok(())
+    }
+
+    pub fn chown_to_www(domain: &Domain) -> Result<(), QicertError> {
+        Self::_chown_to_www(domain, ConfigError::FileSaving)
+    }
+
+    pub fn create(domain: &Domain) -> Result<File, QicertError> {
+        Self::_create(domain, ConfigError::FileExists, ConfigError::FileSaving)
+    }
+
+    pub fn create_backup(domain: &Domain, operation: Operation) -> Result<Backup, QicertError> {
+        Self::_create_backup(domain, operation)
+    }
+
+    pub fn restore(domain: &Domain, existed: bool) -> Result<(), QicertError> {
+        Self::_restore(domain, existed, ConfigError::FileSaving)
+    }
+
+    pub fn remove_domain(domain: &Domain) -> Result<(), QicertError> {
+        Self::_remove_domain(domain, ConfigError::FileSaving)
+    }
+
+    pub fn read(domain: &Domain, file: &mut File) -> Result<String, QicertError> {
+        Self::_read(domain, file, ConfigError::InvalidPath)
+    }
+
+    pub fn write_block(domain: &Domain, file: &mut File, block: &str) -> Result<(), QicertError> {
+        Self::_write(domain, file, block, ConfigError::FileSaving)
+    }
+
+    pub fn reset(domain: &Domain, file: &mut File, content: &str) -> Result<(), QicertError> {
+        Self::_reset(domain, file, content, ConfigError::FileSaving)
+    }
+
+    pub fn write_region(domain: &Domain, file: &mut File, body: &str) -> Result<(), QicertError> {
+        Self::_write_region(domain, file, body, ConfigError::FileSaving)
+    }
+
+    pub fn clear_stale_challenge(
+        domain: &Domain,
+        file: &mut File,
+        content: &mut String,
+    ) -> Result<bool, QicertError> {
+        Self::_clear_stale_challenge(domain, file, content, ConfigError::FileSaving)
+    }
+
+    pub fn append(domain: &Domain) -> Result<File, QicertError> {
+        Self::_append(domain, ConfigError::InvalidPath)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::path::PathBuf;
+
+    #[test]
+    fn find_domain_without_subdomain_file() {
+        let domains = vec![
+            (Domain::new("example", "com", None), true),
+            (Domain::new("example", "com", Some("www")), false),
+            (Domain::new("Example", "com", None), true),
+            (Domain::new("www", "example", None), false),
+        ];
+
+        let haystack = "https://example.com {
+            root * /var/www/example.com/public
+            file_server
+        }";
+
+        for (domain, expected) in domains {
+            if let Ok(domain) = domain {
+                assert_eq!(
+                    ConfigFile::find_domain_in_str(haystack, &domain),
+                    expected,
+                    "domain: {domain}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn find_domain_with_subdomain_in_file() {
+        let domains = vec![
+            (Domain::new("example", "com", None), false),
+            (Domain::new("example", "com", Some("www")), true),
+            (Domain::new("www", "example", None), false),
+        ];
+
+        let haystack = "http://www.example.com {
+            redir https://www.example.com{uri} permanent
+        }";
+
+        for (domain, expected) in domains {
+            if let Ok(domain) = domain {
+                assert_eq!(
+                    ConfigFile::find_domain_in_str(haystack, &domain),
+                    expected,
+                    "domain: {domain}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn find_import_directive() {
+        let caddyfile = "{
+            email admin@example.com
+        }
+
+        import /etc/caddy/qicert.d/*.conf";
+
+        assert!(ConfigFile::has_import(caddyfile));
+
+        assert!(!ConfigFile::has_import(
+            "# import /etc/caddy/qicert.d/*.conf"
+        ));
+    }
+
+    #[test]
+    fn config_file_path_with_subdomain() {
+        let domain = Domain::new_unchecked("example", "com", Some("www"));
+
+        let expected = PathBuf::from("/etc/caddy/qicert.d/example.com.conf");
+
+        let file_path = ConfigFile::file_path(&domain);
+
+        assert_eq!(file_path, expected);
+    }
+}
@@ -0,0 +1,389 @@
+use std::{fs::File, path::PathBuf};
+
+use crate::{
+    audit::Audit,
+    backup::Operation,
+    caddy::config_file::{ConfigError, ConfigFile},
+    certer::{CertBotError, Certer},
+    configuration_file::ConfigurationFile,
+    domain::Domain,
+    error::QicertError,
+    profile::Profile,
+    report::{Action, Report},
+    webroot::WebRoot,
+    webserver::WebServer,
+};
+
+use super::{http_config::HttpConfig, Caddy, CaddyError, CaddyReload, CaddyTls};
+
+pub struct Configurator;
+
+impl Configurator {
+    fn create_file(domain: &Domain) -> Result<File, QicertError> {
+        if ConfigFile::file_exists(domain) {
+            return Err(ConfigError::InvalidPath)?;
+        }
+
+        ConfigFile::ensure_import()?;
+
+        let path = ConfigFile::file_path(domain);
+
+        let file = Report::step(
+            Action::CreateConfig,
+            Some(&path),
+            ConfigFile::create(domain),
+        )?;
+
+        ConfigFile::chown_to_www(domain)?;
+
+        Ok(file)
+    }
+
+    fn add_well_known(file: &mut File, domain: &Domain) -> Result<(), QicertError> {
+        // Nothing to serve when the built-in responder answers the challenge.
+        if !Certer::uses_webroot() {
+            return Ok(());
+        }
+
+        let site_block = ConfigFile::mark_challenge(domain, &HttpConfig::http_well_known(domain));
+
+        let written = ConfigFile::write_block(domain, file, &site_block);
+
+        Report::step(
+            Action::WriteConfig,
+            Some(&ConfigFile::file_path(domain)),
+            written,
+        )?;
+
+        Ok(())
+    }
+
+    fn add_redirect_and_https(
+        file: &mut File,
+        domain: &Domain,
+        profile: &Profile,
+    ) -> Result<(), QicertError> {
+        let redirect_block = HttpConfig::http_redirect(domain);
+        let https_block = HttpConfig::https_content(domain, profile);
+
+        ConfigFile::write_region(domain, file, &format!("{redirect_block}\n{https_block}"))?;
+
+        Report::done(Action::WriteConfig, Some(&ConfigFile::file_path(domain)));
+
+        Ok(())
+    }
+
+    fn add_automatic_https(
+        file: &mut File,
+        domain: &Domain,
+        profile: &Profile,
+    ) -> Result<(), QicertError> {
+        let https_block = HttpConfig::https_automatic(domain, profile);
+
+        ConfigFile::write_region(domain, file, &https_block)?;
+
+        Report::done(Action::WriteConfig, Some(&ConfigFile::file_path(domain)));
+
+        Ok(())
+    }
+
+    fn check_and_reload(reload: &CaddyReload) -> Result<(), QicertError> {
+        let reloaded = Caddy::check_and_reload(reload);
+
+        Audit::reload(&reloaded);
+
+        Report::step(Action::Reload, None, reloaded)
+    }
+
+    fn certify(domain: &Domain) -> Result<(), QicertError> {
+        let issued = Certer::run(domain);
+
+        Audit::certificate(&issued);
+
+        Report::step(Action::Certificate, None, issued)
+    }
+
+    /// Writes the site block and lets Caddy take care of the certificate.
+    fn provision_automatic(
+        file: &mut File,
+        domain: &Domain,
+        profile: &Profile,
+        reload: &CaddyReload,
+    ) -> Result<(), QicertError> {
+        Self::add_automatic_https(file, domain, profile)?;
+
+        Self::create_webroot(domain, profile);
+
+        Self::check_and_reload(reload)?;
+
+        Ok(())
+    }
+
+    /// Serves the ACME challenge over plain http, runs certbot and then swaps the
+    /// challenge block for a redirect and a site block using the issued certificate.
+    fn provision_with_certbot(
+        file: &mut File,
+        content_backup: &str,
+        domain: &Domain,
+        profile: &Profile,
+        reload: &CaddyReload,
+    ) -> Result<(), QicertError> {
+        Self::add_well_known(file, domain)?;
+
+        Self::create_webroot(domain, profile);
+
+        if Certer::uses_webroot() {
+            Self::check_and_reload(reload)?;
+        }
+
+        Self::certify(domain)?;
+
+        ConfigFile::reset(domain, file, content_backup)?;
+
+        Self::finish_with_certbot(file, domain, profile, reload)
+    }
+
+    /// The part left once certbot has issued the certificate.
+    fn finish_with_certbot(
+        file: &mut File,
+        domain: &Domain,
+        profile: &Profile,
+        reload: &CaddyReload,
+    ) -> Result<(), QicertError> {
+        Self::add_redirect_and_https(file, domain, profile)?;
+
+        Self::check_and_reload(reload)?;
+
+        Ok(())
+    }
+
+    fn create_webroot(domain: &Domain, profile: &Profile) {
+        if !profile.uses_default_webroot(domain) {
+            return;
+        }
+
+        let created = WebRoot::create_and_set_chown(domain, &Caddy::layout().owner());
+
+        match Report::step(
+            Action::Webroot,
+            Some(&WebRoot::build_pathbuf(domain)),
+            created,
+        ) {
+            Ok(_) => Report::say(format!("Webroot created for {}", domain.unicode())),
+            Err(e) => Report::say(format!("{e} error for {}", domain.unicode())),
+        };
+    }
+
+    fn provision(
+        file: &mut File,
+        content_backup: &str,
+        domain: &Domain,
+        profile: &Profile,
+        tls: CaddyTls,
+        reload: &CaddyReload,
+    ) -> Result<(), QicertError> {
+        match tls {
+            CaddyTls::Automatic => Self::provision_automatic(file, domain, profile, reload),
+            CaddyTls::Certbot => {
+                Self::provision_with_certbot(file, content_backup, domain, profile, reload)
+            }
+        }
+    }
+
+    fn create(
+        domain: &Domain,
+        profile: &Profile,
+        tls: CaddyTls,
+        reload: &CaddyReload,
+    ) -> Result<(), QicertError> {
+        let mut file = Self::create_file(domain)?;
+
+        Self::provision(&mut file, "", domain, profile, tls, reload)
+    }
+
+    /// Drops the challenge block an interrupted run left behind. Tells whether that
+    /// run got as far as the certificate, leaving only the configuration to write.
+    fn clear_interrupted_run(
+        domain: &Domain,
+        file: &mut File,
+        content: &mut String,
+    ) -> Result<bool, QicertError> {
+        if !ConfigFile::clear_stale_challenge(domain, file, content)? {
+            return Ok(false);
+        }
+
+        Report::say(format!(
+            "Removed the challenge block an interrupted run left for {}",
+            domain.unicode()
+        ));
+
+        let issued = Certer::has_certificate(domain);
+
+        if issued {
+            Report::say(format!(
+                "Certificate for {} already issued, resuming",
+                domain.unicode()
+            ));
+        }
+
+        Ok(issued)
+    }
+
+    fn append(
+        domain: &Domain,
+        profile: &Profile,
+        tls: CaddyTls,
+        reload: &CaddyReload,
+    ) -> Result<(), QicertError> {
+        ConfigFile::ensure_import()?;
+
+        let mut file = ConfigFile::append(domain)?;
+
+        let mut content_backup = ConfigFile::read(domain, &mut file)?;
+
+        let resume = Self::clear_interrupted_run(domain, &mut file, &mut content_backup)?;
+
+        if !ConfigFile::find_domain_in_str(content_backup.as_str(), domain) {
+            match resume && tls == CaddyTls::Certbot {
+                true => Self::finish_with_certbot(&mut file, domain, profile, reload)?,
+                false => Self::provision(&mut file, &content_backup, domain, profile, tls, reload)?,
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Certbot is only required when Caddy is not issuing the certificate itself.
+    fn ensure_caddy_and_certbot_installed(tls: CaddyTls) -> Result<(), QicertError> {
+        if !Caddy::is_installed() {
+            return Err(CaddyError::NotInstalled)?;
+        }
+
+        if tls == CaddyTls::Certbot && !Certer::is_installed() {
+            return Err(CertBotError::NotInstalled)?;
+        }
+
+        Ok(())
+    }
+
+    /// Files whose changes go to the audit log.
+    fn audited_files(domain: &Domain) -> Vec<PathBuf> {
+        vec![
+            ConfigFile::file_path(domain),
+            PathBuf::from(ConfigFile::caddyfile_path()),
+        ]
+    }
+
+    pub fn append_or_create(
+        domain: &Domain,
+        profile: &Profile,
+        tls: CaddyTls,
+        reload: &CaddyReload,
+    ) -> Result<(), QicertError> {
+        Audit::run(
+            Operation::Provision,
+            Caddy::SERVER,
+            domain,
+            &Self::audited_files(domain),
+            || Self::_append_or_create(domain, profile, tls, reload),
+        )
+    }
+
+    /// Rewrites the site blocks of a hostname that already has its certificate.
+    pub fn update(
+        domain: &Domain,
+        profile: &Profile,
+        tls: CaddyTls,
+        reload: &CaddyReload,
+    ) -> Result<(), QicertError> {
+        Audit::run(
+            Operation::Update,
+            Caddy::SERVER,
+            domain,
+            &Self::audited_files(domain),
+            || Self::_update(domain, profile, tls, reload),
+        )
+    }
+
+    pub fn remove(domain: &Domain, reload: &CaddyReload) -> Result<(), QicertError> {
+        Audit::run(
+            Operation::Remove,
+            Caddy::SERVER,
+            domain,
+            &Self::audited_files(domain),
+            || Self::_remove(domain, reload),
+        )
+    }
+
+    fn _append_or_create(
+        domain: &Domain,
+        profile: &Profile,
+        tls: CaddyTls,
+        reload: &CaddyReload,
+    ) -> Result<(), QicertError> {
+        Self::ensure_caddy_and_certbot_installed(tls)?;
+
+        let existed = ConfigFile::file_exists(domain);
+
+        if existed {
+            let backup = ConfigFile::create_backup(domain, Operation::Provision);
+
+            Report::step(
+                Action::Backup,
+                Some(&ConfigFile::backup_dir(domain)),
+                backup,
+            )?;
+        }
+
+        let result = match existed {
+            true => Self::append(domain, profile, tls, reload),
+            false => Self::create(domain, profile, tls, reload),
+        };
+
+        result.or_else(|cause| Self::rollback(domain, existed, cause))
+    }
+
+    /// Puts the site file back the way it was before a failed run.
+    fn rollback(domain: &Domain, existed: bool, cause: QicertError) -> Result<(), QicertError> {
+        let path = ConfigFile::file_path(domain);
+
+        match Report::step(
+            Action::Rollback,
+            Some(&path),
+            ConfigFile::restore(domain, existed),
+        ) {
+            Ok(_) => Err(cause),
+            Err(e) => Err(QicertError::rollback(cause, e)),
+        }
+    }
+
+    fn _update(
+        domain: &Domain,
+        profile: &Profile,
+        tls: CaddyTls,
+        reload: &CaddyReload,
+    ) -> Result<(), QicertError> {
+        ConfigFile::create_backup(domain, Operation::Update)?;
+        ConfigFile::remove_domain(domain)?;
+
+        let mut file = ConfigFile::append(domain)?;
+
+        match tls {
+            CaddyTls::Automatic => Self::add_automatic_https(&mut file, domain, profile)?,
+            CaddyTls::Certbot => Self::add_redirect_and_https(&mut file, domain, profile)?,
+        }
+
+        Self::check_and_reload(reload)?;
+
+        Ok(())
+    }
+
+    fn _remove(domain: &Domain, reload: &CaddyReload) -> Result<(), QicertError> {
+        ConfigFile::create_backup(domain, Operation::Remove)?;
+        ConfigFile::remove_domain(domain)?;
+
+        Self::check_and_reload(reload)?;
+
+        Ok(())
+    }
+}
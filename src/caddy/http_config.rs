@@ -0,0 +1,165 @@
+use crate::{domain::Domain, profile::Profile, settings::Settings};
+
+pub struct HttpConfig;
+
+impl HttpConfig {
+    pub fn http_well_known(domain: &Domain) -> String {
+        let challenge_root = Settings::current().challenge_root().display();
+
+        format!(
+            "http://{domain} {{
+    handle /.well-known/acme-challenge/* {{
+        root * {challenge_root}
+        file_server
+    }}
+}}"
+        )
+    }
+
+    pub fn http_redirect(domain: &Domain) -> String {
+        format!(
+            "http://{domain} {{
+    redir https://{domain}{{uri}} permanent
+}}"
+        )
+    }
+
+    fn site_content(profile: &Profile) -> String {
+        match profile {
+            Profile::Static(root) => format!(
+                "root * {}
+    file_server",
+                root.display()
+            ),
+            Profile::Proxy(upstream) => format!("reverse_proxy {upstream}"),
+        }
+    }
+
+    /// Site block served with a certificate issued by certbot through qicert.
+    pub fn https_content(domain: &Domain, profile: &Profile) -> String {
+        let site = Self::site_content(profile);
+
+        format!(
+            "https://{domain} {{
+    tls /etc/letsencrypt/live/{domain}/fullchain.pem /etc/letsencrypt/live/{domain}/privkey.pem
+    {site}
+}}"
+        )
+    }
+
+    /// Site block relying on Caddy's automatic HTTPS to obtain the certificate.
+    pub fn https_automatic(domain: &Domain, profile: &Profile) -> String {
+        let site = Self::site_content(profile);
+
+        format!(
+            "https://{domain} {{
+    {site}
+}}"
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn http_well_known() {
+        let expected = "http://example.com {
+    handle /.well-known/acme-challenge/* {
+        root * /var/www/.well-known/challenge
+        file_server
+    }
+}"
+        .to_string();
+
+        let domain = Domain::new("example", "com", None).unwrap();
+
+        assert_eq!(HttpConfig::http_well_known(&domain), expected);
+    }
+
+    #[test]
+    fn http_well_known_with_long_subdomain() {
+        let expected = "http://test1.staging1.example.com {
+    handle /.well-known/acme-challenge/* {
+        root * /var/www/.well-known/challenge
+        file_server
+    }
+}"
+        .to_string();
+
+        let domain = Domain::new("example", "com", Some("test1.staging1")).unwrap();
+
+        assert_eq!(HttpConfig::http_well_known(&domain), expected);
+    }
+
+    #[test]
+    fn http_redirect_to_https() {
+        let expected = "http://www.example.com {
+    redir https://www.example.com{uri} permanent
+}"
+        .to_string();
+
+        let domain = Domain::new("example", "com", Some("www")).unwrap();
+
+        assert_eq!(HttpConfig::http_redirect(&domain), expected);
+    }
+
+    #[test]
+    fn https_block_with_certificate() {
+        let expected = "https://example.com {
+    tls /etc/letsencrypt/live/example.com/fullchain.pem /etc/letsencrypt/live/example.com/privkey.pem
+    root * /var/www/example.com/public
+    file_server
+}"
+        .to_string();
+
+        let domain = Domain::new("example", "com", None).unwrap();
+
+        assert_eq!(
+            HttpConfig::https_content(&domain, &Profile::default_for(&domain)),
+            expected
+        );
+    }
+
+    #[test]
+    fn https_block_automatic() {
+        let expected = "https://test.example.com {
+    root * /var/www/test.example.com/public
+    file_server
+}"
+        .to_string();
+
+        let domain = Domain::new("example", "com", Some("test")).unwrap();
+
+        assert_eq!(
+            HttpConfig::https_automatic(&domain, &Profile::default_for(&domain)),
+            expected
+        );
+    }
+
+    #[test]
+    fn https_block_proxy() {
+        let expected = "https://app.example.com {
+    reverse_proxy 127.0.0.1:3000
+}";
+
+        let domain = Domain::new("example", "com", Some("app")).unwrap();
+        let profile = Profile::Proxy("127.0.0.1:3000".parse().unwrap());
+
+        assert_eq!(HttpConfig::https_automatic(&domain, &profile), expected);
+    }
+
+    #[test]
+    fn https_block_with_custom_root() {
+        let expected = "https://example.com {
+    root * /srv/example
+    file_server
+}";
+
+        let domain = Domain::new("example", "com", None).unwrap();
+        let profile = Profile::Static("/srv/example".into());
+
+        assert_eq!(HttpConfig::https_automatic(&domain, &profile), expected);
+    }
+}
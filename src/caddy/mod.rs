@@ -0,0 +1,225 @@
+pub(crate) mod config_file;
+pub(crate) mod configurator;
+pub mod http_config;
+
+use std::{
+    error::Error,
+    fmt::Display,
+    io::{self, Read, Write},
+    net::TcpStream,
+    process::Command,
+    time::Duration,
+};
+
+use crate::{
+    error::{run_command, QicertError},
+    layout::Server,
+    webserver::WebServer,
+};
+
+use self::config_file::ConfigFile;
+
+#[derive(Debug, Clone, Copy)]
+pub enum CaddyError {
+    CannotReload,
+    BadConfiguration,
+    NotInstalled,
+    AdminApi,
+}
+
+impl Error for CaddyError {}
+
+impl Display for CaddyError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::CannotReload => write!(f, "Could not reload Caddy."),
+            Self::BadConfiguration => write!(f, "caddy validate failed. Bad Configuration."),
+            Self::NotInstalled => write!(f, "Caddy was not found."),
+            Self::AdminApi => write!(f, "Caddy admin API refused the new configuration."),
+        }
+    }
+}
+
+/// Where the certificate served by Caddy comes from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum CaddyTls {
+    /// Let Caddy obtain and renew the certificate by itself.
+    #[default]
+    Automatic,
+    /// Request the certificate with certbot and point Caddy at it with `tls cert key`.
+    Certbot,
+}
+
+/// How a new configuration is handed over to a running Caddy.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub enum CaddyReload {
+    #[default]
+    ServiceManager,
+    AdminApi(String),
+}
+
+pub struct Caddy;
+
+impl WebServer for Caddy {
+    const SERVER: Server = Server::Caddy;
+}
+
+impl Caddy {
+    pub fn check() -> Result<(), QicertError> {
+        run_command(
+            Command::new(Self::layout().binary())
+                .arg("validate")
+                .arg("--config")
+                .arg(ConfigFile::caddyfile_path())
+                .arg("--adapter")
+                .arg("caddyfile"),
+            CaddyError::BadConfiguration,
+        )?;
+
+        Ok(())
+    }
+
+    pub fn reload(mode: &CaddyReload) -> Result<(), QicertError> {
+        match mode {
+            CaddyReload::ServiceManager => Self::_reload(CaddyError::CannotReload),
+            CaddyReload::AdminApi(address) => {
+                let path = ConfigFile::caddyfile_path();
+
+                let caddyfile = std::fs::read_to_string(path)
+                    .map_err(|e| QicertError::io(CaddyError::CannotReload, path, e))?;
+
+                Self::load_through_admin_api(address, &caddyfile)
+            }
+        }
+    }
+
+    /// Posts a Caddyfile to the `/load` endpoint of the admin API, Caddy adapts it itself.
+    pub fn load_through_admin_api(address: &str, caddyfile: &str) -> Result<(), QicertError> {
+        let endpoint = format!("POST http://{address}/load");
+
+        let request = format!(
+            "POST /load HTTP/1.1\r\n\
+            Host: {address}\r\n\
+            Content-Type: text/caddyfile\r\n\
+            Content-Length: {}\r\n\
+            Connection: close\r\n\r\n\
+            {caddyfile}",
+            caddyfile.len()
+        );
+
+        let mut response = String::new();
+
+        TcpStream::connect(address)
+            .and_then(|mut stream| {
+                stream.set_read_timeout(Some(Duration::from_secs(30)))?;
+                stream.write_all(request.as_bytes())?;
+                stream.read_to_string(&mut response)
+            })
+            .map_err(|e| QicertError::command(CaddyError::CannotReload, &endpoint, e))?;
+
+        let status = response
+            .lines()
+            .next()
+            .and_then(|l| l.split_whitespace().nth(1))
+            .unwrap_or_default();
+
+        if status != "200" {
+            // Caddy explains what it did not like in the body.
+            let body = response.split("\r\n\r\n").nth(1).unwrap_or_default();
+            let refusal = io::Error::other(format!("HTTP {status}: {}", body.trim()));
+
+            return Err(QicertError::command(
+                CaddyError::AdminApi,
+                endpoint,
+                refusal,
+            ));
+        }
+
+        Ok(())
+    }
+
+    pub fn check_and_reload(mode: &CaddyReload) -> Result<(), QicertError> {
+        if !Self::is_installed() {
+            return Err(CaddyError::NotInstalled)?;
+        }
+
+        Self::check()?;
+        Self::reload(mode)?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::{io::BufRead, io::BufReader, net::TcpListener, thread};
+
+    fn fake_admin_api(status: &'static str) -> (String, thread::JoinHandle<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+
+        let handle = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream);
+
+            let mut head = String::new();
+            let mut content_length = 0;
+
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+
+                if let Some(len) = line.strip_prefix("Content-Length: ") {
+                    content_length = len.trim().parse().unwrap();
+                }
+
+                if line == "\r\n" {
+                    break;
+                }
+
+                head.push_str(&line);
+            }
+
+            let mut body = vec![0; content_length];
+            reader.read_exact(&mut body).unwrap();
+
+            let response = format!("HTTP/1.1 {status}\r\nContent-Length: 0\r\n\r\n");
+            reader.get_mut().write_all(response.as_bytes()).unwrap();
+
+            head + &String::from_utf8(body).unwrap()
+        });
+
+        (address, handle)
+    }
+
+    #[test]
+    fn admin_api_receives_caddyfile() {
+        let (address, handle) = fake_admin_api("200 OK");
+
+        let caddyfile = "import /etc/caddy/qicert.d/*.conf\n";
+
+        assert!(Caddy::load_through_admin_api(&address, caddyfile).is_ok());
+
+        let request = handle.join().unwrap();
+
+        assert!(request.starts_with("POST /load HTTP/1.1\r\n"));
+        assert!(request.contains("Content-Type: text/caddyfile\r\n"));
+        assert!(request.ends_with(caddyfile));
+    }
+
+    #[test]
+    fn admin_api_rejection_is_an_error() {
+        let (address, handle) = fake_admin_api("400 Bad Request");
+
+        let error = Caddy::load_through_admin_api(&address, "bad {").unwrap_err();
+
+        assert!(matches!(
+            error.downcast_ref::<CaddyError>(),
+            Some(CaddyError::AdminApi)
+        ));
+        assert!(error.chain().contains("caused by: HTTP 400"));
+
+        handle.join().unwrap();
+    }
+}
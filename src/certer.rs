@@ -1,16 +1,27 @@
 use std::{
     error::Error,
     fmt::Display,
-    path::PathBuf,
-    process::{Child, Command, Stdio},
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    sync::atomic::{AtomicU32, Ordering},
 };
 
-use crate::domain::Domain;
+use crate::{
+    domain::Domain,
+    error::{run_command, QicertError},
+    reachability::Reachability,
+    report::Report,
+    responder::{Hook, Responder},
+    run::RunOptions,
+    settings::Settings,
+};
 
 #[derive(Debug)]
 pub enum CertBotError {
     NotInstalled,
     ProcessFailure,
+    Responder,
 }
 
 impl Error for CertBotError {}
@@ -20,36 +31,248 @@ impl Display for CertBotError {
         match self {
             CertBotError::NotInstalled => write!(f, "Certbot is not installed"),
             CertBotError::ProcessFailure => write!(f, "Certbot failed to create certificate"),
+            CertBotError::Responder => write!(f, "The built-in challenge responder failed"),
         }
     }
 }
 
+/// How the http-01 challenge gets answered.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Challenge {
+    /// Certbot drops the token in the challenge root, served by the configured web server.
+    #[default]
+    Webroot,
+    /// qicert answers on the given address itself, no web server needs to be running.
+    Standalone(SocketAddr),
+}
+
+/// Tells apart the responders of one process, the pid those of different ones.
+static RESPONDERS: AtomicU32 = AtomicU32::new(0);
+
 pub struct Certer;
 
 impl Certer {
-    const CERTBOT_BIN_PATH: &str = "/usr/bin/certbot";
+    const RESPONDER_DIR: &str = "/run/qicert";
+    const LIVE_PATH: &str = "/etc/letsencrypt/live";
 
     pub fn is_installed() -> bool {
-        PathBuf::from(Self::CERTBOT_BIN_PATH).is_file()
+        Settings::current().certbot().is_file()
+    }
+
+    fn certbot() -> Command {
+        Command::new(Settings::current().certbot())
+    }
+
+    /// `certbot certonly`, pointed at the configured ACME server if there is one.
+    fn certonly() -> Command {
+        let mut command = Self::certbot();
+
+        command.arg("certonly");
+
+        if let Some(server) = Settings::current().acme_server() {
+            command.arg("--server").arg(server);
+        }
+
+        command
+    }
+
+    /// The challenge of the current run, see `RunOptions`.
+    pub fn challenge() -> Challenge {
+        RunOptions::current().challenge()
+    }
+
+    fn checks_reachability() -> bool {
+        RunOptions::current().checks_reachability()
+    }
+
+    fn reachability(domain: &Domain, webroot: &Path) -> Reachability {
+        let reachability = Reachability::new(webroot);
+
+        match RunOptions::current().resolve() {
+            Some(address) => reachability.resolve(domain.to_string(), address),
+            None => reachability,
+        }
+    }
+
+    /// Whether the web server has to serve the ACME challenge block during issuance.
+    pub fn uses_webroot() -> bool {
+        Self::challenge() == Challenge::Webroot
+    }
+
+    pub fn run(domain: &Domain) -> Result<(), QicertError> {
+        Self::run_at(domain, Settings::current().challenge_root())
+    }
+
+    /// Like `run`, with the webroot tokens written under `webroot` instead of the
+    /// challenge root, for a site that already serves its own.
+    pub fn run_at(domain: &Domain, webroot: &Path) -> Result<(), QicertError> {
+        match Self::challenge() {
+            Challenge::Webroot => Self::run_webroot(domain, webroot),
+            Challenge::Standalone(address) => Self::run_with_responder(domain, address),
+        }
+    }
+
+    fn run_webroot(domain: &Domain, webroot: &Path) -> Result<(), QicertError> {
+        if !Self::is_installed() {
+            return Err(CertBotError::NotInstalled)?;
+        }
+
+        if Self::checks_reachability() {
+            Self::reachability(domain, webroot).check_webroot(domain)?;
+        }
+
+        run_command(
+            Self::certonly()
+                .arg("--webroot")
+                .arg("-w")
+                .arg(webroot)
+                .arg("-d")
+                .arg(domain.to_string().as_str())
+                .stdout(Stdio::piped()),
+            CertBotError::ProcessFailure,
+        )?;
+
+        Ok(())
     }
 
-    pub fn run(domain: &Domain) -> Result<(), CertBotError> {
+    /// A control socket of its own for every responder, so concurrent runs do not take
+    /// over each other's.
+    fn responder_socket() -> PathBuf {
+        let n = RESPONDERS.fetch_add(1, Ordering::Relaxed);
+
+        Path::new(Self::RESPONDER_DIR).join(format!("responder-{}-{n}.sock", std::process::id()))
+    }
+
+    /// Single quotes `arg` for the shell certbot runs its hooks with.
+    fn shell_quote(arg: &str) -> String {
+        format!("'{}'", arg.replace('\'', r"'\''"))
+    }
+
+    /// Serves the challenge from the built-in responder while certbot runs in manual
+    /// mode, its hooks hand the tokens over through the responder's control socket.
+    fn run_with_responder(domain: &Domain, address: SocketAddr) -> Result<(), QicertError> {
+        if !Self::is_installed() {
+            return Err(CertBotError::NotInstalled)?;
+        }
+
+        let qicert = std::env::current_exe()
+            .map_err(|e| QicertError::io(CertBotError::Responder, "/proc/self/exe", e))?;
+        let qicert = Self::shell_quote(&qicert.to_string_lossy());
+
+        let mut responder = Responder::start(address)?;
+
+        Report::say(format!(
+            "Answering http-01 challenges on {}",
+            responder.address()
+        ));
+
+        if Self::checks_reachability() {
+            Self::reachability(domain, Settings::current().challenge_root())
+                .check_responder(&responder, domain)?;
+        }
+
+        let socket = Self::responder_socket();
+
+        responder.listen_for_hooks(&socket)?;
+
+        let output = run_command(
+            Self::certonly()
+                .arg("--manual")
+                .arg("--preferred-challenges")
+                .arg("http")
+                .arg("--manual-auth-hook")
+                .arg(format!("{qicert} hook auth"))
+                .arg("--manual-cleanup-hook")
+                .arg(format!("{qicert} hook cleanup"))
+                .arg("-d")
+                .arg(domain.to_string().as_str())
+                .env(Hook::SOCKET_ENV, &socket)
+                .stdout(Stdio::piped()),
+            CertBotError::ProcessFailure,
+        );
+
+        responder.shutdown();
+
+        output?;
+
+        Ok(())
+    }
+
+    pub fn has_certificate(domain: &Domain) -> bool {
+        PathBuf::from(Self::LIVE_PATH)
+            .join(domain.to_string())
+            .join("fullchain.pem")
+            .is_file()
+    }
+
+    /// Removes the certificate and its renewal configuration, without revoking it.
+    pub fn delete(domain: &Domain) -> Result<(), QicertError> {
+        if !Self::is_installed() {
+            return Err(CertBotError::NotInstalled)?;
+        }
+
+        run_command(
+            Self::certbot()
+                .arg("delete")
+                .arg("--non-interactive")
+                .arg("--cert-name")
+                .arg(domain.to_string().as_str())
+                .stdout(Stdio::piped()),
+            CertBotError::ProcessFailure,
+        )?;
+
+        Ok(())
+    }
+
+    /// Lets certbot answer the challenge itself on `http_port`, for servers such as
+    /// HAProxy that forward the challenge instead of serving files from a webroot.
+    pub fn run_standalone(
+        domain: &Domain,
+        http_port: u16,
+        deploy_hook: &str,
+    ) -> Result<(), QicertError> {
         if !Self::is_installed() {
-            return Err(CertBotError::NotInstalled);
+            return Err(CertBotError::NotInstalled)?;
         }
 
-        Command::new("certbot")
-            .arg("certonly")
-            .arg("--webroot")
-            .arg("-w")
-            .arg("/var/www/.well-known/challenge")
-            .arg("-d")
-            .arg(domain.to_string().as_str())
-            .stdout(Stdio::piped())
-            .spawn()
-            .and_then(Child::wait_with_output)
-            .map_err(|_| CertBotError::ProcessFailure)?;
+        run_command(
+            Self::certonly()
+                .arg("--standalone")
+                .arg("--http-01-port")
+                .arg(http_port.to_string())
+                .arg("--deploy-hook")
+                .arg(deploy_hook)
+                .arg("-d")
+                .arg(domain.to_string().as_str())
+                .stdout(Stdio::piped()),
+            CertBotError::ProcessFailure,
+        )?;
 
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn hook_commands_survive_the_shell() {
+        assert_eq!(
+            Certer::shell_quote("/usr/local/bin/qicert"),
+            "'/usr/local/bin/qicert'"
+        );
+        assert_eq!(
+            Certer::shell_quote("/opt/it's here/qicert"),
+            r"'/opt/it'\''s here/qicert'"
+        );
+    }
+
+    #[test]
+    fn every_responder_gets_its_own_socket() {
+        assert_ne!(Certer::responder_socket(), Certer::responder_socket());
+        assert!(Certer::responder_socket()
+            .to_string_lossy()
+            .starts_with(&format!("/run/qicert/responder-{}-", std::process::id())));
+    }
+}
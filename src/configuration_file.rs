@@ -1,9 +1,27 @@
-use std::{error::Error, fs::File, io::SeekFrom, path::PathBuf};
-
-use crate::domain::Domain;
-
-pub(crate) trait ConfigurationFile<'a> {
-    const SITES_AVAILABLE: &'a str;
+use std::{
+    error::Error,
+    fs::{self, File},
+    io::{self, Read, Seek, SeekFrom, Write},
+    path::PathBuf,
+    process::Command,
+};
+
+use crate::{
+    backup::{Backup, BackupError, Backups, Operation},
+    domain::Domain,
+    error::{run_command, QicertError},
+    layout::{Layout, Server},
+    region::{Region, RegionError},
+};
+
+pub(crate) trait ConfigurationFile {
+    const SERVER: Server;
+    const CHALLENGE_BEGIN: &'static str = "# BEGIN qicert-challenge";
+    const CHALLENGE_END: &'static str = "# END qicert-challenge";
+
+    fn layout() -> Layout {
+        Layout::current(Self::SERVER)
+    }
 
     fn find_domain_in_str<S: AsRef<str>>(haystack: S, domain: &Domain) -> bool {
         let needle = Self::server_name(domain);
@@ -17,12 +35,179 @@ pub(crate) trait ConfigurationFile<'a> {
             .any(|l| l.ends_with(&needle))
     }
 
+    /// Top-level blocks of a configuration file, each one with the text leading up to it.
+    fn split_blocks(haystack: &str) -> Vec<&str> {
+        let mut blocks = Vec::new();
+        let mut depth = 0usize;
+        let mut start = 0;
+
+        for (i, char) in haystack.char_indices() {
+            match char {
+                '{' => depth += 1,
+                '}' => {
+                    depth = depth.saturating_sub(1);
+
+                    if depth == 0 {
+                        blocks.push(&haystack[start..=i]);
+                        start = i + 1;
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        if start < haystack.len() {
+            blocks.push(&haystack[start..]);
+        }
+
+        blocks
+    }
+
+    /// Comment lines around the temporary challenge block. A run that dies before
+    /// swapping the block out leaves them behind for the next run to find.
+    fn challenge_markers(domain: &Domain) -> (String, String) {
+        (
+            format!("{} {domain}", Self::CHALLENGE_BEGIN),
+            format!("{} {domain}", Self::CHALLENGE_END),
+        )
+    }
+
+    fn mark_challenge(domain: &Domain, block: &str) -> String {
+        let (begin, end) = Self::challenge_markers(domain);
+
+        format!("{begin}\n{block}\n{end}")
+    }
+
+    fn find_challenge_in_str(haystack: &str, domain: &Domain) -> bool {
+        let (begin, _) = Self::challenge_markers(domain);
+
+        haystack.lines().any(|l| l.trim() == begin)
+    }
+
+    /// Hostnames whose marked challenge block is in `haystack`.
+    fn challenge_hostnames(haystack: &str) -> Vec<&str> {
+        haystack
+            .lines()
+            .filter_map(|l| {
+                l.trim()
+                    .strip_prefix(Self::CHALLENGE_BEGIN)?
+                    .strip_prefix(' ')
+            })
+            .map(str::trim)
+            .collect()
+    }
+
+    /// `haystack` without the marked challenge block of `domain`. A block whose end
+    /// marker never made it to disk runs to the end of the file.
+    fn remove_challenge_from_str(haystack: &str, domain: &Domain) -> String {
+        let (begin, end) = Self::challenge_markers(domain);
+
+        let mut inside = false;
+
+        haystack
+            .split_inclusive('\n')
+            .filter(|line| {
+                let line = line.trim();
+
+                if line == begin {
+                    inside = true;
+                } else if inside && line == end {
+                    inside = false;
+
+                    return false;
+                }
+
+                !inside
+            })
+            .collect()
+    }
+
+    /// Drops the challenge block an interrupted run left in the file, from the file
+    /// and from `content`, its current content. Tells whether there was one.
+    fn _clear_stale_challenge<E: Error + Send + Sync + 'static>(
+        domain: &Domain,
+        file: &mut File,
+        content: &mut String,
+        err: E,
+    ) -> Result<bool, QicertError> {
+        if !Self::find_challenge_in_str(content, domain) {
+            return Ok(false);
+        }
+
+        *content = Self::remove_challenge_from_str(content, domain);
+
+        Self::_reset(domain, file, content, err)?;
+
+        Ok(true)
+    }
+
+    /// `haystack` without the blocks serving `domain`: its region when qicert wrote
+    /// one, every block naming it in files written before regions.
+    fn remove_domain_from_str(haystack: &str, domain: &Domain) -> String {
+        let haystack = Self::remove_challenge_from_str(haystack, domain);
+
+        if let Some(region) = Region::find(&haystack, domain) {
+            return region.remove_from(&haystack);
+        }
+
+        Self::split_blocks(&haystack)
+            .into_iter()
+            .filter(|block| !Self::find_domain_in_str(block, domain))
+            .collect()
+    }
+
+    /// Refuses to touch the region of `domain` once someone edited it.
+    fn ensure_region_untouched(haystack: &str, domain: &Domain) -> Result<(), QicertError> {
+        match Region::find(haystack, domain) {
+            Some(region) if region.is_edited() => Err(RegionError::Edited {
+                path: Self::file_path(domain),
+                hostname: domain.to_string(),
+            })?,
+            _ => Ok(()),
+        }
+    }
+
+    /// The region of `domain` was written by another qicert version and is untouched,
+    /// so that regenerating it loses nothing.
+    fn is_outdated(domain: &Domain) -> bool {
+        let Ok(content) = fs::read_to_string(Self::file_path(domain)) else {
+            return false;
+        };
+
+        Region::find(&content, domain).is_some_and(|region| {
+            region.version() != env!("CARGO_PKG_VERSION") && !region.is_edited()
+        })
+    }
+
+    fn contains_domain(domain: &Domain) -> bool {
+        fs::read_to_string(Self::file_path(domain))
+            .is_ok_and(|content| Self::find_domain_in_str(content, domain))
+    }
+
+    fn _remove_domain<E: Error + Send + Sync + 'static>(
+        domain: &Domain,
+        err: E,
+    ) -> Result<(), QicertError> {
+        let file_path = Self::file_path(domain);
+
+        let content = match fs::read_to_string(&file_path) {
+            Ok(content) => content,
+            Err(e) => return Err(QicertError::io(err, file_path, e)),
+        };
+
+        Self::ensure_region_untouched(&content, domain)?;
+
+        fs::write(&file_path, Self::remove_domain_from_str(&content, domain))
+            .map_err(|e| QicertError::io(err, &file_path, e))
+    }
+
     fn sites_enabled_path() -> PathBuf {
-        PathBuf::from(Self::SITES_AVAILABLE)
+        Self::layout().config_dir().to_path_buf()
     }
 
+    /// Sites are grouped in one file per registrable domain.
     fn file_name(domain: &Domain) -> String {
-        format!("{}.{}.conf", domain.get_name(), domain.get_tld())
+        format!("{}.conf", domain.registrable_domain())
     }
 
     fn file_path(domain: &Domain) -> PathBuf {
@@ -35,8 +220,9 @@ pub(crate) trait ConfigurationFile<'a> {
         base_path
     }
 
-    fn backup_path(domain: &Domain) -> PathBuf {
-        Self::file_path(domain).with_extension("conf.bak")
+    /// Directory holding the backup history of the file.
+    fn backup_dir(domain: &Domain) -> PathBuf {
+        Backups::current().dir_for(&Self::file_path(domain))
     }
 
     fn file_exists(domain: &Domain) -> bool {
@@ -45,66 +231,127 @@ pub(crate) trait ConfigurationFile<'a> {
         conf_path.exists() || conf_path.is_file()
     }
 
-    fn _chown_to_www<E: Error>(domain: &Domain, err: E) -> Result<(), E> {
-        use std::process::Command;
-
+    fn _chown_to_www<E: Error + Send + Sync + 'static>(
+        domain: &Domain,
+        err: E,
+    ) -> Result<(), QicertError> {
         let conf_file_path = Self::file_path(domain);
 
         if !conf_file_path.exists() {
-            return Err(err);
+            let missing = io::Error::from(io::ErrorKind::NotFound);
+
+            return Err(QicertError::io(err, conf_file_path, missing));
         }
 
-        Command::new("chown")
-            .arg("www-data:www-data")
-            .arg(conf_file_path)
-            .spawn()
-            .and_then(|mut c| c.wait())
-            .map_err(|_| err)?;
+        run_command(
+            Command::new("chown")
+                .arg(Self::layout().owner())
+                .arg(conf_file_path),
+            err,
+        )?;
 
         Ok(())
     }
 
-    fn _create<E: Error>(domain: &Domain, found_err: E, saving_err: E) -> Result<File, E> {
+    fn _create<E: Error + Send + Sync + 'static>(
+        domain: &Domain,
+        found_err: E,
+        saving_err: E,
+    ) -> Result<File, QicertError> {
         if Self::file_exists(domain) {
-            return Err(found_err);
+            return Err(QicertError::failed(found_err));
         }
 
         let conf_path = Self::file_path(domain);
 
-        let file = File::create(conf_path).map_err(|_| saving_err)?;
+        File::create(&conf_path).map_err(|e| QicertError::io(saving_err, conf_path, e))
+    }
 
-        Ok(file)
+    fn _create_backup(domain: &Domain, operation: Operation) -> Result<Backup, QicertError> {
+        Backups::current().save(&Self::file_path(domain), Self::SERVER, domain, operation)
     }
 
-    fn _create_backup<E: Error>(domain: &Domain, saving_err: E) -> Result<(), E> {
+    /// Puts back what the file held before the run, its latest backup. A file the run
+    /// created is emptied rather than deleted, so the links and includes pointing at it
+    /// stay valid.
+    fn _restore<E: Error + Send + Sync + 'static>(
+        domain: &Domain,
+        existed: bool,
+        err: E,
+    ) -> Result<(), QicertError> {
         let file_path = Self::file_path(domain);
-        let backup_path = Self::backup_path(domain);
 
-        std::fs::copy(file_path, backup_path).map_err(|_| saving_err)?;
+        if !existed {
+            return fs::write(&file_path, "").map_err(|e| QicertError::io(err, file_path, e));
+        }
 
-        Ok(())
+        let Some(backup) = Backups::current().latest(&file_path)? else {
+            let id = Self::backup_dir(domain).display().to_string();
+
+            return Err(QicertError::failed(BackupError::NotFound(id)));
+        };
+
+        let restored = fs::copy(backup.path(), &file_path).map(|_| ());
+
+        restored.map_err(|e| QicertError::io(err, file_path, e))
     }
 
-    fn truncate_file(file: &mut File) -> Result<(), Box<dyn Error>> {
-        use std::io::Seek;
+    fn _read<E: Error + Send + Sync + 'static>(
+        domain: &Domain,
+        file: &mut File,
+        err: E,
+    ) -> Result<String, QicertError> {
+        let mut content = String::new();
 
-        file.set_len(0)?;
+        file.read_to_string(&mut content)
+            .map_err(|e| QicertError::io(err, Self::file_path(domain), e))?;
 
-        file.seek(SeekFrom::End(0))?;
+        Ok(content)
+    }
 
-        Ok(())
+    /// Writes the blocks generated for `domain` as its region.
+    fn _write_region<E: Error + Send + Sync + 'static>(
+        domain: &Domain,
+        file: &mut File,
+        body: &str,
+        err: E,
+    ) -> Result<(), QicertError> {
+        Self::_write(domain, file, &Region::wrap(domain, body), err)
+    }
+
+    fn _write<E: Error + Send + Sync + 'static>(
+        domain: &Domain,
+        file: &mut File,
+        content: &str,
+        err: E,
+    ) -> Result<(), QicertError> {
+        writeln!(file, "{content}").map_err(|e| QicertError::io(err, Self::file_path(domain), e))
+    }
+
+    /// Empties the file and writes `content` back, dropping what was added since.
+    fn _reset<E: Error + Send + Sync + 'static>(
+        domain: &Domain,
+        file: &mut File,
+        content: &str,
+        err: E,
+    ) -> Result<(), QicertError> {
+        file.set_len(0)
+            .and_then(|_| file.seek(SeekFrom::End(0)))
+            .and_then(|_| file.write_all(content.as_bytes()))
+            .map_err(|e| QicertError::io(err, Self::file_path(domain), e))
     }
 
-    fn _append<E: Error>(domain: &Domain, err: E) -> Result<File, E> {
+    fn _append<E: Error + Send + Sync + 'static>(
+        domain: &Domain,
+        err: E,
+    ) -> Result<File, QicertError> {
         let conf_path = Self::file_path(domain);
 
-        let file = std::fs::OpenOptions::new()
+        fs::OpenOptions::new()
             .append(true)
             .read(true)
-            .open(conf_path)
-            .map_err(|_| err)?;
-
-        Ok(file)
+            .open(&conf_path)
+            .map_err(|e| QicertError::io(err, conf_path, e))
     }
 
     fn server_name(domain: &Domain) -> String;
@@ -0,0 +1,96 @@
+use std::{
+    fs,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+};
+
+/// Addresses assigned to this host's interfaces, read from procfs.
+pub fn local_addresses() -> Vec<IpAddr> {
+    let v4 = fs::read_to_string("/proc/net/fib_trie")
+        .map(|t| parse_fib_trie(&t))
+        .unwrap_or_default();
+
+    let v6 = fs::read_to_string("/proc/net/if_inet6")
+        .map(|t| parse_if_inet6(&t))
+        .unwrap_or_default();
+
+    v4.into_iter()
+        .map(IpAddr::V4)
+        .chain(v6.into_iter().map(IpAddr::V6))
+        .collect()
+}
+
+/// Local IPv4 addresses are the leaves followed by a `/32 host LOCAL` line.
+pub fn parse_fib_trie(fib_trie: &str) -> Vec<Ipv4Addr> {
+    let mut addresses = Vec::new();
+    let mut last_leaf = None;
+
+    for line in fib_trie.lines().map(str::trim) {
+        if let Some(leaf) = line.strip_prefix("|-- ") {
+            last_leaf = leaf.parse::<Ipv4Addr>().ok();
+        } else if line == "/32 host LOCAL" {
+            if let Some(address) = last_leaf.take() {
+                if !addresses.contains(&address) {
+                    addresses.push(address);
+                }
+            }
+        }
+    }
+
+    addresses
+}
+
+pub fn parse_if_inet6(if_inet6: &str) -> Vec<Ipv6Addr> {
+    if_inet6
+        .lines()
+        .filter_map(|l| l.split_whitespace().next())
+        .filter_map(|hex| u128::from_str_radix(hex, 16).ok())
+        .map(Ipv6Addr::from)
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn local_ipv4_from_fib_trie() {
+        let fib_trie = "Main:
+  +-- 0.0.0.0/0 3 0 5
+     |-- 0.0.0.0
+        /0 universe UNICAST
+     +-- 127.0.0.0/8 2 0 2
+        +-- 127.0.0.0/31 1 0 0
+           |-- 127.0.0.0
+              /8 host LOCAL
+           |-- 127.0.0.1
+              /32 host LOCAL
+        |-- 127.255.255.255
+           /32 link BROADCAST
+     +-- 203.0.113.0/24 2 0 2
+        |-- 203.0.113.10
+           /32 host LOCAL
+Local:
+  +-- 0.0.0.0/0 3 0 5
+        |-- 203.0.113.10
+           /32 host LOCAL";
+
+        assert_eq!(
+            parse_fib_trie(fib_trie),
+            vec![Ipv4Addr::new(127, 0, 0, 1), Ipv4Addr::new(203, 0, 113, 10)]
+        );
+    }
+
+    #[test]
+    fn local_ipv6_from_if_inet6() {
+        let if_inet6 = "00000000000000000000000000000001 01 80 10 80       lo
+20010db8000000000000000000000010 02 40 00 80     eth0";
+
+        assert_eq!(
+            parse_if_inet6(if_inet6),
+            vec![
+                Ipv6Addr::LOCALHOST,
+                "2001:db8::10".parse::<Ipv6Addr>().unwrap()
+            ]
+        );
+    }
+}
@@ -0,0 +1,254 @@
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RecordType {
+    A,
+    Aaaa,
+    Caa,
+}
+
+impl RecordType {
+    pub fn code(self) -> u16 {
+        match self {
+            Self::A => 1,
+            Self::Aaaa => 28,
+            Self::Caa => 257,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Record {
+    A(Ipv4Addr),
+    Aaaa(Ipv6Addr),
+    Caa {
+        critical: bool,
+        tag: String,
+        value: String,
+    },
+    Other,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResponseCode {
+    NoError,
+    ServFail,
+    NxDomain,
+    Refused,
+    Other(u8),
+}
+
+impl From<u8> for ResponseCode {
+    fn from(code: u8) -> Self {
+        match code {
+            0 => Self::NoError,
+            2 => Self::ServFail,
+            3 => Self::NxDomain,
+            5 => Self::Refused,
+            other => Self::Other(other),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Response {
+    pub id: u16,
+    pub truncated: bool,
+    pub code: ResponseCode,
+    pub records: Vec<Record>,
+}
+
+/// Recursive query for a single name and type.
+pub fn query(id: u16, name: &str, record_type: RecordType) -> Vec<u8> {
+    let mut message = Vec::with_capacity(64);
+
+    message.extend_from_slice(&id.to_be_bytes());
+    message.extend_from_slice(&0x0100u16.to_be_bytes());
+    message.extend_from_slice(&1u16.to_be_bytes());
+    message.extend_from_slice(&[0; 6]);
+
+    encode_name(&mut message, name);
+
+    message.extend_from_slice(&record_type.code().to_be_bytes());
+    message.extend_from_slice(&1u16.to_be_bytes());
+
+    message
+}
+
+pub fn encode_name(message: &mut Vec<u8>, name: &str) {
+    for label in name
+        .trim_end_matches('.')
+        .split('.')
+        .filter(|l| !l.is_empty())
+    {
+        message.push(label.len() as u8);
+        message.extend_from_slice(label.as_bytes());
+    }
+
+    message.push(0);
+}
+
+struct Reader<'a> {
+    message: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn u8(&mut self) -> Option<u8> {
+        let byte = *self.message.get(self.position)?;
+        self.position += 1;
+        Some(byte)
+    }
+
+    fn u16(&mut self) -> Option<u16> {
+        Some(u16::from_be_bytes([self.u8()?, self.u8()?]))
+    }
+
+    fn bytes(&mut self, len: usize) -> Option<&'a [u8]> {
+        let bytes = self.message.get(self.position..self.position + len)?;
+        self.position += len;
+        Some(bytes)
+    }
+
+    /// Skips a possibly compressed name, the answers are matched by type only.
+    fn skip_name(&mut self) -> Option<()> {
+        loop {
+            let len = self.u8()?;
+
+            match len {
+                0 => return Some(()),
+                l if l & 0xC0 == 0xC0 => {
+                    self.u8()?;
+                    return Some(());
+                }
+                l => {
+                    self.bytes(l as usize)?;
+                }
+            }
+        }
+    }
+}
+
+fn parse_caa(rdata: &[u8]) -> Option<Record> {
+    let flags = *rdata.first()?;
+    let tag_len = *rdata.get(1)? as usize;
+    let tag = rdata.get(2..2 + tag_len)?;
+    let value = rdata.get(2 + tag_len..)?;
+
+    Some(Record::Caa {
+        critical: flags & 0x80 != 0,
+        tag: String::from_utf8_lossy(tag).to_lowercase(),
+        value: String::from_utf8_lossy(value).to_string(),
+    })
+}
+
+pub fn parse(message: &[u8]) -> Option<Response> {
+    let mut reader = Reader {
+        message,
+        position: 0,
+    };
+
+    let id = reader.u16()?;
+    let flags = reader.u16()?;
+    let questions = reader.u16()?;
+    let answers = reader.u16()?;
+    reader.u16()?;
+    reader.u16()?;
+
+    for _ in 0..questions {
+        reader.skip_name()?;
+        reader.bytes(4)?;
+    }
+
+    let mut records = Vec::with_capacity(answers as usize);
+
+    for _ in 0..answers {
+        reader.skip_name()?;
+
+        let record_type = reader.u16()?;
+        reader.u16()?;
+        reader.bytes(4)?;
+        let len = reader.u16()? as usize;
+        let rdata = reader.bytes(len)?;
+
+        let record = match (record_type, rdata.len()) {
+            (1, 4) => Record::A(Ipv4Addr::new(rdata[0], rdata[1], rdata[2], rdata[3])),
+            (28, 16) => {
+                let octets: [u8; 16] = rdata.try_into().ok()?;
+                Record::Aaaa(Ipv6Addr::from(octets))
+            }
+            (257, _) => parse_caa(rdata)?,
+            _ => Record::Other,
+        };
+
+        records.push(record);
+    }
+
+    Some(Response {
+        id,
+        truncated: flags & 0x0200 != 0,
+        code: ResponseCode::from((flags & 0x000F) as u8),
+        records,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn query_wire_format() {
+        let message = query(0xBEEF, "www.example.com", RecordType::Aaaa);
+
+        let expected = [
+            0xBE, 0xEF, 0x01, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 3, b'w', b'w',
+            b'w', 7, b'e', b'x', b'a', b'm', b'p', b'l', b'e', 3, b'c', b'o', b'm', 0, 0x00, 0x1C,
+            0x00, 0x01,
+        ];
+
+        assert_eq!(message, expected);
+    }
+
+    #[test]
+    fn parse_compressed_answers() {
+        let mut message = query(7, "example.com", RecordType::A);
+        // Flip to a response with two answers.
+        message[2] = 0x81;
+        message[3] = 0x80;
+        message[7] = 2;
+
+        for (record_type, rdata) in [
+            (1u16, vec![192, 0, 2, 1]),
+            (257, b"\x80\x05issueletsencrypt.org".to_vec()),
+        ] {
+            message.extend_from_slice(&[0xC0, 12]);
+            message.extend_from_slice(&record_type.to_be_bytes());
+            message.extend_from_slice(&[0, 1, 0, 0, 0x0E, 0x10]);
+            message.extend_from_slice(&(rdata.len() as u16).to_be_bytes());
+            message.extend_from_slice(&rdata);
+        }
+
+        let response = parse(&message).unwrap();
+
+        assert_eq!(response.id, 7);
+        assert_eq!(response.code, ResponseCode::NoError);
+        assert_eq!(
+            response.records,
+            vec![
+                Record::A(Ipv4Addr::new(192, 0, 2, 1)),
+                Record::Caa {
+                    critical: true,
+                    tag: String::from("issue"),
+                    value: String::from("letsencrypt.org"),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn truncated_message_is_rejected() {
+        let message = query(7, "example.com", RecordType::A);
+
+        assert!(parse(&message[..10]).is_none());
+    }
+}
@@ -0,0 +1,676 @@
+mod host;
+mod message;
+
+use std::{
+    error::Error,
+    fmt::Display,
+    fs,
+    io::{self, Read, Write},
+    net::{IpAddr, SocketAddr, TcpStream, UdpSocket},
+    time::Duration,
+};
+
+use crate::domain::Domain;
+
+pub use message::{Record, RecordType, ResponseCode};
+
+#[derive(Debug)]
+pub enum DnsError {
+    Io(io::ErrorKind),
+    Malformed,
+    Failure { name: String, code: ResponseCode },
+}
+
+impl Display for DnsError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Io(kind) => write!(f, "the nameserver could not be queried ({kind})"),
+            Self::Malformed => write!(f, "the nameserver sent a malformed answer"),
+            Self::Failure { name, code } => write!(f, "the lookup of {name} failed with {code:?}"),
+        }
+    }
+}
+
+impl Error for DnsError {}
+
+impl From<io::Error> for DnsError {
+    fn from(e: io::Error) -> Self {
+        Self::Io(e.kind())
+    }
+}
+
+/// Stub resolver asking a single recursive nameserver.
+#[derive(Debug, Clone)]
+pub struct Resolver {
+    nameserver: SocketAddr,
+    timeout: Duration,
+}
+
+impl Resolver {
+    const RESOLV_CONF: &str = "/etc/resolv.conf";
+
+    pub fn new(nameserver: SocketAddr) -> Self {
+        Self {
+            nameserver,
+            timeout: Duration::from_secs(5),
+        }
+    }
+
+    /// First nameserver of `/etc/resolv.conf`, the way the libc resolver would pick it.
+    pub fn system() -> Self {
+        let nameserver = fs::read_to_string(Self::RESOLV_CONF)
+            .ok()
+            .and_then(|conf| {
+                conf.lines()
+                    .filter_map(|l| l.trim().strip_prefix("nameserver"))
+                    .find_map(|ip| ip.trim().parse::<IpAddr>().ok())
+            })
+            .unwrap_or(IpAddr::from([127, 0, 0, 1]));
+
+        Self::new(SocketAddr::new(nameserver, 53))
+    }
+
+    fn query_id() -> u16 {
+        let mut bytes = [0u8; 2];
+
+        fs::File::open("/dev/urandom")
+            .and_then(|mut f| f.read_exact(&mut bytes))
+            .map(|_| u16::from_be_bytes(bytes))
+            .unwrap_or(std::process::id() as u16)
+    }
+
+    fn exchange_udp(&self, query: &[u8]) -> Result<Vec<u8>, DnsError> {
+        let bind: SocketAddr = match self.nameserver {
+            SocketAddr::V4(_) => "0.0.0.0:0".parse().map_err(|_| DnsError::Malformed)?,
+            SocketAddr::V6(_) => "[::]:0".parse().map_err(|_| DnsError::Malformed)?,
+        };
+
+        let socket = UdpSocket::bind(bind)?;
+        socket.set_read_timeout(Some(self.timeout))?;
+        socket.connect(self.nameserver)?;
+        socket.send(query)?;
+
+        let mut buffer = vec![0; 4096];
+        let len = socket.recv(&mut buffer)?;
+        buffer.truncate(len);
+
+        Ok(buffer)
+    }
+
+    /// Retried over TCP when the UDP answer came back truncated.
+    fn exchange_tcp(&self, query: &[u8]) -> Result<Vec<u8>, DnsError> {
+        let mut stream = TcpStream::connect_timeout(&self.nameserver, self.timeout)?;
+        stream.set_read_timeout(Some(self.timeout))?;
+
+        stream.write_all(&(query.len() as u16).to_be_bytes())?;
+        stream.write_all(query)?;
+
+        let mut len = [0u8; 2];
+        stream.read_exact(&mut len)?;
+
+        let mut buffer = vec![0; u16::from_be_bytes(len) as usize];
+        stream.read_exact(&mut buffer)?;
+
+        Ok(buffer)
+    }
+
+    pub fn lookup(&self, name: &str, record_type: RecordType) -> Result<Vec<Record>, DnsError> {
+        let id = Self::query_id();
+        let query = message::query(id, name, record_type);
+
+        let mut response =
+            message::parse(&self.exchange_udp(&query)?).ok_or(DnsError::Malformed)?;
+
+        if response.truncated {
+            response = message::parse(&self.exchange_tcp(&query)?).ok_or(DnsError::Malformed)?;
+        }
+
+        if response.id != id {
+            return Err(DnsError::Malformed);
+        }
+
+        match response.code {
+            ResponseCode::NoError | ResponseCode::NxDomain => Ok(response.records),
+            code => Err(DnsError::Failure {
+                name: name.to_string(),
+                code,
+            }),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Warning,
+    Error,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Finding {
+    pub severity: Severity,
+    pub message: String,
+}
+
+impl Finding {
+    fn warning<S: Into<String>>(message: S) -> Self {
+        Self {
+            severity: Severity::Warning,
+            message: message.into(),
+        }
+    }
+
+    fn error<S: Into<String>>(message: S) -> Self {
+        Self {
+            severity: Severity::Error,
+            message: message.into(),
+        }
+    }
+}
+
+impl Display for Finding {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.severity {
+            Severity::Warning => write!(f, "warning: {}", self.message),
+            Severity::Error => write!(f, "error: {}", self.message),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct DnsCheckFailed(pub Vec<Finding>);
+
+impl Display for DnsCheckFailed {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "DNS pre-validation failed")?;
+
+        for finding in self.0.iter().filter(|f| f.severity == Severity::Error) {
+            write!(f, "\n  {}", finding.message)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Error for DnsCheckFailed {}
+
+/// Makes sure the hostname points at this machine and that CAA lets the CA issue for it.
+pub struct DnsCheck {
+    resolver: Resolver,
+    local_addresses: Vec<IpAddr>,
+    ca: Option<String>,
+}
+
+impl DnsCheck {
+    pub const LETS_ENCRYPT: &'static str = "letsencrypt.org";
+
+    /// CAA identifiers of the CAs whose ACME directory is on, or under, a known host.
+    const KNOWN_CAS: &'static [(&'static str, &'static str)] = &[
+        ("api.letsencrypt.org", Self::LETS_ENCRYPT),
+        ("acme.zerossl.com", "sectigo.com"),
+        ("api.pki.goog", "pki.goog"),
+        ("api.buypass.com", "buypass.com"),
+        ("api.test4.buypass.no", "buypass.com"),
+        ("acme.ssl.com", "ssl.com"),
+    ];
+
+    pub fn new(resolver: Resolver) -> Self {
+        Self {
+            resolver,
+            local_addresses: host::local_addresses(),
+            ca: Some(Self::LETS_ENCRYPT.to_string()),
+        }
+    }
+
+    /// Checks CAA for `ca`, the CAA identifier of the CA issuing the certificate.
+    pub fn with_ca<S: Into<String>>(mut self, ca: S) -> Self {
+        self.ca = Some(ca.into());
+        self
+    }
+
+    /// Checks CAA for the CA behind the ACME directory `acme_server`, Let's Encrypt
+    /// without one. For a CA qicert does not know, a restricting CAA record set is only
+    /// a warning.
+    pub fn for_acme_server(mut self, acme_server: Option<&str>) -> Self {
+        self.ca = match acme_server {
+            Some(url) => Self::ca_of(url).map(str::to_string),
+            None => Some(Self::LETS_ENCRYPT.to_string()),
+        };
+        self
+    }
+
+    fn ca_of(acme_server: &str) -> Option<&'static str> {
+        let host = acme_server
+            .split_once("://")
+            .map_or(acme_server, |(_, rest)| rest)
+            .split(['/', ':'])
+            .next()?
+            .to_ascii_lowercase();
+
+        Self::KNOWN_CAS
+            .iter()
+            .find(|(known, _)| host == *known || host.ends_with(&format!(".{known}")))
+            .map(|(_, ca)| *ca)
+    }
+
+    #[cfg(test)]
+    pub fn with_local_addresses(mut self, local_addresses: Vec<IpAddr>) -> Self {
+        self.local_addresses = local_addresses;
+        self
+    }
+
+    /// Warnings are returned, errors fail the check.
+    pub fn verify(&self, domain: &Domain) -> Result<Vec<Finding>, DnsCheckFailed> {
+        let findings = self.run(domain);
+
+        if findings.iter().any(|f| f.severity == Severity::Error) {
+            return Err(DnsCheckFailed(findings));
+        }
+
+        Ok(findings)
+    }
+
+    pub fn run(&self, domain: &Domain) -> Vec<Finding> {
+        let name = domain.to_string();
+
+        let mut findings = self.check_addresses(&name);
+        findings.extend(self.check_caa(&name));
+
+        findings
+    }
+
+    fn check_addresses(&self, name: &str) -> Vec<Finding> {
+        let mut published = Vec::new();
+
+        for record_type in [RecordType::A, RecordType::Aaaa] {
+            match self.resolver.lookup(name, record_type) {
+                Ok(records) => published.extend(records.into_iter().filter_map(|r| match r {
+                    Record::A(ip) => Some(IpAddr::V4(ip)),
+                    Record::Aaaa(ip) => Some(IpAddr::V6(ip)),
+                    _ => None,
+                })),
+                Err(e) => {
+                    return vec![Finding::error(format!(
+                        "{record_type:?} lookup for {name}: {e}"
+                    ))]
+                }
+            }
+        }
+
+        if published.is_empty() {
+            return vec![Finding::error(format!("{name} has no A or AAAA record"))];
+        }
+
+        let foreign: Vec<String> = published
+            .iter()
+            .filter(|ip| !self.local_addresses.contains(ip))
+            .map(IpAddr::to_string)
+            .collect();
+
+        if foreign.is_empty() {
+            return vec![];
+        }
+
+        let which = if foreign.len() == published.len() {
+            format!(
+                "none of the addresses of {name} belong to this host ({})",
+                foreign.join(", ")
+            )
+        } else {
+            let verb = if foreign.len() == 1 { "does" } else { "do" };
+
+            format!(
+                "{} published for {name} {verb} not belong to this host",
+                foreign.join(", ")
+            )
+        };
+
+        vec![Finding::warning(format!(
+            "{which}, fine behind NAT or a load balancer, otherwise the challenge will fail"
+        ))]
+    }
+
+    /// Candidate names for the relevant CAA record set, from the name up to the TLD.
+    fn caa_tree(name: &str) -> Vec<String> {
+        let labels: Vec<&str> = name.split('.').collect();
+
+        (0..labels.len()).map(|i| labels[i..].join(".")).collect()
+    }
+
+    fn check_caa(&self, name: &str) -> Vec<Finding> {
+        for candidate in Self::caa_tree(name) {
+            let records = match self.resolver.lookup(&candidate, RecordType::Caa) {
+                Ok(records) => records,
+                Err(e) => {
+                    return vec![Finding::error(format!(
+                        "CAA lookup for {candidate}: {e}, CAs refuse to issue when it fails"
+                    ))]
+                }
+            };
+
+            let caa: Vec<(bool, String, String)> = records
+                .into_iter()
+                .filter_map(|r| match r {
+                    Record::Caa {
+                        critical,
+                        tag,
+                        value,
+                    } => Some((critical, tag, value)),
+                    _ => None,
+                })
+                .collect();
+
+            if caa.is_empty() {
+                continue;
+            }
+
+            return Self::evaluate_caa(&candidate, &caa, self.ca.as_deref());
+        }
+
+        vec![]
+    }
+
+    fn evaluate_caa(owner: &str, caa: &[(bool, String, String)], ca: Option<&str>) -> Vec<Finding> {
+        if let Some((_, tag, _)) = caa.iter().find(|(critical, tag, _)| {
+            *critical && !["issue", "issuewild", "iodef"].contains(&tag.as_str())
+        }) {
+            return vec![Finding::error(format!(
+                "CAA at {owner} has an unknown critical property '{tag}', no CA may issue"
+            ))];
+        }
+
+        let issuers: Vec<&str> = caa
+            .iter()
+            .filter(|(_, tag, _)| tag == "issue")
+            .map(|(_, _, value)| value.split(';').next().unwrap_or_default().trim())
+            .collect();
+
+        if issuers.is_empty() {
+            return vec![];
+        }
+
+        let allowed = issuers
+            .iter()
+            .map(|i| if i.is_empty() { "no CA" } else { i })
+            .collect::<Vec<_>>()
+            .join(", ");
+
+        match ca {
+            Some(ca) if issuers.iter().any(|issuer| issuer.eq_ignore_ascii_case(ca)) => vec![],
+            Some(ca) => vec![Finding::error(format!(
+                "CAA at {owner} only allows {allowed}, not {ca}"
+            ))],
+            None => vec![Finding::warning(format!(
+                "CAA at {owner} only allows {allowed}, the CA of the ACME server has to be one of them"
+            ))],
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::{
+        collections::HashMap,
+        net::{Ipv4Addr, Ipv6Addr},
+        thread,
+    };
+
+    type Answers = Vec<(u16, Vec<u8>)>;
+    type Zone = HashMap<(String, u16), Answers>;
+
+    /// Stub nameserver answering from `zone` and NXDOMAIN for everything else.
+    fn stub_nameserver(zone: Zone) -> SocketAddr {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let address = socket.local_addr().unwrap();
+
+        thread::spawn(move || loop {
+            let mut buffer = [0u8; 512];
+            let Ok((len, peer)) = socket.recv_from(&mut buffer) else {
+                return;
+            };
+
+            let query = &buffer[..len];
+
+            let mut position = 12;
+            let mut labels = Vec::new();
+            while query[position] != 0 {
+                let l = query[position] as usize;
+                labels.push(
+                    String::from_utf8_lossy(&query[position + 1..position + 1 + l]).to_string(),
+                );
+                position += l + 1;
+            }
+            let question_end = position + 5;
+            let qtype = u16::from_be_bytes([query[position + 1], query[position + 2]]);
+
+            let answers = zone.get(&(labels.join("."), qtype));
+
+            let mut response = query[..question_end].to_vec();
+            response[2] = 0x81;
+            response[3] = if answers.is_some() { 0x80 } else { 0x83 };
+
+            let answers = answers.cloned().unwrap_or_default();
+            response[6..8].copy_from_slice(&(answers.len() as u16).to_be_bytes());
+
+            for (record_type, rdata) in answers {
+                response.extend_from_slice(&[0xC0, 12]);
+                response.extend_from_slice(&record_type.to_be_bytes());
+                response.extend_from_slice(&[0, 1, 0, 0, 0, 60]);
+                response.extend_from_slice(&(rdata.len() as u16).to_be_bytes());
+                response.extend_from_slice(&rdata);
+            }
+
+            socket.send_to(&response, peer).unwrap();
+        });
+
+        address
+    }
+
+    fn caa(tag: &str, value: &str) -> (u16, Vec<u8>) {
+        let mut rdata = vec![0, tag.len() as u8];
+        rdata.extend_from_slice(tag.as_bytes());
+        rdata.extend_from_slice(value.as_bytes());
+
+        (257, rdata)
+    }
+
+    fn zone(entries: Vec<(&str, u16, Answers)>) -> Zone {
+        entries
+            .into_iter()
+            .map(|(name, qtype, records)| ((name.to_string(), qtype), records))
+            .collect()
+    }
+
+    const HOST_IP: Ipv4Addr = Ipv4Addr::new(203, 0, 113, 10);
+
+    fn check(zone: Zone) -> Vec<Finding> {
+        check_for(zone, None)
+    }
+
+    fn check_for(zone: Zone, acme_server: Option<&str>) -> Vec<Finding> {
+        let domain = Domain::new("example", "com", Some("www")).unwrap();
+
+        DnsCheck::new(Resolver::new(stub_nameserver(zone)))
+            .with_local_addresses(vec![IpAddr::V4(HOST_IP)])
+            .for_acme_server(acme_server)
+            .run(&domain)
+    }
+
+    #[test]
+    fn resolver_reads_a_and_aaaa() {
+        let nameserver = stub_nameserver(zone(vec![
+            ("example.com", 1, vec![(1, HOST_IP.octets().to_vec())]),
+            (
+                "example.com",
+                28,
+                vec![(
+                    28,
+                    "2001:db8::1".parse::<Ipv6Addr>().unwrap().octets().to_vec(),
+                )],
+            ),
+        ]));
+
+        let resolver = Resolver::new(nameserver);
+
+        assert_eq!(
+            resolver.lookup("example.com", RecordType::A).unwrap(),
+            vec![Record::A(HOST_IP)]
+        );
+        assert_eq!(
+            resolver.lookup("example.com", RecordType::Aaaa).unwrap(),
+            vec![Record::Aaaa("2001:db8::1".parse().unwrap())]
+        );
+        assert_eq!(
+            resolver.lookup("nope.example.com", RecordType::A).unwrap(),
+            vec![]
+        );
+    }
+
+    #[test]
+    fn pointing_at_this_host_without_caa() {
+        let findings = check(zone(vec![(
+            "www.example.com",
+            1,
+            vec![(1, HOST_IP.octets().to_vec())],
+        )]));
+
+        assert_eq!(findings, vec![]);
+    }
+
+    #[test]
+    fn missing_records_are_an_error() {
+        let findings = check(zone(vec![]));
+
+        assert_eq!(findings.len(), 1);
+        assert_eq!(findings[0].severity, Severity::Error);
+        assert!(findings[0].message.contains("no A or AAAA record"));
+    }
+
+    #[test]
+    fn only_the_foreign_addresses_are_named() {
+        let findings = check(zone(vec![(
+            "www.example.com",
+            1,
+            vec![(1, HOST_IP.octets().to_vec()), (1, vec![198, 51, 100, 7])],
+        )]));
+
+        assert_eq!(findings.len(), 1);
+        assert_eq!(findings[0].severity, Severity::Warning);
+        assert_eq!(
+            findings[0].message,
+            "198.51.100.7 published for www.example.com does not belong to this host, \
+             fine behind NAT or a load balancer, otherwise the challenge will fail"
+        );
+    }
+
+    #[test]
+    fn foreign_address_is_a_warning() {
+        let findings = check(zone(vec![(
+            "www.example.com",
+            1,
+            vec![(1, vec![198, 51, 100, 7])],
+        )]));
+
+        assert_eq!(findings.len(), 1);
+        assert_eq!(findings[0].severity, Severity::Warning);
+        assert!(findings[0].message.contains("198.51.100.7"));
+    }
+
+    #[test]
+    fn caa_on_parent_allowing_lets_encrypt() {
+        let findings = check(zone(vec![
+            ("www.example.com", 1, vec![(1, HOST_IP.octets().to_vec())]),
+            (
+                "example.com",
+                257,
+                vec![
+                    caa("issue", "sectigo.com"),
+                    caa("issue", "letsencrypt.org; validationmethods=http-01"),
+                ],
+            ),
+        ]));
+
+        assert_eq!(findings, vec![]);
+    }
+
+    #[test]
+    fn caa_forbidding_lets_encrypt() {
+        let findings = check(zone(vec![
+            ("www.example.com", 1, vec![(1, HOST_IP.octets().to_vec())]),
+            (
+                "example.com",
+                257,
+                vec![
+                    caa("issue", "sectigo.com"),
+                    caa("iodef", "mailto:x@example.com"),
+                ],
+            ),
+        ]));
+
+        assert_eq!(findings.len(), 1);
+        assert_eq!(findings[0].severity, Severity::Error);
+        assert!(findings[0].message.contains("only allows sectigo.com"));
+    }
+
+    #[test]
+    fn caa_is_checked_for_the_ca_of_the_acme_server() {
+        let sectigo_only = || {
+            zone(vec![
+                ("www.example.com", 1, vec![(1, HOST_IP.octets().to_vec())]),
+                ("example.com", 257, vec![caa("issue", "sectigo.com")]),
+            ])
+        };
+
+        let findings = check_for(sectigo_only(), Some("https://acme.zerossl.com/v2/DV90"));
+
+        assert_eq!(findings, vec![]);
+
+        let findings = check_for(
+            sectigo_only(),
+            Some("https://acme-staging-v02.api.letsencrypt.org/directory"),
+        );
+
+        assert_eq!(findings.len(), 1);
+        assert_eq!(findings[0].severity, Severity::Error);
+        assert!(findings[0].message.ends_with("not letsencrypt.org"));
+
+        let findings = check_for(sectigo_only(), Some("https://acme.example.net/directory"));
+
+        assert_eq!(findings.len(), 1);
+        assert_eq!(findings[0].severity, Severity::Warning);
+
+        assert_eq!(
+            DnsCheck::ca_of("https://dv.acme-v02.api.pki.goog/directory"),
+            Some("pki.goog")
+        );
+    }
+
+    #[test]
+    fn closest_caa_record_set_wins() {
+        let findings = check(zone(vec![
+            ("www.example.com", 1, vec![(1, HOST_IP.octets().to_vec())]),
+            (
+                "www.example.com",
+                257,
+                vec![caa("issue", "letsencrypt.org")],
+            ),
+            ("example.com", 257, vec![caa("issue", ";")]),
+        ]));
+
+        assert_eq!(findings, vec![]);
+    }
+
+    #[test]
+    fn caa_tree_walk() {
+        assert_eq!(
+            DnsCheck::caa_tree("a.b.example.co.uk"),
+            vec![
+                "a.b.example.co.uk",
+                "b.example.co.uk",
+                "example.co.uk",
+                "co.uk",
+                "uk"
+            ]
+        );
+    }
+}
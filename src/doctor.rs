@@ -0,0 +1,1109 @@
+use std::{
+    error::Error,
+    fmt::Display,
+    fs,
+    os::unix::fs::MetadataExt,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use crate::{
+    apache::{self, Apache},
+    audit::Audit,
+    backup::{Backups, Operation},
+    caddy::{self, Caddy, CaddyReload},
+    configuration_file::ConfigurationFile,
+    domain::Domain,
+    error::{run_command, QicertError},
+    haproxy::{self, HaProxy},
+    layout::{Enable, Layout, Server},
+    lighttpd::{self, Lighttpd},
+    listen::Listen,
+    lock::{Lock, Mode},
+    nginx::{self, Nginx},
+    preflight::Preflight,
+    region::Region,
+    report::{Action, Report},
+    settings::Settings,
+};
+
+#[derive(Debug)]
+pub enum DoctorError {
+    Unhealthy(usize),
+    NotFixed,
+}
+
+impl Display for DoctorError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Unhealthy(1) => write!(f, "1 problem needs attention"),
+            Self::Unhealthy(count) => write!(f, "{count} problems need attention"),
+            Self::NotFixed => write!(f, "The problem could not be fixed"),
+        }
+    }
+}
+
+impl Error for DoctorError {}
+
+/// Something wrong with a site that is already configured.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Issue {
+    /// A certificate or key file the configuration points at.
+    MissingCertificate(PathBuf),
+    KeyMismatch {
+        certificate: PathBuf,
+        key: PathBuf,
+    },
+    Expiring {
+        certificate: PathBuf,
+        not_after: String,
+    },
+    /// The port 80 block serving only the challenge, left by a run that died before
+    /// replacing it.
+    LingeringChallenge(PathBuf),
+    DanglingLink {
+        link: PathBuf,
+        target: PathBuf,
+    },
+    WebrootOwner {
+        root: PathBuf,
+        owner: String,
+    },
+    /// A region qicert wrote that was changed by hand since.
+    EditedRegion(PathBuf),
+    ConfigTest(String),
+}
+
+impl Issue {
+    /// Fixes that only undo what qicert itself left behind.
+    pub fn is_fixable(&self) -> bool {
+        matches!(
+            self,
+            Self::LingeringChallenge(_) | Self::DanglingLink { .. } | Self::WebrootOwner { .. }
+        )
+    }
+}
+
+impl Display for Issue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::MissingCertificate(path) => {
+                write!(f, "{} is referenced but does not exist", path.display())
+            }
+            Self::KeyMismatch { certificate, key } => write!(
+                f,
+                "{} is not the key of {}",
+                key.display(),
+                certificate.display()
+            ),
+            Self::Expiring {
+                certificate,
+                not_after,
+            } => write!(f, "{} expires {not_after}", certificate.display()),
+            Self::LingeringChallenge(file) => write!(
+                f,
+                "{} still holds the challenge block of an interrupted run",
+                file.display()
+            ),
+            Self::DanglingLink { link, target } => write!(
+                f,
+                "{} points at {}, which does not exist",
+                link.display(),
+                target.display()
+            ),
+            Self::WebrootOwner { root, owner } => {
+                write!(f, "{} is not owned by {owner}", root.display())
+            }
+            Self::EditedRegion(file) => write!(
+                f,
+                "the qicert block in {} was edited by hand, qicert no longer updates or removes it",
+                file.display()
+            ),
+            Self::ConfigTest(error) => write!(f, "the configuration test fails: {error}"),
+        }
+    }
+}
+
+/// An issue with the server and the hostname it was found for.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Finding {
+    server: Server,
+    hostname: Option<String>,
+    issue: Issue,
+}
+
+impl Finding {
+    pub fn server(&self) -> Server {
+        self.server
+    }
+
+    pub fn hostname(&self) -> Option<&str> {
+        self.hostname.as_deref()
+    }
+
+    pub fn issue(&self) -> &Issue {
+        &self.issue
+    }
+
+    /// What to do about it, by hand or with `--fix`.
+    pub fn advice(&self) -> String {
+        let hostname = self.hostname.as_deref().unwrap_or("the site");
+
+        match &self.issue {
+            Issue::MissingCertificate(_) => format!(
+                "issue the certificate again with qicert for {hostname}, or correct the path"
+            ),
+            Issue::KeyMismatch { .. } => {
+                String::from("point the key directive at the privkey.pem next to the certificate")
+            }
+            Issue::Expiring { .. } => format!(
+                "run certbot renew --cert-name {hostname} and check that the renewal timer runs"
+            ),
+            Issue::LingeringChallenge(_) => format!(
+                "run qicert again for {hostname} to finish the interrupted run, or qicert doctor --fix removes the block"
+            ),
+            Issue::DanglingLink { .. } => String::from("qicert doctor --fix removes the link"),
+            Issue::WebrootOwner { owner, .. } => {
+                format!("qicert doctor --fix runs chown -R {owner} on it")
+            }
+            Issue::EditedRegion(_) => String::from(
+                "move the changes out of the block, or delete its BEGIN and END lines to keep them as your own",
+            ),
+            Issue::ConfigTest(_) => {
+                String::from("fix the reported line by hand, qicert does not guess what was meant")
+            }
+        }
+    }
+}
+
+impl Display for Finding {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let server = format!("{:?}", self.server).to_lowercase();
+
+        write!(
+            f,
+            "{server} {}: {}",
+            self.hostname.as_deref().unwrap_or("-"),
+            self.issue
+        )
+    }
+}
+
+/// How doctor reads the site blocks of a server, and its configuration test.
+trait Checkup: ConfigurationFile {
+    fn read_block(block: &str) -> Block;
+
+    /// The challenge block as written before the markers, always with the default listen.
+    fn well_known(domain: &Domain) -> String;
+
+    fn check() -> Result<(), QicertError>;
+}
+
+impl Checkup for nginx::config_file::ConfigFile {
+    fn read_block(block: &str) -> Block {
+        Block::parse(
+            block,
+            &Directives {
+                names: &["server_name"],
+                certificate: "ssl_certificate",
+                key: "ssl_certificate_key",
+                root: "root",
+            },
+        )
+    }
+
+    fn well_known(domain: &Domain) -> String {
+        nginx::http_config::HttpConfig::http_well_known(domain, &Listen::default())
+    }
+
+    fn check() -> Result<(), QicertError> {
+        Nginx::check()
+    }
+}
+
+impl Checkup for apache::config_file::ConfigFile {
+    fn read_block(block: &str) -> Block {
+        Block::parse(
+            block,
+            &Directives {
+                names: &["ServerName", "ServerAlias"],
+                certificate: "SSLCertificateFile",
+                key: "SSLCertificateKeyFile",
+                root: "DocumentRoot",
+            },
+        )
+    }
+
+    fn well_known(domain: &Domain) -> String {
+        apache::http_config::HttpConfig::http_well_known(domain, &Listen::default())
+    }
+
+    fn check() -> Result<(), QicertError> {
+        Apache::check()
+    }
+}
+
+impl Checkup for lighttpd::config_file::ConfigFile {
+    fn read_block(block: &str) -> Block {
+        Block::parse(
+            block,
+            &Directives {
+                names: &["$HTTP[\"host\"]"],
+                certificate: "ssl.pemfile",
+                key: "ssl.privkey",
+                root: "server.document-root",
+            },
+        )
+    }
+
+    fn well_known(domain: &Domain) -> String {
+        lighttpd::http_config::HttpConfig::http_well_known(domain)
+    }
+
+    fn check() -> Result<(), QicertError> {
+        Lighttpd::check()
+    }
+}
+
+/// Caddy names the site in the address before the block and takes the certificate
+/// and the key in one `tls` line.
+impl Checkup for caddy::config_file::ConfigFile {
+    fn read_block(block: &str) -> Block {
+        let mut parsed = Block::default();
+        let mut address = true;
+
+        for line in block.lines().map(str::trim).filter(|l| !l.starts_with('#')) {
+            let words = line
+                .split_whitespace()
+                .map(|w| w.trim_end_matches(','))
+                .collect::<Vec<_>>();
+
+            if address {
+                if let Some((&"{", sites)) = words.split_last() {
+                    parsed.names = sites.iter().filter_map(|s| Self::hostname(s)).collect();
+                    address = false;
+                }
+
+                continue;
+            }
+
+            match words.as_slice() {
+                ["tls", certificate, key, ..] if certificate.starts_with('/') => {
+                    parsed.certificate = Some(PathBuf::from(certificate));
+                    parsed.key = Some(PathBuf::from(key));
+                }
+                ["root", .., root] if root.starts_with('/') => {
+                    parsed.roots.push(PathBuf::from(root));
+                }
+                _ => {}
+            }
+        }
+
+        parsed
+    }
+
+    fn well_known(domain: &Domain) -> String {
+        caddy::http_config::HttpConfig::http_well_known(domain)
+    }
+
+    fn check() -> Result<(), QicertError> {
+        Caddy::check()
+    }
+}
+
+impl caddy::config_file::ConfigFile {
+    /// The hostname of a site address, none for snippets and bare ports.
+    fn hostname(address: &str) -> Option<String> {
+        let host = address
+            .strip_prefix("https://")
+            .or_else(|| address.strip_prefix("http://"))
+            .unwrap_or(address);
+
+        let host = match host.rsplit_once(':') {
+            Some((host, port)) if port.chars().all(|c| c.is_ascii_digit()) => host,
+            _ => host,
+        };
+
+        (!host.is_empty() && !host.starts_with('(')).then(|| host.to_lowercase())
+    }
+}
+
+/// How a server spells the directives doctor reads.
+struct Directives {
+    names: &'static [&'static str],
+    certificate: &'static str,
+    key: &'static str,
+    root: &'static str,
+}
+
+/// The directives of one server block doctor looks at.
+#[derive(Debug, Default, PartialEq, Eq)]
+struct Block {
+    names: Vec<String>,
+    certificate: Option<PathBuf>,
+    key: Option<PathBuf>,
+    roots: Vec<PathBuf>,
+}
+
+impl Block {
+    /// Lighttpd puts an operator between the directive and its value.
+    const OPERATORS: [&str; 5] = ["=", "==", "=~", "+=", "{"];
+
+    fn parse(block: &str, directives: &Directives) -> Self {
+        let mut parsed = Self::default();
+
+        for line in block.lines().map(str::trim).filter(|l| !l.starts_with('#')) {
+            let mut words = line.split_whitespace();
+
+            let Some(directive) = words.next() else {
+                continue;
+            };
+
+            let mut values = words
+                .filter(|w| !Self::OPERATORS.contains(w))
+                .map(|w| w.trim_end_matches(';').trim_matches('"'))
+                .filter(|w| !w.is_empty());
+
+            let is = |name: &str| directive.eq_ignore_ascii_case(name);
+
+            if directives.names.iter().any(|name| is(name)) {
+                parsed.names.extend(values.map(str::to_lowercase));
+            } else if is(directives.certificate) {
+                parsed.certificate = values.next().map(PathBuf::from);
+            } else if is(directives.key) {
+                parsed.key = values.next().map(PathBuf::from);
+            } else if is(directives.root) {
+                parsed.roots.extend(values.next().map(PathBuf::from));
+            }
+        }
+
+        parsed
+    }
+}
+
+/// Looks for the ways configured sites go wrong over time: missing or mismatched
+/// certificate files, expiry, blocks and links left by interrupted runs, webroot
+/// ownership and a failing configuration test. HAProxy only gets the certificate
+/// checks and the configuration test, it has neither webroots nor challenge blocks.
+#[derive(Debug, Clone)]
+pub struct Doctor {
+    servers: Vec<Server>,
+    caddy_reload: CaddyReload,
+}
+
+impl Doctor {
+    pub const SERVERS: [Server; 5] = [
+        Server::Apache,
+        Server::Caddy,
+        Server::Haproxy,
+        Server::Lighttpd,
+        Server::Nginx,
+    ];
+    const EXPIRY_DAYS: u64 = 30;
+    const OPENSSL: &str = "openssl";
+
+    /// Examines the servers doctor knows that are installed.
+    pub fn new() -> Self {
+        let servers = Self::SERVERS
+            .into_iter()
+            .filter(|server| Layout::current(*server).binary().is_file())
+            .collect();
+
+        Self {
+            servers,
+            caddy_reload: CaddyReload::default(),
+        }
+    }
+
+    /// How Caddy is reloaded after `--fix` removed a challenge block from its sites.
+    pub fn with_caddy_reload(mut self, caddy_reload: CaddyReload) -> Self {
+        self.caddy_reload = caddy_reload;
+        self
+    }
+
+    pub fn examine(&self) -> Vec<Finding> {
+        let mut findings = Vec::new();
+
+        for server in &self.servers {
+            let found = match server {
+                Server::Apache => Self::examine_server::<apache::config_file::ConfigFile>(),
+                Server::Caddy => Self::examine_server::<caddy::config_file::ConfigFile>(),
+                Server::Haproxy => Self::examine_haproxy(),
+                Server::Lighttpd => Self::examine_server::<lighttpd::config_file::ConfigFile>(),
+                Server::Nginx => Self::examine_server::<nginx::config_file::ConfigFile>(),
+            };
+
+            for finding in found {
+                if !findings.contains(&finding) {
+                    findings.push(finding);
+                }
+            }
+        }
+
+        findings
+    }
+
+    /// Prints every finding with its advice and, with `fix`, applies the safe fixes.
+    /// Fails when problems are left.
+    pub fn run(&self, fix: bool) -> Result<(), QicertError> {
+        if !Preflight::is_installed(Path::new(Self::OPENSSL)) {
+            Report::say("openssl was not found, certificate expiry and key checks are skipped");
+        }
+
+        let findings = self.examine();
+
+        if findings.is_empty() {
+            Report::say("No problems found");
+
+            return Ok(());
+        }
+
+        let mut left = 0;
+
+        for finding in &findings {
+            Report::say(finding);
+
+            if fix && finding.issue().is_fixable() {
+                match self.repair(finding) {
+                    Ok(()) => {
+                        Report::say("  fixed");
+
+                        continue;
+                    }
+                    Err(e) => Report::say(format!("  not fixed: {}", e.chain())),
+                }
+            }
+
+            Report::say(format!("  {}", finding.advice()));
+
+            left += 1;
+        }
+
+        if left > 0 {
+            return Err(DoctorError::Unhealthy(left))?;
+        }
+
+        Ok(())
+    }
+
+    pub fn repair(&self, finding: &Finding) -> Result<(), QicertError> {
+        match (&finding.issue, &finding.hostname) {
+            (Issue::DanglingLink { link, .. }, _) => {
+                fs::remove_file(link).map_err(|e| QicertError::io(DoctorError::NotFixed, link, e))
+            }
+            (Issue::WebrootOwner { root, owner }, _) => {
+                run_command(
+                    Command::new("chown").arg("-R").arg(owner).arg(root),
+                    DoctorError::NotFixed,
+                )?;
+
+                Ok(())
+            }
+            (Issue::LingeringChallenge(file), Some(hostname)) => match finding.server {
+                Server::Apache => Self::remove_challenge::<apache::config_file::ConfigFile>(
+                    file,
+                    hostname,
+                    Apache::check_and_reload,
+                ),
+                Server::Caddy => {
+                    Self::remove_challenge::<caddy::config_file::ConfigFile>(file, hostname, || {
+                        Caddy::check_and_reload(&self.caddy_reload)
+                    })
+                }
+                Server::Lighttpd => Self::remove_challenge::<lighttpd::config_file::ConfigFile>(
+                    file,
+                    hostname,
+                    Lighttpd::check_and_reload,
+                ),
+                Server::Nginx => Self::remove_challenge::<nginx::config_file::ConfigFile>(
+                    file,
+                    hostname,
+                    Nginx::check_and_reload,
+                ),
+                Server::Haproxy => Err(DoctorError::NotFixed)?,
+            },
+            _ => Err(DoctorError::NotFixed)?,
+        }
+    }
+
+    fn examine_server<C: Checkup>() -> Vec<Finding> {
+        let layout = C::layout();
+
+        let mut findings = Vec::new();
+
+        let mut found = |hostname: Option<&str>, issue| {
+            findings.push(Finding {
+                server: C::SERVER,
+                hostname: hostname.map(str::to_string),
+                issue,
+            })
+        };
+
+        if let Err(e) = C::check() {
+            found(None, Issue::ConfigTest(e.chain()));
+        }
+
+        for (link, target) in Self::dangling_links(&layout) {
+            found(None, Issue::DanglingLink { link, target });
+        }
+
+        for file in Self::config_files(layout.config_dir()) {
+            let Ok(content) = fs::read_to_string(&file) else {
+                continue;
+            };
+
+            for region in Region::all(&content) {
+                if region.is_edited() {
+                    found(Some(region.hostname()), Issue::EditedRegion(file.clone()));
+                }
+            }
+
+            for hostname in C::challenge_hostnames(&content) {
+                found(Some(hostname), Issue::LingeringChallenge(file.clone()));
+            }
+
+            for block in C::split_blocks(&content) {
+                let parsed = C::read_block(block);
+
+                let Some(hostname) = parsed.names.first() else {
+                    continue;
+                };
+
+                for issue in Self::examine_block::<C>(&file, block, &parsed, &layout.owner()) {
+                    found(Some(hostname), issue);
+                }
+            }
+        }
+
+        findings
+    }
+
+    /// HAProxy has no site blocks, its hostnames and their bundles are in the crt-list.
+    /// Each bundle holds the key after the chain.
+    fn examine_haproxy() -> Vec<Finding> {
+        use haproxy::config_file::ConfigFile;
+
+        let mut findings = Vec::new();
+
+        let mut found = |hostname: Option<&str>, issue| {
+            findings.push(Finding {
+                server: Server::Haproxy,
+                hostname: hostname.map(str::to_string),
+                issue,
+            })
+        };
+
+        if let Err(e) = HaProxy::check() {
+            found(None, Issue::ConfigTest(e.chain()));
+        }
+
+        for (bundle, hostname) in ConfigFile::crt_list_entries(ConfigFile::read_crt_list()) {
+            for issue in Self::examine_certificate(&bundle, Some(&bundle)) {
+                found(Some(&hostname), issue);
+            }
+        }
+
+        findings
+    }
+
+    fn examine_block<C: Checkup>(
+        file: &Path,
+        block: &str,
+        parsed: &Block,
+        owner: &str,
+    ) -> Vec<Issue> {
+        let mut issues = Vec::new();
+
+        // Marked blocks are found by their markers, whatever they listen on.
+        let lingering = Domain::from_hostname(&parsed.names[0]).is_ok_and(|domain| {
+            !C::find_challenge_in_str(block, &domain)
+                && Self::is_challenge_block::<C>(block, &domain)
+        });
+
+        if lingering {
+            issues.push(Issue::LingeringChallenge(file.to_path_buf()));
+        }
+
+        if let Some(certificate) = &parsed.certificate {
+            issues.extend(Self::examine_certificate(
+                certificate,
+                parsed.key.as_deref(),
+            ));
+        }
+
+        let settings = Settings::current();
+
+        for root in &parsed.roots {
+            // Only the webroots qicert creates, a site's own root may belong to anyone.
+            let managed = root.starts_with(settings.webroot())
+                && !root.starts_with(settings.challenge_root());
+
+            if managed && root.is_dir() && !Self::is_owned_by(root, owner) {
+                issues.push(Issue::WebrootOwner {
+                    root: root.clone(),
+                    owner: owner.to_string(),
+                });
+            }
+        }
+
+        issues
+    }
+
+    fn examine_certificate(certificate: &Path, key: Option<&Path>) -> Vec<Issue> {
+        let mut issues = Vec::new();
+
+        for path in std::iter::once(certificate).chain(key) {
+            if !path.exists() {
+                issues.push(Issue::MissingCertificate(path.to_path_buf()));
+            }
+        }
+
+        if !issues.is_empty() {
+            return issues;
+        }
+
+        if let Some(not_after) = Self::expires_soon(certificate) {
+            issues.push(Issue::Expiring {
+                certificate: certificate.to_path_buf(),
+                not_after,
+            });
+        }
+
+        if let Some(key) = key {
+            if Self::public_keys(certificate, key).is_some_and(|(a, b)| a != b) {
+                issues.push(Issue::KeyMismatch {
+                    certificate: certificate.to_path_buf(),
+                    key: key.to_path_buf(),
+                });
+            }
+        }
+
+        issues
+    }
+
+    /// The `notAfter` date when the certificate expires within the warning window.
+    /// Nothing when openssl cannot tell, for instance without read access.
+    fn expires_soon(certificate: &Path) -> Option<String> {
+        let output = Command::new(Self::OPENSSL)
+            .args(["x509", "-noout", "-enddate", "-checkend"])
+            .arg((Self::EXPIRY_DAYS * 86_400).to_string())
+            .arg("-in")
+            .arg(certificate)
+            .output()
+            .ok()?;
+
+        let stdout = String::from_utf8_lossy(&output.stdout);
+
+        let not_after = stdout
+            .lines()
+            .find_map(|line| line.strip_prefix("notAfter="))?;
+
+        (!output.status.success()).then(|| not_after.to_string())
+    }
+
+    /// Public keys of the certificate and of the private key, as PEM.
+    fn public_keys(certificate: &Path, key: &Path) -> Option<(String, String)> {
+        let public_key = |command: &mut Command| {
+            let output = command.output().ok()?;
+
+            output
+                .status
+                .success()
+                .then(|| String::from_utf8_lossy(&output.stdout).into_owned())
+        };
+
+        let certificate = public_key(
+            Command::new(Self::OPENSSL)
+                .args(["x509", "-noout", "-pubkey", "-in"])
+                .arg(certificate),
+        )?;
+
+        let key = public_key(
+            Command::new(Self::OPENSSL)
+                .args(["pkey", "-pubout", "-in"])
+                .arg(key),
+        )?;
+
+        Some((certificate, key))
+    }
+
+    fn is_owned_by(path: &Path, owner: &str) -> bool {
+        let (user, group) = owner.split_once(':').unwrap_or((owner, owner));
+
+        let Ok(metadata) = fs::metadata(path) else {
+            return true;
+        };
+
+        Preflight::user_id(user).is_none_or(|uid| uid == metadata.uid())
+            && Preflight::group_id(group).is_none_or(|gid| gid == metadata.gid())
+    }
+
+    /// Links in the directory that enables sites, `sites-enabled` next to the config
+    /// directory for `a2ensite`.
+    fn dangling_links(layout: &Layout) -> Vec<(PathBuf, PathBuf)> {
+        let enabled = match layout.enable() {
+            Enable::Symlink(enabled) => enabled.clone(),
+            Enable::Command(_) => layout.config_dir().with_file_name("sites-enabled"),
+            Enable::Included | Enable::Include(_) => return Vec::new(),
+        };
+
+        let Ok(entries) = fs::read_dir(enabled) else {
+            return Vec::new();
+        };
+
+        entries
+            .flatten()
+            .map(|entry| entry.path())
+            .filter(|link| link.is_symlink() && !link.exists())
+            .map(|link| {
+                let target = fs::read_link(&link).unwrap_or_default();
+
+                (link, target)
+            })
+            .collect()
+    }
+
+    fn config_files(dir: &Path) -> Vec<PathBuf> {
+        let Ok(entries) = fs::read_dir(dir) else {
+            return Vec::new();
+        };
+
+        let mut files = entries
+            .flatten()
+            .map(|entry| entry.path())
+            .filter(|path| path.is_file() && path.extension().is_some_and(|e| e == "conf"))
+            .collect::<Vec<_>>();
+
+        files.sort();
+
+        files
+    }
+
+    /// The block is the one qicert writes for the challenge, whatever precedes it. Only
+    /// for unmarked blocks, older runs always wrote them with the default listen lines.
+    fn is_challenge_block<C: Checkup>(block: &str, domain: &Domain) -> bool {
+        let block = block.split_whitespace().collect::<Vec<_>>();
+        let well_known = C::well_known(domain);
+        let well_known = well_known.split_whitespace().collect::<Vec<_>>();
+
+        block.ends_with(&well_known)
+    }
+
+    /// Marked blocks go with their markers, older runs left them unmarked.
+    fn without_challenge<C: Checkup>(content: &str, domain: &Domain) -> String {
+        let content = C::remove_challenge_from_str(content, domain);
+
+        C::split_blocks(&content)
+            .into_iter()
+            .filter(|block| !Self::is_challenge_block::<C>(block, domain))
+            .collect()
+    }
+
+    fn remove_challenge<C: Checkup>(
+        file: &Path,
+        hostname: &str,
+        check_and_reload: impl FnOnce() -> Result<(), QicertError>,
+    ) -> Result<(), QicertError> {
+        let domain = Domain::from_hostname(hostname)?;
+
+        let _global = Lock::global(Mode::Shared)?;
+        let _file = Lock::config_file(file)?;
+
+        let files = [file.to_path_buf()];
+
+        Audit::run(Operation::Repair, C::SERVER, &domain, &files, || {
+            let content = fs::read_to_string(file)
+                .map_err(|e| QicertError::io(DoctorError::NotFixed, file, e))?;
+
+            let backups = Backups::current();
+
+            Report::step(
+                Action::Backup,
+                Some(&backups.dir_for(file)),
+                backups.save(file, C::SERVER, &domain, Operation::Repair),
+            )?;
+
+            let written = fs::write(file, Self::without_challenge::<C>(&content, &domain))
+                .map_err(|e| QicertError::io(DoctorError::NotFixed, file, e));
+
+            Report::step(Action::WriteConfig, Some(file), written)?;
+
+            let reloaded = check_and_reload();
+
+            Audit::reload(&reloaded);
+
+            let Err(cause) = Report::step(Action::Reload, None, reloaded) else {
+                return Ok(());
+            };
+
+            let undone = fs::write(file, &content)
+                .map_err(|e| QicertError::io(DoctorError::NotFixed, file, e));
+
+            match Report::step(Action::Rollback, Some(file), undone) {
+                Ok(_) => Err(cause),
+                Err(e) => Err(QicertError::rollback(cause, e)),
+            }
+        })
+    }
+}
+
+impl Default for Doctor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_support::scratch;
+
+    type NginxFile = nginx::config_file::ConfigFile;
+    type ApacheFile = apache::config_file::ConfigFile;
+
+    fn domain() -> Domain {
+        Domain::new("example", "com", Some("www")).unwrap()
+    }
+
+    #[test]
+    fn reads_nginx_directives() {
+        let block = "server {
+            server_name www.example.com example.com;
+            listen 443 ssl;
+            # ssl_certificate /old/cert.pem;
+            ssl_certificate /etc/letsencrypt/live/www.example.com/fullchain.pem;
+            ssl_certificate_key /etc/letsencrypt/live/www.example.com/privkey.pem;
+            root /var/www/www.example.com;
+        }";
+
+        assert_eq!(
+            NginxFile::read_block(block),
+            Block {
+                names: vec![String::from("www.example.com"), String::from("example.com")],
+                certificate: Some(PathBuf::from(
+                    "/etc/letsencrypt/live/www.example.com/fullchain.pem"
+                )),
+                key: Some(PathBuf::from(
+                    "/etc/letsencrypt/live/www.example.com/privkey.pem"
+                )),
+                roots: vec![PathBuf::from("/var/www/www.example.com")],
+            }
+        );
+    }
+
+    #[test]
+    fn reads_apache_directives() {
+        let block = r#"
+        <VirtualHost *:443>
+            ServerName www.example.com
+            DocumentRoot "/var/www/www.example.com"
+            SSLCertificateFile "/etc/letsencrypt/live/www.example.com/fullchain.pem"
+            SSLCertificateKeyFile /etc/letsencrypt/live/www.example.com/privkey.pem
+        </VirtualHost>"#;
+
+        let parsed = ApacheFile::read_block(block);
+
+        assert_eq!(parsed.names, vec![String::from("www.example.com")]);
+        assert_eq!(
+            parsed.certificate.as_deref(),
+            Some(Path::new(
+                "/etc/letsencrypt/live/www.example.com/fullchain.pem"
+            ))
+        );
+        assert_eq!(
+            parsed.key.as_deref(),
+            Some(Path::new(
+                "/etc/letsencrypt/live/www.example.com/privkey.pem"
+            ))
+        );
+        assert_eq!(
+            parsed.roots,
+            vec![PathBuf::from("/var/www/www.example.com")]
+        );
+    }
+
+    #[test]
+    fn reads_lighttpd_directives() {
+        let block = r#"
+$SERVER["socket"] == ":443" {
+    ssl.engine = "enable"
+    $HTTP["host"] == "www.example.com" {
+        ssl.pemfile = "/etc/letsencrypt/live/www.example.com/fullchain.pem"
+        ssl.privkey = "/etc/letsencrypt/live/www.example.com/privkey.pem"
+        server.document-root = "/var/www/www.example.com"
+    }
+}"#;
+
+        assert_eq!(
+            lighttpd::config_file::ConfigFile::read_block(block),
+            Block {
+                names: vec![String::from("www.example.com")],
+                certificate: Some(PathBuf::from(
+                    "/etc/letsencrypt/live/www.example.com/fullchain.pem"
+                )),
+                key: Some(PathBuf::from(
+                    "/etc/letsencrypt/live/www.example.com/privkey.pem"
+                )),
+                roots: vec![PathBuf::from("/var/www/www.example.com")],
+            }
+        );
+    }
+
+    #[test]
+    fn reads_caddy_sites() {
+        type CaddyFile = caddy::config_file::ConfigFile;
+
+        let block = "https://www.example.com, example.com:8443 {
+    tls /etc/letsencrypt/live/www.example.com/fullchain.pem /etc/letsencrypt/live/www.example.com/privkey.pem
+    root * /var/www/www.example.com
+    file_server
+}";
+
+        assert_eq!(
+            CaddyFile::read_block(block),
+            Block {
+                names: vec![String::from("www.example.com"), String::from("example.com")],
+                certificate: Some(PathBuf::from(
+                    "/etc/letsencrypt/live/www.example.com/fullchain.pem"
+                )),
+                key: Some(PathBuf::from(
+                    "/etc/letsencrypt/live/www.example.com/privkey.pem"
+                )),
+                roots: vec![PathBuf::from("/var/www/www.example.com")],
+            }
+        );
+
+        let snippet = "(common) {\n    encode gzip\n}";
+
+        assert_eq!(CaddyFile::read_block(snippet), Block::default());
+        assert_eq!(
+            CaddyFile::read_block("example.org {\n    tls admin@example.org\n}"),
+            Block {
+                names: vec![String::from("example.org")],
+                ..Block::default()
+            }
+        );
+    }
+
+    #[test]
+    fn removes_only_the_lingering_challenge_block() {
+        let domain = domain();
+
+        let user_block = "server {
+    listen 80;
+    server_name blog.example.com;
+    root /srv/blog;
+}
+";
+        let content = format!(
+            "{user_block}{}\n",
+            nginx::http_config::HttpConfig::http_well_known(&domain, &Listen::default())
+        );
+
+        let blocks = NginxFile::split_blocks(&content);
+
+        assert!(!Doctor::is_challenge_block::<NginxFile>(blocks[0], &domain));
+        assert!(Doctor::is_challenge_block::<NginxFile>(blocks[1], &domain));
+
+        assert_eq!(
+            Doctor::without_challenge::<NginxFile>(&content, &domain),
+            user_block
+        );
+    }
+
+    #[test]
+    fn marked_challenge_block_is_found_whatever_it_listens_on() {
+        let domain = domain();
+
+        let listen = Listen::default().with_dual_stack(true).with_http_port(8080);
+        let block = NginxFile::mark_challenge(
+            &domain,
+            &nginx::http_config::HttpConfig::http_well_known(&domain, &listen),
+        );
+        let content = format!("{block}\n");
+
+        assert!(!Doctor::is_challenge_block::<NginxFile>(&block, &domain));
+        assert_eq!(
+            NginxFile::challenge_hostnames(&content),
+            vec!["www.example.com"]
+        );
+        assert_eq!(
+            Doctor::without_challenge::<NginxFile>(&content, &domain),
+            ""
+        );
+    }
+
+    #[test]
+    fn apache_challenge_block_is_found() {
+        let domain = domain();
+
+        let content = format!(
+            "{}\n{}",
+            apache::http_config::HttpConfig::http_well_known(&domain, &Listen::default()),
+            apache::http_config::HttpConfig::http_redirect(&domain, &Listen::default())
+        );
+
+        assert_eq!(
+            Doctor::without_challenge::<ApacheFile>(&content, &domain),
+            format!(
+                "\n{}",
+                apache::http_config::HttpConfig::http_redirect(&domain, &Listen::default())
+            )
+        );
+    }
+
+    #[test]
+    fn finds_dangling_links() {
+        let dir = scratch("doctor-links");
+
+        let available = dir.join("sites-available");
+        let enabled = dir.join("sites-enabled");
+
+        fs::create_dir_all(&available).unwrap();
+        fs::create_dir_all(&enabled).unwrap();
+
+        fs::write(available.join("example.com.conf"), "").unwrap();
+
+        std::os::unix::fs::symlink(
+            available.join("example.com.conf"),
+            enabled.join("example.com.conf"),
+        )
+        .unwrap();
+        std::os::unix::fs::symlink(
+            available.join("example.org.conf"),
+            enabled.join("example.org.conf"),
+        )
+        .unwrap();
+
+        let layout = Layout::defaults(crate::layout::Family::Debian, Server::Nginx)
+            .with_config_dir(&available)
+            .with_enable(Enable::Symlink(enabled.clone()));
+
+        assert_eq!(
+            Doctor::dangling_links(&layout),
+            vec![(
+                enabled.join("example.org.conf"),
+                available.join("example.org.conf")
+            )]
+        );
+    }
+
+    #[test]
+    fn only_safe_issues_are_fixed() {
+        let finding = Finding {
+            server: Server::Nginx,
+            hostname: Some(String::from("www.example.com")),
+            issue: Issue::Expiring {
+                certificate: PathBuf::from("/etc/letsencrypt/live/www.example.com/fullchain.pem"),
+                not_after: String::from("Nov  1 12:00:00 2026 GMT"),
+            },
+        };
+
+        assert!(!finding.issue().is_fixable());
+        assert_eq!(
+            finding.to_string(),
+            "nginx www.example.com: /etc/letsencrypt/live/www.example.com/fullchain.pem expires Nov  1 12:00:00 2026 GMT"
+        );
+        assert!(Issue::LingeringChallenge(PathBuf::new()).is_fixable());
+    }
+}
@@ -1,25 +1,18 @@
 use std::{fmt::Display, str::FromStr};
 
-use super::DomainError;
+use super::{label::Label, DomainError};
 
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub struct DomainName(String);
 
 impl DomainName {
-    fn is_valid_char(char: char) -> bool {
-        matches!(char, 'a'..='z' | '0'..='9' | '-')
-    }
-
-    pub fn is_valid(name: &str) -> bool {
+    /// The name is a single label, `example` in `www.example.com`.
+    pub fn validate(name: &str) -> Result<(), DomainError> {
         if name.is_empty() {
-            return false;
-        }
-
-        if name.starts_with('-') | name.ends_with('-') {
-            return false;
+            return Err(DomainError::EmptyLabel(name.to_string()));
         }
 
-        name.chars().all(Self::is_valid_char)
+        Label::validate(name)
     }
 }
 
@@ -33,11 +26,9 @@ impl FromStr for DomainName {
     type Err = DomainError;
 
     fn from_str(value: &str) -> Result<Self, Self::Err> {
-        let value = value.to_lowercase();
+        let value = super::to_ascii(value).ok_or(Self::Err::InvalidName)?;
 
-        if !Self::is_valid(value.as_str()) {
-            return Err(Self::Err::InvalidName);
-        }
+        Self::validate(&value)?;
 
         Ok(Self(value))
     }
@@ -49,6 +40,9 @@ mod test {
 
     #[test]
     fn valid_domain_name_from_str() {
+        let longest = "a".repeat(63);
+        let too_long = "a".repeat(64);
+
         let names = vec![
             ("example", true),
             (".", false),
@@ -62,6 +56,8 @@ mod test {
             ("-not-valid", false),
             ("not-valid-either-", false),
             ("not_valid", false),
+            (longest.as_str(), true),
+            (too_long.as_str(), false),
         ];
 
         for (name, expected) in names {
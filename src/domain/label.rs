@@ -0,0 +1,96 @@
+use super::DomainError;
+
+/// LDH rules shared by every part of a hostname (RFC 1035 section 2.3.1, RFC 1123 section 2.1).
+pub struct Label;
+
+impl Label {
+    pub const MAX_LENGTH: usize = 63;
+
+    fn is_valid_char(char: char) -> bool {
+        matches!(char, 'a'..='z' | '0'..='9' | '-')
+    }
+
+    /// Checks a single label, already lowercased and in its A-label form.
+    pub fn validate(label: &str) -> Result<(), DomainError> {
+        if label.len() > Self::MAX_LENGTH {
+            return Err(DomainError::LabelTooLong {
+                label: label.to_string(),
+                length: label.len(),
+            });
+        }
+
+        if let Some(char) = label.chars().find(|c| !Self::is_valid_char(*c)) {
+            return Err(DomainError::InvalidCharacter {
+                label: label.to_string(),
+                char,
+            });
+        }
+
+        if label.starts_with('-') || label.ends_with('-') {
+            return Err(DomainError::HyphenAtEdge(label.to_string()));
+        }
+
+        Ok(())
+    }
+
+    /// Checks every dot separated label of `value`.
+    pub fn validate_all(value: &str) -> Result<(), DomainError> {
+        value.split('.').try_for_each(|label| {
+            if label.is_empty() {
+                return Err(DomainError::EmptyLabel(value.to_string()));
+            }
+
+            Self::validate(label)
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn label_lengths() {
+        assert!(Label::validate(&"a".repeat(63)).is_ok());
+
+        assert!(matches!(
+            Label::validate(&"a".repeat(64)),
+            Err(DomainError::LabelTooLong { length: 64, .. })
+        ));
+    }
+
+    #[test]
+    fn ldh_per_label() {
+        let labels = vec![
+            ("www", true),
+            ("a-b", true),
+            ("xn--mnchen-3ya", true),
+            ("-www", false),
+            ("www-", false),
+            ("w_w", false),
+            ("w w", false),
+        ];
+
+        for (label, expected) in labels {
+            assert_eq!(Label::validate(label).is_ok(), expected, "label: {label}");
+        }
+    }
+
+    #[test]
+    fn errors_name_the_label() {
+        let error = Label::validate_all("staging.-test").unwrap_err();
+
+        assert!(matches!(&error, DomainError::HyphenAtEdge(label) if label == "-test"));
+
+        let error = Label::validate_all("staging..test").unwrap_err();
+
+        assert_eq!(error.to_string(), "'staging..test' has an empty label");
+
+        let error = Label::validate_all("sta!ging").unwrap_err();
+
+        assert_eq!(
+            error.to_string(),
+            "The label 'sta!ging' contains '!', only letters, digits and hyphens are allowed"
+        );
+    }
+}
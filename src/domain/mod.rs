@@ -1,12 +1,16 @@
 mod domain_name;
+mod label;
+mod public_suffix;
 mod subdomain;
 mod tld;
 
-use std::{error::Error, fmt::Display, str::FromStr};
+use std::{error::Error, fmt::Display, net::IpAddr, str::FromStr};
 
 use crate::domain::{domain_name::DomainName, subdomain::SubDomain, tld::Tld};
 
-#[derive(Debug, PartialEq, Eq)]
+pub use public_suffix::{PublicSuffixError, PublicSuffixList};
+
+#[derive(Debug, PartialEq, Eq, Clone)]
 pub struct Domain {
     name: DomainName,
     tld: Tld,
@@ -19,6 +23,15 @@ pub enum DomainError {
     InvalidSubdomain,
     InvalidTld,
     TldTooShort,
+    EmptyLabel(String),
+    LabelTooLong { label: String, length: usize },
+    InvalidCharacter { label: String, char: char },
+    HyphenAtEdge(String),
+    NumericTld(String),
+    IpLiteral(String),
+    NameTooLong { name: String, length: usize },
+    UnknownSuffix(String),
+    PublicSuffix(String),
 }
 
 impl Display for DomainError {
@@ -29,19 +42,67 @@ impl Display for DomainError {
             Self::InvalidName => write!(f, "Invalid domain name"),
             Self::InvalidSubdomain => write!(f, "The subdomain given is invalid"),
             Self::InvalidTld => write!(f, "The TLD is invalid"),
+            Self::EmptyLabel(value) => write!(f, "'{value}' has an empty label"),
+            Self::LabelTooLong { label, length } => write!(
+                f,
+                "The label '{label}' is {length} octets long, the limit is {}",
+                label::Label::MAX_LENGTH
+            ),
+            Self::InvalidCharacter { label, char } => write!(
+                f,
+                "The label '{label}' contains '{char}', only letters, digits and hyphens are allowed"
+            ),
+            Self::HyphenAtEdge(label) => {
+                write!(f, "The label '{label}' starts or ends with a hyphen")
+            }
+            Self::NumericTld(label) => write!(f, "The TLD '{label}' is all numeric"),
+            Self::IpLiteral(value) => write!(
+                f,
+                "{value} is an IP address, certificates are issued for hostnames"
+            ),
+            Self::NameTooLong { name, length } => write!(
+                f,
+                "{name} is {length} octets long, the limit is {}",
+                Domain::MAX_LENGTH
+            ),
+            Self::UnknownSuffix(tld) => {
+                write!(f, "'{tld}' is not in the Public Suffix List")
+            }
+            Self::PublicSuffix(name) => write!(
+                f,
+                "{name} is a public suffix, certificates are issued for names under it"
+            ),
         }
     }
 }
 
 impl Error for DomainError {}
 
+/// UTS #46 mapping followed by punycode, so `MÜNCHEN` becomes `xn--mnchen-3ya`.
+/// ASCII input only gets lowercased.
+fn to_ascii(value: &str) -> Option<String> {
+    idna::domain_to_ascii(value).ok()
+}
+
 impl Domain {
+    /// Longest hostname in its A-label form, without the root dot.
+    pub const MAX_LENGTH: usize = 253;
+
     pub fn new<S: AsRef<str>>(
         name: S,
         tld: S,
         subdomain: Option<&str>,
     ) -> Result<Self, DomainError> {
         fn inner(name: &str, tld: &str, subdomain: Option<&str>) -> Result<Domain, DomainError> {
+            let literal = match subdomain {
+                Some(sub) => format!("{sub}.{name}.{tld}"),
+                None => format!("{name}.{tld}"),
+            };
+
+            if literal.parse::<IpAddr>().is_ok() {
+                return Err(DomainError::IpLiteral(literal));
+            }
+
             let name = DomainName::from_str(name)?;
 
             let tld = Tld::from_str(tld)?;
@@ -53,12 +114,46 @@ impl Domain {
 
             let domain = Domain::from_parts(name, tld, subdomain);
 
+            let length = domain.to_string().len();
+
+            if length > Domain::MAX_LENGTH {
+                return Err(DomainError::NameTooLong {
+                    name: domain.to_string(),
+                    length,
+                });
+            }
+
+            if PublicSuffixList::current()
+                .registrable_domain(&domain.to_string())
+                .is_none()
+            {
+                return Err(DomainError::PublicSuffix(domain.to_string()));
+            }
+
             Ok(domain)
         }
 
         inner(name.as_ref(), tld.as_ref(), subdomain)
     }
 
+    /// Splits a full hostname at its registrable domain, so `www.example.co.uk`
+    /// becomes subdomain `www`, name `example` and TLD `co.uk`.
+    pub fn from_hostname(hostname: &str) -> Result<Self, DomainError> {
+        let ascii = to_ascii(hostname).ok_or(DomainError::InvalidName)?;
+
+        let Some(registrable) = PublicSuffixList::current().registrable_domain(&ascii) else {
+            return Err(DomainError::PublicSuffix(ascii));
+        };
+
+        let (name, tld) = registrable.split_once('.').ok_or(DomainError::MissingTld)?;
+
+        let subdomain = ascii
+            .strip_suffix(registrable.as_str())
+            .and_then(|s| s.strip_suffix('.'));
+
+        Self::new(name, tld, subdomain)
+    }
+
     pub fn from_parts(name: DomainName, tld: Tld, subdomain: Option<SubDomain>) -> Self {
         Self {
             name,
@@ -80,8 +175,18 @@ impl Domain {
         self.tld.clone()
     }
 
-    pub fn conf_file_name(&self) -> String {
-        format!("{}.{}.conf", self.name, self.tld)
+    /// The public suffix plus one label, regardless of how the hostname was split
+    /// into subdomain, name and TLD.
+    pub fn registrable_domain(&self) -> String {
+        PublicSuffixList::current()
+            .registrable_domain(&self.to_string())
+            .unwrap_or_else(|| format!("{}.{}", self.get_name(), self.get_tld()))
+    }
+
+    /// The hostname with its A-labels decoded, for messages shown to the user.
+    /// `Display` gives the A-label form used by certbot and the server configs.
+    pub fn unicode(&self) -> String {
+        idna::domain_to_unicode(&self.to_string()).0
     }
 }
 
@@ -179,6 +284,132 @@ mod test {
         }
     }
 
+    #[test]
+    fn tld_has_to_be_a_public_suffix() {
+        let domains = vec![
+            ("example", "github.io", None, true),
+            ("example", "co.uk", None, true),
+            ("foo", "bar.baz", None, false),
+            ("co", "uk", None, false),
+            ("co", "uk", Some("example"), true),
+        ];
+
+        for (name, tld, subdomain, expected) in domains {
+            let domain = Domain::new(name, tld, subdomain);
+
+            assert_eq!(domain.is_ok(), expected, "{name} {tld} {subdomain:?}");
+        }
+    }
+
+    #[test]
+    fn from_hostname_splits_at_the_registrable_domain() {
+        let hostnames = vec![
+            (
+                "www.example.co.uk",
+                Domain::new("example", "co.uk", Some("www")),
+            ),
+            ("example.com", Domain::new("example", "com", None)),
+            (
+                "a.b.example.com",
+                Domain::new("example", "com", Some("a.b")),
+            ),
+            ("bücher.de", Domain::new("bücher", "de", None)),
+            (
+                "foo.experts-comptables.fr",
+                Domain::new("foo", "experts-comptables.fr", None),
+            ),
+        ];
+
+        for (hostname, expected) in hostnames {
+            assert_eq!(
+                Domain::from_hostname(hostname).unwrap(),
+                expected.unwrap(),
+                "{hostname}"
+            );
+        }
+
+        assert!(Domain::from_hostname("co.uk").is_err());
+        assert!(Domain::from_hostname("example..com").is_err());
+    }
+
+    #[test]
+    fn registrable_domain_ignores_the_split() {
+        let split = Domain::new("co", "uk", Some("www.example")).unwrap();
+        let proper = Domain::new("example", "co.uk", Some("www")).unwrap();
+
+        assert_eq!(split.registrable_domain(), "example.co.uk");
+        assert_eq!(proper.registrable_domain(), "example.co.uk");
+    }
+
+    #[test]
+    fn ip_literals_are_rejected() {
+        let literals = vec![
+            ("192.168.1", "1", None),
+            ("0", "1", Some("10.0")),
+            ("1", "1", Some("127.0")),
+        ];
+
+        for (name, tld, subdomain) in literals {
+            let domain = Domain::new(name, tld, subdomain);
+
+            assert!(
+                matches!(domain, Err(DomainError::IpLiteral(_))),
+                "{name} {tld} {subdomain:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn name_length_limit() {
+        let label = "a".repeat(63);
+        let subdomain = [label.as_str(); 3].join(".");
+
+        let domain = Domain::new("example", "com", Some(&subdomain));
+
+        assert!(domain.is_ok());
+
+        let subdomain = [label.as_str(); 4].join(".");
+
+        let domain = Domain::new("example", "com", Some(&subdomain));
+
+        assert!(matches!(
+            domain,
+            Err(DomainError::NameTooLong { length: 267, .. })
+        ));
+    }
+
+    #[test]
+    fn internationalized_domains_keep_both_forms() {
+        let domains = vec![
+            ("münchen", "de", None, "xn--mnchen-3ya.de", "münchen.de"),
+            ("MÜNCHEN", "de", None, "xn--mnchen-3ya.de", "münchen.de"),
+            ("пример", "рф", None, "xn--e1afmkfd.xn--p1ai", "пример.рф"),
+            (
+                "example",
+                "com",
+                Some("bücher"),
+                "xn--bcher-kva.example.com",
+                "bücher.example.com",
+            ),
+            ("faß", "de", None, "xn--fa-hia.de", "faß.de"),
+            ("example", "com", None, "example.com", "example.com"),
+        ];
+
+        for (name, tld, subdomain, ascii, unicode) in domains {
+            let domain = Domain::new(name, tld, subdomain).unwrap();
+
+            assert_eq!(domain.to_string(), ascii);
+            assert_eq!(domain.unicode(), unicode);
+        }
+    }
+
+    #[test]
+    fn a_labels_are_accepted_as_given() {
+        let domain = Domain::new("xn--mnchen-3ya", "de", None).unwrap();
+
+        assert_eq!(domain, Domain::new("münchen", "de", None).unwrap());
+    }
+
     #[test]
     fn build_from_parts_str_with_subdomain() {
         let domain_parts = vec![
@@ -188,6 +419,9 @@ mod test {
             ("example", "com.mx", "staging02.test", true),
             ("example", "net", "staging-02.test", true),
             ("example", "net", "staging02-", false),
+            ("example", "net", "staging..test", false),
+            ("example", "net", "staging.-test", false),
+            ("example", "net", "staging-.test", false),
         ];
 
         for (domain_name, tld, subdomain, expected) in domain_parts {
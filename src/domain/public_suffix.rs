@@ -0,0 +1,211 @@
+use std::{collections::HashSet, error::Error, fmt::Display, fs, path::Path, sync::OnceLock};
+
+use crate::error::QicertError;
+
+/// Public Suffix List rules (https://publicsuffix.org/list/), in A-label form.
+#[derive(Debug, Default)]
+pub struct PublicSuffixList {
+    rules: HashSet<String>,
+    wildcards: HashSet<String>,
+    exceptions: HashSet<String>,
+}
+
+#[derive(Debug)]
+pub enum PublicSuffixError {
+    Unreadable,
+    NoRules,
+    NotSaved,
+}
+
+impl Display for PublicSuffixError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Unreadable => write!(f, "The Public Suffix List could not be read"),
+            Self::NoRules => write!(f, "The file given has no Public Suffix List rules"),
+            Self::NotSaved => write!(
+                f,
+                "The Public Suffix List could not be saved to {}",
+                PublicSuffixList::UPDATED_PATH
+            ),
+        }
+    }
+}
+
+impl Error for PublicSuffixError {}
+
+impl PublicSuffixList {
+    const EMBEDDED: &'static str = include_str!("../../data/public_suffix_list.dat");
+    pub const UPDATED_PATH: &'static str = "/var/lib/qicert/public_suffix_list.dat";
+    pub const DEFAULT_SOURCE: &'static str = "/usr/share/publicsuffix/public_suffix_list.dat";
+
+    pub fn parse(list: &str) -> Self {
+        let mut psl = Self::default();
+
+        let rules = list
+            .lines()
+            .filter_map(|line| line.split_whitespace().next())
+            .filter(|rule| !rule.starts_with("//"));
+
+        for rule in rules {
+            if let Some(exception) = rule.strip_prefix('!') {
+                psl.exceptions.extend(super::to_ascii(exception));
+            } else if let Some(parent) = rule.strip_prefix("*.") {
+                psl.wildcards.extend(super::to_ascii(parent));
+            } else {
+                psl.rules.extend(super::to_ascii(rule));
+            }
+        }
+
+        psl
+    }
+
+    fn is_empty(&self) -> bool {
+        self.rules.is_empty() && self.wildcards.is_empty()
+    }
+
+    /// The list saved by `qicert update-psl` when there is one, the copy built into
+    /// the binary otherwise. Tests always use the built-in copy.
+    pub fn current() -> &'static Self {
+        static CURRENT: OnceLock<PublicSuffixList> = OnceLock::new();
+
+        CURRENT.get_or_init(|| {
+            if !cfg!(test) {
+                if let Ok(list) = fs::read_to_string(Self::UPDATED_PATH) {
+                    let psl = Self::parse(&list);
+
+                    if !psl.is_empty() {
+                        return psl;
+                    }
+                }
+            }
+
+            Self::parse(Self::EMBEDDED)
+        })
+    }
+
+    /// Installs a newer list, usually the one kept up to date by the distribution,
+    /// and returns how many rules it has.
+    pub fn update(source: &Path) -> Result<usize, QicertError> {
+        let list = fs::read_to_string(source)
+            .map_err(|e| QicertError::io(PublicSuffixError::Unreadable, source, e))?;
+
+        let psl = Self::parse(&list);
+
+        if psl.is_empty() {
+            return Err(PublicSuffixError::NoRules)?;
+        }
+
+        let destination = Path::new(Self::UPDATED_PATH);
+
+        if let Some(parent) = destination.parent() {
+            fs::create_dir_all(parent)
+                .map_err(|e| QicertError::io(PublicSuffixError::NotSaved, parent, e))?;
+        }
+
+        fs::write(destination, list)
+            .map_err(|e| QicertError::io(PublicSuffixError::NotSaved, destination, e))?;
+
+        Ok(psl.rules.len() + psl.wildcards.len() + psl.exceptions.len())
+    }
+
+    /// Number of trailing labels of `name` making up its public suffix, `None` when
+    /// only the implicit `*` rule matches.
+    fn suffix_labels(&self, name: &str) -> Option<usize> {
+        let labels: Vec<&str> = name.split('.').collect();
+        let count = labels.len();
+
+        if let Some(i) = (0..count).find(|&i| self.exceptions.contains(&labels[i..].join("."))) {
+            return Some(count - i - 1);
+        }
+
+        (0..count)
+            .find(|&i| {
+                self.rules.contains(&labels[i..].join("."))
+                    || (i + 1 < count && self.wildcards.contains(&labels[i + 1..].join(".")))
+            })
+            .map(|i| count - i)
+    }
+
+    /// Whether `name` is exactly a listed public suffix, like `com`, `co.uk` or `github.io`.
+    pub fn is_suffix(&self, name: &str) -> bool {
+        self.suffix_labels(name) == Some(name.split('.').count())
+    }
+
+    /// The public suffix plus one label, `example.co.uk` for `www.example.co.uk`.
+    pub fn registrable_domain(&self, name: &str) -> Option<String> {
+        let labels: Vec<&str> = name.split('.').collect();
+        let suffix = self.suffix_labels(name).unwrap_or(1);
+
+        if suffix >= labels.len() {
+            return None;
+        }
+
+        Some(labels[labels.len() - suffix - 1..].join("."))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn known_suffixes() {
+        let psl = PublicSuffixList::current();
+
+        let suffixes = vec![
+            ("com", true),
+            ("co.uk", true),
+            ("com.mx", true),
+            ("github.io", true),
+            ("xn--p1ai", true),
+            ("bar.baz", false),
+            ("example.com", false),
+            ("uk.co", false),
+        ];
+
+        for (name, expected) in suffixes {
+            assert_eq!(psl.is_suffix(name), expected, "{name}");
+        }
+    }
+
+    #[test]
+    fn registrable_domains() {
+        let psl = PublicSuffixList::current();
+
+        let names = vec![
+            ("www.example.com", Some("example.com")),
+            ("a.b.example.co.uk", Some("example.co.uk")),
+            ("user.github.io", Some("user.github.io")),
+            ("co.uk", None),
+            ("com", None),
+        ];
+
+        for (name, expected) in names {
+            assert_eq!(psl.registrable_domain(name).as_deref(), expected, "{name}");
+        }
+    }
+
+    #[test]
+    fn wildcard_and_exception_rules() {
+        let psl = PublicSuffixList::parse("// comment\n*.ck\n!www.ck\nuk\nco.uk\n\n// ===END===\n");
+
+        assert!(psl.is_suffix("anything.ck"));
+        assert!(!psl.is_suffix("www.ck"));
+        assert_eq!(
+            psl.registrable_domain("shop.example.ck").as_deref(),
+            Some("shop.example.ck")
+        );
+        assert_eq!(psl.registrable_domain("www.ck").as_deref(), Some("www.ck"));
+        assert_eq!(
+            psl.registrable_domain("a.example.co.uk").as_deref(),
+            Some("example.co.uk")
+        );
+    }
+
+    #[test]
+    fn unicode_rules_are_stored_as_a_labels() {
+        let psl = PublicSuffixList::parse("рф\n");
+
+        assert!(psl.is_suffix("xn--p1ai"));
+    }
+}
@@ -1,6 +1,6 @@
 use std::{fmt::Display, str::FromStr};
 
-use super::DomainError;
+use super::{label::Label, DomainError};
 
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub struct SubDomain(String);
@@ -12,24 +12,9 @@ impl Display for SubDomain {
 }
 
 impl SubDomain {
-    pub fn is_valid(subdomain: &str) -> bool {
-        if subdomain.is_empty() {
-            return false;
-        }
-
-        if subdomain.starts_with('.') || subdomain.ends_with('.') {
-            return false;
-        }
-
-        if subdomain.starts_with('-') || subdomain.ends_with('-') {
-            return false;
-        }
-
-        subdomain.chars().all(Self::is_valid_char)
-    }
-
-    fn is_valid_char(char: char) -> bool {
-        matches!(char, 'a'..='z' | '0'..='9' | '.' | '-')
+    /// One or more labels in front of the name, `staging.api` in `staging.api.example.com`.
+    pub fn validate(subdomain: &str) -> Result<(), DomainError> {
+        Label::validate_all(subdomain)
     }
 }
 
@@ -37,11 +22,9 @@ impl FromStr for SubDomain {
     type Err = DomainError;
 
     fn from_str(value: &str) -> Result<Self, Self::Err> {
-        let value = value.to_lowercase();
+        let value = super::to_ascii(value).ok_or(Self::Err::InvalidSubdomain)?;
 
-        if !Self::is_valid(&value) {
-            return Err(Self::Err::InvalidSubdomain);
-        }
+        Self::validate(&value)?;
 
         Ok(Self(value))
     }
@@ -67,6 +50,9 @@ mod test {
             ("multiple.part.", false),
             ("", false),
             ("'", false),
+            ("multiple..parts", false),
+            ("multiple.-parts", false),
+            ("multiple-.parts", false),
         ];
 
         for (value, expected) in subdomains {
@@ -1,6 +1,6 @@
 use std::{fmt::Display, str::FromStr};
 
-use super::DomainError;
+use super::{label::Label, DomainError, PublicSuffixList};
 
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub struct Tld(String);
@@ -12,24 +12,26 @@ impl Display for Tld {
 }
 
 impl Tld {
-    pub fn is_valid<S: AsRef<str>>(value: S) -> bool {
-        fn inner(value: &str) -> bool {
-            if value.is_empty() {
-                return false;
-            }
-
-            if value.starts_with('.') || value.ends_with('.') {
-                return false;
-            }
-
-            value.chars().all(Tld::is_valid_char)
+    /// Besides the LDH rules, the top-level label only has hyphens as an A-label and
+    /// can not be all numeric (RFC 3696 section 2). Labels below it, like the one of
+    /// `experts-comptables.fr`, are left to the Public Suffix List.
+    pub fn validate(value: &str) -> Result<(), DomainError> {
+        Label::validate_all(value)?;
+
+        let top = value.rsplit('.').next().unwrap_or(value);
+
+        if !top.starts_with("xn--") && top.contains('-') {
+            return Err(DomainError::InvalidCharacter {
+                label: top.to_string(),
+                char: '-',
+            });
         }
 
-        inner(value.as_ref())
-    }
+        if top.chars().all(|c| c.is_ascii_digit()) {
+            return Err(DomainError::NumericTld(top.to_string()));
+        }
 
-    fn is_valid_char(char: char) -> bool {
-        matches!(char,  'a'..='z' | '0'..='9'| '.')
+        Ok(())
     }
 }
 
@@ -37,18 +39,20 @@ impl FromStr for Tld {
     type Err = DomainError;
 
     fn from_str(value: &str) -> Result<Self, Self::Err> {
-        let value = value.to_lowercase();
-
         if value.is_empty() {
             return Err(Self::Err::MissingTld);
         }
 
+        let value = super::to_ascii(value).ok_or(Self::Err::InvalidTld)?;
+
         if value.len() < 2 {
             return Err(Self::Err::TldTooShort);
         }
 
-        if !Self::is_valid(value.as_str()) {
-            return Err(Self::Err::InvalidTld);
+        Self::validate(&value)?;
+
+        if !PublicSuffixList::current().is_suffix(&value) {
+            return Err(Self::Err::UnknownSuffix(value));
         }
 
         Ok(Self(value))
@@ -66,15 +70,27 @@ mod test {
             ("com.", false),
             (".com", false),
             (".net.", false),
-            ("com.ca", true),
+            ("com.ca", false),
+            ("co.uk", true),
+            ("bar.baz", false),
             ("com.ca.", false),
             ("com-ca", false),
+            ("experts-comptables.fr", true),
+            ("accident-investigation.aero", true),
+            ("us-east-1.amazonaws.com", true),
+            ("fr.experts-comptables", false),
             ("com ca", false),
             ("COM", true),
             ("a", false),
             ("1", false),
             ("!net#$@", false),
             ("", false),
+            ("рф", true),
+            ("xn--p1ai", true),
+            ("xn--", false),
+            ("123", false),
+            ("com.123", false),
+            ("com..mx", false),
         ];
 
         for (value, expected) in tlds {
@@ -95,7 +111,7 @@ mod test {
             ("NET", "net"),
             ("Net", "net"),
             ("nET", "net"),
-            ("EDU.cA", "edu.ca"),
+            ("CO.uK", "co.uk"),
         ];
 
         for (value, expected) in tlds {
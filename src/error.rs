@@ -0,0 +1,325 @@
+use std::{
+    error::Error,
+    fmt::Display,
+    io,
+    path::{Path, PathBuf},
+    process::{Child, Command, ExitStatus, Output, Stdio},
+};
+
+use crate::{
+    apache::{config_file::ConfigError as ApacheConfigError, ApacheError},
+    audit::AuditError,
+    backup::BackupError,
+    caddy::{config_file::ConfigError as CaddyConfigError, CaddyError},
+    certer::CertBotError,
+    dns::{DnsCheckFailed, DnsError},
+    doctor::DoctorError,
+    domain::{DomainError, PublicSuffixError},
+    haproxy::{config_file::ConfigError as HaProxyConfigError, HaProxyError},
+    lighttpd::{config_file::ConfigError as LighttpdConfigError, LighttpdError},
+    listen::ListenError,
+    lock::LockError,
+    manifest::ManifestError,
+    nginx::{config_file::ConfigError as NginxConfigError, upgrade::UpgradeError, NginxError},
+    preflight::PreflightFailed,
+    reachability::ReachabilityError,
+    region::RegionError,
+    responder::ResponderError,
+    settings::SettingsError,
+    webroot::WebRootError,
+};
+
+/// Every failure qicert reports. The module error says what went wrong, the variant
+/// keeps what it went wrong with: the path, the command line or the source error.
+#[derive(Debug)]
+pub enum QicertError {
+    Failed(Box<dyn Error + Send + Sync>),
+    Io {
+        error: Box<dyn Error + Send + Sync>,
+        path: PathBuf,
+        source: io::Error,
+    },
+    Command {
+        error: Box<dyn Error + Send + Sync>,
+        command: String,
+        source: io::Error,
+    },
+    Exit {
+        error: Box<dyn Error + Send + Sync>,
+        command: String,
+        status: ExitStatus,
+        stderr: String,
+    },
+    /// Provisioning failed and putting the configuration back failed too.
+    Rollback {
+        cause: Box<QicertError>,
+        rollback: Box<QicertError>,
+    },
+}
+
+impl QicertError {
+    pub fn failed<E: Error + Send + Sync + 'static>(error: E) -> Self {
+        Self::Failed(Box::new(error))
+    }
+
+    pub fn io<E: Error + Send + Sync + 'static, P: AsRef<Path>>(
+        error: E,
+        path: P,
+        source: io::Error,
+    ) -> Self {
+        Self::Io {
+            error: Box::new(error),
+            path: path.as_ref().to_path_buf(),
+            source,
+        }
+    }
+
+    /// `command` could not be run, or reported its failure through `source`.
+    pub fn command<E: Error + Send + Sync + 'static, C: Into<String>>(
+        error: E,
+        command: C,
+        source: io::Error,
+    ) -> Self {
+        Self::Command {
+            error: Box::new(error),
+            command: command.into(),
+            source,
+        }
+    }
+
+    pub fn rollback(cause: QicertError, rollback: QicertError) -> Self {
+        Self::Rollback {
+            cause: Box::new(cause),
+            rollback: Box::new(rollback),
+        }
+    }
+
+    /// The module error describing the failure, the error itself for a failed rollback.
+    pub fn kind(&self) -> &(dyn Error + 'static) {
+        match self {
+            Self::Failed(error)
+            | Self::Io { error, .. }
+            | Self::Command { error, .. }
+            | Self::Exit { error, .. } => error.as_ref(),
+            Self::Rollback { .. } => self,
+        }
+    }
+
+    pub fn downcast_ref<T: Error + 'static>(&self) -> Option<&T> {
+        self.kind().downcast_ref()
+    }
+
+    /// The message followed by every error that led to it, one per line.
+    pub fn chain(&self) -> String {
+        let mut chain = self.to_string();
+
+        let mut source = self.source();
+
+        while let Some(error) = source {
+            chain.push_str(&format!("\n  caused by: {error}"));
+            source = error.source();
+        }
+
+        chain
+    }
+}
+
+impl Display for QicertError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Failed(error) => write!(f, "{error}"),
+            Self::Io { error, path, .. } => write!(f, "{error} ({})", path.display()),
+            Self::Command { error, command, .. } => write!(f, "{error} (`{command}`)"),
+            Self::Exit {
+                error,
+                command,
+                status,
+                stderr,
+            } => {
+                write!(f, "{error} (`{command}` {status})")?;
+
+                for line in stderr.lines().filter(|l| !l.trim().is_empty()) {
+                    write!(f, "\n    {}", line.trim_end())?;
+                }
+
+                Ok(())
+            }
+            Self::Rollback { cause, .. } => write!(
+                f,
+                "{cause}, and restoring the previous configuration failed"
+            ),
+        }
+    }
+}
+
+impl Error for QicertError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::Failed(error) => error.source(),
+            Self::Io { source, .. } | Self::Command { source, .. } => Some(source),
+            Self::Exit { .. } => None,
+            Self::Rollback { rollback, .. } => Some(rollback.as_ref()),
+        }
+    }
+}
+
+macro_rules! from_module_errors {
+    ($($error:ty),* $(,)?) => {
+        $(
+            impl From<$error> for QicertError {
+                fn from(error: $error) -> Self {
+                    Self::failed(error)
+                }
+            }
+        )*
+    };
+}
+
+from_module_errors!(
+    ApacheConfigError,
+    ApacheError,
+    AuditError,
+    BackupError,
+    CaddyConfigError,
+    CaddyError,
+    CertBotError,
+    DnsCheckFailed,
+    DnsError,
+    DoctorError,
+    DomainError,
+    HaProxyConfigError,
+    HaProxyError,
+    LighttpdConfigError,
+    LighttpdError,
+    ListenError,
+    LockError,
+    ManifestError,
+    NginxConfigError,
+    NginxError,
+    PreflightFailed,
+    PublicSuffixError,
+    ReachabilityError,
+    RegionError,
+    ResponderError,
+    SettingsError,
+    UpgradeError,
+    WebRootError,
+);
+
+fn command_line(command: &Command) -> String {
+    std::iter::once(command.get_program())
+        .chain(command.get_args())
+        .map(|part| part.to_string_lossy())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Runs `command` to completion with its stderr captured, so a failure can show it.
+/// `error` says what the failure means to the caller.
+pub fn run_command<E: Error + Send + Sync + 'static>(
+    command: &mut Command,
+    error: E,
+) -> Result<Output, QicertError> {
+    let output = command
+        .stderr(Stdio::piped())
+        .spawn()
+        .and_then(Child::wait_with_output);
+
+    let output = match output {
+        Ok(output) => output,
+        Err(source) => return Err(QicertError::command(error, command_line(command), source)),
+    };
+
+    if !output.status.success() {
+        return Err(QicertError::Exit {
+            error: Box::new(error),
+            command: command_line(command),
+            status: output.status,
+            stderr: String::from_utf8_lossy(&output.stderr).into_owned(),
+        });
+    }
+
+    Ok(output)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn errors_can_move_across_threads() {
+        let error = QicertError::failed(NginxError::CannotReload);
+
+        let message = std::thread::spawn(move || error.to_string())
+            .join()
+            .unwrap();
+
+        assert_eq!(message, NginxError::CannotReload.to_string());
+    }
+
+    #[test]
+    fn io_error_keeps_path_and_source() {
+        let path = Path::new("/nonexistent/qicert/example.com.conf");
+
+        let source = std::fs::read_to_string(path).unwrap_err();
+
+        let error = QicertError::io(NginxConfigError::FileSaving, path, source);
+
+        assert_eq!(
+            error.to_string(),
+            "Configuration file could not be written to disk (/nonexistent/qicert/example.com.conf)"
+        );
+
+        assert!(error
+            .chain()
+            .contains("\n  caused by: No such file or directory"));
+
+        assert!(matches!(
+            error.downcast_ref::<NginxConfigError>(),
+            Some(NginxConfigError::FileSaving)
+        ));
+    }
+
+    #[test]
+    fn failed_command_shows_command_line_and_stderr() {
+        let mut command = Command::new("sh");
+        command.arg("-c").arg("echo 'bad directive' >&2; exit 3");
+
+        let error = run_command(&mut command, NginxError::BadConfiguration).unwrap_err();
+
+        let message = error.to_string();
+
+        assert!(message.starts_with("Nginx -t failed. Bad Configuration. (`sh -c echo"));
+        assert!(message.contains("exit status: 3)"));
+        assert!(message.ends_with("\n    bad directive"));
+    }
+
+    #[test]
+    fn missing_command_keeps_the_io_error() {
+        let mut command = Command::new("/nonexistent/qicert-binary");
+
+        let error = run_command(&mut command, CertBotError::ProcessFailure).unwrap_err();
+
+        assert!(matches!(error, QicertError::Command { .. }));
+        assert_eq!(error.chain().lines().count(), 2);
+    }
+
+    #[test]
+    fn rollback_chains_both_failures() {
+        let cause = QicertError::from(CertBotError::ProcessFailure);
+        let rollback = QicertError::io(
+            NginxConfigError::FileSaving,
+            "/etc/nginx/sites-available/example.com.conf",
+            io::Error::other("read-only file system"),
+        );
+
+        let error = QicertError::rollback(cause, rollback);
+
+        assert_eq!(
+            error.chain(),
+            "Certbot failed to create certificate, and restoring the previous configuration failed
+  caused by: Configuration file could not be written to disk (/etc/nginx/sites-available/example.com.conf)
+  caused by: read-only file system"
+        );
+    }
+}
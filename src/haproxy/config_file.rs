@@ -0,0 +1,312 @@
+use std::{
+    error::Error,
+    fmt::Display,
+    fs::{self, File},
+    io::Write,
+    os::unix::fs::OpenOptionsExt,
+    path::PathBuf,
+};
+
+use crate::{
+    backup::{Backup, Operation},
+    configuration_file::ConfigurationFile,
+    domain::Domain,
+    error::QicertError,
+    layout::Server,
+};
+
+use super::http_config::HttpConfig;
+
+#[derive(Debug)]
+pub enum ConfigError {
+    FileSaving,
+    InvalidPath,
+    CertificateBundle,
+    CrtList,
+}
+
+impl Display for ConfigError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::FileSaving => {
+                write!(f, "HAProxy configuration file could not be written to disk")
+            }
+            Self::InvalidPath => write!(
+                f,
+                "An invalid path was given for a HAProxy configuration file"
+            ),
+            Self::CertificateBundle => write!(f, "The HAProxy PEM bundle could not be written"),
+            Self::CrtList => write!(f, "The HAProxy crt-list could not be updated"),
+        }
+    }
+}
+
+impl Error for ConfigError {}
+
+pub struct ConfigFile;
+
+/// HAProxy cannot split a frontend across files, so every hostname shares one managed
+/// file, and the hostnames themselves live in the crt-list.
+impl ConfigurationFile for ConfigFile {
+    const SERVER: Server = Server::Haproxy;
+
+    fn file_name(_domain: &Domain) -> String {
+        String::from(Self::FILE_NAME)
+    }
+
+    fn server_name(domain: &Domain) -> String {
+        format!("/{domain}.pem {domain}")
+    }
+}
+
+impl ConfigFile {
+    const MAIN_CONFIG: &str = "/etc/haproxy/haproxy.cfg";
+    const QICERT_DIR: &str = "/etc/haproxy/qicert";
+    const FILE_NAME: &str = "qicert.cfg";
+
+    /// The managed file every hostname shares.
+    pub fn shared_path() -> PathBuf {
+        Self::sites_enabled_path().join(Self::FILE_NAME)
+    }
+
+    pub fn main_config_path() -> &'static str {
+        Self::MAIN_CONFIG
+    }
+
+    pub fn crt_list_path() -> PathBuf {
+        PathBuf::from(Self::QICERT_DIR).join("crt-list")
+    }
+
+    pub fn certs_path() -> PathBuf {
+        PathBuf::from(Self::QICERT_DIR).join("certs")
+    }
+
+    pub fn pem_path(domain: &Domain) -> PathBuf {
+        Self::certs_path().join(format!("{domain}.pem"))
+    }
+
+    pub fn hostnames_in_crt_list<S: AsRef<str>>(crt_list: S) -> Vec<String> {
+        Self::crt_list_entries(crt_list)
+            .into_iter()
+            .map(|(_, hostname)| hostname)
+            .collect()
+    }
+
+    /// Bundle and hostname of each crt-list line.
+    pub fn crt_list_entries<S: AsRef<str>>(crt_list: S) -> Vec<(PathBuf, String)> {
+        crt_list
+            .as_ref()
+            .lines()
+            .map(str::trim)
+            .filter(|l| !l.is_empty() && !l.starts_with('#'))
+            .filter_map(|l| {
+                let mut words = l.split_whitespace();
+
+                Some((PathBuf::from(words.next()?), words.next()?.to_string()))
+            })
+            .collect()
+    }
+
+    pub fn read_crt_list() -> String {
+        fs::read_to_string(Self::crt_list_path()).unwrap_or_default()
+    }
+
+    pub fn is_in_crt_list(domain: &Domain) -> bool {
+        Self::find_domain_in_str(Self::read_crt_list(), domain)
+    }
+
+    /// HAProxy wants the chain and the key in a single file.
+    pub fn bundle_pem(domain: &Domain) -> Result<(), QicertError> {
+        let live = PathBuf::from("/etc/letsencrypt/live").join(domain.to_string());
+        let bundle_err =
+            |path: &PathBuf, e| QicertError::io(ConfigError::CertificateBundle, path, e);
+
+        let fullchain_path = live.join("fullchain.pem");
+        let fullchain = fs::read(&fullchain_path).map_err(|e| bundle_err(&fullchain_path, e))?;
+
+        let privkey_path = live.join("privkey.pem");
+        let privkey = fs::read(&privkey_path).map_err(|e| bundle_err(&privkey_path, e))?;
+
+        let certs_path = Self::certs_path();
+        fs::create_dir_all(&certs_path).map_err(|e| bundle_err(&certs_path, e))?;
+
+        let pem_path = Self::pem_path(domain);
+
+        let mut bundle = fs::OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(true)
+            .mode(0o600)
+            .open(&pem_path)
+            .map_err(|e| bundle_err(&pem_path, e))?;
+
+        bundle
+            .write_all(&fullchain)
+            .and_then(|_| bundle.write_all(&privkey))
+            .map_err(|e| bundle_err(&pem_path, e))?;
+
+        Ok(())
+    }
+
+    pub fn add_to_crt_list(domain: &Domain) -> Result<(), QicertError> {
+        if Self::is_in_crt_list(domain) {
+            return Ok(());
+        }
+
+        let pem_path = Self::pem_path(domain);
+        let entry = HttpConfig::crt_list_entry(&pem_path.to_string_lossy(), domain);
+
+        fs::create_dir_all(Self::QICERT_DIR)
+            .map_err(|e| QicertError::io(ConfigError::CrtList, Self::QICERT_DIR, e))?;
+
+        let crt_list_path = Self::crt_list_path();
+
+        let mut crt_list = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&crt_list_path)
+            .map_err(|e| QicertError::io(ConfigError::CrtList, &crt_list_path, e))?;
+
+        writeln!(crt_list, "{entry}")
+            .map_err(|e| QicertError::io(ConfigError::CrtList, &crt_list_path, e))?;
+
+        Ok(())
+    }
+
+    /// Drops the hostname from the crt-list, its bundle stays.
+    pub fn remove_from_crt_list(domain: &Domain) -> Result<(), QicertError> {
+        let crt_list = Self::read_crt_list();
+
+        let remaining: String = crt_list
+            .lines()
+            .filter(|l| !Self::find_domain_in_str(l, domain))
+            .map(|l| format!("{l}\n"))
+            .collect();
+
+        let crt_list_path = Self::crt_list_path();
+
+        fs::write(&crt_list_path, remaining)
+            .map_err(|e| QicertError::io(ConfigError::CrtList, crt_list_path, e))?;
+
+        Ok(())
+    }
+
+    pub fn remove_bundle(domain: &Domain) -> Result<(), QicertError> {
+        let pem_path = Self::pem_path(domain);
+
+        match fs::remove_file(&pem_path) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                Err(QicertError::io(ConfigError::CertificateBundle, pem_path, e))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Regenerates the managed file from the hostnames currently in the crt-list.
+    pub fn write(domain: &Domain, operation: Operation) -> Result<(), QicertError> {
+        if Self::file_exists(domain) {
+            Self::create_backup(domain, operation)?;
+        }
+
+        let sites_enabled = Self::sites_enabled_path();
+
+        fs::create_dir_all(&sites_enabled)
+            .map_err(|e| QicertError::io(ConfigError::InvalidPath, sites_enabled, e))?;
+
+        let hostnames = Self::hostnames_in_crt_list(Self::read_crt_list());
+        let crt_list = Self::crt_list_path();
+
+        let content = HttpConfig::content(&hostnames, &crt_list.to_string_lossy());
+
+        let file_path = Self::file_path(domain);
+
+        File::create(&file_path)
+            .and_then(|mut file| file.write_all(content.as_bytes()))
+            .map_err(|e| QicertError::io(ConfigError::FileSaving, file_path, e))?;
+
+        Ok(())
+    }
+
+    pub fn create_backup(domain: &Domain, operation: Operation) -> Result<Backup, QicertError> {
+        Self::_create_backup(domain, operation)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn find_domain_in_crt_list() {
+        let domains = vec![
+            (Domain::new("example", "com", None), true),
+            (Domain::new("example", "com", Some("www")), false),
+            (Domain::new("Example", "com", None), true),
+            (Domain::new("www", "example", None), false),
+        ];
+
+        let haystack = "/etc/haproxy/qicert/certs/example.com.pem example.com
+        /etc/haproxy/qicert/certs/test.example.com.pem test.example.com";
+
+        for (domain, expected) in domains {
+            if let Ok(domain) = domain {
+                assert_eq!(
+                    ConfigFile::find_domain_in_str(haystack, &domain),
+                    expected,
+                    "domain: {domain}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn bundles_from_crt_list() {
+        let crt_list = "# managed by qicert
+/etc/haproxy/qicert/certs/www.example.com.pem www.example.com
+
+/etc/haproxy/qicert/certs/broken.pem
+";
+
+        assert_eq!(
+            ConfigFile::crt_list_entries(crt_list),
+            vec![(
+                PathBuf::from("/etc/haproxy/qicert/certs/www.example.com.pem"),
+                String::from("www.example.com")
+            )]
+        );
+    }
+
+    #[test]
+    fn hostnames_from_crt_list() {
+        let crt_list = "# managed by qicert
+/etc/haproxy/qicert/certs/example.com.pem example.com
+
+/etc/haproxy/qicert/certs/www.example.com.pem www.example.com
+";
+
+        assert_eq!(
+            ConfigFile::hostnames_in_crt_list(crt_list),
+            vec!["example.com", "www.example.com"]
+        );
+    }
+
+    #[test]
+    fn config_file_path_is_shared() {
+        let domain = Domain::new_unchecked("example", "com", Some("www"));
+        let other = Domain::new_unchecked("example", "net", None);
+
+        let expected = PathBuf::from("/etc/haproxy/conf.d/qicert.cfg");
+
+        assert_eq!(ConfigFile::file_path(&domain), expected);
+        assert_eq!(ConfigFile::file_path(&other), expected);
+    }
+
+    #[test]
+    fn pem_path() {
+        let domain = Domain::new_unchecked("example", "com", Some("www"));
+
+        let expected = PathBuf::from("/etc/haproxy/qicert/certs/www.example.com.pem");
+
+        assert_eq!(ConfigFile::pem_path(&domain), expected);
+    }
+}
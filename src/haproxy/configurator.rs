@@ -0,0 +1,225 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    audit::Audit,
+    backup::{BackupError, Backups, Operation},
+    certer::{CertBotError, Certer},
+    configuration_file::ConfigurationFile,
+    domain::Domain,
+    error::QicertError,
+    report::{Action, Report},
+    webserver::WebServer,
+};
+
+use super::{config_file::ConfigFile, http_config::HttpConfig, HaProxy, HaProxyError};
+
+pub struct Configurator;
+
+impl Configurator {
+    fn responder_port() -> u16 {
+        HttpConfig::ACME_RESPONDER
+            .rsplit(':')
+            .next()
+            .and_then(|p| p.parse().ok())
+            .unwrap_or(8402)
+    }
+
+    fn write(domain: &Domain, operation: Operation) -> Result<(), QicertError> {
+        let written = ConfigFile::write(domain, operation);
+
+        Report::step(
+            Action::WriteConfig,
+            Some(&ConfigFile::file_path(domain)),
+            written,
+        )?;
+
+        Ok(())
+    }
+
+    fn check_and_reload() -> Result<(), QicertError> {
+        let reloaded = HaProxy::check_and_reload();
+
+        Audit::reload(&reloaded);
+
+        Report::step(Action::Reload, None, reloaded)
+    }
+
+    fn issue(domain: &Domain) -> Result<(), QicertError> {
+        Self::write(domain, Operation::Provision)?;
+        Self::check_and_reload()?;
+
+        let issued = Certer::run_standalone(
+            domain,
+            Self::responder_port(),
+            &HaProxy::deploy_hook(domain),
+        );
+
+        Audit::certificate(&issued);
+
+        Report::step(Action::Certificate, None, issued)?;
+
+        ConfigFile::bundle_pem(domain)?;
+
+        let listed = ConfigFile::add_to_crt_list(domain);
+
+        Report::step(Action::CrtList, Some(&ConfigFile::crt_list_path()), listed)?;
+
+        Self::write(domain, Operation::Provision)?;
+        Self::check_and_reload()?;
+
+        Ok(())
+    }
+
+    fn ensure_haproxy_and_certbot_installed() -> Result<(), QicertError> {
+        if !HaProxy::is_installed() {
+            return Err(HaProxyError::NotInstalled)?;
+        }
+
+        if !Certer::is_installed() {
+            return Err(CertBotError::NotInstalled)?;
+        }
+
+        Ok(())
+    }
+
+    /// Files whose changes go to the audit log.
+    fn audited_files(domain: &Domain) -> Vec<PathBuf> {
+        vec![ConfigFile::file_path(domain), ConfigFile::crt_list_path()]
+    }
+
+    pub fn append_or_create(domain: &Domain) -> Result<(), QicertError> {
+        Audit::run(
+            Operation::Provision,
+            HaProxy::SERVER,
+            domain,
+            &Self::audited_files(domain),
+            || Self::_append_or_create(domain),
+        )
+    }
+
+    pub fn remove(domain: &Domain) -> Result<(), QicertError> {
+        Audit::run(
+            Operation::Remove,
+            HaProxy::SERVER,
+            domain,
+            &Self::audited_files(domain),
+            || Self::_remove(domain),
+        )
+    }
+
+    /// Copies `file` into the backup history, if there is one yet, and hands back what
+    /// it holds. The writes of the run take backups of their own and may prune this one.
+    fn backup(file: &Path, domain: &Domain) -> Result<Option<Vec<u8>>, QicertError> {
+        if !file.exists() {
+            return Ok(None);
+        }
+
+        let content =
+            fs::read(file).map_err(|e| QicertError::io(BackupError::NotSaved, file, e))?;
+        let saved = Backups::current().save(file, HaProxy::SERVER, domain, Operation::Provision);
+
+        Report::step(
+            Action::Backup,
+            Some(&Backups::current().dir_for(file)),
+            saved,
+        )?;
+
+        Ok(Some(content))
+    }
+
+    fn _append_or_create(domain: &Domain) -> Result<(), QicertError> {
+        Self::ensure_haproxy_and_certbot_installed()?;
+
+        if ConfigFile::is_in_crt_list(domain) {
+            Report::skipped(Action::CrtList, Some(&ConfigFile::crt_list_path()));
+            Report::say(format!(
+                "{} is already in the crt-list. Skipping",
+                domain.unicode()
+            ));
+
+            return Ok(());
+        }
+
+        let config = Self::backup(&ConfigFile::file_path(domain), domain)?;
+        let crt_list = Self::backup(&ConfigFile::crt_list_path(), domain)?;
+
+        Self::issue(domain).or_else(|cause| Self::rollback(domain, &config, &crt_list, cause))
+    }
+
+    /// Puts the managed file and the crt-list back the way they were before a failed
+    /// run, and drops the bundle it wrote.
+    fn rollback(
+        domain: &Domain,
+        config: &Option<Vec<u8>>,
+        crt_list: &Option<Vec<u8>>,
+        cause: QicertError,
+    ) -> Result<(), QicertError> {
+        let config_path = ConfigFile::file_path(domain);
+        let crt_list_path = ConfigFile::crt_list_path();
+
+        let restored = Self::restore(&config_path, config.as_deref())
+            .and_then(|_| Self::restore(&crt_list_path, crt_list.as_deref()))
+            .and_then(|_| ConfigFile::remove_bundle(domain));
+
+        match Report::step(Action::Rollback, Some(&config_path), restored) {
+            Ok(_) => Err(cause),
+            Err(e) => Err(QicertError::rollback(cause, e)),
+        }
+    }
+
+    /// Writes back what `file` held, or empties a file the run created.
+    fn restore(file: &Path, content: Option<&[u8]>) -> Result<(), QicertError> {
+        let restored = match content {
+            Some(content) => fs::write(file, content),
+            None if file.exists() => fs::write(file, ""),
+            None => Ok(()),
+        };
+
+        restored.map_err(|e| QicertError::io(BackupError::NotRestored, file, e))
+    }
+
+    fn _remove(domain: &Domain) -> Result<(), QicertError> {
+        ConfigFile::remove_from_crt_list(domain)?;
+        ConfigFile::remove_bundle(domain)?;
+
+        Self::write(domain, Operation::Remove)?;
+        Self::check_and_reload()?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_support::scratch;
+
+    #[test]
+    fn restore_puts_back_the_content_or_empties_the_file() {
+        let dir = scratch("haproxy-restore");
+
+        let crt_list = dir.join("crt-list");
+        let before = "/etc/haproxy/qicert/certs/example.org.pem example.org\n";
+
+        fs::write(&crt_list, "changed by the run\n").unwrap();
+
+        Configurator::restore(&crt_list, Some(before.as_bytes())).unwrap();
+
+        assert_eq!(fs::read_to_string(&crt_list).unwrap(), before);
+
+        let created = dir.join("qicert.cfg");
+
+        fs::write(&created, "frontend qicert\n").unwrap();
+
+        Configurator::restore(&created, None).unwrap();
+
+        assert_eq!(fs::read_to_string(&created).unwrap(), "");
+
+        Configurator::restore(&dir.join("missing"), None).unwrap();
+
+        assert!(!dir.join("missing").exists());
+    }
+}
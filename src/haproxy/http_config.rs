@@ -0,0 +1,178 @@
+use crate::domain::Domain;
+
+pub struct HttpConfig;
+
+impl HttpConfig {
+    pub const ACME_RESPONDER: &'static str = "127.0.0.1:8402";
+    pub const UPSTREAM: &'static str = "127.0.0.1:8080";
+
+    /// Port 80 frontend: ACME challenges go to the local responder, every host with a
+    /// certificate gets redirected to https.
+    pub fn http_frontend(hostnames: &[String]) -> String {
+        let mut frontend = String::from(
+            "frontend qicert_http
+    bind :80
+    mode http
+    acl qicert_acme path_beg /.well-known/acme-challenge/
+    use_backend qicert_acme if qicert_acme",
+        );
+
+        if !hostnames.is_empty() {
+            let hosts = hostnames.join(" ");
+
+            frontend.push_str(&format!(
+                "
+    acl qicert_tls hdr(host),field(1,:) -i {hosts}
+    http-request redirect scheme https code 301 if qicert_tls !qicert_acme"
+            ));
+        }
+
+        frontend
+    }
+
+    pub fn https_frontend(crt_list: &str) -> String {
+        format!(
+            "frontend qicert_https
+    bind :443 ssl crt-list {crt_list}
+    mode http
+    default_backend qicert_upstream"
+        )
+    }
+
+    pub fn acme_backend() -> String {
+        format!(
+            "backend qicert_acme
+    mode http
+    server responder {}",
+            Self::ACME_RESPONDER
+        )
+    }
+
+    pub fn upstream_backend() -> String {
+        format!(
+            "backend qicert_upstream
+    mode http
+    server local {}",
+            Self::UPSTREAM
+        )
+    }
+
+    /// Whole managed file. The https frontend is left out until there is at least one
+    /// certificate in the crt-list, HAProxy refuses to start with an empty one.
+    pub fn content(hostnames: &[String], crt_list: &str) -> String {
+        let mut sections = vec![Self::http_frontend(hostnames)];
+
+        if !hostnames.is_empty() {
+            sections.push(Self::https_frontend(crt_list));
+        }
+
+        sections.push(Self::acme_backend());
+        sections.push(Self::upstream_backend());
+
+        sections.join("\n\n") + "\n"
+    }
+
+    pub fn crt_list_entry(pem_path: &str, domain: &Domain) -> String {
+        format!("{pem_path} {domain}")
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn http_frontend_without_certificates() {
+        let expected = "frontend qicert_http
+    bind :80
+    mode http
+    acl qicert_acme path_beg /.well-known/acme-challenge/
+    use_backend qicert_acme if qicert_acme"
+            .to_string();
+
+        assert_eq!(HttpConfig::http_frontend(&[]), expected);
+    }
+
+    #[test]
+    fn http_frontend_with_certificates() {
+        let expected = "frontend qicert_http
+    bind :80
+    mode http
+    acl qicert_acme path_beg /.well-known/acme-challenge/
+    use_backend qicert_acme if qicert_acme
+    acl qicert_tls hdr(host),field(1,:) -i example.com www.example.com
+    http-request redirect scheme https code 301 if qicert_tls !qicert_acme"
+            .to_string();
+
+        let hostnames = vec!["example.com".to_string(), "www.example.com".to_string()];
+
+        assert_eq!(HttpConfig::http_frontend(&hostnames), expected);
+    }
+
+    #[test]
+    fn content_without_certificates_has_no_https_frontend() {
+        let expected = "frontend qicert_http
+    bind :80
+    mode http
+    acl qicert_acme path_beg /.well-known/acme-challenge/
+    use_backend qicert_acme if qicert_acme
+
+backend qicert_acme
+    mode http
+    server responder 127.0.0.1:8402
+
+backend qicert_upstream
+    mode http
+    server local 127.0.0.1:8080
+"
+        .to_string();
+
+        assert_eq!(
+            HttpConfig::content(&[], "/etc/haproxy/qicert/crt-list"),
+            expected
+        );
+    }
+
+    #[test]
+    fn content_with_certificates() {
+        let expected = "frontend qicert_http
+    bind :80
+    mode http
+    acl qicert_acme path_beg /.well-known/acme-challenge/
+    use_backend qicert_acme if qicert_acme
+    acl qicert_tls hdr(host),field(1,:) -i example.com
+    http-request redirect scheme https code 301 if qicert_tls !qicert_acme
+
+frontend qicert_https
+    bind :443 ssl crt-list /etc/haproxy/qicert/crt-list
+    mode http
+    default_backend qicert_upstream
+
+backend qicert_acme
+    mode http
+    server responder 127.0.0.1:8402
+
+backend qicert_upstream
+    mode http
+    server local 127.0.0.1:8080
+"
+        .to_string();
+
+        let hostnames = vec!["example.com".to_string()];
+
+        assert_eq!(
+            HttpConfig::content(&hostnames, "/etc/haproxy/qicert/crt-list"),
+            expected
+        );
+    }
+
+    #[test]
+    fn crt_list_entry() {
+        let domain = Domain::new("example", "com", Some("www")).unwrap();
+
+        assert_eq!(
+            HttpConfig::crt_list_entry("/etc/haproxy/qicert/certs/www.example.com.pem", &domain),
+            "/etc/haproxy/qicert/certs/www.example.com.pem www.example.com"
+        );
+    }
+}
@@ -0,0 +1,100 @@
+pub(crate) mod config_file;
+pub(crate) mod configurator;
+pub mod http_config;
+
+use std::{error::Error, fmt::Display, process::Command};
+
+use crate::{
+    domain::Domain,
+    error::{run_command, QicertError},
+    layout::Server,
+    webserver::WebServer,
+};
+
+use self::config_file::ConfigFile;
+
+#[derive(Debug, Clone, Copy)]
+pub enum HaProxyError {
+    CannotReload,
+    BadConfiguration,
+    NotInstalled,
+}
+
+impl Error for HaProxyError {}
+
+impl Display for HaProxyError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::CannotReload => write!(f, "Could not reload HAProxy."),
+            Self::BadConfiguration => write!(f, "haproxy -c failed. Bad Configuration."),
+            Self::NotInstalled => write!(f, "HAProxy was not found."),
+        }
+    }
+}
+
+pub struct HaProxy;
+
+impl WebServer for HaProxy {
+    const SERVER: Server = Server::Haproxy;
+}
+
+impl HaProxy {
+    /// HAProxy's own reload is graceful: the master starts new workers and lets the old
+    /// ones finish their connections.
+    pub fn reload() -> Result<(), QicertError> {
+        Self::_reload(HaProxyError::CannotReload)
+    }
+
+    pub fn check() -> Result<(), QicertError> {
+        run_command(
+            Command::new(Self::layout().binary())
+                .arg("-c")
+                .arg("-f")
+                .arg(ConfigFile::main_config_path())
+                .arg("-f")
+                .arg(ConfigFile::shared_path()),
+            HaProxyError::BadConfiguration,
+        )?;
+
+        Ok(())
+    }
+
+    pub fn check_and_reload() -> Result<(), QicertError> {
+        if !Self::is_installed() {
+            return Err(HaProxyError::NotInstalled)?;
+        }
+
+        Self::check()?;
+        Self::reload()?;
+
+        Ok(())
+    }
+
+    /// Shell command certbot runs after each renewal so HAProxy gets a fresh bundle.
+    pub fn deploy_hook(domain: &Domain) -> String {
+        let pem = ConfigFile::pem_path(domain);
+
+        format!(
+            "cat $RENEWED_LINEAGE/fullchain.pem $RENEWED_LINEAGE/privkey.pem > {} && {}",
+            pem.to_string_lossy(),
+            Self::reload_command()
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn deploy_hook_rebuilds_bundle() {
+        let domain = Domain::new_unchecked("example", "com", Some("www"));
+
+        let expected = format!(
+            "cat $RENEWED_LINEAGE/fullchain.pem $RENEWED_LINEAGE/privkey.pem > /etc/haproxy/qicert/certs/www.example.com.pem && {}",
+            HaProxy::reload_command()
+        );
+
+        assert_eq!(HaProxy::deploy_hook(&domain), expected);
+    }
+}
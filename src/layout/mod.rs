@@ -0,0 +1,470 @@
+mod os_release;
+
+use std::{
+    cell::RefCell,
+    path::{Path, PathBuf},
+    sync::{Mutex, OnceLock},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::settings::Settings;
+
+pub use os_release::{Family, OsRelease};
+
+/// Web servers qicert knows how to configure.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Server {
+    Apache,
+    Caddy,
+    Haproxy,
+    Lighttpd,
+    Nginx,
+}
+
+/// How a configuration file placed in the config directory becomes active.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum Enable {
+    /// Debian style `*-available` directory with a symlink in the given `*-enabled` one.
+    Symlink(PathBuf),
+    /// A helper such as `a2ensite` taking the file name without `.conf`.
+    Command(String),
+    /// The directory is already included by the main configuration.
+    Included,
+    /// The stock main configuration at the given path includes no directory, qicert
+    /// adds the line including its own when it is missing.
+    Include(PathBuf),
+}
+
+static OVERRIDES: Mutex<Vec<(Server, Layout)>> = Mutex::new(Vec::new());
+
+thread_local! {
+    static RUN_LAYOUTS: RefCell<Vec<(Server, Layout)>> = const { RefCell::new(Vec::new()) };
+}
+
+/// A layout in force on this thread until dropped, see `Layout::for_run`.
+#[derive(Debug)]
+pub(crate) struct RunLayout {
+    server: Server,
+}
+
+impl Drop for RunLayout {
+    fn drop(&mut self) {
+        RUN_LAYOUTS.with_borrow_mut(|layouts| {
+            if let Some(at) = layouts.iter().rposition(|(s, _)| *s == self.server) {
+                layouts.remove(at);
+            }
+        });
+    }
+}
+
+/// Where a web server keeps its configuration on this host and how it is run.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Layout {
+    config_dir: PathBuf,
+    enable: Enable,
+    binary: PathBuf,
+    service: String,
+    web_user: String,
+    pid_file: PathBuf,
+    reload_signal: Option<&'static str>,
+}
+
+impl Layout {
+    pub(crate) const SBIN_DIRS: [&str; 4] =
+        ["/usr/sbin", "/usr/bin", "/usr/local/sbin", "/usr/local/bin"];
+
+    fn new(config_dir: &str, enable: Enable, binary: &str, service: &str, web_user: &str) -> Self {
+        Self {
+            config_dir: PathBuf::from(config_dir),
+            enable,
+            binary: PathBuf::from(binary),
+            service: service.to_string(),
+            web_user: web_user.to_string(),
+            pid_file: PathBuf::new(),
+            reload_signal: None,
+        }
+    }
+
+    /// PID file of the master process and the signal that makes it reload gracefully.
+    /// Caddy 2 ignores signals for reloading, it has to go through its API.
+    fn master(family: Family, server: Server) -> (&'static str, Option<&'static str>) {
+        match (server, family) {
+            (Server::Nginx, Family::Alpine) => ("/run/nginx/nginx.pid", Some("HUP")),
+            (Server::Nginx, _) => ("/run/nginx.pid", Some("HUP")),
+            (Server::Apache, Family::Debian) => ("/run/apache2/apache2.pid", Some("USR1")),
+            (Server::Apache, Family::Alpine) => ("/run/apache2/httpd.pid", Some("USR1")),
+            (Server::Apache, _) => ("/run/httpd/httpd.pid", Some("USR1")),
+            (Server::Caddy, _) => ("/run/caddy/caddy.pid", None),
+            (Server::Haproxy, _) => ("/run/haproxy.pid", Some("USR2")),
+            (Server::Lighttpd, _) => ("/run/lighttpd.pid", Some("USR1")),
+        }
+    }
+
+    fn symlink(dir: &str) -> Enable {
+        Enable::Symlink(PathBuf::from(dir))
+    }
+
+    fn include(main: &str) -> Enable {
+        Enable::Include(PathBuf::from(main))
+    }
+
+    /// Packaging defaults for each distribution family.
+    pub fn defaults(family: Family, server: Server) -> Self {
+        let (pid_file, reload_signal) = Self::master(family, server);
+
+        Self {
+            pid_file: PathBuf::from(pid_file),
+            reload_signal,
+            ..Self::packaging(family, server)
+        }
+    }
+
+    fn packaging(family: Family, server: Server) -> Self {
+        use Family::*;
+        use Server::*;
+
+        match (server, family) {
+            (Nginx, Debian) => Self::new(
+                "/etc/nginx/sites-available",
+                Self::symlink("/etc/nginx/sites-enabled"),
+                "/usr/sbin/nginx",
+                "nginx",
+                "www-data",
+            ),
+            (Nginx, RedHat) => Self::new(
+                "/etc/nginx/conf.d",
+                Enable::Included,
+                "/usr/sbin/nginx",
+                "nginx",
+                "nginx",
+            ),
+            (Nginx, Alpine) => Self::new(
+                "/etc/nginx/http.d",
+                Enable::Included,
+                "/usr/sbin/nginx",
+                "nginx",
+                "nginx",
+            ),
+            (Nginx, Arch) => Self::new(
+                "/etc/nginx/qicert.d",
+                Self::include("/etc/nginx/nginx.conf"),
+                "/usr/bin/nginx",
+                "nginx",
+                "http",
+            ),
+            (Apache, Debian) => Self::new(
+                "/etc/apache2/sites-available",
+                Enable::Command(String::from("a2ensite")),
+                "/usr/sbin/apache2",
+                "apache2",
+                "www-data",
+            ),
+            (Apache, RedHat) => Self::new(
+                "/etc/httpd/conf.d",
+                Enable::Included,
+                "/usr/sbin/httpd",
+                "httpd",
+                "apache",
+            ),
+            (Apache, Alpine) => Self::new(
+                "/etc/apache2/conf.d",
+                Enable::Included,
+                "/usr/sbin/httpd",
+                "apache2",
+                "apache",
+            ),
+            (Apache, Arch) => Self::new(
+                "/etc/httpd/conf/qicert.d",
+                Self::include("/etc/httpd/conf/httpd.conf"),
+                "/usr/bin/httpd",
+                "httpd",
+                "http",
+            ),
+            (Caddy, Alpine) => Self::new(
+                "/etc/caddy/qicert.d",
+                Enable::Included,
+                "/usr/sbin/caddy",
+                "caddy",
+                "caddy",
+            ),
+            (Caddy, _) => Self::new(
+                "/etc/caddy/qicert.d",
+                Enable::Included,
+                "/usr/bin/caddy",
+                "caddy",
+                "caddy",
+            ),
+            (Haproxy, _) => Self::new(
+                "/etc/haproxy/conf.d",
+                Enable::Included,
+                "/usr/sbin/haproxy",
+                "haproxy",
+                "haproxy",
+            ),
+            (Lighttpd, Debian) => Self::new(
+                "/etc/lighttpd/conf-available",
+                Self::symlink("/etc/lighttpd/conf-enabled"),
+                "/usr/sbin/lighttpd",
+                "lighttpd",
+                "www-data",
+            ),
+            (Lighttpd, Arch) => Self::new(
+                "/etc/lighttpd/conf.d",
+                Enable::Included,
+                "/usr/bin/lighttpd",
+                "lighttpd",
+                "http",
+            ),
+            (Lighttpd, _) => Self::new(
+                "/etc/lighttpd/conf.d",
+                Enable::Included,
+                "/usr/sbin/lighttpd",
+                "lighttpd",
+                "lighttpd",
+            ),
+        }
+    }
+
+    /// Adjusts the packaging defaults to what is actually installed, for hosts where
+    /// the server was built from source or the family guess was wrong.
+    fn inspect(mut self) -> Self {
+        if !self.binary.is_file() {
+            let file_name = self.binary.file_name().map(PathBuf::from);
+
+            let found = file_name.and_then(|name| {
+                Self::SBIN_DIRS
+                    .iter()
+                    .map(|dir| Path::new(dir).join(&name))
+                    .find(|p| p.is_file())
+            });
+
+            if let Some(binary) = found {
+                self.binary = binary;
+            }
+        }
+
+        if let Enable::Symlink(enabled) = &self.enable {
+            let conf_d = self.config_dir.with_file_name("conf.d");
+
+            if !enabled.is_dir() && conf_d.is_dir() {
+                self.config_dir = conf_d;
+                self.enable = Enable::Included;
+            }
+        }
+
+        self
+    }
+
+    pub fn with_config_dir<P: Into<PathBuf>>(mut self, config_dir: P) -> Self {
+        self.config_dir = config_dir.into();
+        self
+    }
+
+    pub fn with_enable(mut self, enable: Enable) -> Self {
+        self.enable = enable;
+        self
+    }
+
+    pub fn with_binary<P: Into<PathBuf>>(mut self, binary: P) -> Self {
+        self.binary = binary.into();
+        self
+    }
+
+    pub fn with_service<S: Into<String>>(mut self, service: S) -> Self {
+        self.service = service.into();
+        self
+    }
+
+    pub fn with_web_user<S: Into<String>>(mut self, web_user: S) -> Self {
+        self.web_user = web_user.into();
+        self
+    }
+
+    /// Uses `layout` for `server` from now on instead of the detected one.
+    pub fn set_override(server: Server, layout: Layout) {
+        if let Ok(mut overrides) = OVERRIDES.lock() {
+            overrides.retain(|(s, _)| *s != server);
+            overrides.push((server, layout));
+        }
+    }
+
+    /// Uses `layout` for `server` on this thread until the returned guard is dropped,
+    /// ahead of the one set with `set_override`.
+    pub(crate) fn for_run(server: Server, layout: Layout) -> RunLayout {
+        RUN_LAYOUTS.with_borrow_mut(|layouts| layouts.push((server, layout)));
+
+        RunLayout { server }
+    }
+
+    fn overridden(server: Server) -> Option<Self> {
+        let run = RUN_LAYOUTS.with_borrow(|layouts| {
+            layouts
+                .iter()
+                .rev()
+                .find(|(s, _)| *s == server)
+                .map(|(_, layout)| layout.clone())
+        });
+
+        if run.is_some() {
+            return run;
+        }
+
+        let overrides = OVERRIDES.lock().ok()?;
+
+        overrides
+            .iter()
+            .find(|(s, _)| *s == server)
+            .map(|(_, layout)| layout.clone())
+    }
+
+    fn family() -> Family {
+        static FAMILY: OnceLock<Family> = OnceLock::new();
+
+        *FAMILY.get_or_init(|| OsRelease::read().family())
+    }
+
+    /// Layout of `server` on this host, or the one set for the run or with `set_override`.
+    pub fn current(server: Server) -> Self {
+        if let Some(layout) = Self::overridden(server) {
+            return layout;
+        }
+
+        Self::defaults(Self::family(), server).inspect()
+    }
+
+    pub fn config_dir(&self) -> &Path {
+        &self.config_dir
+    }
+
+    pub fn pid_file(&self) -> &Path {
+        &self.pid_file
+    }
+
+    pub fn reload_signal(&self) -> Option<&'static str> {
+        self.reload_signal
+    }
+
+    pub fn enable(&self) -> &Enable {
+        &self.enable
+    }
+
+    pub fn binary(&self) -> &Path {
+        &self.binary
+    }
+
+    pub fn service(&self) -> &str {
+        &self.service
+    }
+
+    /// `user:group` argument for chown, distributions use a group named after the user.
+    /// A `www_owner` setting wins over the distribution's user.
+    pub fn owner(&self) -> String {
+        match Settings::current().www_owner() {
+            Some(owner) => owner.to_string(),
+            None => format!("{0}:{0}", self.web_user),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn debian_nginx_uses_symlinks() {
+        let layout = Layout::defaults(Family::Debian, Server::Nginx);
+
+        assert_eq!(layout.config_dir(), Path::new("/etc/nginx/sites-available"));
+        assert_eq!(
+            layout.enable(),
+            &Enable::Symlink(PathBuf::from("/etc/nginx/sites-enabled"))
+        );
+        assert_eq!(layout.owner(), "www-data:www-data");
+    }
+
+    #[test]
+    fn redhat_apache_is_httpd_with_conf_d() {
+        let layout = Layout::defaults(Family::RedHat, Server::Apache);
+
+        assert_eq!(layout.config_dir(), Path::new("/etc/httpd/conf.d"));
+        assert_eq!(layout.enable(), &Enable::Included);
+        assert_eq!(layout.binary(), Path::new("/usr/sbin/httpd"));
+        assert_eq!(layout.service(), "httpd");
+        assert_eq!(layout.owner(), "apache:apache");
+    }
+
+    #[test]
+    fn debian_apache_enables_with_a2ensite() {
+        let layout = Layout::defaults(Family::Debian, Server::Apache);
+
+        assert_eq!(layout.enable(), &Enable::Command(String::from("a2ensite")));
+        assert_eq!(layout.service(), "apache2");
+    }
+
+    #[test]
+    fn web_users_per_family() {
+        let users = vec![
+            (Family::Debian, Server::Nginx, "www-data:www-data"),
+            (Family::RedHat, Server::Nginx, "nginx:nginx"),
+            (Family::Alpine, Server::Nginx, "nginx:nginx"),
+            (Family::Arch, Server::Nginx, "http:http"),
+            (Family::Arch, Server::Apache, "http:http"),
+            (Family::Alpine, Server::Apache, "apache:apache"),
+            (Family::RedHat, Server::Lighttpd, "lighttpd:lighttpd"),
+        ];
+
+        for (family, server, expected) in users {
+            let layout = Layout::defaults(family, server);
+
+            assert_eq!(layout.owner(), expected, "{family:?} {server:?}");
+        }
+    }
+
+    #[test]
+    fn arch_includes_its_own_directory() {
+        let nginx = Layout::defaults(Family::Arch, Server::Nginx);
+
+        assert_eq!(nginx.config_dir(), Path::new("/etc/nginx/qicert.d"));
+        assert_eq!(
+            nginx.enable(),
+            &Enable::Include(PathBuf::from("/etc/nginx/nginx.conf"))
+        );
+
+        let apache = Layout::defaults(Family::Arch, Server::Apache);
+
+        assert_eq!(apache.config_dir(), Path::new("/etc/httpd/conf/qicert.d"));
+        assert_eq!(
+            apache.enable(),
+            &Enable::Include(PathBuf::from("/etc/httpd/conf/httpd.conf"))
+        );
+    }
+
+    #[test]
+    fn run_layout_ends_with_the_run_and_stays_on_its_thread() {
+        let custom =
+            Layout::defaults(Family::Debian, Server::Haproxy).with_config_dir("/srv/haproxy");
+
+        let run = Layout::for_run(Server::Haproxy, custom.clone());
+
+        assert_eq!(Layout::current(Server::Haproxy), custom);
+        assert_ne!(
+            std::thread::spawn(|| Layout::current(Server::Haproxy))
+                .join()
+                .unwrap(),
+            custom
+        );
+
+        drop(run);
+
+        assert_ne!(Layout::current(Server::Haproxy), custom);
+    }
+
+    #[test]
+    fn alpine_nginx_uses_http_d() {
+        let layout = Layout::defaults(Family::Alpine, Server::Nginx);
+
+        assert_eq!(layout.config_dir(), Path::new("/etc/nginx/http.d"));
+    }
+}
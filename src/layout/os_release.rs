@@ -0,0 +1,118 @@
+use std::{fs, path::Path};
+
+/// The few fields of `/etc/os-release` qicert cares about.
+#[derive(Debug, Default, PartialEq, Eq, Clone)]
+pub struct OsRelease {
+    id: String,
+    id_like: Vec<String>,
+}
+
+/// Distribution families sharing the same packaging of the web servers.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Family {
+    Debian,
+    RedHat,
+    Alpine,
+    Arch,
+}
+
+impl OsRelease {
+    const PATHS: [&str; 2] = ["/etc/os-release", "/usr/lib/os-release"];
+
+    pub fn read() -> Self {
+        Self::PATHS
+            .iter()
+            .map(Path::new)
+            .find_map(|p| fs::read_to_string(p).ok())
+            .map(Self::parse)
+            .unwrap_or_default()
+    }
+
+    pub fn parse<S: AsRef<str>>(content: S) -> Self {
+        let mut os_release = Self::default();
+
+        for line in content.as_ref().lines().map(str::trim) {
+            let Some((key, value)) = line.split_once('=') else {
+                continue;
+            };
+
+            let value = value.trim_matches(|c| c == '"' || c == '\'').to_lowercase();
+
+            match key {
+                "ID" => os_release.id = value,
+                "ID_LIKE" => {
+                    os_release.id_like = value.split_whitespace().map(String::from).collect()
+                }
+                _ => {}
+            }
+        }
+
+        os_release
+    }
+
+    fn family_of(id: &str) -> Option<Family> {
+        match id {
+            "debian" | "ubuntu" | "raspbian" | "linuxmint" | "pop" => Some(Family::Debian),
+            "rhel" | "fedora" | "centos" | "rocky" | "almalinux" | "ol" | "amzn" => {
+                Some(Family::RedHat)
+            }
+            "alpine" => Some(Family::Alpine),
+            "arch" | "manjaro" | "endeavouros" => Some(Family::Arch),
+            _ => None,
+        }
+    }
+
+    /// Falls back to `ID_LIKE` for derivatives, and to Debian, the layout qicert was
+    /// written for, when nothing is recognised.
+    pub fn family(&self) -> Family {
+        std::iter::once(&self.id)
+            .chain(self.id_like.iter())
+            .find_map(|id| Self::family_of(id))
+            .unwrap_or(Family::Debian)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_os_release() {
+        let content = r#"NAME="Rocky Linux"
+VERSION="9.3 (Blue Onyx)"
+ID="rocky"
+ID_LIKE="rhel centos fedora"
+"#;
+
+        let os_release = OsRelease::parse(content);
+
+        assert_eq!(os_release.id, "rocky");
+        assert_eq!(os_release.id_like, vec!["rhel", "centos", "fedora"]);
+    }
+
+    #[test]
+    fn family_from_os_release() {
+        let releases = vec![
+            ("ID=debian", Family::Debian),
+            ("ID=ubuntu\nID_LIKE=debian", Family::Debian),
+            ("ID=fedora", Family::RedHat),
+            (
+                "ID=\"almalinux\"\nID_LIKE=\"rhel centos fedora\"",
+                Family::RedHat,
+            ),
+            ("ID=somethingnew\nID_LIKE=\"rhel fedora\"", Family::RedHat),
+            ("ID=alpine", Family::Alpine),
+            ("ID=arch", Family::Arch),
+            ("ID=manjaro\nID_LIKE=arch", Family::Arch),
+            ("", Family::Debian),
+        ];
+
+        for (content, expected) in releases {
+            assert_eq!(
+                OsRelease::parse(content).family(),
+                expected,
+                "os-release: {content}"
+            );
+        }
+    }
+}
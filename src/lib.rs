@@ -0,0 +1,41 @@
+//! Obtains Let's Encrypt certificates with certbot and writes the https configuration
+//! for Apache, Caddy, HAProxy, Lighttpd and Nginx.
+//!
+//! [`provisioner::Provisioner`] runs the whole process for one domain, the same way the
+//! `qicert` command does. The `http_config` modules of each server give access to the
+//! configuration blocks on their own.
+
+#![cfg(unix)]
+
+pub mod apache;
+pub mod audit;
+pub mod backup;
+pub mod caddy;
+pub mod certer;
+mod configuration_file;
+pub mod dns;
+pub mod doctor;
+pub mod domain;
+pub mod error;
+pub mod haproxy;
+pub mod layout;
+pub mod lighttpd;
+pub mod listen;
+pub mod lock;
+pub mod manifest;
+pub mod nginx;
+pub mod preflight;
+pub mod profile;
+pub mod provisioner;
+mod reachability;
+pub mod region;
+pub mod report;
+pub mod responder;
+pub mod run;
+pub mod service_manager;
+pub mod settings;
+#[cfg(test)]
+mod test_support;
+mod timestamp;
+mod webroot;
+mod webserver;
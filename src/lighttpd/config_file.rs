@@ -0,0 +1,183 @@
+use std::{error::Error, fmt::Display, fs::File};
+
+use crate::{
+    backup::{Backup, Operation},
+    configuration_file::ConfigurationFile,
+    domain::Domain,
+    error::QicertError,
+    layout::Server,
+};
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Linking,
+    FileSaving,
+    InvalidPath,
+    SymlinkExists,
+    FileExists,
+}
+
+impl Display for ConfigError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Linking => write!(f, "Lighttpd configuration file could not be soft linked"),
+            Self::FileSaving => {
+                write!(
+                    f,
+                    "Lighttpd configuration file could not be written to disk"
+                )
+            }
+            Self::InvalidPath => write!(
+                f,
+                "An invalid path was given for a Lighttpd configuration file"
+            ),
+            Self::SymlinkExists => write!(f, "Symlink already exists"),
+            Self::FileExists => write!(f, "Lighttpd configuration file already exists"),
+        }
+    }
+}
+
+impl Error for ConfigError {}
+
+pub struct ConfigFile;
+
+impl ConfigurationFile for ConfigFile {
+    const SERVER: Server = Server::Lighttpd;
+
+    fn server_name(domain: &Domain) -> String {
+        format!("$HTTP[\"host\"] == \"{domain}\" {{")
+    }
+}
+
+impl ConfigFile {
+    pub fn chown_to_www(domain: &Domain) -> Result<(), QicertError> {
+        Self::_chown_to_www(domain, ConfigError::FileSaving)
+    }
+
+    pub fn create(domain: &Domain) -> Result<File, QicertError> {
+        Self::_create(domain, ConfigError::FileExists, ConfigError::FileSaving)
+    }
+
+    pub fn create_backup(domain: &Domain, operation: Operation) -> Result<Backup, QicertError> {
+        Self::_create_backup(domain, operation)
+    }
+
+    pub fn restore(domain: &Domain, existed: bool) -> Result<(), QicertError> {
+        Self::_restore(domain, existed, ConfigError::FileSaving)
+    }
+
+    pub fn remove_domain(domain: &Domain) -> Result<(), QicertError> {
+        Self::_remove_domain(domain, ConfigError::FileSaving)
+    }
+
+    pub fn read(domain: &Domain, file: &mut File) -> Result<String, QicertError> {
+        Self::_read(domain, file, ConfigError::InvalidPath)
+    }
+
+    pub fn write_block(domain: &Domain, file: &mut File, block: &str) -> Result<(), QicertError> {
+        Self::_write(domain, file, block, ConfigError::FileSaving)
+    }
+
+    pub fn reset(domain: &Domain, file: &mut File, content: &str) -> Result<(), QicertError> {
+        Self::_reset(domain, file, content, ConfigError::FileSaving)
+    }
+
+    pub fn write_region(domain: &Domain, file: &mut File, body: &str) -> Result<(), QicertError> {
+        Self::_write_region(domain, file, body, ConfigError::FileSaving)
+    }
+
+    pub fn clear_stale_challenge(
+        domain: &Domain,
+        file: &mut File,
+        content: &mut String,
+    ) -> Result<bool, QicertError> {
+        Self::_clear_stale_challenge(domain, file, content, ConfigError::FileSaving)
+    }
+
+    pub fn append(domain: &Domain) -> Result<File, QicertError> {
+        Self::_append(domain, ConfigError::InvalidPath)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_support::debian_layout;
+    use std::path::PathBuf;
+
+    #[test]
+    fn find_domain_without_subdomain_file() {
+        let domains = vec![
+            (Domain::new("example", "com", None), true),
+            (Domain::new("example", "com", Some("www")), false),
+            (Domain::new("Example", "com", None), true),
+            (Domain::new("www", "example", None), false),
+        ];
+
+        let haystack = r#"
+$SERVER["socket"] == ":443" {
+    $HTTP["host"] == "example.com" {
+        server.document-root = "/var/www/example.com/public"
+    }
+}"#;
+
+        for (domain, expected) in domains {
+            if let Ok(domain) = domain {
+                assert_eq!(
+                    ConfigFile::find_domain_in_str(haystack, &domain),
+                    expected,
+                    "domain: {domain}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn find_domain_with_commented_lines() {
+        let domains = vec![
+            (Domain::new("example", "com", None), false),
+            (Domain::new("example", "com", Some("www")), true),
+        ];
+
+        let haystack = r#"
+    # $HTTP["host"] == "example.com" {
+    $HTTP["host"] == "www.example.com" {"#;
+
+        for (domain, expected) in domains {
+            if let Ok(domain) = domain {
+                assert_eq!(
+                    ConfigFile::find_domain_in_str(haystack, &domain),
+                    expected,
+                    "domain: {domain}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn config_file_path_with_subdomain() {
+        debian_layout(ConfigFile::SERVER);
+
+        let domain = Domain::new_unchecked("example", "com", Some("www"));
+
+        let expected = PathBuf::from("/etc/lighttpd/conf-available/example.com.conf");
+
+        let file_path = ConfigFile::file_path(&domain);
+
+        assert_eq!(file_path, expected);
+    }
+
+    #[test]
+    fn backup_dir_path() {
+        debian_layout(ConfigFile::SERVER);
+
+        let domain = Domain::new_unchecked("example", "com", None);
+
+        let expected =
+            PathBuf::from("/var/lib/qicert/backups/etc-lighttpd-conf-available-example.com.conf");
+
+        let backup_dir = ConfigFile::backup_dir(&domain);
+
+        assert_eq!(backup_dir, expected);
+    }
+}
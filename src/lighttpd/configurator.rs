@@ -0,0 +1,322 @@
+use std::{fs::File, path::PathBuf};
+
+use crate::lighttpd::config_file::{ConfigError, ConfigFile};
+use crate::lighttpd::http_config::HttpConfig;
+use crate::lighttpd::linker::Linker;
+use crate::{configuration_file::ConfigurationFile, lighttpd::Lighttpd, webserver::WebServer};
+
+use crate::{
+    audit::Audit,
+    backup::Operation,
+    certer::{CertBotError, Certer},
+    domain::Domain,
+    error::QicertError,
+    lighttpd::LighttpdError,
+    profile::Profile,
+    report::{Action, Report},
+    webroot::WebRoot,
+};
+
+pub struct Configurator;
+
+impl Configurator {
+    fn create_file_and_link(domain: &Domain) -> Result<File, QicertError> {
+        if ConfigFile::file_exists(domain) {
+            return Err(ConfigError::InvalidPath)?;
+        }
+
+        let path = ConfigFile::file_path(domain);
+
+        let file = Report::step(
+            Action::CreateConfig,
+            Some(&path),
+            ConfigFile::create(domain),
+        )?;
+
+        ConfigFile::chown_to_www(domain)?;
+
+        let link = Linker::symlink_path(domain);
+
+        let message = match Linker::create(domain) {
+            Ok(_) => {
+                Report::done(Action::Link, link.as_deref());
+                "Link created"
+            }
+            Err(e) => match e.downcast_ref::<ConfigError>() {
+                Some(ConfigError::SymlinkExists) => {
+                    Report::skipped(Action::Link, link.as_deref());
+                    "Link exists. Skipping"
+                }
+                Some(ConfigError::Linking) => {
+                    Report::failed(Action::Link, link.as_deref(), &e);
+                    "Missing permissions"
+                }
+                _ => return Err(e),
+            },
+        };
+
+        Report::say(message);
+
+        Ok(file)
+    }
+
+    fn add_well_known(file: &mut File, domain: &Domain) -> Result<(), QicertError> {
+        // Nothing to serve when the built-in responder answers the challenge.
+        if !Certer::uses_webroot() {
+            return Ok(());
+        }
+
+        let server_block = ConfigFile::mark_challenge(domain, &HttpConfig::http_well_known(domain));
+
+        let written = ConfigFile::write_block(domain, file, &server_block);
+
+        Report::step(
+            Action::WriteConfig,
+            Some(&ConfigFile::file_path(domain)),
+            written,
+        )?;
+
+        Ok(())
+    }
+
+    /// Both blocks go in one region, the part of the file qicert owns for the hostname.
+    fn add_redirect_and_https(
+        file: &mut File,
+        domain: &Domain,
+        profile: &Profile,
+    ) -> Result<(), QicertError> {
+        let redirect_block = HttpConfig::http_redirect(domain);
+        let https_block = HttpConfig::https_content(domain, profile);
+
+        ConfigFile::write_region(domain, file, &format!("{redirect_block}\n{https_block}"))?;
+
+        Report::done(Action::WriteConfig, Some(&ConfigFile::file_path(domain)));
+
+        Ok(())
+    }
+
+    fn create_webroot(domain: &Domain) -> Result<(), QicertError> {
+        let created = WebRoot::create_and_set_chown(domain, &Lighttpd::layout().owner());
+
+        Report::step(
+            Action::Webroot,
+            Some(&WebRoot::build_pathbuf(domain)),
+            created,
+        )?;
+
+        Ok(())
+    }
+
+    fn check_and_reload() -> Result<(), QicertError> {
+        let reloaded = Lighttpd::check_and_reload();
+
+        Audit::reload(&reloaded);
+
+        Report::step(Action::Reload, None, reloaded)
+    }
+
+    fn certify(domain: &Domain) -> Result<(), QicertError> {
+        let issued = Certer::run(domain);
+
+        Audit::certificate(&issued);
+
+        Report::step(Action::Certificate, None, issued)
+    }
+
+    fn create(domain: &Domain, profile: &Profile) -> Result<(), QicertError> {
+        let mut file = Self::create_file_and_link(domain)?;
+
+        Self::add_well_known(&mut file, domain)?;
+
+        if profile.uses_default_webroot(domain) {
+            Self::create_webroot(domain)?;
+        }
+        if Certer::uses_webroot() {
+            Self::check_and_reload()?;
+        }
+
+        Self::certify(domain)?;
+        ConfigFile::reset(domain, &mut file, "")?;
+        Self::add_redirect_and_https(&mut file, domain, profile)?;
+
+        Self::check_and_reload()?;
+
+        Ok(())
+    }
+
+    fn ensure_lighttpd_and_certbot_installed() -> Result<(), QicertError> {
+        if !Lighttpd::is_installed() {
+            return Err(LighttpdError::NotInstalled)?;
+        }
+
+        if !Certer::is_installed() {
+            return Err(CertBotError::NotInstalled)?;
+        }
+
+        Ok(())
+    }
+
+    /// Files whose changes go to the audit log.
+    fn audited_files(domain: &Domain) -> Vec<PathBuf> {
+        vec![ConfigFile::file_path(domain)]
+    }
+
+    pub fn append_or_create(domain: &Domain, profile: &Profile) -> Result<(), QicertError> {
+        Audit::run(
+            Operation::Provision,
+            Lighttpd::SERVER,
+            domain,
+            &Self::audited_files(domain),
+            || Self::_append_or_create(domain, profile),
+        )
+    }
+
+    /// Rewrites the blocks of a site that already has its certificate.
+    pub fn update(domain: &Domain, profile: &Profile) -> Result<(), QicertError> {
+        Audit::run(
+            Operation::Update,
+            Lighttpd::SERVER,
+            domain,
+            &Self::audited_files(domain),
+            || Self::_update(domain, profile),
+        )
+    }
+
+    pub fn remove(domain: &Domain) -> Result<(), QicertError> {
+        Audit::run(
+            Operation::Remove,
+            Lighttpd::SERVER,
+            domain,
+            &Self::audited_files(domain),
+            || Self::_remove(domain),
+        )
+    }
+
+    fn _append_or_create(domain: &Domain, profile: &Profile) -> Result<(), QicertError> {
+        Self::ensure_lighttpd_and_certbot_installed()?;
+
+        let existed = ConfigFile::file_exists(domain);
+
+        if existed {
+            let backup = ConfigFile::create_backup(domain, Operation::Provision);
+
+            Report::step(
+                Action::Backup,
+                Some(&ConfigFile::backup_dir(domain)),
+                backup,
+            )?;
+        }
+
+        let result = match existed {
+            true => Self::append(domain, profile),
+            false => Self::create(domain, profile),
+        };
+
+        result.or_else(|cause| Self::rollback(domain, existed, cause))
+    }
+
+    /// Puts the configuration file back the way it was before a failed run.
+    fn rollback(domain: &Domain, existed: bool, cause: QicertError) -> Result<(), QicertError> {
+        let path = ConfigFile::file_path(domain);
+
+        match Report::step(
+            Action::Rollback,
+            Some(&path),
+            ConfigFile::restore(domain, existed),
+        ) {
+            Ok(_) => Err(cause),
+            Err(e) => Err(QicertError::rollback(cause, e)),
+        }
+    }
+
+    /// Drops the challenge block an interrupted run left behind. Tells whether that
+    /// run got as far as the certificate, leaving only the configuration to write.
+    fn clear_interrupted_run(
+        domain: &Domain,
+        file: &mut File,
+        content: &mut String,
+    ) -> Result<bool, QicertError> {
+        if !ConfigFile::clear_stale_challenge(domain, file, content)? {
+            return Ok(false);
+        }
+
+        Report::say(format!(
+            "Removed the challenge block an interrupted run left for {}",
+            domain.unicode()
+        ));
+
+        let issued = Certer::has_certificate(domain);
+
+        if issued {
+            Report::say(format!(
+                "Certificate for {} already issued, resuming",
+                domain.unicode()
+            ));
+        }
+
+        Ok(issued)
+    }
+
+    fn append(domain: &Domain, profile: &Profile) -> Result<(), QicertError> {
+        let mut file = ConfigFile::append(domain)?;
+
+        let mut content_backup = ConfigFile::read(domain, &mut file)?;
+
+        let resume = Self::clear_interrupted_run(domain, &mut file, &mut content_backup)?;
+
+        if !ConfigFile::find_domain_in_str(content_backup.as_str(), domain) {
+            if !resume {
+                Self::add_well_known(&mut file, domain)?;
+
+                if profile.uses_default_webroot(domain) {
+                    match Self::create_webroot(domain) {
+                        Ok(_) => Report::say(format!("Webroot created for {}", domain.unicode())),
+                        Err(e) => Report::say(format!("{e} error for {}", domain.unicode())),
+                    };
+                }
+
+                if Certer::uses_webroot() {
+                    Self::check_and_reload()?;
+                }
+
+                Self::certify(domain)?;
+            }
+
+            ConfigFile::reset(domain, &mut file, &content_backup)?;
+
+            Self::add_redirect_and_https(&mut file, domain, profile)?;
+
+            Self::check_and_reload()?;
+        }
+
+        Ok(())
+    }
+
+    fn _update(domain: &Domain, profile: &Profile) -> Result<(), QicertError> {
+        ConfigFile::create_backup(domain, Operation::Update)?;
+        ConfigFile::remove_domain(domain)?;
+
+        let mut file = ConfigFile::append(domain)?;
+
+        Self::add_redirect_and_https(&mut file, domain, profile)?;
+
+        if let Err(e) = Linker::create(domain) {
+            if !matches!(e.downcast_ref(), Some(ConfigError::SymlinkExists)) {
+                return Err(e);
+            }
+        }
+
+        Self::check_and_reload()?;
+
+        Ok(())
+    }
+
+    fn _remove(domain: &Domain) -> Result<(), QicertError> {
+        ConfigFile::create_backup(domain, Operation::Remove)?;
+        ConfigFile::remove_domain(domain)?;
+
+        Self::check_and_reload()?;
+
+        Ok(())
+    }
+}
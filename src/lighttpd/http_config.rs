@@ -0,0 +1,254 @@
+use crate::{domain::Domain, profile::Profile, settings::Settings};
+
+pub struct HttpConfig;
+
+impl HttpConfig {
+    fn host_condition(domain: &Domain) -> String {
+        format!("$HTTP[\"host\"] == \"{domain}\" {{")
+    }
+
+    pub fn http_well_known(domain: &Domain) -> String {
+        let challenge_root = Settings::current().challenge_root().display();
+
+        let host = Self::host_condition(domain);
+
+        format!(
+            "
+$SERVER[\"socket\"] == \":80\" {{
+    {host}
+        alias.url += ( \"/.well-known/acme-challenge/\" => \"{challenge_root}/.well-known/acme-challenge/\" )
+    }}
+}}"
+        )
+    }
+
+    pub fn http_redirect(domain: &Domain) -> String {
+        let host = Self::host_condition(domain);
+
+        format!(
+            "
+$SERVER[\"socket\"] == \":80\" {{
+    {host}
+        url.redirect = ( \"^/(.*)\" => \"https://{domain}/$1\" )
+    }}
+}}"
+        )
+    }
+
+    fn site_content(profile: &Profile) -> String {
+        match profile {
+            Profile::Static(root) => {
+                format!("server.document-root = \"{}\"", root.display())
+            }
+            Profile::Proxy(upstream) => format!(
+                "proxy.server = ( \"\" => ( ( \"host\" => \"{}\", \"port\" => {} ) ) )",
+                upstream.ip(),
+                upstream.port()
+            ),
+        }
+    }
+
+    pub fn https_content(domain: &Domain, profile: &Profile) -> String {
+        let host = Self::host_condition(domain);
+        let site = Self::site_content(profile);
+
+        format!(
+            "
+$SERVER[\"socket\"] == \":443\" {{
+    ssl.engine = \"enable\"
+    {host}
+        ssl.pemfile = \"/etc/letsencrypt/live/{domain}/fullchain.pem\"
+        ssl.privkey = \"/etc/letsencrypt/live/{domain}/privkey.pem\"
+        {site}
+    }}
+}}"
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::domain::Domain;
+
+    use super::{HttpConfig, Profile};
+
+    #[test]
+    fn http_well_known() {
+        let expected = r#"
+$SERVER["socket"] == ":80" {
+    $HTTP["host"] == "example.com" {
+        alias.url += ( "/.well-known/acme-challenge/" => "/var/www/.well-known/challenge/.well-known/acme-challenge/" )
+    }
+}"#
+        .to_string();
+
+        let domain = Domain::new("example", "com", None).unwrap();
+
+        let http_config = HttpConfig::http_well_known(&domain);
+
+        assert_eq!(http_config, expected);
+    }
+
+    #[test]
+    fn http_well_known_with_short_subdomain() {
+        let expected = r#"
+$SERVER["socket"] == ":80" {
+    $HTTP["host"] == "test.example.com" {
+        alias.url += ( "/.well-known/acme-challenge/" => "/var/www/.well-known/challenge/.well-known/acme-challenge/" )
+    }
+}"#
+        .to_string();
+
+        let domain = Domain::new("example", "com", Some("test")).unwrap();
+
+        let http_config = HttpConfig::http_well_known(&domain);
+
+        assert_eq!(http_config, expected);
+    }
+
+    #[test]
+    fn http_well_known_with_long_subdomain() {
+        let expected = r#"
+$SERVER["socket"] == ":80" {
+    $HTTP["host"] == "test1.staging1.example.com" {
+        alias.url += ( "/.well-known/acme-challenge/" => "/var/www/.well-known/challenge/.well-known/acme-challenge/" )
+    }
+}"#
+        .to_string();
+
+        let domain = Domain::new("example", "com", Some("test1.staging1")).unwrap();
+
+        let http_config = HttpConfig::http_well_known(&domain);
+
+        assert_eq!(http_config, expected);
+    }
+
+    #[test]
+    fn http_redirect_to_https() {
+        let expected = r#"
+$SERVER["socket"] == ":80" {
+    $HTTP["host"] == "example.com" {
+        url.redirect = ( "^/(.*)" => "https://example.com/$1" )
+    }
+}"#
+        .to_string();
+
+        let domain = Domain::new("example", "com", None).unwrap();
+
+        let http_config = HttpConfig::http_redirect(&domain);
+
+        assert_eq!(http_config, expected);
+    }
+
+    #[test]
+    fn http_redirect_to_https_with_short_subdomain() {
+        let expected = r#"
+$SERVER["socket"] == ":80" {
+    $HTTP["host"] == "test.example.com" {
+        url.redirect = ( "^/(.*)" => "https://test.example.com/$1" )
+    }
+}"#
+        .to_string();
+
+        let domain = Domain::new("example", "com", Some("test")).unwrap();
+
+        let http_config = HttpConfig::http_redirect(&domain);
+
+        assert_eq!(http_config, expected);
+    }
+
+    #[test]
+    fn http_redirect_to_https_with_long_subdomain() {
+        let expected = r#"
+$SERVER["socket"] == ":80" {
+    $HTTP["host"] == "test1.staging1.example.com" {
+        url.redirect = ( "^/(.*)" => "https://test1.staging1.example.com/$1" )
+    }
+}"#
+        .to_string();
+
+        let domain = Domain::new("example", "com", Some("test1.staging1")).unwrap();
+
+        let http_config = HttpConfig::http_redirect(&domain);
+
+        assert_eq!(http_config, expected);
+    }
+
+    #[test]
+    fn https_block() {
+        let expected = r#"
+$SERVER["socket"] == ":443" {
+    ssl.engine = "enable"
+    $HTTP["host"] == "example.com" {
+        ssl.pemfile = "/etc/letsencrypt/live/example.com/fullchain.pem"
+        ssl.privkey = "/etc/letsencrypt/live/example.com/privkey.pem"
+        server.document-root = "/var/www/example.com/public"
+    }
+}"#
+        .to_string();
+
+        let domain = Domain::new("example", "com", None).unwrap();
+
+        let http_config = HttpConfig::https_content(&domain, &Profile::default_for(&domain));
+
+        assert_eq!(http_config, expected);
+    }
+
+    #[test]
+    fn https_block_with_short_subdomain() {
+        let expected = r#"
+$SERVER["socket"] == ":443" {
+    ssl.engine = "enable"
+    $HTTP["host"] == "test.example.com" {
+        ssl.pemfile = "/etc/letsencrypt/live/test.example.com/fullchain.pem"
+        ssl.privkey = "/etc/letsencrypt/live/test.example.com/privkey.pem"
+        server.document-root = "/var/www/test.example.com/public"
+    }
+}"#
+        .to_string();
+
+        let domain = Domain::new("example", "com", Some("test")).unwrap();
+
+        let http_config = HttpConfig::https_content(&domain, &Profile::default_for(&domain));
+
+        assert_eq!(http_config, expected);
+    }
+
+    #[test]
+    fn https_block_with_long_subdomain() {
+        let expected = r#"
+$SERVER["socket"] == ":443" {
+    ssl.engine = "enable"
+    $HTTP["host"] == "test1.staging1.example.com" {
+        ssl.pemfile = "/etc/letsencrypt/live/test1.staging1.example.com/fullchain.pem"
+        ssl.privkey = "/etc/letsencrypt/live/test1.staging1.example.com/privkey.pem"
+        server.document-root = "/var/www/test1.staging1.example.com/public"
+    }
+}"#
+        .to_string();
+
+        let domain = Domain::new("example", "com", Some("test1.staging1")).unwrap();
+
+        let http_config = HttpConfig::https_content(&domain, &Profile::default_for(&domain));
+
+        assert_eq!(http_config, expected);
+    }
+
+    #[test]
+    fn https_block_proxy() {
+        let expected = r#"
+$SERVER["socket"] == ":443" {
+    ssl.engine = "enable"
+    $HTTP["host"] == "app.example.com" {
+        ssl.pemfile = "/etc/letsencrypt/live/app.example.com/fullchain.pem"
+        ssl.privkey = "/etc/letsencrypt/live/app.example.com/privkey.pem"
+        proxy.server = ( "" => ( ( "host" => "127.0.0.1", "port" => 3000 ) ) )
+    }
+}"#;
+
+        let domain = Domain::new("example", "com", Some("app")).unwrap();
+        let profile = Profile::Proxy("127.0.0.1:3000".parse().unwrap());
+
+        assert_eq!(HttpConfig::https_content(&domain, &profile), expected);
+    }
+}
@@ -0,0 +1,44 @@
+use std::{os::unix::fs as unix_fs, path::PathBuf};
+
+use crate::{
+    configuration_file::ConfigurationFile,
+    domain::Domain,
+    error::QicertError,
+    layout::Enable,
+    lighttpd::config_file::{ConfigError, ConfigFile},
+};
+
+pub struct Linker;
+
+impl Linker {
+    pub fn symlink_path(domain: &Domain) -> Option<PathBuf> {
+        match ConfigFile::layout().enable() {
+            Enable::Symlink(conf_enabled) => Some(conf_enabled.join(ConfigFile::file_name(domain))),
+            _ => None,
+        }
+    }
+
+    /// Always the case for layouts that load the whole config directory.
+    pub fn is_enabled(domain: &Domain) -> bool {
+        Self::symlink_path(domain).is_none_or(|l| l.is_symlink())
+    }
+
+    pub fn exists(domain: &Domain) -> bool {
+        Self::symlink_path(domain).is_some_and(|l| l.is_symlink())
+    }
+
+    pub fn create(domain: &Domain) -> Result<(), QicertError> {
+        if Self::exists(domain) {
+            return Err(ConfigError::SymlinkExists)?;
+        }
+
+        let Some(symlink) = Self::symlink_path(domain) else {
+            return Ok(());
+        };
+
+        unix_fs::symlink(ConfigFile::file_path(domain), &symlink)
+            .map_err(|e| QicertError::io(ConfigError::Linking, symlink, e))?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,69 @@
+pub(crate) mod config_file;
+pub(crate) mod configurator;
+pub mod http_config;
+pub(crate) mod linker;
+
+use std::{error::Error, fmt::Display, process::Command};
+
+use crate::{
+    error::{run_command, QicertError},
+    layout::Server,
+    webserver::WebServer,
+};
+
+#[derive(Debug, Clone, Copy)]
+pub enum LighttpdError {
+    CannotReload,
+    BadConfiguration,
+    NotInstalled,
+}
+
+impl Error for LighttpdError {}
+
+impl Display for LighttpdError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::CannotReload => write!(f, "Could not reload Lighttpd."),
+            Self::BadConfiguration => write!(f, "lighttpd -tt failed. Bad Configuration."),
+            Self::NotInstalled => write!(f, "Lighttpd was not found."),
+        }
+    }
+}
+
+pub struct Lighttpd;
+
+impl WebServer for Lighttpd {
+    const SERVER: Server = Server::Lighttpd;
+}
+
+impl Lighttpd {
+    const MAIN_CONFIG: &str = "/etc/lighttpd/lighttpd.conf";
+
+    pub fn reload() -> Result<(), QicertError> {
+        Self::_reload(LighttpdError::CannotReload)
+    }
+
+    /// `-tt` also loads the modules, which catches a missing mod_openssl or mod_alias.
+    pub fn check() -> Result<(), QicertError> {
+        run_command(
+            Command::new(Self::layout().binary())
+                .arg("-tt")
+                .arg("-f")
+                .arg(Self::MAIN_CONFIG),
+            LighttpdError::BadConfiguration,
+        )?;
+
+        Ok(())
+    }
+
+    pub fn check_and_reload() -> Result<(), QicertError> {
+        if !Self::is_installed() {
+            return Err(LighttpdError::NotInstalled)?;
+        }
+
+        Self::check()?;
+        Self::reload()?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,230 @@
+use std::{error::Error, fmt::Display, net::IpAddr};
+
+use crate::layout::Server;
+
+#[derive(Debug)]
+pub enum ListenError {
+    Unsupported {
+        server: Server,
+        option: &'static str,
+    },
+}
+
+impl Display for ListenError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Unsupported {
+                server: Server::Apache,
+                option,
+            } => write!(f, "{option} can only be set for Nginx sites"),
+            Self::Unsupported { server, option } => write!(
+                f,
+                "{option} can not be set for {server:?} sites, only for Nginx and Apache ones"
+            ),
+        }
+    }
+}
+
+impl Error for ListenError {}
+
+/// Addresses, ports and protocols of the generated http and https blocks. The default
+/// listens on every address on ports 80 and 443, which for Nginx means IPv4 only.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Listen {
+    addresses: Vec<IpAddr>,
+    dual_stack: bool,
+    http_port: u16,
+    https_port: u16,
+    default_server: bool,
+    http2: bool,
+    http3: bool,
+}
+
+impl Default for Listen {
+    fn default() -> Self {
+        Self {
+            addresses: Vec::new(),
+            dual_stack: false,
+            http_port: Self::HTTP_PORT,
+            https_port: Self::HTTPS_PORT,
+            default_server: false,
+            http2: false,
+            http3: false,
+        }
+    }
+}
+
+impl Listen {
+    pub const HTTP_PORT: u16 = 80;
+    pub const HTTPS_PORT: u16 = 443;
+
+    /// Binds to `address` instead of every address, once per address to bind to.
+    pub fn with_address(mut self, address: IpAddr) -> Self {
+        self.addresses.push(address);
+        self
+    }
+
+    /// Listens on `[::]` as well when no address is given.
+    pub fn with_dual_stack(mut self, dual_stack: bool) -> Self {
+        self.dual_stack = dual_stack;
+        self
+    }
+
+    /// The ACME challenge always comes in on port 80, another http port needs it
+    /// forwarded.
+    pub fn with_http_port(mut self, port: u16) -> Self {
+        self.http_port = port;
+        self
+    }
+
+    pub fn with_https_port(mut self, port: u16) -> Self {
+        self.https_port = port;
+        self
+    }
+
+    /// Answers requests for names no other block of the port has.
+    pub fn with_default_server(mut self, default_server: bool) -> Self {
+        self.default_server = default_server;
+        self
+    }
+
+    pub fn with_http2(mut self, http2: bool) -> Self {
+        self.http2 = http2;
+        self
+    }
+
+    /// Accepts HTTP/3 over QUIC on the https port and advertises it with `Alt-Svc`.
+    pub fn with_http3(mut self, http3: bool) -> Self {
+        self.http3 = http3;
+        self
+    }
+
+    pub fn addresses(&self) -> &[IpAddr] {
+        &self.addresses
+    }
+
+    pub fn is_dual_stack(&self) -> bool {
+        self.dual_stack
+    }
+
+    pub fn http_port(&self) -> u16 {
+        self.http_port
+    }
+
+    pub fn https_port(&self) -> u16 {
+        self.https_port
+    }
+
+    pub fn is_default_server(&self) -> bool {
+        self.default_server
+    }
+
+    pub fn has_http2(&self) -> bool {
+        self.http2
+    }
+
+    pub fn has_http3(&self) -> bool {
+        self.http3
+    }
+
+    /// `:<port>` to add to https URLs, nothing for 443.
+    pub(crate) fn https_port_suffix(&self) -> String {
+        match self.https_port {
+            Self::HTTPS_PORT => String::new(),
+            port => format!(":{port}"),
+        }
+    }
+
+    /// `address:` prefix of a socket, with brackets around IPv6 addresses.
+    pub(crate) fn host(address: &IpAddr) -> String {
+        match address {
+            IpAddr::V4(address) => format!("{address}:"),
+            IpAddr::V6(address) => format!("[{address}]:"),
+        }
+    }
+
+    /// Options that differ from the default, by name.
+    fn options(&self) -> Vec<&'static str> {
+        let default = Self::default();
+
+        [
+            (self.addresses != default.addresses, "bind addresses"),
+            (self.dual_stack, "dual-stack"),
+            (self.http_port != default.http_port, "the http port"),
+            (self.https_port != default.https_port, "the https port"),
+            (self.default_server, "default_server"),
+            (self.http2, "http2"),
+            (self.http3, "http3"),
+        ]
+        .into_iter()
+        .filter_map(|(set, option)| set.then_some(option))
+        .collect()
+    }
+
+    /// Refuses options `server` has no equivalent for. Apache's `*` already covers
+    /// IPv6 and it always offers HTTP/2, but it has neither `default_server` nor QUIC.
+    pub fn supported_by(&self, server: Server) -> Result<(), ListenError> {
+        let unsupported = |option: &&str| match server {
+            Server::Nginx => false,
+            Server::Apache => matches!(*option, "default_server" | "http3"),
+            _ => true,
+        };
+
+        match self.options().into_iter().find(unsupported) {
+            Some(option) => Err(ListenError::Unsupported { server, option }),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn options_are_checked_per_server() {
+        assert!(Listen::default().supported_by(Server::Caddy).is_ok());
+
+        let listen = Listen::default()
+            .with_address("2001:db8::1".parse().unwrap())
+            .with_http2(true);
+
+        assert!(listen.supported_by(Server::Apache).is_ok());
+
+        let listen = listen.with_https_port(8443);
+
+        assert!(listen.supported_by(Server::Nginx).is_ok());
+        assert!(listen.supported_by(Server::Apache).is_ok());
+        assert_eq!(
+            listen
+                .clone()
+                .with_default_server(true)
+                .supported_by(Server::Apache)
+                .unwrap_err()
+                .to_string(),
+            "default_server can only be set for Nginx sites"
+        );
+        assert_eq!(
+            listen
+                .supported_by(Server::Lighttpd)
+                .unwrap_err()
+                .to_string(),
+            "bind addresses can not be set for Lighttpd sites, only for Nginx and Apache ones"
+        );
+
+        let listen = listen.with_http3(true);
+
+        assert!(listen.supported_by(Server::Nginx).is_ok());
+        assert_eq!(
+            Listen::default()
+                .with_http3(true)
+                .supported_by(Server::Apache)
+                .unwrap_err()
+                .to_string(),
+            "http3 can only be set for Nginx sites"
+        );
+
+        assert_eq!(listen.https_port_suffix(), ":8443");
+        assert_eq!(Listen::host(&listen.addresses()[0]), "[2001:db8::1]:");
+    }
+}
@@ -0,0 +1,305 @@
+use std::{
+    cell::Cell,
+    error::Error,
+    fmt::Display,
+    fs::{self, File, OpenOptions, TryLockError},
+    io::Write,
+    path::{Path, PathBuf},
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::{error::QicertError, run::RunOptions};
+
+#[derive(Debug)]
+pub enum LockError {
+    Busy { path: PathBuf, holder: Option<u32> },
+    Unavailable,
+}
+
+impl Display for LockError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Busy { path, holder } => {
+                write!(f, "{} is held by another qicert run", path.display())?;
+
+                if let Some(pid) = holder {
+                    write!(f, " (pid {pid})")?;
+                }
+
+                write!(f, ", try again later or use --wait")
+            }
+            Self::Unavailable => write!(f, "The lock file could not be opened"),
+        }
+    }
+}
+
+impl Error for LockError {}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    Shared,
+    Exclusive,
+}
+
+thread_local! {
+    /// Set while this thread holds the global lock exclusively. Other threads of the
+    /// process still have to take their locks, flock keeps them out.
+    static GLOBAL_EXCLUSIVE: Cell<bool> = const { Cell::new(false) };
+}
+
+/// flock(2) based lock, released when dropped or when the process dies.
+///
+/// An exclusive holder writes its pid into the lock file so others can tell who they
+/// are waiting for, and empties it again on release.
+#[derive(Debug)]
+pub struct Lock {
+    file: Option<File>,
+    mode: Mode,
+    global: bool,
+}
+
+impl Lock {
+    pub const DIR: &str = "/run/qicert";
+    const GLOBAL: &str = "qicert.lock";
+    const POLL: Duration = Duration::from_millis(100);
+
+    /// How long the run waits for a busy lock, see `RunOptions`.
+    fn wait() -> Duration {
+        RunOptions::current().wait()
+    }
+
+    /// Held shared by every run and exclusively by the ones touching everything, such
+    /// as `apply`. Runs nested in an exclusive holder on the same thread get it for
+    /// free. The lock has to be dropped on the thread that took it.
+    pub fn global(mode: Mode) -> Result<Self, QicertError> {
+        Self::global_in(Path::new(Self::DIR), mode)
+    }
+
+    /// Exclusive lock on a configuration file, and with it on its backup.
+    pub fn config_file(config: &Path) -> Result<Self, QicertError> {
+        Self::config_file_in(Path::new(Self::DIR), config)
+    }
+
+    fn is_nested() -> bool {
+        GLOBAL_EXCLUSIVE.with(Cell::get)
+    }
+
+    fn global_in(dir: &Path, mode: Mode) -> Result<Self, QicertError> {
+        if Self::is_nested() {
+            return Ok(Self::nested());
+        }
+
+        let mut lock = Self::acquire(&dir.join(Self::GLOBAL), mode, Self::wait())?;
+
+        if mode == Mode::Exclusive {
+            lock.global = true;
+            GLOBAL_EXCLUSIVE.with(|held| held.set(true));
+        }
+
+        Ok(lock)
+    }
+
+    fn config_file_in(dir: &Path, config: &Path) -> Result<Self, QicertError> {
+        if Self::is_nested() {
+            return Ok(Self::nested());
+        }
+
+        Self::acquire(&Self::path_for(dir, config), Mode::Exclusive, Self::wait())
+    }
+
+    fn nested() -> Self {
+        Self {
+            file: None,
+            mode: Mode::Shared,
+            global: false,
+        }
+    }
+
+    /// `/etc/nginx/sites-available/example.com.conf` is locked through
+    /// `<dir>/etc-nginx-sites-available-example.com.conf.lock`.
+    fn path_for(dir: &Path, config: &Path) -> PathBuf {
+        let name = config
+            .to_string_lossy()
+            .trim_start_matches('/')
+            .replace('/', "-");
+
+        dir.join(format!("{name}.lock"))
+    }
+
+    fn holder(path: &Path) -> Option<u32> {
+        fs::read_to_string(path).ok()?.trim().parse().ok()
+    }
+
+    fn open(path: &Path) -> Result<File, QicertError> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)
+                .map_err(|e| QicertError::io(LockError::Unavailable, parent, e))?;
+        }
+
+        OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)
+            .map_err(|e| QicertError::io(LockError::Unavailable, path, e))
+    }
+
+    /// Polls for the lock until `wait` runs out. The lock file is never removed, flock
+    /// lets go of it once its holder is gone.
+    pub fn acquire(path: &Path, mode: Mode, wait: Duration) -> Result<Self, QicertError> {
+        let started = Instant::now();
+        let mut file = Self::open(path)?;
+
+        loop {
+            let locked = match mode {
+                Mode::Shared => file.try_lock_shared(),
+                Mode::Exclusive => file.try_lock(),
+            };
+
+            match locked {
+                Ok(()) => {
+                    // Without an exclusive holder any pid left in the file is stale.
+                    let recorded = match mode {
+                        Mode::Shared => file.set_len(0),
+                        Mode::Exclusive => file
+                            .set_len(0)
+                            .and_then(|_| write!(file, "{}", std::process::id())),
+                    };
+
+                    recorded.map_err(|e| QicertError::io(LockError::Unavailable, path, e))?;
+
+                    return Ok(Self {
+                        file: Some(file),
+                        mode,
+                        global: false,
+                    });
+                }
+                Err(TryLockError::Error(e)) => {
+                    return Err(QicertError::io(LockError::Unavailable, path, e))
+                }
+                Err(TryLockError::WouldBlock) => {}
+            }
+
+            if started.elapsed() >= wait {
+                return Err(LockError::Busy {
+                    path: path.to_path_buf(),
+                    holder: Self::holder(path),
+                })?;
+            }
+
+            thread::sleep(Self::POLL);
+        }
+    }
+}
+
+impl Drop for Lock {
+    fn drop(&mut self) {
+        if let Some(file) = &self.file {
+            if self.mode == Mode::Exclusive {
+                let _ = file.set_len(0);
+            }
+
+            let _ = file.unlock();
+        }
+
+        if self.global {
+            GLOBAL_EXCLUSIVE.with(|held| held.set(false));
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_support::scratch;
+
+    #[test]
+    fn lock_name_follows_config_path() {
+        assert_eq!(
+            Lock::path_for(
+                Path::new("/run/qicert"),
+                Path::new("/etc/nginx/sites-available/example.com.conf")
+            ),
+            PathBuf::from("/run/qicert/etc-nginx-sites-available-example.com.conf.lock")
+        );
+    }
+
+    #[test]
+    fn exclusive_lock_is_busy_until_dropped() {
+        let dir = scratch("lock-busy");
+        let path = dir.join("test.lock");
+
+        let held = Lock::acquire(&path, Mode::Exclusive, Duration::ZERO).unwrap();
+
+        let error = Lock::acquire(&path, Mode::Shared, Duration::ZERO).unwrap_err();
+
+        assert!(matches!(
+            error.downcast_ref::<LockError>(),
+            Some(LockError::Busy { holder: Some(pid), .. }) if *pid == std::process::id()
+        ));
+
+        drop(held);
+
+        let first = Lock::acquire(&path, Mode::Shared, Duration::ZERO).unwrap();
+        let second = Lock::acquire(&path, Mode::Shared, Duration::ZERO).unwrap();
+
+        assert!(Lock::acquire(&path, Mode::Exclusive, Duration::ZERO).is_err());
+
+        drop((first, second));
+    }
+
+    #[test]
+    fn waits_for_the_holder() {
+        let dir = scratch("lock-wait");
+        let path = dir.join("test.lock");
+
+        let held = Lock::acquire(&path, Mode::Exclusive, Duration::ZERO).unwrap();
+
+        let release = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(200));
+            drop(held);
+        });
+
+        assert!(Lock::acquire(&path, Mode::Exclusive, Duration::from_secs(5)).is_ok());
+
+        release.join().unwrap();
+    }
+
+    #[test]
+    fn exclusive_global_lock_only_covers_its_own_thread() {
+        let dir = scratch("lock-nested");
+        let config = Path::new("/etc/nginx/sites-available/example.com.conf");
+
+        let global = Lock::global_in(&dir, Mode::Exclusive).unwrap();
+
+        // Nested on the holder's thread: nothing more to take.
+        let nested = Lock::config_file_in(&dir, config).unwrap();
+
+        assert!(nested.file.is_none());
+
+        let other = {
+            let dir = dir.to_path_buf();
+
+            thread::spawn(move || {
+                let file = Lock::config_file_in(&dir, config).unwrap();
+
+                let busy = Lock::global_in(&dir, Mode::Shared).is_err_and(|e| {
+                    matches!(e.downcast_ref::<LockError>(), Some(LockError::Busy { .. }))
+                });
+
+                (file.file.is_some(), busy)
+            })
+        };
+
+        let (locked, busy) = other.join().unwrap();
+
+        assert!(locked, "another thread takes the file lock for real");
+        assert!(busy, "and waits for the global lock");
+
+        drop((nested, global));
+
+        assert!(!Lock::is_nested());
+    }
+}
@@ -1,70 +1,464 @@
-#![cfg(unix)]
+use std::{
+    net::{IpAddr, SocketAddr},
+    path::{Path, PathBuf},
+    process::ExitCode,
+    time::Duration,
+};
 
-mod apache;
-mod certer;
-mod configuration_file;
-mod domain;
-mod nginx;
-mod webroot;
-mod webserver;
-use std::error::Error;
+use qicert::audit::Audit;
+use qicert::backup::Backups;
+use qicert::caddy::{CaddyReload, CaddyTls};
+use qicert::certer::Challenge;
+use qicert::dns::Resolver;
+use qicert::doctor::Doctor;
+use qicert::domain::{Domain, PublicSuffixList};
+use qicert::error::QicertError;
+use qicert::layout::Server;
+use qicert::listen::Listen;
+use qicert::lock::{Lock, Mode};
+use qicert::manifest::{Apply, Manifest};
+use qicert::provisioner::Provisioner;
+use qicert::report::{Output, Report};
+use qicert::responder::Hook;
+use qicert::run::RunOptions;
+use qicert::service_manager::ServiceManagers;
+use qicert::settings::{Layer, Settings};
 
-use crate::domain::Domain;
-
-use clap::{Parser, ValueEnum};
+use clap::{Parser, Subcommand, ValueEnum};
 
 #[derive(Debug, Clone, Copy, ValueEnum)]
 enum WebServers {
     Apache,
+    Caddy,
+    Haproxy,
+    Lighttpd,
     Nginx,
 }
 
+impl From<WebServers> for Server {
+    fn from(webserver: WebServers) -> Self {
+        match webserver {
+            WebServers::Apache => Server::Apache,
+            WebServers::Caddy => Server::Caddy,
+            WebServers::Haproxy => Server::Haproxy,
+            WebServers::Lighttpd => Server::Lighttpd,
+            WebServers::Nginx => Server::Nginx,
+        }
+    }
+}
+
 #[derive(Parser)]
 #[command(name = "qicert")]
 #[command(author = "Jose Higuera <contact@higuera.dev>")]
 #[command(about = "A very simple tool built as a wrapper on top of certbot 
     with nginx and manual certification in mind")]
 #[command(version, long_about = None)]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
 struct Cli {
-    #[arg(value_enum)]
-    webserver: WebServers,
-    #[arg(short = 'd', long)]
-    domain: String,
+    #[command(subcommand)]
+    command: Option<Commands>,
+
+    #[arg(value_enum, required = true)]
+    webserver: Option<WebServers>,
+    #[arg(short = 'd', long, required = true)]
+    domain: Option<String>,
 
     #[arg(short = 's', long)]
     subdomain: Option<String>,
 
-    #[arg(short = 't', long)]
-    tld: String,
+    #[arg(short = 't', long, required = true)]
+    tld: Option<String>,
+
+    /// Move the existing http-only Nginx site of the hostname to https in place
+    #[arg(long)]
+    upgrade: bool,
+
+    /// Address the Nginx or Apache site listens on instead of every address, repeatable
+    #[arg(long = "bind", value_name = "ADDRESS")]
+    bind: Vec<IpAddr>,
+
+    /// Listen on [::] next to every IPv4 address in Nginx blocks when no --bind is given
+    #[arg(long)]
+    ipv6: bool,
+
+    /// Port of the generated Nginx or Apache http blocks
+    #[arg(long, value_name = "PORT", default_value_t = Listen::HTTP_PORT)]
+    http_port: u16,
+
+    /// Port of the generated Nginx or Apache https block
+    #[arg(long, value_name = "PORT", default_value_t = Listen::HTTPS_PORT)]
+    https_port: u16,
+
+    /// Make the Nginx site the default_server of its ports
+    #[arg(long)]
+    default_server: bool,
+
+    /// Enable HTTP/2 in the Nginx https block, Apache always offers it
+    #[arg(long)]
+    http2: bool,
+
+    /// Accept HTTP/3 over QUIC in the Nginx https block and advertise it with Alt-Svc
+    #[arg(long)]
+    http3: bool,
+
+    /// Certificate source for Caddy sites
+    #[arg(long, value_enum, default_value_t = CaddyTls::Automatic)]
+    caddy_tls: CaddyTls,
+
+    /// Reload Caddy through its admin API at the given address instead of the service manager
+    #[arg(long, value_name = "ADDRESS", global = true)]
+    caddy_admin: Option<String>,
+
+    /// How to reload the web server, detected from the running init system by default
+    #[arg(long, value_enum, global = true)]
+    service_manager: Option<ServiceManagers>,
+
+    /// Answer the http-01 challenge with the built-in responder instead of the web server
+    #[arg(long, global = true)]
+    standalone: bool,
+
+    /// Address the built-in responder binds to
+    #[arg(
+        long,
+        value_name = "ADDRESS",
+        default_value = "0.0.0.0:80",
+        requires = "standalone",
+        global = true
+    )]
+    listen: SocketAddr,
+
+    /// Do not fetch a test token through the challenge location before calling certbot
+    #[arg(long, global = true)]
+    skip_reachability_check: bool,
+
+    /// Fetch the test token from this address instead of the one DNS gives for the domain
+    #[arg(long, value_name = "ADDRESS", global = true)]
+    resolve: Option<SocketAddr>,
+
+    /// Do not compare the domain's A/AAAA and CAA records with this host before starting
+    #[arg(long, global = true)]
+    skip_dns_check: bool,
+
+    /// Do not check privileges, target directories, the web user, programs and port 80 first
+    #[arg(long, global = true)]
+    skip_preflight: bool,
+
+    /// Nameserver used by the DNS check instead of the first one in /etc/resolv.conf
+    #[arg(long, value_name = "ADDRESS", global = true)]
+    nameserver: Option<SocketAddr>,
+
+    /// Settings file read instead of /etc/qicert/config.toml
+    #[arg(
+        long = "config",
+        id = "config_file",
+        value_name = "FILE",
+        global = true
+    )]
+    config_file: Option<PathBuf>,
+
+    /// Print progress as text, or a JSON report of every step once the run is over
+    #[arg(long, value_enum, default_value_t = Output::Text, global = true)]
+    output: Output,
+
+    /// Seconds to wait for another qicert run working on the same files to finish
+    #[arg(long, value_name = "SECONDS", default_value_t = 0, global = true)]
+    wait: u64,
+
+    /// `user:group` owning webroots and configuration files
+    #[arg(long, value_name = "OWNER", global = true)]
+    www_owner: Option<String>,
+
+    /// Directory the site webroots are created in
+    #[arg(long, value_name = "DIR", global = true)]
+    webroot: Option<PathBuf>,
+
+    /// Directory certbot drops the http-01 tokens in
+    #[arg(long, value_name = "DIR", global = true)]
+    challenge_root: Option<PathBuf>,
+
+    /// Path to the certbot binary
+    #[arg(long, value_name = "FILE", global = true)]
+    certbot: Option<PathBuf>,
+
+    /// ACME directory URL handed to certbot
+    #[arg(long, value_name = "URL", global = true)]
+    acme_server: Option<String>,
+
+    /// Directory the configuration file backups are kept in
+    #[arg(long, value_name = "DIR", global = true)]
+    backup_dir: Option<PathBuf>,
+
+    /// Backups kept for each configuration file
+    #[arg(long, value_name = "COUNT", global = true)]
+    backup_keep: Option<usize>,
+
+    /// JSON lines file recording every change qicert makes
+    #[arg(long, value_name = "FILE", global = true)]
+    audit_log: Option<PathBuf>,
+
+    /// Send audit entries to syslog or journald through /dev/log as well
+    #[arg(long, global = true)]
+    audit_syslog: bool,
 }
 
-fn main() -> Result<(), Box<dyn Error>> {
+#[derive(Subcommand)]
+enum Commands {
+    /// Certbot manual hooks feeding the built-in challenge responder
+    Hook {
+        #[arg(value_enum)]
+        action: HookAction,
+    },
+    /// Create, update or remove sites until the host matches a manifest
+    Apply {
+        /// TOML file with one [[site]] table per hostname
+        manifest: PathBuf,
+
+        /// Only print the changes that would be made
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Inspect the qicert settings
+    Config {
+        #[command(subcommand)]
+        action: ConfigAction,
+    },
+    /// List or restore the backups taken before configuration files were changed
+    Backups {
+        #[command(subcommand)]
+        action: BackupsAction,
+    },
+    /// Print the audit log after checking that no entry was altered
+    History,
+    /// Check the configured sites of every installed web server and print how to fix what is wrong
+    Doctor {
+        /// Apply the fixes that only undo what qicert left behind
+        #[arg(long)]
+        fix: bool,
+    },
+    /// Replace the built-in Public Suffix List with a newer copy
+    UpdatePsl {
+        #[arg(default_value = PublicSuffixList::DEFAULT_SOURCE)]
+        source: PathBuf,
+    },
+}
+
+#[derive(Subcommand)]
+enum ConfigAction {
+    /// Print the effective settings and where each one comes from
+    Show,
+}
+
+#[derive(Subcommand)]
+enum BackupsAction {
+    /// Print every backup, oldest first for each file
+    List,
+    /// Put a backup back in place, then test the configuration and reload the server
+    Restore {
+        /// Backup id as shown by `backups list`
+        id: String,
+    },
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum HookAction {
+    Auth,
+    Cleanup,
+}
+
+fn main() -> ExitCode {
     let cli = Cli::parse();
+    let output = cli.output;
+
+    Report::finish(output, run(cli))
+}
+
+fn run(cli: Cli) -> Result<(), QicertError> {
+    let overrides = Layer {
+        www_owner: cli.www_owner,
+        webroot: cli.webroot,
+        challenge_root: cli.challenge_root,
+        certbot: cli.certbot,
+        acme_server: cli.acme_server,
+        backup_dir: cli.backup_dir,
+        backup_keep: cli.backup_keep,
+        audit_log: cli.audit_log,
+        audit_syslog: cli.audit_syslog.then_some(true),
+    };
+
+    Settings::load(cli.config_file.as_deref(), overrides)?.install();
+
+    let mut options = RunOptions::default()
+        .with_skip_reachability_check(cli.skip_reachability_check)
+        .with_wait(Duration::from_secs(cli.wait))
+        .with_output(cli.output);
+
+    if let Some(service_manager) = cli.service_manager {
+        options = options.with_service_manager(service_manager);
+    }
+
+    if let Some(address) = cli.resolve {
+        options = options.with_resolve(address);
+    }
+
+    if cli.standalone {
+        options = options.with_challenge(Challenge::Standalone(cli.listen));
+    }
+
+    let resolver = match cli.nameserver {
+        Some(nameserver) => Resolver::new(nameserver),
+        None => Resolver::system(),
+    };
+
+    let reload = match cli.caddy_admin {
+        Some(address) => CaddyReload::AdminApi(address),
+        None => CaddyReload::ServiceManager,
+    };
+
+    if let Some(command) = cli.command {
+        let resolver = (!cli.skip_dns_check).then_some(resolver);
+        let preflight = !cli.skip_preflight;
+
+        return options.scope(|| match command {
+            Commands::Hook { action } => handle_hook(action),
+            Commands::UpdatePsl { source } => handle_update_psl(&source),
+            Commands::Config {
+                action: ConfigAction::Show,
+            } => {
+                Report::say(Settings::current().to_string().trim_end());
+
+                Ok(())
+            }
+            Commands::Backups { action } => handle_backups(action, &reload),
+            Commands::History => handle_history(),
+            Commands::Doctor { fix } => Doctor::new().with_caddy_reload(reload).run(fix),
+            Commands::Apply { manifest, dry_run } => {
+                let apply = Apply::new(reload)
+                    .dry_run(dry_run)
+                    .with_options(options.clone());
+
+                handle_apply(&manifest, apply, resolver, preflight)
+            }
+        });
+    }
+
+    let (Some(webserver), Some(name), Some(tld)) = (cli.webserver, cli.domain, cli.tld) else {
+        unreachable!("clap requires the webserver, domain and tld without a subcommand");
+    };
 
-    let name = cli.domain;
-    let tld = cli.tld;
     let subdomain = cli.subdomain;
 
     let domain = Domain::new(name, tld, subdomain.as_deref())?;
 
-    match cli.webserver {
-        WebServers::Apache => handle_apache(&domain)?,
-        WebServers::Nginx => handle_nginx(&domain)?,
+    let listen = cli
+        .bind
+        .into_iter()
+        .fold(Listen::default(), Listen::with_address)
+        .with_dual_stack(cli.ipv6)
+        .with_http_port(cli.http_port)
+        .with_https_port(cli.https_port)
+        .with_default_server(cli.default_server)
+        .with_http2(cli.http2)
+        .with_http3(cli.http3);
+
+    let mut provisioner = Provisioner::new(domain, webserver.into())
+        .listen(listen)
+        .caddy_tls(cli.caddy_tls)
+        .caddy_reload(reload)
+        .options(options);
+
+    if !cli.skip_dns_check {
+        provisioner = provisioner.dns_check(resolver);
+    }
+
+    if !cli.skip_preflight {
+        provisioner = provisioner.preflight();
+    }
+
+    if cli.upgrade {
+        provisioner = provisioner.upgrade();
+    }
+
+    provisioner.run().into_result()
+}
+
+fn handle_hook(action: HookAction) -> Result<(), QicertError> {
+    match action {
+        HookAction::Auth => Hook::auth()?,
+        HookAction::Cleanup => Hook::cleanup()?,
     }
 
     Ok(())
 }
 
-fn handle_apache(domain: &Domain) -> Result<(), Box<dyn Error>> {
-    use apache::configurator::Configurator;
-    Configurator::append_or_create(&domain)?;
+fn handle_update_psl(source: &Path) -> Result<(), QicertError> {
+    let _lock = Lock::global(Mode::Exclusive)?;
+
+    let rules = PublicSuffixList::update(source)?;
+
+    Report::say(format!(
+        "Public Suffix List with {rules} rules saved to {}",
+        PublicSuffixList::UPDATED_PATH
+    ));
 
     Ok(())
 }
 
-fn handle_nginx(domain: &Domain) -> Result<(), Box<dyn Error>> {
-    use crate::nginx::configurator::Configurator;
-    Configurator::append_or_create(&domain)?;
+fn handle_backups(action: BackupsAction, reload: &CaddyReload) -> Result<(), QicertError> {
+    let backups = Backups::current();
+
+    match action {
+        BackupsAction::List => {
+            for backup in backups.list()? {
+                let metadata = backup.metadata();
+
+                Report::say(format!(
+                    "{:<64}{:<10}{:<32}qicert {}",
+                    backup.id(),
+                    metadata.operation().to_string(),
+                    metadata.hostname(),
+                    metadata.version()
+                ));
+            }
+        }
+        BackupsAction::Restore { id } => {
+            let backup = backups.restore(&id, reload)?;
+
+            Report::say(format!(
+                "{} restored from {}",
+                backup.metadata().file().display(),
+                backup.id()
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+fn handle_history() -> Result<(), QicertError> {
+    for entry in Audit::read(Settings::current().audit_log())? {
+        Report::say(entry);
+    }
+
+    Ok(())
+}
+
+fn handle_apply(
+    path: &Path,
+    mut apply: Apply,
+    resolver: Option<Resolver>,
+    preflight: bool,
+) -> Result<(), QicertError> {
+    let manifest = Manifest::read(path)?;
+
+    if let Some(resolver) = resolver {
+        apply = apply.with_dns_check(resolver);
+    }
+
+    if preflight {
+        apply = apply.with_preflight();
+    }
+
+    apply.run(&manifest)?;
 
     Ok(())
 }
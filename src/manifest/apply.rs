@@ -0,0 +1,388 @@
+use crate::{
+    apache,
+    audit::Audit,
+    backup::{Backup, Backups, Operation},
+    caddy,
+    caddy::{CaddyReload, CaddyTls},
+    certer::Certer,
+    configuration_file::ConfigurationFile,
+    dns::{DnsCheck, Resolver},
+    error::QicertError,
+    haproxy,
+    layout::Server,
+    lighttpd,
+    lock::{Lock, Mode},
+    nginx,
+    preflight::Preflight,
+    provisioner::Provisioner,
+    report::Report,
+    run::RunOptions,
+    settings::Settings,
+};
+
+use super::{
+    plan::{Action, Change, Live, Plan},
+    state::State,
+    Entry, Manifest, ManifestError,
+};
+
+/// Brings the hosts in line with a manifest, one hostname at a time.
+pub struct Apply {
+    reload: CaddyReload,
+    dns: Option<Resolver>,
+    preflight: bool,
+    dry_run: bool,
+    options: RunOptions,
+}
+
+impl Apply {
+    pub fn new(reload: CaddyReload) -> Self {
+        Self {
+            reload,
+            dns: None,
+            preflight: false,
+            dry_run: false,
+            options: RunOptions::default(),
+        }
+    }
+
+    /// Runs the DNS pre-validation before each hostname that gets a new certificate.
+    pub fn with_dns_check(mut self, resolver: Resolver) -> Self {
+        self.dns = Some(resolver);
+        self
+    }
+
+    /// Runs the preflight checks for every server in the manifest before the first change.
+    pub fn with_preflight(mut self) -> Self {
+        self.preflight = true;
+        self
+    }
+
+    /// Holds for the whole run and every hostname provisioned on the way.
+    pub fn with_options(mut self, options: RunOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    /// Prints the changes without touching anything.
+    pub fn dry_run(mut self, dry_run: bool) -> Self {
+        self.dry_run = dry_run;
+        self
+    }
+
+    fn live(entry: &Entry) -> Live {
+        let domain = &entry.domain;
+
+        let (configured, enabled, outdated) = match entry.server {
+            Server::Apache => (
+                apache::config_file::ConfigFile::contains_domain(domain),
+                true,
+                apache::config_file::ConfigFile::is_outdated(domain),
+            ),
+            Server::Caddy => (
+                caddy::config_file::ConfigFile::contains_domain(domain),
+                true,
+                caddy::config_file::ConfigFile::is_outdated(domain),
+            ),
+            // The whole HAProxy file is regenerated on every change.
+            Server::Haproxy => (
+                haproxy::config_file::ConfigFile::is_in_crt_list(domain),
+                true,
+                false,
+            ),
+            Server::Lighttpd => (
+                lighttpd::config_file::ConfigFile::contains_domain(domain),
+                lighttpd::linker::Linker::is_enabled(domain),
+                lighttpd::config_file::ConfigFile::is_outdated(domain),
+            ),
+            Server::Nginx => (
+                nginx::config_file::ConfigFile::contains_domain(domain),
+                nginx::linker::Linker::is_enabled(domain),
+                nginx::config_file::ConfigFile::is_outdated(domain),
+            ),
+        };
+
+        Live {
+            configured,
+            certificate: Certer::has_certificate(domain),
+            enabled,
+            outdated,
+        }
+    }
+
+    /// Drops what a previous, interrupted run left for the hostname, and returns the
+    /// copies taken before.
+    fn strip(entry: &Entry) -> Result<Vec<Backup>, QicertError> {
+        let domain = &entry.domain;
+        let files = Provisioner::locked_files(entry.server, domain);
+
+        Audit::run(Operation::Provision, entry.server, domain, &files, || {
+            Self::_strip(entry)
+        })
+    }
+
+    fn _strip(entry: &Entry) -> Result<Vec<Backup>, QicertError> {
+        let domain = &entry.domain;
+
+        let backup = match entry.server {
+            Server::Apache => {
+                let backup =
+                    apache::config_file::ConfigFile::create_backup(domain, Operation::Provision)?;
+                apache::config_file::ConfigFile::remove_domain(domain)?;
+                backup
+            }
+            Server::Caddy => {
+                let backup =
+                    caddy::config_file::ConfigFile::create_backup(domain, Operation::Provision)?;
+                caddy::config_file::ConfigFile::remove_domain(domain)?;
+                backup
+            }
+            // The bundle stays, the run writes it again and the restored line needs it.
+            Server::Haproxy => {
+                let crt_list = haproxy::config_file::ConfigFile::crt_list_path();
+                let backup = Backups::current().save(
+                    &crt_list,
+                    entry.server,
+                    domain,
+                    Operation::Provision,
+                )?;
+                haproxy::config_file::ConfigFile::remove_from_crt_list(domain)?;
+                backup
+            }
+            Server::Lighttpd => {
+                let backup =
+                    lighttpd::config_file::ConfigFile::create_backup(domain, Operation::Provision)?;
+                lighttpd::config_file::ConfigFile::remove_domain(domain)?;
+                backup
+            }
+            Server::Nginx => {
+                let backup =
+                    nginx::config_file::ConfigFile::create_backup(domain, Operation::Provision)?;
+                nginx::config_file::ConfigFile::remove_domain(domain)?;
+                backup
+            }
+        };
+
+        Ok(vec![backup])
+    }
+
+    /// A failed run is rolled back to the file the strip left, without the site. The
+    /// copies taken before the strip go back instead, so that the site keeps working.
+    fn unstrip<F>(
+        provisioned: Result<(), QicertError>,
+        stripped: &[Backup],
+        restore: F,
+    ) -> Result<(), QicertError>
+    where
+        F: Fn(&Backup) -> Result<(), QicertError>,
+    {
+        let Err(cause) = provisioned else {
+            return Ok(());
+        };
+
+        match stripped.iter().try_for_each(restore) {
+            Ok(_) => Err(cause),
+            Err(e) => Err(QicertError::rollback(cause, e)),
+        }
+    }
+
+    fn create(&self, change: &Change) -> Result<(), QicertError> {
+        let Entry {
+            server,
+            domain,
+            profile,
+            listen,
+            ..
+        } = &change.entry;
+
+        if let Some(resolver) = &self.dns {
+            let check =
+                DnsCheck::new(resolver.clone()).for_acme_server(Settings::current().acme_server());
+
+            for warning in check.verify(domain)? {
+                Report::say(format!("  {warning}"));
+            }
+        }
+
+        let stripped = match change.live.configured {
+            true => Self::strip(&change.entry)?,
+            false => Vec::new(),
+        };
+
+        let provisioned = Provisioner::new(domain.clone(), *server)
+            .profile(profile.clone())
+            .listen(listen.clone())
+            .caddy_tls(change.entry.caddy_tls())
+            .caddy_reload(self.reload.clone())
+            .options(self.options.clone())
+            .run()
+            .into_result();
+
+        Self::unstrip(provisioned, &stripped, |backup| {
+            Backups::current()
+                .restore(backup.id(), &self.reload)
+                .map(|_| ())
+        })
+    }
+
+    fn update(&self, change: &Change) -> Result<(), QicertError> {
+        let Entry {
+            server,
+            domain,
+            profile,
+            listen,
+            ..
+        } = &change.entry;
+
+        match server {
+            Server::Apache => apache::configurator::Configurator::update(domain, profile, listen),
+            Server::Caddy => caddy::configurator::Configurator::update(
+                domain,
+                profile,
+                change.entry.caddy_tls(),
+                &self.reload,
+            ),
+            // Every HAProxy hostname goes to the same upstream, there is nothing to update.
+            Server::Haproxy => Ok(()),
+            Server::Lighttpd => lighttpd::configurator::Configurator::update(domain, profile),
+            Server::Nginx => nginx::configurator::Configurator::update(domain, profile, listen),
+        }
+    }
+
+    fn remove(&self, change: &Change) -> Result<(), QicertError> {
+        let domain = &change.entry.domain;
+
+        if change.live.configured {
+            match change.entry.server {
+                Server::Apache => apache::configurator::Configurator::remove(domain)?,
+                Server::Caddy => caddy::configurator::Configurator::remove(domain, &self.reload)?,
+                Server::Haproxy => haproxy::configurator::Configurator::remove(domain)?,
+                Server::Lighttpd => lighttpd::configurator::Configurator::remove(domain)?,
+                Server::Nginx => nginx::configurator::Configurator::remove(domain)?,
+            }
+        }
+
+        if change.delete_certificate {
+            Certer::delete(domain)?;
+        }
+
+        Ok(())
+    }
+
+    fn execute(&self, change: &Change) -> Result<(), QicertError> {
+        match change.action {
+            Action::Create => self.create(change),
+            Action::Update => self.update(change),
+            Action::Remove => self.remove(change),
+            Action::Keep => Ok(()),
+        }
+    }
+
+    /// Keeps going after a failed change so one bad hostname does not hold back the
+    /// rest, the failed ones are retried on the next run.
+    pub fn run(&self, manifest: &Manifest) -> Result<Vec<Change>, QicertError> {
+        self.options.scope(|| self._run(manifest))
+    }
+
+    fn _run(&self, manifest: &Manifest) -> Result<Vec<Change>, QicertError> {
+        let desired = manifest.entries()?;
+
+        if self.preflight && !self.dry_run {
+            desired
+                .iter()
+                .fold(Preflight::new(), |preflight, entry| {
+                    let certbot =
+                        entry.server != Server::Caddy || entry.caddy_tls() == CaddyTls::Certbot;
+
+                    preflight.server(entry.server, certbot)
+                })
+                .verify()?;
+        }
+
+        let _lock = match self.dry_run {
+            true => None,
+            false => Some(Lock::global(Mode::Exclusive)?),
+        };
+
+        let applied = State::load()?;
+
+        let changes = Plan::diff(&desired, &applied, Self::live);
+
+        if self.dry_run {
+            changes.iter().for_each(Report::say);
+
+            return Ok(changes);
+        }
+
+        let mut recorded = Vec::new();
+        let mut failures = 0;
+
+        for change in &changes {
+            Report::say(change);
+
+            match self.execute(change) {
+                Ok(_) if change.action == Action::Remove => {}
+                Ok(_) => recorded.push(change.entry.clone()),
+                Err(e) => {
+                    Report::say(format!("  {}", e.chain()));
+                    failures += 1;
+
+                    let previous = applied.iter().find(|a| a.is_same_site(&change.entry));
+                    recorded.extend(previous.cloned());
+                }
+            }
+        }
+
+        State::save(&recorded)?;
+
+        if failures > 0 {
+            return Err(ManifestError::Failed(failures))?;
+        }
+
+        Ok(changes)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{backup::BackupError, certer::CertBotError, domain::Domain, test_support::scratch};
+    use std::fs;
+
+    #[test]
+    fn failed_run_after_a_strip_puts_the_site_back() {
+        let dir = scratch("apply-unstrip");
+        let file = dir.join("example.com.conf");
+        let backups = Backups::new(dir.join("backups"), 10);
+        let domain = Domain::new("example", "com", None).unwrap();
+
+        let site = "server {\n    server_name example.com;\n}\n";
+
+        fs::write(&file, site).unwrap();
+
+        let stripped = vec![backups
+            .save(&file, Server::Nginx, &domain, Operation::Provision)
+            .unwrap()];
+
+        // What the provisioner's own rollback leaves: the file as stripped.
+        fs::write(&file, "").unwrap();
+
+        let restore = |backup: &Backup| {
+            fs::copy(backup.path(), backup.metadata().file())
+                .map(|_| ())
+                .map_err(|e| QicertError::io(BackupError::NotRestored, backup.path(), e))
+        };
+
+        assert!(Apply::unstrip(Ok(()), &stripped, restore).is_ok());
+        assert_eq!(fs::read_to_string(&file).unwrap(), "");
+
+        let failed = Err(QicertError::failed(CertBotError::ProcessFailure));
+        let error = Apply::unstrip(failed, &stripped, restore).unwrap_err();
+
+        assert!(matches!(
+            error.downcast_ref(),
+            Some(CertBotError::ProcessFailure)
+        ));
+        assert_eq!(fs::read_to_string(&file).unwrap(), site);
+    }
+}
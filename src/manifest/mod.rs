@@ -0,0 +1,440 @@
+mod apply;
+mod plan;
+mod state;
+
+use std::{
+    collections::HashSet,
+    error::Error,
+    fmt::Display,
+    fs,
+    net::{IpAddr, SocketAddr},
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    caddy::CaddyTls, domain::Domain, error::QicertError, haproxy::http_config::HttpConfig,
+    layout::Server, listen::Listen, profile::Profile,
+};
+
+pub use apply::Apply;
+
+#[derive(Debug)]
+pub enum ManifestError {
+    Unreadable,
+    Invalid(String),
+    Hostname {
+        hostname: String,
+        reason: String,
+    },
+    Profile {
+        hostname: String,
+        reason: &'static str,
+    },
+    Duplicate(String),
+    StateNotSaved,
+    Failed(usize),
+}
+
+impl Display for ManifestError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Unreadable => write!(f, "The manifest could not be read"),
+            Self::Invalid(reason) => write!(f, "Invalid manifest: {reason}"),
+            Self::Hostname { hostname, reason } => write!(f, "{hostname}: {reason}"),
+            Self::Profile { hostname, reason } => write!(f, "{hostname}: {reason}"),
+            Self::Duplicate(hostname) => write!(f, "{hostname} is listed more than once"),
+            Self::StateNotSaved => write!(f, "The applied state could not be saved"),
+            Self::Failed(count) => write!(f, "{count} change(s) could not be applied"),
+        }
+    }
+}
+
+impl Error for ManifestError {}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ProfileKind {
+    #[default]
+    Static,
+    Proxy,
+}
+
+/// Who obtains the certificate, only Caddy can do it by itself.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Certificates {
+    Certbot,
+    Automatic,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct Tls {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub certificates: Option<Certificates>,
+}
+
+/// The `listen` table of a site, named after the command line options.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct ListenOptions {
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub bind: Vec<IpAddr>,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub ipv6: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub http_port: Option<u16>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub https_port: Option<u16>,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub default_server: bool,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub http2: bool,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub http3: bool,
+}
+
+impl ListenOptions {
+    fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+
+    fn listen(&self) -> Listen {
+        self.bind
+            .iter()
+            .copied()
+            .fold(Listen::default(), Listen::with_address)
+            .with_dual_stack(self.ipv6)
+            .with_http_port(self.http_port.unwrap_or(Listen::HTTP_PORT))
+            .with_https_port(self.https_port.unwrap_or(Listen::HTTPS_PORT))
+            .with_default_server(self.default_server)
+            .with_http2(self.http2)
+            .with_http3(self.http3)
+    }
+}
+
+impl From<&Listen> for ListenOptions {
+    fn from(listen: &Listen) -> Self {
+        Self {
+            bind: listen.addresses().to_vec(),
+            ipv6: listen.is_dual_stack(),
+            http_port: (listen.http_port() != Listen::HTTP_PORT).then_some(listen.http_port()),
+            https_port: (listen.https_port() != Listen::HTTPS_PORT).then_some(listen.https_port()),
+            default_server: listen.is_default_server(),
+            http2: listen.has_http2(),
+            http3: listen.has_http3(),
+        }
+    }
+}
+
+/// A `[[site]]` table of the manifest.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct Site {
+    pub webserver: Server,
+    pub hostname: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub aliases: Vec<String>,
+    #[serde(default)]
+    pub profile: ProfileKind,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub root: Option<PathBuf>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub upstream: Option<SocketAddr>,
+    #[serde(default)]
+    pub tls: Tls,
+    #[serde(default, skip_serializing_if = "ListenOptions::is_default")]
+    pub listen: ListenOptions,
+}
+
+/// One hostname of a site, the unit qicert provisions.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+    pub server: Server,
+    pub domain: Domain,
+    pub profile: Profile,
+    pub certificates: Certificates,
+    pub listen: Listen,
+}
+
+impl Entry {
+    pub fn caddy_tls(&self) -> CaddyTls {
+        match self.certificates {
+            Certificates::Automatic => CaddyTls::Automatic,
+            Certificates::Certbot => CaddyTls::Certbot,
+        }
+    }
+
+    fn is_same_site(&self, other: &Self) -> bool {
+        self.server == other.server && self.domain == other.domain
+    }
+}
+
+impl Site {
+    fn profile(&self, domain: &Domain, hostname: &str) -> Result<Profile, ManifestError> {
+        let error = |reason| ManifestError::Profile {
+            hostname: hostname.to_string(),
+            reason,
+        };
+
+        if self.webserver == Server::Haproxy {
+            if self.root.is_some() || self.upstream.is_some() {
+                return Err(error("HAProxy sites always forward to the qicert upstream"));
+            }
+
+            let upstream = HttpConfig::UPSTREAM
+                .parse()
+                .map_err(|_| error("invalid HAProxy upstream"))?;
+
+            return Ok(Profile::Proxy(upstream));
+        }
+
+        match (self.profile, &self.root, self.upstream) {
+            (ProfileKind::Static, Some(root), None) => Ok(Profile::Static(root.clone())),
+            (ProfileKind::Static, None, None) => Ok(Profile::default_for(domain)),
+            (ProfileKind::Static, _, Some(_)) => {
+                Err(error("upstream is only used by the proxy profile"))
+            }
+            (ProfileKind::Proxy, None, Some(upstream)) => Ok(Profile::Proxy(upstream)),
+            (ProfileKind::Proxy, _, None) => Err(error("the proxy profile needs an upstream")),
+            (ProfileKind::Proxy, Some(_), _) => {
+                Err(error("root is only used by the static profile"))
+            }
+        }
+    }
+
+    fn certificates(&self, hostname: &str) -> Result<Certificates, ManifestError> {
+        match (self.webserver, self.tls.certificates) {
+            (Server::Caddy, None) => Ok(Certificates::Automatic),
+            (_, None) => Ok(Certificates::Certbot),
+            (Server::Caddy, Some(certificates))
+            | (_, Some(certificates @ Certificates::Certbot)) => Ok(certificates),
+            (_, Some(Certificates::Automatic)) => Err(ManifestError::Profile {
+                hostname: hostname.to_string(),
+                reason: "only Caddy obtains certificates automatically",
+            }),
+        }
+    }
+
+    fn listen(&self, hostname: &str) -> Result<Listen, ManifestError> {
+        let listen = self.listen.listen();
+
+        listen
+            .supported_by(self.webserver)
+            .map_err(|e| ManifestError::Hostname {
+                hostname: hostname.to_string(),
+                reason: e.to_string(),
+            })?;
+
+        Ok(listen)
+    }
+
+    fn entries(&self) -> Result<Vec<Entry>, ManifestError> {
+        std::iter::once(&self.hostname)
+            .chain(&self.aliases)
+            .map(|hostname| {
+                let domain =
+                    Domain::from_hostname(hostname).map_err(|e| ManifestError::Hostname {
+                        hostname: hostname.to_string(),
+                        reason: e.to_string(),
+                    })?;
+
+                Ok(Entry {
+                    server: self.webserver,
+                    profile: self.profile(&domain, hostname)?,
+                    certificates: self.certificates(hostname)?,
+                    listen: self.listen(hostname)?,
+                    domain,
+                })
+            })
+            .collect()
+    }
+}
+
+impl From<&Entry> for Site {
+    fn from(entry: &Entry) -> Self {
+        let (profile, root, upstream) = match &entry.profile {
+            _ if entry.server == Server::Haproxy => (ProfileKind::Proxy, None, None),
+            profile if profile.uses_default_webroot(&entry.domain) => {
+                (ProfileKind::Static, None, None)
+            }
+            Profile::Static(root) => (ProfileKind::Static, Some(root.clone()), None),
+            Profile::Proxy(upstream) => (ProfileKind::Proxy, None, Some(*upstream)),
+        };
+
+        Self {
+            webserver: entry.server,
+            hostname: entry.domain.to_string(),
+            aliases: Vec::new(),
+            profile,
+            root,
+            upstream,
+            tls: Tls {
+                certificates: Some(entry.certificates),
+            },
+            listen: ListenOptions::from(&entry.listen),
+        }
+    }
+}
+
+/// Sites listed in a `sites.toml` file given to `qicert apply`.
+#[derive(Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct Manifest {
+    #[serde(default, rename = "site")]
+    pub sites: Vec<Site>,
+}
+
+impl Manifest {
+    pub fn parse(manifest: &str) -> Result<Self, ManifestError> {
+        toml::from_str(manifest).map_err(|e| ManifestError::Invalid(e.message().to_string()))
+    }
+
+    pub fn read(path: &Path) -> Result<Self, QicertError> {
+        let manifest = fs::read_to_string(path)
+            .map_err(|e| QicertError::io(ManifestError::Unreadable, path, e))?;
+
+        Ok(Self::parse(&manifest)?)
+    }
+
+    /// Every hostname of every site, aliases included.
+    pub fn entries(&self) -> Result<Vec<Entry>, ManifestError> {
+        let mut seen = HashSet::new();
+        let mut entries = Vec::new();
+
+        for site in &self.sites {
+            for entry in site.entries()? {
+                if !seen.insert(entry.domain.to_string()) {
+                    return Err(ManifestError::Duplicate(entry.domain.unicode()));
+                }
+
+                entries.push(entry);
+            }
+        }
+
+        Ok(entries)
+    }
+
+    pub fn from_entries(entries: &[Entry]) -> Self {
+        Self {
+            sites: entries.iter().map(Site::from).collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const MANIFEST: &str = r#"
+[[site]]
+webserver = "nginx"
+hostname = "example.com"
+aliases = ["www.example.com"]
+
+[[site]]
+webserver = "apache"
+hostname = "app.example.co.uk"
+profile = "proxy"
+upstream = "127.0.0.1:3000"
+
+[[site]]
+webserver = "caddy"
+hostname = "docs.example.com"
+root = "/srv/docs"
+
+[site.tls]
+certificates = "certbot"
+
+[[site]]
+webserver = "nginx"
+hostname = "v6.example.com"
+
+[site.listen]
+bind = ["2001:db8::10"]
+https_port = 8443
+http2 = true
+"#;
+
+    #[test]
+    fn sites_expand_to_one_entry_per_hostname() {
+        let entries = Manifest::parse(MANIFEST).unwrap().entries().unwrap();
+
+        let hostnames: Vec<String> = entries.iter().map(|e| e.domain.to_string()).collect();
+
+        assert_eq!(
+            hostnames,
+            vec![
+                "example.com",
+                "www.example.com",
+                "app.example.co.uk",
+                "docs.example.com",
+                "v6.example.com"
+            ]
+        );
+
+        assert_eq!(entries[1].server, Server::Nginx);
+        assert_eq!(entries[1].profile, Profile::default_for(&entries[1].domain));
+        assert_eq!(entries[1].certificates, Certificates::Certbot);
+
+        assert_eq!(
+            entries[2].profile,
+            Profile::Proxy("127.0.0.1:3000".parse().unwrap())
+        );
+
+        assert_eq!(entries[3].profile, Profile::Static("/srv/docs".into()));
+        assert_eq!(entries[3].caddy_tls(), CaddyTls::Certbot);
+        assert_eq!(entries[3].listen, Listen::default());
+
+        assert_eq!(
+            entries[4].listen,
+            Listen::default()
+                .with_address("2001:db8::10".parse().unwrap())
+                .with_https_port(8443)
+                .with_http2(true)
+        );
+    }
+
+    #[test]
+    fn invalid_sites_are_reported() {
+        let manifests = vec![
+            "[[site]]\nwebserver = \"nginx\"\nhostname = \"co.uk\"\n",
+            "[[site]]\nwebserver = \"nginx\"\nhostname = \"a.com\"\nprofile = \"proxy\"\n",
+            "[[site]]\nwebserver = \"nginx\"\nhostname = \"a.com\"\nupstream = \"127.0.0.1:1\"\n",
+            "[[site]]\nwebserver = \"haproxy\"\nhostname = \"a.com\"\nroot = \"/srv\"\n",
+            "[[site]]\nwebserver = \"nginx\"\nhostname = \"a.com\"\n[site.tls]\ncertificates = \"automatic\"\n",
+            "[[site]]\nwebserver = \"nginx\"\nhostname = \"a.com\"\naliases = [\"a.com\"]\n",
+            "[[site]]\nwebserver = \"lighttpd\"\nhostname = \"a.com\"\n[site.listen]\nhttp2 = true\n",
+        ];
+
+        for manifest in manifests {
+            let entries = Manifest::parse(manifest).and_then(|m| m.entries());
+
+            assert!(entries.is_err(), "{manifest}");
+        }
+    }
+
+    #[test]
+    fn unknown_keys_are_rejected() {
+        let manifest = "[[site]]\nwebserver = \"nginx\"\nhostname = \"a.com\"\nport = 80\n";
+
+        assert!(matches!(
+            Manifest::parse(manifest),
+            Err(ManifestError::Invalid(_))
+        ));
+    }
+
+    #[test]
+    fn entries_round_trip_through_a_manifest() {
+        let entries = Manifest::parse(MANIFEST).unwrap().entries().unwrap();
+
+        let recorded = toml::to_string(&Manifest::from_entries(&entries)).unwrap();
+
+        assert_eq!(
+            Manifest::parse(&recorded).unwrap().entries().unwrap(),
+            entries
+        );
+    }
+}
@@ -0,0 +1,327 @@
+use std::fmt::Display;
+
+use super::{Certificates, Entry};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    Create,
+    Update,
+    Remove,
+    Keep,
+}
+
+impl Display for Action {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Create => write!(f, "create"),
+            Self::Update => write!(f, "update"),
+            Self::Remove => write!(f, "remove"),
+            Self::Keep => write!(f, "keep"),
+        }
+    }
+}
+
+/// What is on disk for an entry right now.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Live {
+    pub configured: bool,
+    pub certificate: bool,
+    pub enabled: bool,
+    /// Its region was written by another qicert version.
+    pub outdated: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Change {
+    pub action: Action,
+    pub entry: Entry,
+    pub live: Live,
+    pub reason: &'static str,
+    /// Only for a removal, false while another server still serves the hostname
+    /// with the same certificate.
+    pub delete_certificate: bool,
+}
+
+impl Display for Change {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{:<6} {:?} {} ({})",
+            self.action,
+            self.entry.server,
+            self.entry.domain.unicode(),
+            self.reason
+        )
+    }
+}
+
+pub struct Plan;
+
+impl Plan {
+    fn change(action: Action, entry: &Entry, live: Live, reason: &'static str) -> Change {
+        Change {
+            action,
+            entry: entry.clone(),
+            live,
+            reason,
+            delete_certificate: false,
+        }
+    }
+
+    fn desired_change(entry: &Entry, previous: Option<&Entry>, live: Live) -> Change {
+        // Caddy keeps the certificates it obtains by itself out of /etc/letsencrypt.
+        let needs_certificate = entry.certificates == Certificates::Certbot && !live.certificate;
+
+        if !live.configured {
+            return Self::change(Action::Create, entry, live, "not configured");
+        }
+
+        if needs_certificate {
+            return Self::change(Action::Create, entry, live, "no certificate");
+        }
+
+        if previous.is_some_and(|p| p != entry) {
+            return Self::change(Action::Update, entry, live, "settings changed");
+        }
+
+        if !live.enabled {
+            return Self::change(Action::Update, entry, live, "not enabled");
+        }
+
+        if live.outdated {
+            return Self::change(
+                Action::Update,
+                entry,
+                live,
+                "written by another qicert version",
+            );
+        }
+
+        Self::change(Action::Keep, entry, live, "up to date")
+    }
+
+    /// Compares the manifest with what the previous run applied and what is on disk.
+    /// Hostnames applied before and missing from the manifest are removed.
+    pub fn diff(
+        desired: &[Entry],
+        applied: &[Entry],
+        live: impl Fn(&Entry) -> Live,
+    ) -> Vec<Change> {
+        let mut changes: Vec<Change> = desired
+            .iter()
+            .map(|entry| {
+                let previous = applied.iter().find(|a| a.is_same_site(entry));
+
+                Self::desired_change(entry, previous, live(entry))
+            })
+            .collect();
+
+        let removed = applied
+            .iter()
+            .filter(|a| !desired.iter().any(|d| d.is_same_site(a)))
+            .filter_map(|entry| {
+                let live = live(entry);
+                let moved = desired.iter().any(|d| d.domain == entry.domain);
+
+                let mut change = match moved {
+                    true => Self::change(Action::Remove, entry, live, "moved to another server"),
+                    false => Self::change(Action::Remove, entry, live, "not in the manifest"),
+                };
+
+                change.delete_certificate = live.certificate && !moved;
+
+                (live.configured || change.delete_certificate).then_some(change)
+            });
+
+        changes.extend(removed);
+
+        changes
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{domain::Domain, layout::Server, listen::Listen, profile::Profile};
+
+    fn entry(hostname: &str) -> Entry {
+        let domain = Domain::from_hostname(hostname).unwrap();
+
+        Entry {
+            server: Server::Nginx,
+            profile: Profile::default_for(&domain),
+            certificates: Certificates::Certbot,
+            listen: Listen::default(),
+            domain,
+        }
+    }
+
+    const DONE: Live = Live {
+        configured: true,
+        certificate: true,
+        enabled: true,
+        outdated: false,
+    };
+
+    fn actions(changes: &[Change]) -> Vec<(String, Action, &'static str)> {
+        changes
+            .iter()
+            .map(|c| (c.entry.domain.to_string(), c.action, c.reason))
+            .collect()
+    }
+
+    #[test]
+    fn first_run_creates_everything() {
+        let desired = vec![entry("example.com"), entry("www.example.com")];
+
+        let changes = Plan::diff(&desired, &[], |_| Live::default());
+
+        assert_eq!(
+            actions(&changes),
+            vec![
+                ("example.com".into(), Action::Create, "not configured"),
+                ("www.example.com".into(), Action::Create, "not configured"),
+            ]
+        );
+    }
+
+    #[test]
+    fn second_run_keeps_everything() {
+        let desired = vec![entry("example.com"), entry("www.example.com")];
+
+        let changes = Plan::diff(&desired, &desired, |_| DONE);
+
+        assert!(changes.iter().all(|c| c.action == Action::Keep));
+    }
+
+    #[test]
+    fn changed_settings_update_the_site() {
+        let applied = vec![entry("example.com")];
+
+        let mut proxied = entry("example.com");
+        proxied.profile = Profile::Proxy("127.0.0.1:3000".parse().unwrap());
+
+        let changes = Plan::diff(&[proxied], &applied, |_| DONE);
+
+        assert_eq!(
+            actions(&changes),
+            vec![("example.com".into(), Action::Update, "settings changed")]
+        );
+    }
+
+    #[test]
+    fn missing_certificate_or_link_is_repaired() {
+        let desired = vec![entry("example.com"), entry("www.example.com")];
+
+        let changes = Plan::diff(&desired, &desired, |e| Live {
+            certificate: e.domain.to_string() != "example.com",
+            enabled: e.domain.to_string() != "www.example.com",
+            ..DONE
+        });
+
+        assert_eq!(
+            actions(&changes),
+            vec![
+                ("example.com".into(), Action::Create, "no certificate"),
+                ("www.example.com".into(), Action::Update, "not enabled"),
+            ]
+        );
+    }
+
+    #[test]
+    fn regions_of_other_versions_are_regenerated() {
+        let desired = vec![entry("example.com")];
+
+        let changes = Plan::diff(&desired, &desired, |_| Live {
+            outdated: true,
+            ..DONE
+        });
+
+        assert_eq!(
+            actions(&changes),
+            vec![(
+                "example.com".into(),
+                Action::Update,
+                "written by another qicert version"
+            )]
+        );
+    }
+
+    #[test]
+    fn moved_hostnames_keep_their_certificate() {
+        let applied = vec![entry("example.com"), entry("old.example.com")];
+
+        let mut moved = entry("example.com");
+        moved.server = Server::Apache;
+
+        let changes = Plan::diff(&[moved], &applied, |e| match e.server {
+            Server::Apache => Live {
+                configured: false,
+                ..DONE
+            },
+            _ => DONE,
+        });
+
+        assert_eq!(
+            actions(&changes),
+            vec![
+                ("example.com".into(), Action::Create, "not configured"),
+                (
+                    "example.com".into(),
+                    Action::Remove,
+                    "moved to another server"
+                ),
+                (
+                    "old.example.com".into(),
+                    Action::Remove,
+                    "not in the manifest"
+                ),
+            ]
+        );
+
+        assert_eq!(changes[1].entry.server, Server::Nginx);
+        assert!(!changes[1].delete_certificate);
+        assert!(changes[2].delete_certificate);
+
+        // Nothing to remove once the old server's configuration is gone.
+        let changes = Plan::diff(&[changes[0].entry.clone()], &applied[..1], |e| Live {
+            configured: e.server == Server::Apache,
+            ..DONE
+        });
+
+        assert_eq!(
+            actions(&changes),
+            vec![("example.com".into(), Action::Keep, "up to date")]
+        );
+    }
+
+    #[test]
+    fn dropped_hostnames_are_removed_once() {
+        let applied = vec![entry("example.com"), entry("old.example.com")];
+        let desired = vec![entry("example.com")];
+
+        let changes = Plan::diff(&desired, &applied, |_| DONE);
+
+        assert_eq!(
+            actions(&changes),
+            vec![
+                ("example.com".into(), Action::Keep, "up to date"),
+                (
+                    "old.example.com".into(),
+                    Action::Remove,
+                    "not in the manifest"
+                ),
+            ]
+        );
+
+        let changes = Plan::diff(&desired, &applied, |e| {
+            if e.domain.to_string() == "old.example.com" {
+                Live::default()
+            } else {
+                DONE
+            }
+        });
+
+        assert_eq!(changes.len(), 1);
+    }
+}
@@ -0,0 +1,66 @@
+use std::{fs, io, path::Path};
+
+use crate::error::QicertError;
+
+use super::{Entry, Manifest, ManifestError};
+
+/// Hostnames applied by the last `qicert apply`, kept in the manifest format.
+pub struct State;
+
+impl State {
+    pub const PATH: &'static str = "/var/lib/qicert/applied.toml";
+
+    pub fn load_from(path: &Path) -> Result<Vec<Entry>, QicertError> {
+        if !path.exists() {
+            return Ok(Vec::new());
+        }
+
+        Ok(Manifest::read(path)?.entries()?)
+    }
+
+    pub fn save_to(path: &Path, entries: &[Entry]) -> Result<(), QicertError> {
+        let state = toml::to_string(&Manifest::from_entries(entries)).map_err(|e| {
+            QicertError::io(ManifestError::StateNotSaved, path, io::Error::other(e))
+        })?;
+
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)
+                .map_err(|e| QicertError::io(ManifestError::StateNotSaved, parent, e))?;
+        }
+
+        fs::write(path, state).map_err(|e| QicertError::io(ManifestError::StateNotSaved, path, e))
+    }
+
+    pub fn load() -> Result<Vec<Entry>, QicertError> {
+        Self::load_from(Path::new(Self::PATH))
+    }
+
+    pub fn save(entries: &[Entry]) -> Result<(), QicertError> {
+        Self::save_to(Path::new(Self::PATH), entries)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_support::scratch;
+
+    #[test]
+    fn missing_state_is_empty_and_saved_state_loads_back() {
+        let dir = scratch("state");
+        let path = dir.join("applied.toml");
+
+        assert_eq!(State::load_from(&path).unwrap(), vec![]);
+
+        let entries = Manifest::parse(
+            "[[site]]\nwebserver = \"lighttpd\"\nhostname = \"example.com\"\naliases = [\"www.example.com\"]\n",
+        )
+        .unwrap()
+        .entries()
+        .unwrap();
+
+        State::save_to(&path, &entries).unwrap();
+
+        assert_eq!(State::load_from(&path).unwrap(), entries);
+    }
+}
@@ -1,6 +1,17 @@
-use std::{error::Error, fmt::Display, fs};
-
-use crate::{configuration_file::ConfigurationFile, domain::Domain};
+use std::{
+    error::Error,
+    fmt::Display,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    backup::{Backup, Operation},
+    configuration_file::ConfigurationFile,
+    domain::Domain,
+    error::QicertError,
+    layout::Server,
+};
 
 #[derive(Debug)]
 pub enum ConfigError {
@@ -9,6 +20,8 @@ pub enum ConfigError {
     InvalidPath,
     SymlinkExists,
     FileExists,
+    Including,
+    NoHttpBlock { main: PathBuf, directive: String },
 }
 
 impl Display for ConfigError {
@@ -19,14 +32,20 @@ impl Display for ConfigError {
             Self::InvalidPath => write!(f, "An invalid path was given for a configuration file"),
             Self::SymlinkExists => write!(f, "Symlink already exists"),
             Self::FileExists => write!(f, "Configuration file already exists"),
+            Self::Including => write!(f, "The main Nginx configuration could not be updated"),
+            Self::NoHttpBlock { main, directive } => write!(
+                f,
+                "{} has no http block to add `{directive}` to, add it by hand",
+                main.display()
+            ),
         }
     }
 }
 
 impl Error for ConfigError {}
 
-impl<'a> ConfigurationFile<'a> for ConfigFile {
-    const SITES_AVAILABLE: &'a str = "/etc/nginx/sites-available";
+impl ConfigurationFile for ConfigFile {
+    const SERVER: Server = Server::Nginx;
 
     fn server_name(domain: &Domain) -> String {
         format!("server_name {};", domain)
@@ -36,26 +55,128 @@ impl<'a> ConfigurationFile<'a> for ConfigFile {
 pub struct ConfigFile;
 
 impl ConfigFile {
-    pub fn chown_to_www(domain: &Domain) -> Result<(), ConfigError> {
+    pub fn chown_to_www(domain: &Domain) -> Result<(), QicertError> {
         Self::_chown_to_www(domain, ConfigError::FileSaving)
     }
 
-    pub fn create(domain: &Domain) -> Result<fs::File, ConfigError> {
+    pub fn create(domain: &Domain) -> Result<fs::File, QicertError> {
         Self::_create(domain, ConfigError::FileExists, ConfigError::FileSaving)
     }
 
-    pub fn create_backup(domain: &Domain) -> Result<(), ConfigError> {
-        Self::_create_backup(domain, ConfigError::FileSaving)
+    pub fn create_backup(domain: &Domain, operation: Operation) -> Result<Backup, QicertError> {
+        Self::_create_backup(domain, operation)
+    }
+
+    pub fn restore(domain: &Domain, existed: bool) -> Result<(), QicertError> {
+        Self::_restore(domain, existed, ConfigError::FileSaving)
+    }
+
+    pub fn remove_domain(domain: &Domain) -> Result<(), QicertError> {
+        Self::_remove_domain(domain, ConfigError::FileSaving)
+    }
+
+    pub fn read(domain: &Domain, file: &mut fs::File) -> Result<String, QicertError> {
+        Self::_read(domain, file, ConfigError::InvalidPath)
+    }
+
+    pub fn write_block(
+        domain: &Domain,
+        file: &mut fs::File,
+        block: &str,
+    ) -> Result<(), QicertError> {
+        Self::_write(domain, file, block, ConfigError::FileSaving)
+    }
+
+    pub fn reset(domain: &Domain, file: &mut fs::File, content: &str) -> Result<(), QicertError> {
+        Self::_reset(domain, file, content, ConfigError::FileSaving)
+    }
+
+    pub fn write_region(
+        domain: &Domain,
+        file: &mut fs::File,
+        body: &str,
+    ) -> Result<(), QicertError> {
+        Self::_write_region(domain, file, body, ConfigError::FileSaving)
+    }
+
+    pub fn clear_stale_challenge(
+        domain: &Domain,
+        file: &mut fs::File,
+        content: &mut String,
+    ) -> Result<bool, QicertError> {
+        Self::_clear_stale_challenge(domain, file, content, ConfigError::FileSaving)
     }
 
-    pub fn append(domain: &Domain) -> Result<fs::File, ConfigError> {
+    pub fn append(domain: &Domain) -> Result<fs::File, QicertError> {
         Self::_append(domain, ConfigError::InvalidPath)
     }
+
+    pub fn include_directive() -> String {
+        format!("include {}/*.conf;", Self::sites_enabled_path().display())
+    }
+
+    pub fn has_include<S: AsRef<str>>(main: S) -> bool {
+        let directive = Self::include_directive();
+
+        main.as_ref().lines().map(str::trim).any(|l| l == directive)
+    }
+
+    /// `main` with the include line first in its http block, none without one.
+    fn with_include(main: &str) -> Option<String> {
+        let mut offset = 0;
+
+        for line in main.split_inclusive('\n') {
+            offset += line.len();
+
+            let code = line.split('#').next().unwrap_or_default().trim();
+
+            if code
+                .strip_prefix("http")
+                .is_some_and(|rest| rest.trim() == "{")
+            {
+                let indent = &line[..line.len() - line.trim_start().len()];
+
+                return Some(format!(
+                    "{}{}{indent}    {}\n{}",
+                    &main[..offset],
+                    if line.ends_with('\n') { "" } else { "\n" },
+                    Self::include_directive(),
+                    &main[offset..]
+                ));
+            }
+        }
+
+        None
+    }
+
+    /// Makes sure `main`, the main configuration, includes the directory managed by
+    /// qicert.
+    pub fn ensure_include(main: &Path) -> Result<(), QicertError> {
+        let content = fs::read_to_string(main)
+            .map_err(|e| QicertError::io(ConfigError::Including, main, e))?;
+
+        if Self::has_include(&content) {
+            return Ok(());
+        }
+
+        let Some(included) = Self::with_include(&content) else {
+            return Err(ConfigError::NoHttpBlock {
+                main: main.to_path_buf(),
+                directive: Self::include_directive(),
+            })?;
+        };
+
+        fs::write(main, included).map_err(|e| QicertError::io(ConfigError::Including, main, e))?;
+
+        Ok(())
+    }
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::region::{Region, RegionError};
+    use crate::test_support::debian_layout;
     use std::path::PathBuf;
 
     #[test]
@@ -138,6 +259,8 @@ mod test {
 
     #[test]
     fn config_file_path_without_subdomain() {
+        debian_layout(ConfigFile::SERVER);
+
         let domain = Domain::new_unchecked("example", "com", None);
 
         let expected = PathBuf::from("/etc/nginx/sites-available/example.com.conf");
@@ -149,6 +272,8 @@ mod test {
 
     #[test]
     fn config_file_path_with_subdomain() {
+        debian_layout(ConfigFile::SERVER);
+
         let domain = Domain::new_unchecked("example", "com", Some("www"));
 
         let expected = PathBuf::from("/etc/nginx/sites-available/example.com.conf");
@@ -159,24 +284,160 @@ mod test {
     }
 
     #[test]
-    fn backup_file_path() {
+    fn backup_dir_path() {
+        debian_layout(ConfigFile::SERVER);
+
         let domain = Domain::new_unchecked("example", "com", None);
 
-        let expected = PathBuf::from("/etc/nginx/sites-available/example.com.conf.bak");
+        let expected =
+            PathBuf::from("/var/lib/qicert/backups/etc-nginx-sites-available-example.com.conf");
 
-        let backup_path = ConfigFile::backup_path(&domain);
+        let backup_dir = ConfigFile::backup_dir(&domain);
 
-        assert_eq!(backup_path, expected);
+        assert_eq!(backup_dir, expected);
     }
 
     #[test]
-    fn backup_file_path_with_subdomain() {
+    fn backup_dir_path_with_subdomain() {
+        debian_layout(ConfigFile::SERVER);
+
         let domain = Domain::new_unchecked("example", "com", Some("www"));
 
-        let expected = PathBuf::from("/etc/nginx/sites-available/example.com.conf.bak");
+        let expected =
+            PathBuf::from("/var/lib/qicert/backups/etc-nginx-sites-available-example.com.conf");
+
+        let backup_dir = ConfigFile::backup_dir(&domain);
+
+        assert_eq!(backup_dir, expected);
+    }
+
+    #[test]
+    fn include_opens_the_http_block() {
+        debian_layout(ConfigFile::SERVER);
+
+        let main = "user http;\n\nhttp {\n    sendfile on;\n}\n";
+
+        let included = ConfigFile::with_include(main).unwrap();
+
+        assert_eq!(
+            included,
+            "user http;\n\nhttp {\n    include /etc/nginx/sites-available/*.conf;\n    sendfile on;\n}\n"
+        );
+        assert!(ConfigFile::has_include(&included));
+        assert!(!ConfigFile::has_include(main));
+
+        assert_eq!(ConfigFile::with_include("events {}\n"), None);
+    }
+
+    #[test]
+    fn remove_domain_keeps_other_server_blocks() {
+        let domain = Domain::new("example", "com", Some("www")).unwrap();
+
+        let haystack = "server {
+            listen 80;
+            server_name example.com;
+            return 301 https://example.com$request_uri;
+    }
+server {
+            listen 80;
+            server_name www.example.com;
+            return 301 https://www.example.com$request_uri;
+    }
+server {
+            server_name www.example.com;
+            listen 443 ssl;
+            location / {
+                try_files $uri $uri/ =404;
+            }
+    }
+";
+
+        let expected = "server {
+            listen 80;
+            server_name example.com;
+            return 301 https://example.com$request_uri;
+    }
+";
+
+        assert_eq!(
+            ConfigFile::remove_domain_from_str(haystack, &domain),
+            expected
+        );
+    }
+
+    #[test]
+    fn stale_challenge_block_is_found_and_removed_with_its_markers() {
+        let domain = Domain::new("example", "com", Some("www")).unwrap();
+
+        let site = "server {
+            listen 80;
+            server_name example.com;
+            return 301 https://example.com$request_uri;
+    }
+";
+
+        let challenge = ConfigFile::mark_challenge(
+            &domain,
+            "server {
+            listen 80;
+            server_name www.example.com;
+    }",
+        );
+
+        let haystack = format!("{site}{challenge}\n");
+
+        assert!(ConfigFile::find_challenge_in_str(&haystack, &domain));
+        assert!(!ConfigFile::find_challenge_in_str(
+            &haystack,
+            &Domain::new("example", "com", None).unwrap()
+        ));
+
+        assert_eq!(
+            ConfigFile::remove_challenge_from_str(&haystack, &domain),
+            site
+        );
+        assert_eq!(ConfigFile::remove_domain_from_str(&haystack, &domain), site);
+
+        // Cut short before the end marker was written.
+        let truncated = format!("{site}# BEGIN qicert-challenge www.example.com\nserver {{\n");
+
+        assert_eq!(
+            ConfigFile::remove_challenge_from_str(&truncated, &domain),
+            site
+        );
+    }
+
+    #[test]
+    fn only_the_region_of_the_domain_is_removed() {
+        let domain = Domain::new("example", "com", Some("www")).unwrap();
+
+        let own = "server {
+    listen 8080;
+    server_name www.example.com;
+}
+";
+
+        let region = Region::wrap(
+            &domain,
+            "server {
+    server_name www.example.com;
+    listen 443 ssl;
+}",
+        );
+
+        let haystack = format!("{own}{region}\n");
+
+        assert_eq!(ConfigFile::remove_domain_from_str(&haystack, &domain), own);
+
+        assert!(ConfigFile::ensure_region_untouched(&haystack, &domain).is_ok());
+
+        let edited = haystack.replace("listen 443 ssl;", "listen 443 ssl http2;");
 
-        let backup_path = ConfigFile::backup_path(&domain);
+        let error = ConfigFile::ensure_region_untouched(&edited, &domain).unwrap_err();
 
-        assert_eq!(backup_path, expected);
+        assert!(matches!(
+            error.downcast_ref::<RegionError>(),
+            Some(RegionError::Edited { hostname, .. }) if hostname == "www.example.com"
+        ));
     }
 }
@@ -1,146 +1,426 @@
 use std::{
-    error::Error,
-    fs::File,
-    io::{Read, Write},
+    fs::{self, File},
+    path::{Path, PathBuf},
 };
 
 use crate::nginx::config_file::{ConfigError, ConfigFile};
 use crate::nginx::http_config::HttpConfig;
 use crate::nginx::linker::Linker;
+use crate::nginx::upgrade::{HttpBlock, UpgradeError};
 use crate::{configuration_file::ConfigurationFile, nginx::Nginx, webserver::WebServer};
 
-use crate::{certer::Certer, domain::Domain, webroot::WebRoot};
+use crate::{
+    audit::Audit,
+    backup::{Backups, Operation},
+    certer::{CertBotError, Certer},
+    domain::Domain,
+    error::QicertError,
+    listen::Listen,
+    nginx::NginxError,
+    profile::Profile,
+    report::{Action, Report},
+    webroot::WebRoot,
+};
 
 pub struct Configurator;
 
 impl Configurator {
-    fn create_file_and_link(domain: &Domain) -> Result<File, Box<dyn Error>> {
+    fn create_file_and_link(domain: &Domain) -> Result<File, QicertError> {
         if ConfigFile::file_exists(domain) {
             return Err(ConfigError::InvalidPath)?;
         }
 
-        let file = ConfigFile::create(domain)?;
+        let path = ConfigFile::file_path(domain);
+
+        let file = Report::step(
+            Action::CreateConfig,
+            Some(&path),
+            ConfigFile::create(domain),
+        )?;
 
         ConfigFile::chown_to_www(domain)?;
 
+        let link = Linker::symlink_path(domain);
+
         let message = match Linker::create(domain) {
-            Ok(_) => "Link created",
-            Err(ConfigError::SymlinkExists) => "Link exists. Skipping",
-            Err(ConfigError::Linking) => "Missing permissions",
-            _ => panic!("Unexpected error!"),
+            Ok(_) => {
+                Report::done(Action::Link, link.as_deref());
+                "Link created"
+            }
+            Err(e) => match e.downcast_ref::<ConfigError>() {
+                Some(ConfigError::SymlinkExists) => {
+                    Report::skipped(Action::Link, link.as_deref());
+                    "Link exists. Skipping"
+                }
+                Some(ConfigError::Linking) => {
+                    Report::failed(Action::Link, link.as_deref(), &e);
+                    "Missing permissions"
+                }
+                _ => return Err(e),
+            },
         };
 
-        println!("{message}");
+        Report::say(message);
 
         Ok(file)
     }
 
-    fn add_well_known(file: &mut File, domain: &Domain) -> Result<(), Box<dyn Error>> {
-        let server_block = HttpConfig::http_well_known(domain);
+    fn add_well_known(
+        file: &mut File,
+        domain: &Domain,
+        listen: &Listen,
+    ) -> Result<(), QicertError> {
+        // Nothing to serve when the built-in responder answers the challenge.
+        if !Certer::uses_webroot() {
+            return Ok(());
+        }
+
+        let server_block =
+            ConfigFile::mark_challenge(domain, &HttpConfig::http_well_known(domain, listen));
 
-        writeln!(file, "{server_block}").map_err(|_| ConfigError::FileSaving)?;
+        let written = ConfigFile::write_block(domain, file, &server_block);
+
+        Report::step(
+            Action::WriteConfig,
+            Some(&ConfigFile::file_path(domain)),
+            written,
+        )?;
 
         Ok(())
     }
 
-    fn add_redirect(file: &mut File, domain: &Domain) -> Result<(), Box<dyn Error>> {
-        let redirect_block = HttpConfig::http_redirect_content(domain);
+    /// Both blocks go in one region, the part of the file qicert owns for the hostname.
+    fn add_redirect_and_https(
+        file: &mut File,
+        domain: &Domain,
+        profile: &Profile,
+        listen: &Listen,
+    ) -> Result<(), QicertError> {
+        let redirect_block = HttpConfig::http_redirect_content(domain, listen);
+        let https_block = HttpConfig::https_content(domain, profile, listen);
+
+        ConfigFile::write_region(domain, file, &format!("{redirect_block}\n{https_block}"))?;
 
-        writeln!(file, "{redirect_block}")?;
+        Report::done(Action::WriteConfig, Some(&ConfigFile::file_path(domain)));
 
         Ok(())
     }
 
-    fn add_https(file: &mut File, domain: &Domain) -> Result<(), Box<dyn Error>> {
-        let https_block = HttpConfig::https_content(domain);
+    fn create_webroot(domain: &Domain) -> Result<(), QicertError> {
+        let created = WebRoot::create_and_set_chown(domain, &Nginx::layout().owner());
 
-        writeln!(file, "{https_block}")?;
+        Report::step(
+            Action::Webroot,
+            Some(&WebRoot::build_pathbuf(domain)),
+            created,
+        )?;
 
         Ok(())
     }
 
-    fn add_redirect_and_https(file: &mut File, domain: &Domain) -> Result<(), Box<dyn Error>> {
-        Self::add_redirect(file, domain)?;
+    fn check_and_reload() -> Result<(), QicertError> {
+        let reloaded = Nginx::check_and_reload();
 
-        Self::add_https(file, domain)?;
+        Audit::reload(&reloaded);
 
-        Ok(())
+        Report::step(Action::Reload, None, reloaded)
+    }
+
+    fn certify(domain: &Domain) -> Result<(), QicertError> {
+        let issued = Certer::run(domain);
+
+        Audit::certificate(&issued);
+
+        Report::step(Action::Certificate, None, issued)
     }
 
-    fn create(domain: &Domain) -> Result<(), Box<dyn Error>> {
+    fn create(domain: &Domain, profile: &Profile, listen: &Listen) -> Result<(), QicertError> {
         let mut file = Self::create_file_and_link(domain)?;
 
-        Self::add_well_known(&mut file, domain)?;
+        Self::add_well_known(&mut file, domain, listen)?;
 
-        WebRoot::create_and_set_chown(domain)?;
-        Nginx::check_and_reload()?;
-        Certer::run(domain)?;
-        ConfigFile::truncate_file(&mut file)?;
-        Self::add_redirect_and_https(&mut file, domain)?;
+        if profile.uses_default_webroot(domain) {
+            Self::create_webroot(domain)?;
+        }
+        if Certer::uses_webroot() {
+            Self::check_and_reload()?;
+        }
 
-        Nginx::check_and_reload()?;
+        Self::certify(domain)?;
+        ConfigFile::reset(domain, &mut file, "")?;
+        Self::add_redirect_and_https(&mut file, domain, profile, listen)?;
+
+        Self::check_and_reload()?;
 
         Ok(())
     }
 
-    fn panic_if_missing_nginx_or_certbot() {
-        if !Self::are_nginx_and_certbot_installed() {
-            std::panic::set_hook(Box::new(|_| {
-                println!("Nginx or Certbot are missing. Shutting down.");
-            }));
+    fn ensure_nginx_and_certbot_installed() -> Result<(), QicertError> {
+        if !Nginx::is_installed() {
+            return Err(NginxError::NotInstalled)?;
+        }
 
-            panic!()
+        if !Certer::is_installed() {
+            return Err(CertBotError::NotInstalled)?;
         }
+
+        Ok(())
     }
 
-    pub fn append_or_create(domain: &Domain) -> Result<(), Box<dyn Error>> {
-        Self::panic_if_missing_nginx_or_certbot();
+    /// Files whose changes go to the audit log.
+    fn audited_files(domain: &Domain) -> Vec<PathBuf> {
+        vec![ConfigFile::file_path(domain)]
+    }
 
-        if !ConfigFile::file_exists(domain) {
-            return Self::create(domain);
-        }
+    pub fn append_or_create(
+        domain: &Domain,
+        profile: &Profile,
+        listen: &Listen,
+    ) -> Result<(), QicertError> {
+        Audit::run(
+            Operation::Provision,
+            Nginx::SERVER,
+            domain,
+            &Self::audited_files(domain),
+            || Self::_append_or_create(domain, profile, listen),
+        )
+    }
 
-        Self::append(domain)
+    /// Rewrites the blocks of a site that already has its certificate.
+    pub fn update(domain: &Domain, profile: &Profile, listen: &Listen) -> Result<(), QicertError> {
+        Audit::run(
+            Operation::Update,
+            Nginx::SERVER,
+            domain,
+            &Self::audited_files(domain),
+            || Self::_update(domain, profile, listen),
+        )
     }
 
-    fn append(domain: &Domain) -> Result<(), Box<dyn Error>> {
-        ConfigFile::create_backup(domain)?;
+    pub fn remove(domain: &Domain) -> Result<(), QicertError> {
+        Audit::run(
+            Operation::Remove,
+            Nginx::SERVER,
+            domain,
+            &Self::audited_files(domain),
+            || Self::_remove(domain),
+        )
+    }
 
-        let mut file = ConfigFile::append(domain)?;
+    fn _append_or_create(
+        domain: &Domain,
+        profile: &Profile,
+        listen: &Listen,
+    ) -> Result<(), QicertError> {
+        Self::ensure_nginx_and_certbot_installed()?;
 
-        let content_backup = {
-            let mut tmp = String::new();
+        let existed = ConfigFile::file_exists(domain);
 
-            file.read_to_string(&mut tmp)?;
+        if existed {
+            let backup = ConfigFile::create_backup(domain, Operation::Provision);
 
-            tmp
+            Report::step(
+                Action::Backup,
+                Some(&ConfigFile::backup_dir(domain)),
+                backup,
+            )?;
+        }
+
+        let result = match existed {
+            true => Self::append(domain, profile, listen),
+            false => Self::create(domain, profile, listen),
         };
 
+        result.or_else(|cause| Self::rollback(domain, existed, cause))
+    }
+
+    /// Puts the configuration file back the way it was before a failed run.
+    fn rollback(domain: &Domain, existed: bool, cause: QicertError) -> Result<(), QicertError> {
+        let path = ConfigFile::file_path(domain);
+
+        match Report::step(
+            Action::Rollback,
+            Some(&path),
+            ConfigFile::restore(domain, existed),
+        ) {
+            Ok(_) => Err(cause),
+            Err(e) => Err(QicertError::rollback(cause, e)),
+        }
+    }
+
+    /// Drops the challenge block an interrupted run left behind. Tells whether that
+    /// run got as far as the certificate, leaving only the configuration to write.
+    fn clear_interrupted_run(
+        domain: &Domain,
+        file: &mut File,
+        content: &mut String,
+    ) -> Result<bool, QicertError> {
+        if !ConfigFile::clear_stale_challenge(domain, file, content)? {
+            return Ok(false);
+        }
+
+        Report::say(format!(
+            "Removed the challenge block an interrupted run left for {}",
+            domain.unicode()
+        ));
+
+        let issued = Certer::has_certificate(domain);
+
+        if issued {
+            Report::say(format!(
+                "Certificate for {} already issued, resuming",
+                domain.unicode()
+            ));
+        }
+
+        Ok(issued)
+    }
+
+    fn append(domain: &Domain, profile: &Profile, listen: &Listen) -> Result<(), QicertError> {
+        let mut file = ConfigFile::append(domain)?;
+
+        let mut content_backup = ConfigFile::read(domain, &mut file)?;
+
+        let resume = Self::clear_interrupted_run(domain, &mut file, &mut content_backup)?;
+
         if !ConfigFile::find_domain_in_str(content_backup.as_str(), domain) {
-            Self::add_well_known(&mut file, domain)?;
+            if !resume {
+                Self::add_well_known(&mut file, domain, listen)?;
+
+                if profile.uses_default_webroot(domain) {
+                    match Self::create_webroot(domain) {
+                        Ok(_) => Report::say(format!("Webroot created for {}", domain.unicode())),
+                        Err(e) => Report::say(format!("{e} error for {}", domain.unicode())),
+                    };
+                }
+
+                if Certer::uses_webroot() {
+                    Self::check_and_reload()?;
+                }
 
-            match WebRoot::create_and_set_chown(domain) {
-                Ok(_) => println!("Webroot created for {domain}"),
-                Err(e) => println!("{e} error for {domain}"),
-            };
+                Self::certify(domain)?;
+            }
+
+            ConfigFile::reset(domain, &mut file, &content_backup)?;
+
+            Self::add_redirect_and_https(&mut file, domain, profile, listen)?;
+
+            Self::check_and_reload()?;
+        }
+
+        Ok(())
+    }
+
+    /// Moves the port 80 block of the hostname in `file` to https, for a site that
+    /// was set up by hand. The certificate is issued through the webroot the block
+    /// already serves, or through a challenge location added for the time it takes.
+    pub fn upgrade(domain: &Domain, file: &Path) -> Result<(), QicertError> {
+        Audit::run(
+            Operation::Upgrade,
+            Nginx::SERVER,
+            domain,
+            &[file.to_path_buf()],
+            || Self::_upgrade(domain, file),
+        )
+    }
 
-            Nginx::check_and_reload()?;
+    fn _upgrade(domain: &Domain, file: &Path) -> Result<(), QicertError> {
+        Self::ensure_nginx_and_certbot_installed()?;
 
-            Certer::run(domain)?;
+        let content = fs::read_to_string(file)
+            .map_err(|e| QicertError::io(UpgradeError::FileReading, file, e))?;
 
-            ConfigFile::truncate_file(&mut file)?;
+        let block = HttpBlock::find(&content, domain)?;
 
-            file.write_all(content_backup.as_bytes())?;
+        let backups = Backups::current();
 
-            Self::add_redirect_and_https(&mut file, domain)?;
+        Report::step(
+            Action::Backup,
+            Some(&backups.dir_for(file)),
+            backups.save(file, Nginx::SERVER, domain, Operation::Upgrade),
+        )?;
 
-            Nginx::check_and_reload()?;
+        Self::upgrade_block(domain, file, &content, &block).map_err(|cause| {
+            let undone = fs::write(file, &content)
+                .map_err(|e| QicertError::io(ConfigError::FileSaving, file, e));
+
+            match Report::step(Action::Rollback, Some(file), undone) {
+                Ok(_) => cause,
+                Err(e) => QicertError::rollback(cause, e),
+            }
+        })
+    }
+
+    fn upgrade_block(
+        domain: &Domain,
+        file: &Path,
+        content: &str,
+        block: &HttpBlock,
+    ) -> Result<(), QicertError> {
+        let challenge = Certer::uses_webroot() && block.webroot().is_none();
+
+        if Certer::has_certificate(domain) {
+            Report::say(format!(
+                "Certificate for {} already issued, using it",
+                domain.unicode()
+            ));
+        } else if let (true, Some(webroot)) = (Certer::uses_webroot(), block.webroot()) {
+            Report::say(format!(
+                "Issuing through {}, the webroot the site already serves",
+                webroot.display()
+            ));
+
+            let issued = Certer::run_at(domain, webroot);
+
+            Audit::certificate(&issued);
+
+            Report::step(Action::Certificate, None, issued)?;
+        } else {
+            if challenge {
+                let written = fs::write(file, block.with_challenge(content, domain))
+                    .map_err(|e| QicertError::io(ConfigError::FileSaving, file, e));
+
+                Report::step(Action::WriteConfig, Some(file), written)?;
+
+                Self::check_and_reload()?;
+            }
+
+            Self::certify(domain)?;
         }
 
+        let written = fs::write(file, block.upgraded(content, domain, challenge))
+            .map_err(|e| QicertError::io(ConfigError::FileSaving, file, e));
+
+        Report::step(Action::WriteConfig, Some(file), written)?;
+
+        Self::check_and_reload()
+    }
+
+    fn _update(domain: &Domain, profile: &Profile, listen: &Listen) -> Result<(), QicertError> {
+        ConfigFile::create_backup(domain, Operation::Update)?;
+        ConfigFile::remove_domain(domain)?;
+
+        let mut file = ConfigFile::append(domain)?;
+
+        Self::add_redirect_and_https(&mut file, domain, profile, listen)?;
+
+        if let Err(e) = Linker::create(domain) {
+            if !matches!(e.downcast_ref(), Some(ConfigError::SymlinkExists)) {
+                return Err(e);
+            }
+        }
+
+        Self::check_and_reload()?;
+
         Ok(())
     }
 
-    pub fn are_nginx_and_certbot_installed() -> bool {
-        Nginx::is_installed() && Certer::is_installed()
+    fn _remove(domain: &Domain) -> Result<(), QicertError> {
+        ConfigFile::create_backup(domain, Operation::Remove)?;
+        ConfigFile::remove_domain(domain)?;
+
+        Self::check_and_reload()?;
+
+        Ok(())
     }
 }
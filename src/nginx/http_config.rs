@@ -1,18 +1,89 @@
-use crate::{domain::Domain, webroot::WebRoot};
+use crate::{domain::Domain, listen::Listen, profile::Profile, settings::Settings};
 
 pub struct HttpConfig;
 
 impl HttpConfig {
-    pub fn http_redirect_content(domain: &Domain) -> String {
+    /// One `listen` line per address, each followed by `parameters`.
+    fn listen_directives(listen: &Listen, port: u16, parameters: &[&str]) -> String {
+        let mut hosts = listen
+            .addresses()
+            .iter()
+            .map(Listen::host)
+            .collect::<Vec<_>>();
+
+        if hosts.is_empty() {
+            hosts.push(String::new());
+
+            if listen.is_dual_stack() {
+                hosts.push("[::]:".to_string());
+            }
+        }
+
+        hosts
+            .iter()
+            .map(|host| {
+                let mut words = vec![format!("{host}{port}")];
+
+                words.extend(parameters.iter().map(|p| p.to_string()));
+
+                if listen.is_default_server() {
+                    words.push("default_server".to_string());
+                }
+
+                format!("listen {};", words.join(" "))
+            })
+            .collect::<Vec<_>>()
+            .join("\n            ")
+    }
+
+    fn http_listen(listen: &Listen) -> String {
+        Self::listen_directives(listen, listen.http_port(), &[])
+    }
+
+    /// The `listen` lines of the https block, with what HTTP/2 and HTTP/3 need.
+    fn https_listen(listen: &Listen) -> String {
+        let port = listen.https_port();
+
+        let mut directives = vec![Self::listen_directives(listen, port, &["ssl"])];
+
+        if listen.has_http3() {
+            directives.push(Self::listen_directives(listen, port, &["quic"]));
+        }
+
+        if listen.has_http2() {
+            directives.push("http2 on;".to_string());
+        }
+
+        if listen.has_http3() {
+            directives.push("http3 on;".to_string());
+            directives.push(format!(
+                "add_header Alt-Svc 'h3=\":{port}\"; ma=86400' always;"
+            ));
+        }
+
+        directives.join("\n            ")
+    }
+
+    pub fn http_redirect_content(domain: &Domain, listen: &Listen) -> String {
+        Self::redirect_from(
+            domain,
+            &Self::http_listen(listen),
+            &listen.https_port_suffix(),
+        )
+    }
+
+    /// The redirect block with `http_listen` as its `listen` lines, to `port` after the
+    /// hostname.
+    pub(crate) fn redirect_from(domain: &Domain, http_listen: &str, port: &str) -> String {
         let domain_name = domain.to_string();
 
         format!(
             "server {{
-            listen 80;
+            {http_listen}
     
             server_name {domain_name};
     
-            return 301 https://{domain_name}$request_uri;
+            return 301 https://{domain_name}{port}$request_uri;
     }}"
         )
     }
@@ -21,33 +92,80 @@ impl HttpConfig {
         format!("server_name {domain}")
     }
 
-    pub fn http_well_known(domain: &Domain) -> String {
+    /// The location serving the challenge root, inside a server block.
+    pub fn acme_location() -> String {
+        let challenge_root = Settings::current().challenge_root().display();
+
+        format!(
+            "location ^~ /.well-known/acme-challenge/ {{
+                root {challenge_root};
+                allow all;
+                default_type \"text/plain\";
+            }}"
+        )
+    }
+
+    pub fn http_well_known(domain: &Domain, listen: &Listen) -> String {
+        let location = Self::acme_location();
+
         let server_name = Self::server_name(domain);
 
+        let http_listen = Self::http_listen(listen);
+
         format!(
             "server {{
-            listen 80;
+            {http_listen}
     
             {server_name};
     
-            location ^~ /.well-known/acme-challenge/ {{
-                root /var/www/.well-known/challenge;
-                allow all;
-                default_type \"text/plain\";
-            }}
+            {location}
     }}"
         )
     }
 
-    pub fn https_content(domain: &Domain) -> String {
+    /// Certificate directives of an https server block.
+    pub fn ssl_directives(domain: &Domain) -> Vec<String> {
+        vec![
+            format!("ssl_certificate /etc/letsencrypt/live/{domain}/fullchain.pem;"),
+            format!("ssl_certificate_key /etc/letsencrypt/live/{domain}/privkey.pem;"),
+            format!("ssl_trusted_certificate /etc/letsencrypt/live/{domain}/fullchain.pem;"),
+            "include /etc/letsencrypt/options-ssl-nginx.conf;".to_string(),
+            "ssl_dhparam /etc/letsencrypt/ssl-dhparams.pem;".to_string(),
+        ]
+    }
+
+    fn site_content(profile: &Profile) -> String {
+        match profile {
+            Profile::Static(root) => format!(
+                "root {};
+            index index.html;
+            location / {{
+                try_files $uri $uri/ =404;
+            }}",
+                root.display()
+            ),
+            Profile::Proxy(upstream) => format!(
+                "location / {{
+                proxy_pass http://{upstream};
+                proxy_set_header Host $host;
+                proxy_set_header X-Forwarded-For $proxy_add_x_forwarded_for;
+                proxy_set_header X-Forwarded-Proto $scheme;
+            }}"
+            ),
+        }
+    }
+
+    pub fn https_content(domain: &Domain, profile: &Profile, listen: &Listen) -> String {
         let server_name = Self::server_name(domain);
 
-        let root = format!("root {}", WebRoot::build_path_string(domain));
+        let site = Self::site_content(profile);
+
+        let https_listen = Self::https_listen(listen);
 
         format!(
             r##"server {{
             {server_name};
-            listen 443 ssl;
+            {https_listen}
         
             ssl_certificate /etc/letsencrypt/live/{domain}/fullchain.pem;
             ssl_certificate_key /etc/letsencrypt/live/{domain}/privkey.pem;
@@ -56,11 +174,7 @@ impl HttpConfig {
             include /etc/letsencrypt/options-ssl-nginx.conf;
             ssl_dhparam /etc/letsencrypt/ssl-dhparams.pem;
 
-            {root};
-            index index.html;
-            location / {{
-                try_files $uri $uri/ =404;
-            }}
+            {site}
     }}"##
         )
     }
@@ -88,7 +202,7 @@ mod test {
         let domain = Domain::new("example", "com", None);
 
         if let Ok(domain) = domain {
-            let challenge_block = HttpConfig::http_well_known(&domain);
+            let challenge_block = HttpConfig::http_well_known(&domain, &Listen::default());
 
             assert_eq!(challenge_block, expected);
         }
@@ -112,7 +226,7 @@ mod test {
         let domain = Domain::new("example", "com", Some("test"));
 
         if let Ok(domain) = domain {
-            let challenge_block = HttpConfig::http_well_known(&domain);
+            let challenge_block = HttpConfig::http_well_known(&domain, &Listen::default());
 
             assert_eq!(challenge_block, expected);
         }
@@ -142,7 +256,11 @@ mod test {
         let domain = Domain::new("example", "com", None);
 
         if let Ok(domain) = domain {
-            let http_block = HttpConfig::https_content(&domain);
+            let http_block = HttpConfig::https_content(
+                &domain,
+                &Profile::default_for(&domain),
+                &Listen::default(),
+            );
 
             assert_eq!(http_block, expected);
         }
@@ -173,7 +291,11 @@ mod test {
         let domain = Domain::new("example", "com", Some("www"));
 
         if let Ok(domain) = domain {
-            let http_block = HttpConfig::https_content(&domain);
+            let http_block = HttpConfig::https_content(
+                &domain,
+                &Profile::default_for(&domain),
+                &Listen::default(),
+            );
 
             assert_eq!(http_block, expected);
         }
@@ -193,7 +315,7 @@ mod test {
         let domain = Domain::new("example", "com", None);
 
         if let Ok(domain) = domain {
-            let redirect_block = HttpConfig::http_redirect_content(&domain);
+            let redirect_block = HttpConfig::http_redirect_content(&domain, &Listen::default());
 
             assert_eq!(redirect_block, expected);
         }
@@ -213,9 +335,87 @@ mod test {
         let domain = Domain::new("example", "com", Some("www"));
 
         if let Ok(domain) = domain {
-            let redirect_block = HttpConfig::http_redirect_content(&domain);
+            let redirect_block = HttpConfig::http_redirect_content(&domain, &Listen::default());
 
             assert_eq!(redirect_block, expected);
         }
     }
+
+    #[test]
+    fn https_block_proxy() {
+        let expected = r##"server {
+            server_name app.example.com;
+            listen 443 ssl;
+        
+            ssl_certificate /etc/letsencrypt/live/app.example.com/fullchain.pem;
+            ssl_certificate_key /etc/letsencrypt/live/app.example.com/privkey.pem;
+            ssl_trusted_certificate /etc/letsencrypt/live/app.example.com/fullchain.pem;
+        
+            include /etc/letsencrypt/options-ssl-nginx.conf;
+            ssl_dhparam /etc/letsencrypt/ssl-dhparams.pem;
+
+            location / {
+                proxy_pass http://127.0.0.1:3000;
+                proxy_set_header Host $host;
+                proxy_set_header X-Forwarded-For $proxy_add_x_forwarded_for;
+                proxy_set_header X-Forwarded-Proto $scheme;
+            }
+    }"##;
+
+        let domain = Domain::new("example", "com", Some("app")).unwrap();
+        let profile = Profile::Proxy("127.0.0.1:3000".parse().unwrap());
+
+        assert_eq!(
+            HttpConfig::https_content(&domain, &profile, &Listen::default()),
+            expected
+        );
+    }
+
+    #[test]
+    fn listen_options() {
+        let domain = Domain::new("example", "com", Some("www")).unwrap();
+
+        let listen = Listen::default()
+            .with_dual_stack(true)
+            .with_default_server(true)
+            .with_https_port(8443)
+            .with_http2(true)
+            .with_http3(true);
+
+        let https = HttpConfig::https_content(&domain, &Profile::default_for(&domain), &listen);
+
+        assert!(https.contains(
+            r#"listen 8443 ssl default_server;
+            listen [::]:8443 ssl default_server;
+            listen 8443 quic default_server;
+            listen [::]:8443 quic default_server;
+            http2 on;
+            http3 on;
+            add_header Alt-Svc 'h3=":8443"; ma=86400' always;
+        "#
+        ));
+
+        let redirect = HttpConfig::http_redirect_content(&domain, &listen);
+
+        assert!(redirect.contains(
+            "listen 80 default_server;
+            listen [::]:80 default_server;"
+        ));
+        assert!(redirect.contains("return 301 https://www.example.com:8443$request_uri;"));
+
+        let listen = Listen::default()
+            .with_address("192.0.2.10".parse().unwrap())
+            .with_address("2001:db8::10".parse().unwrap())
+            .with_dual_stack(true)
+            .with_http_port(8080);
+
+        let well_known = HttpConfig::http_well_known(&domain, &listen);
+
+        assert!(well_known.contains(
+            "listen 192.0.2.10:8080;
+            listen [2001:db8::10]:8080;
+"
+        ));
+        assert!(!well_known.contains("[::]"));
+    }
 }
@@ -1,40 +1,52 @@
-use std::os::unix::fs as unix_fs;
+use std::{os::unix::fs as unix_fs, path::PathBuf};
 
 use crate::{
     configuration_file::ConfigurationFile,
     domain::Domain,
-    nginx::{
-        config_file::{ConfigError, ConfigFile},
-        sites::Sites,
-    },
+    error::QicertError,
+    layout::Enable,
+    nginx::config_file::{ConfigError, ConfigFile},
 };
 
 pub struct Linker;
 
 impl Linker {
-    pub fn exists(domain: &Domain) -> bool {
-        let file_name = domain.conf_file_name();
-
-        let (_, mut site_symlink) = Sites::paths();
+    pub fn symlink_path(domain: &Domain) -> Option<PathBuf> {
+        match ConfigFile::layout().enable() {
+            Enable::Symlink(sites_enabled) => {
+                Some(sites_enabled.join(ConfigFile::file_name(domain)))
+            }
+            _ => None,
+        }
+    }
 
-        site_symlink.push(file_name);
+    /// Always the case for layouts that load the whole config directory.
+    pub fn is_enabled(domain: &Domain) -> bool {
+        Self::symlink_path(domain).is_none_or(|l| l.is_symlink())
+    }
 
-        site_symlink.exists() && site_symlink.is_symlink()
+    pub fn exists(domain: &Domain) -> bool {
+        Self::symlink_path(domain).is_some_and(|l| l.exists() && l.is_symlink())
     }
 
-    pub fn create(domain: &Domain) -> Result<(), ConfigError> {
-        if Self::exists(domain) {
-            return Err(ConfigError::SymlinkExists);
+    /// Layouts without a `sites-enabled` directory load every file in the config
+    /// directory, so there is nothing to link. Where the main configuration does not
+    /// load it yet, it gets the include line instead.
+    pub fn create(domain: &Domain) -> Result<(), QicertError> {
+        if let Enable::Include(main) = ConfigFile::layout().enable() {
+            return ConfigFile::ensure_include(main);
         }
 
-        let file_name = ConfigFile::file_name(domain);
-
-        let (mut sites_available, mut sites_enabled) = Sites::paths();
+        if Self::exists(domain) {
+            return Err(ConfigError::SymlinkExists)?;
+        }
 
-        sites_available.push(file_name.as_str());
-        sites_enabled.push(file_name.as_str());
+        let Some(site_symlink) = Self::symlink_path(domain) else {
+            return Ok(());
+        };
 
-        unix_fs::symlink(sites_available, sites_enabled).map_err(|_| ConfigError::Linking)?;
+        unix_fs::symlink(ConfigFile::file_path(domain), &site_symlink)
+            .map_err(|e| QicertError::io(ConfigError::Linking, site_symlink, e))?;
 
         Ok(())
     }
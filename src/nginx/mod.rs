@@ -2,15 +2,15 @@ pub mod config_file;
 pub(crate) mod configurator;
 pub mod http_config;
 pub(crate) mod linker;
-pub(crate) mod sites;
+pub mod upgrade;
 
-use std::{
-    error::Error,
-    fmt::Display,
-    process::{Child, Command},
-};
+use std::{error::Error, fmt::Display, process::Command};
 
-use crate::webserver::WebServer;
+use crate::{
+    error::{run_command, QicertError},
+    layout::Server,
+    webserver::WebServer,
+};
 
 #[derive(Debug, Clone, Copy)]
 pub enum NginxError {
@@ -26,40 +26,34 @@ impl Display for NginxError {
         match self {
             NginxError::CannotReload => write!(f, "Could not reload Nginx."),
             NginxError::BadConfiguration => write!(f, "Nginx -t failed. Bad Configuration."),
-            NginxError::NotInstalled => write!(f, "Nginx was not found in /usr/sbin."),
+            NginxError::NotInstalled => write!(f, "Nginx was not found."),
         }
     }
 }
 
-impl<'a> WebServer<'a> for Nginx {
-    const BINARY_NAME: &'a str = "nginx";
-    const WEBSERVER_SBIN_PATH: &'a str = "/usr/sbin/nginx";
+impl WebServer for Nginx {
+    const SERVER: Server = Server::Nginx;
 }
 
 pub struct Nginx;
 
 impl Nginx {
-    pub fn reload() -> Result<(), NginxError> {
+    pub fn reload() -> Result<(), QicertError> {
         Self::_reload(NginxError::CannotReload)
     }
 
-    pub fn check() -> Result<(), NginxError> {
-        let output = Command::new(Self::BINARY_NAME)
-            .arg("-t")
-            .spawn()
-            .and_then(Child::wait_with_output)
-            .map_err(|_| NginxError::BadConfiguration)?;
-
-        if !output.status.success() {
-            return Err(NginxError::BadConfiguration);
-        }
+    pub fn check() -> Result<(), QicertError> {
+        run_command(
+            Command::new(Self::layout().binary()).arg("-t"),
+            NginxError::BadConfiguration,
+        )?;
 
         Ok(())
     }
 
-    pub fn check_and_reload() -> Result<(), NginxError> {
+    pub fn check_and_reload() -> Result<(), QicertError> {
         if !Self::is_installed() {
-            return Err(NginxError::NotInstalled);
+            return Err(NginxError::NotInstalled)?;
         }
 
         Self::check()?;
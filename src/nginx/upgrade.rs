@@ -0,0 +1,681 @@
+use std::{
+    error::Error,
+    fmt::Display,
+    fs,
+    ops::Range,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    configuration_file::ConfigurationFile,
+    domain::Domain,
+    error::QicertError,
+    layout::Server,
+    nginx::{config_file::ConfigFile, http_config::HttpConfig},
+    region::Region,
+};
+
+#[derive(Debug)]
+pub enum UpgradeError {
+    Unsupported(Server),
+    NoHttpBlock(String),
+    OtherNames {
+        hostname: String,
+        names: Vec<String>,
+    },
+    FileReading,
+}
+
+impl Display for UpgradeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Unsupported(server) => {
+                write!(f, "--upgrade only works with Nginx sites, not {server:?}")
+            }
+            Self::NoHttpBlock(hostname) => write!(
+                f,
+                "No server block listening on port 80 for {hostname} was found to upgrade"
+            ),
+            Self::OtherNames { hostname, names } => write!(
+                f,
+                "The port 80 block of {hostname} also answers for {}, \
+                give them a server block of their own before upgrading it",
+                names.join(" ")
+            ),
+            Self::FileReading => write!(f, "Configuration file could not be read"),
+        }
+    }
+}
+
+impl Error for UpgradeError {}
+
+/// Where nginx already serves a hostname.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Site {
+    Http(PathBuf),
+    Https(PathBuf),
+}
+
+impl Site {
+    /// Looks through the configuration directory, https wins if both are found.
+    pub(crate) fn locate(domain: &Domain) -> Result<Self, QicertError> {
+        let mut http = None;
+
+        for file in Self::files(ConfigFile::layout().config_dir()) {
+            let Ok(content) = fs::read_to_string(&file) else {
+                continue;
+            };
+
+            let blocks = HttpBlock::all(&content);
+
+            if blocks.iter().any(|b| b.serves(domain) && b.is_https()) {
+                return Ok(Self::Https(file));
+            }
+
+            if http.is_none() && blocks.iter().any(|b| b.serves(domain) && b.is_plain_http()) {
+                http = Some(file);
+            }
+        }
+
+        match http {
+            Some(file) => Ok(Self::Http(file)),
+            None => Err(UpgradeError::NoHttpBlock(domain.to_string()))?,
+        }
+    }
+
+    /// Every site file, `sites-available/default` has no extension.
+    fn files(dir: &Path) -> Vec<PathBuf> {
+        let Ok(entries) = fs::read_dir(dir) else {
+            return Vec::new();
+        };
+
+        let mut files = entries
+            .flatten()
+            .map(|entry| entry.path())
+            .filter(|path| path.is_file())
+            .collect::<Vec<_>>();
+
+        files.sort();
+
+        files
+    }
+}
+
+/// One line of a block, with the brace depth it starts at.
+struct Line<'a> {
+    range: Range<usize>,
+    depth: usize,
+    code: &'a str,
+    in_acme: bool,
+}
+
+impl<'a> Line<'a> {
+    fn lines(block: &'a str) -> Vec<Self> {
+        let mut lines = Vec::new();
+        let mut depth = 0;
+        let mut acme_depth = None;
+        let mut offset = 0;
+
+        for line in block.split_inclusive('\n') {
+            let code = line.split('#').next().unwrap_or_default().trim();
+
+            if acme_depth.is_none()
+                && code.starts_with("location")
+                && code.contains("/.well-known/acme-challenge")
+            {
+                acme_depth = Some(depth + 1);
+            }
+
+            lines.push(Self {
+                range: offset..offset + line.len(),
+                depth,
+                code,
+                in_acme: acme_depth.is_some_and(|d| depth >= d),
+            });
+
+            for char in code.chars() {
+                match char {
+                    '{' => depth += 1,
+                    '}' => depth = depth.saturating_sub(1),
+                    _ => {}
+                }
+            }
+
+            if acme_depth.is_some_and(|d| depth < d) {
+                acme_depth = None;
+            }
+
+            offset += line.len();
+        }
+
+        lines
+    }
+
+    /// `directive value...` of a directive of the server block itself.
+    fn directive(&self) -> Option<(&'a str, Vec<&'a str>)> {
+        if self.depth != 1 || self.in_acme {
+            return None;
+        }
+
+        self.words()
+    }
+
+    fn words(&self) -> Option<(&'a str, Vec<&'a str>)> {
+        let mut words = self.code.strip_suffix(';')?.split_whitespace();
+
+        Some((words.next()?, words.collect()))
+    }
+
+    fn indent(line: &str) -> &str {
+        &line[..line.len() - line.trim_start().len()]
+    }
+}
+
+/// A server block of a site file and the directives `--upgrade` works with.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct HttpBlock {
+    range: Range<usize>,
+    names: Vec<String>,
+    listens: Vec<Vec<String>>,
+    root: Option<PathBuf>,
+    acme_root: Option<PathBuf>,
+}
+
+impl HttpBlock {
+    const HTTPS_PORT: u16 = 443;
+    const HTTP_PORT: u16 = 80;
+
+    /// The server blocks of `content`.
+    fn all(content: &str) -> Vec<Self> {
+        ConfigFile::split_blocks(content)
+            .into_iter()
+            .filter_map(|block| {
+                let start = block.as_ptr() as usize - content.as_ptr() as usize;
+
+                Self::parse(block, start..start + block.len())
+            })
+            .collect()
+    }
+
+    fn parse(block: &str, range: Range<usize>) -> Option<Self> {
+        let lines = Line::lines(block);
+
+        let opening = lines
+            .iter()
+            .find(|l| l.depth == 0 && l.code.contains('{'))?;
+
+        if opening.code.split('{').next()?.trim() != "server" {
+            return None;
+        }
+
+        let mut parsed = Self {
+            range,
+            names: Vec::new(),
+            listens: Vec::new(),
+            root: None,
+            acme_root: None,
+        };
+
+        for line in &lines {
+            if line.in_acme {
+                match line.words() {
+                    Some(("root", values)) => {
+                        parsed.acme_root = values.first().map(PathBuf::from);
+                    }
+                    Some(("alias", values)) => {
+                        parsed.acme_root = values
+                            .first()
+                            .map(|alias| alias.trim_end_matches('/'))
+                            .and_then(|alias| alias.strip_suffix("/.well-known/acme-challenge"))
+                            .map(PathBuf::from);
+                    }
+                    _ => {}
+                }
+
+                continue;
+            }
+
+            match line.directive() {
+                Some(("listen", values)) => {
+                    parsed
+                        .listens
+                        .push(values.into_iter().map(String::from).collect());
+                }
+                Some(("server_name", values)) => {
+                    parsed
+                        .names
+                        .extend(values.into_iter().map(str::to_lowercase));
+                }
+                Some(("root", values)) => parsed.root = values.first().map(PathBuf::from),
+                _ => {}
+            }
+        }
+
+        Some(parsed)
+    }
+
+    /// Port of a `listen` address, 80 when it only names the host.
+    fn port(address: &str) -> Option<u16> {
+        if address.starts_with("unix:") {
+            return None;
+        }
+
+        if let Ok(port) = address.parse() {
+            return Some(port);
+        }
+
+        let port = address
+            .rsplit_once(':')
+            .and_then(|(_, port)| port.parse().ok());
+
+        Some(port.unwrap_or(Self::HTTP_PORT))
+    }
+
+    fn is_tls(listen: &[String]) -> bool {
+        listen.iter().any(|value| value == "ssl" || value == "quic")
+    }
+
+    fn is_http_listen(listen: &[String]) -> bool {
+        listen
+            .first()
+            .and_then(|address| Self::port(address))
+            .is_some_and(|port| port == Self::HTTP_PORT)
+            && !Self::is_tls(listen)
+    }
+
+    fn serves(&self, domain: &Domain) -> bool {
+        self.names.contains(&domain.to_string().to_lowercase())
+    }
+
+    fn is_https(&self) -> bool {
+        self.listens.iter().any(|listen| {
+            Self::is_tls(listen)
+                || listen
+                    .first()
+                    .and_then(|address| Self::port(address))
+                    .is_some_and(|port| port == Self::HTTPS_PORT)
+        })
+    }
+
+    /// A block without `listen` listens on port 80.
+    fn is_plain_http(&self) -> bool {
+        self.listens
+            .iter()
+            .all(|listen| Self::is_http_listen(listen))
+    }
+
+    /// The port 80 block of the hostname in `content`, as long as it serves nothing else.
+    pub(crate) fn find(content: &str, domain: &Domain) -> Result<Self, UpgradeError> {
+        let hostname = domain.to_string().to_lowercase();
+
+        let block = Self::all(content)
+            .into_iter()
+            .find(|block| block.serves(domain) && block.is_plain_http())
+            .ok_or_else(|| UpgradeError::NoHttpBlock(hostname.clone()))?;
+
+        let names = block
+            .names
+            .iter()
+            .filter(|name| **name != hostname)
+            .cloned()
+            .collect::<Vec<_>>();
+
+        if !names.is_empty() {
+            return Err(UpgradeError::OtherNames { hostname, names });
+        }
+
+        Ok(block)
+    }
+
+    /// Where the block already serves `/.well-known/acme-challenge/` from.
+    pub(crate) fn webroot(&self) -> Option<&Path> {
+        self.acme_root.as_deref().or(self.root.as_deref())
+    }
+
+    /// `content` with a marked challenge location at the end of the block.
+    pub(crate) fn with_challenge(&self, content: &str, domain: &Domain) -> String {
+        let block = &content[self.range.clone()];
+
+        let Some(closing) = block.rfind('}') else {
+            return content.to_string();
+        };
+
+        let location = ConfigFile::mark_challenge(domain, &Self::location());
+
+        format!(
+            "{}{}{}{}",
+            &content[..self.range.start],
+            &block[..closing],
+            Self::indented(&location, Self::inner_indent(block)),
+            &content[self.range.start + closing..]
+        )
+    }
+
+    /// `content` with the block listening on 443 with the certificate of `domain`,
+    /// and a region redirecting port 80 right after it. Everything else in the block
+    /// stays. `challenge` adds the challenge location of the challenge root, which
+    /// renewals reach through the redirect.
+    pub(crate) fn upgraded(&self, content: &str, domain: &Domain, challenge: bool) -> String {
+        let block = &content[self.range.clone()];
+
+        let lines = Line::lines(block);
+
+        let indent = Self::inner_indent(block);
+
+        let anchor = lines
+            .iter()
+            .rposition(|line| matches!(line.directive(), Some(("listen", _))))
+            .or_else(|| lines.iter().position(|line| line.code.contains('{')));
+
+        let mut directives = Vec::new();
+
+        if self.listens.is_empty() {
+            directives.push(format!("listen {} ssl;", Self::HTTPS_PORT));
+        }
+
+        directives.extend(HttpConfig::ssl_directives(domain));
+
+        let mut converted = String::new();
+
+        for (i, line) in lines.iter().enumerate() {
+            let text = &block[line.range.clone()];
+
+            match line.directive() {
+                Some(("listen", values)) => {
+                    converted.push_str(Line::indent(text));
+                    converted.push_str(&Self::https_listen(&values));
+
+                    if let Some(comment) = text.find('#').map(|at| text[at..].trim_end()) {
+                        converted.push(' ');
+                        converted.push_str(comment);
+                    }
+
+                    converted.push('\n');
+                }
+                _ if challenge && i == lines.len() - 1 => {
+                    if let Some(closing) = text.rfind('}') {
+                        let location = Self::location();
+
+                        converted.push_str(&text[..closing]);
+                        converted.push_str(&Self::indented(&location, indent));
+                        converted.push_str(&text[closing..]);
+                    } else {
+                        converted.push_str(text);
+                    }
+                }
+                _ => converted.push_str(text),
+            }
+
+            if Some(i) == anchor {
+                if !converted.ends_with('\n') {
+                    converted.push('\n');
+                }
+
+                for directive in &directives {
+                    converted.push_str(&format!("{indent}{directive}\n"));
+                }
+            }
+        }
+
+        let redirect = Region::wrap(
+            domain,
+            &HttpConfig::redirect_from(domain, &self.http_listen(), ""),
+        );
+
+        format!(
+            "{}{converted}\n\n{redirect}{}",
+            &content[..self.range.start],
+            &content[self.range.end..]
+        )
+    }
+
+    /// The block's own `listen` lines, for the redirect to take over port 80 exactly
+    /// as the block listened on it.
+    fn http_listen(&self) -> String {
+        if self.listens.is_empty() {
+            return format!("listen {};", Self::HTTP_PORT);
+        }
+
+        self.listens
+            .iter()
+            .map(|values| format!("listen {};", values.join(" ")))
+            .collect::<Vec<_>>()
+            .join("\n            ")
+    }
+
+    /// `listen` moved from 80 to 443 with `ssl`, the other parameters kept.
+    fn https_listen(values: &[&str]) -> String {
+        let Some((address, parameters)) = values.split_first() else {
+            return format!("listen {} ssl;", Self::HTTPS_PORT);
+        };
+
+        let port = Self::HTTP_PORT.to_string();
+
+        let address = if *address == port {
+            Self::HTTPS_PORT.to_string()
+        } else if let Some(host) = address.strip_suffix(&format!(":{port}")) {
+            format!("{host}:{}", Self::HTTPS_PORT)
+        } else {
+            format!("{address}:{}", Self::HTTPS_PORT)
+        };
+
+        let mut words = vec![address.as_str(), "ssl"];
+
+        words.extend(parameters);
+
+        format!("listen {};", words.join(" "))
+    }
+
+    /// Indentation of the first directive in the block.
+    fn inner_indent(block: &str) -> &str {
+        Line::lines(block)
+            .iter()
+            .find(|line| line.depth == 1 && !line.code.is_empty())
+            .map(|line| Line::indent(&block[line.range.clone()]))
+            .unwrap_or("    ")
+    }
+
+    /// The challenge location indented from its own first line.
+    fn location() -> String {
+        let location = HttpConfig::acme_location();
+
+        let mut lines = location.lines();
+        let first = lines.next().unwrap_or_default();
+        let rest = lines.collect::<Vec<_>>();
+
+        let margin = rest
+            .iter()
+            .map(|line| line.len() - line.trim_start().len())
+            .min()
+            .unwrap_or(0);
+
+        std::iter::once(first.to_string())
+            .chain(rest.iter().map(|line| line[margin..].to_string()))
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    fn indented(text: &str, indent: &str) -> String {
+        text.lines()
+            .map(|line| format!("{indent}{line}\n"))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn domain() -> Domain {
+        Domain::new("example", "com", Some("www")).unwrap()
+    }
+
+    const SITE: &str = "server {
+    listen 80 default_server;
+    listen [::]:80;
+
+    server_name www.example.com;
+
+    root /srv/www/example;
+
+    location /api/ {
+        proxy_pass http://127.0.0.1:8000;
+    }
+}
+";
+
+    #[test]
+    fn finds_the_port_80_block_and_its_webroot() {
+        let content =
+            format!("server {{\n    listen 443 ssl;\n    server_name example.com;\n}}\n{SITE}");
+
+        let block = HttpBlock::find(&content, &domain()).unwrap();
+
+        assert_eq!(block.webroot(), Some(Path::new("/srv/www/example")));
+        assert_eq!(
+            block.listens,
+            vec![
+                vec!["80".to_string(), "default_server".to_string()],
+                vec!["[::]:80".to_string()]
+            ]
+        );
+
+        let acme = SITE.replace(
+            "location /api/",
+            "location ^~ /.well-known/acme-challenge/ {\n        alias /var/lib/acme/.well-known/acme-challenge/;\n    }\n\n    location /api/",
+        );
+
+        let block = HttpBlock::find(&acme, &domain()).unwrap();
+
+        assert_eq!(block.webroot(), Some(Path::new("/var/lib/acme")));
+        assert_eq!(block.root, Some(PathBuf::from("/srv/www/example")));
+    }
+
+    #[test]
+    fn https_and_shared_blocks_are_not_upgraded() {
+        let https = SITE.replace("listen 80 default_server;", "listen 443 ssl;");
+
+        assert!(matches!(
+            HttpBlock::find(&https, &domain()),
+            Err(UpgradeError::NoHttpBlock(_))
+        ));
+
+        let shared = SITE.replace("www.example.com;", "www.example.com example.com;");
+
+        match HttpBlock::find(&shared, &domain()) {
+            Err(UpgradeError::OtherNames { names, .. }) => assert_eq!(names, ["example.com"]),
+            other => panic!("{other:?}"),
+        }
+
+        let no_listen = SITE
+            .replace("    listen 80 default_server;\n", "")
+            .replace("    listen [::]:80;\n", "");
+
+        assert!(HttpBlock::find(&no_listen, &domain()).is_ok());
+    }
+
+    #[test]
+    fn converts_the_block_and_adds_the_redirect() {
+        let domain = domain();
+
+        let content = format!("# kept\n{SITE}");
+
+        let block = HttpBlock::find(&content, &domain).unwrap();
+
+        let upgraded = block.upgraded(&content, &domain, false);
+
+        let expected = "# kept
+server {
+    listen 443 ssl default_server;
+    listen [::]:443 ssl;
+    ssl_certificate /etc/letsencrypt/live/www.example.com/fullchain.pem;
+    ssl_certificate_key /etc/letsencrypt/live/www.example.com/privkey.pem;
+    ssl_trusted_certificate /etc/letsencrypt/live/www.example.com/fullchain.pem;
+    include /etc/letsencrypt/options-ssl-nginx.conf;
+    ssl_dhparam /etc/letsencrypt/ssl-dhparams.pem;
+
+    server_name www.example.com;
+
+    root /srv/www/example;
+
+    location /api/ {
+        proxy_pass http://127.0.0.1:8000;
+    }
+}
+";
+
+        assert!(upgraded.starts_with(expected), "{upgraded}");
+
+        let region = Region::find(&upgraded, &domain).unwrap();
+
+        assert!(!region.is_edited());
+        assert!(upgraded.ends_with("# END qicert\n"));
+
+        let upgraded = HttpBlock::all(&upgraded);
+
+        assert!(upgraded[0].is_https());
+        assert!(upgraded[1].is_plain_http());
+    }
+
+    #[test]
+    fn the_redirect_keeps_the_original_listens() {
+        let domain = domain();
+
+        let content = SITE.replace(
+            "    listen 80 default_server;\n    listen [::]:80;\n",
+            "    listen [::]:80 default_server;\n    listen 192.0.2.1:80;\n",
+        );
+
+        let block = HttpBlock::find(&content, &domain).unwrap();
+
+        let upgraded = block.upgraded(&content, &domain, false);
+
+        assert!(upgraded.contains("    listen [::]:443 ssl default_server;\n"));
+        assert!(upgraded.contains("    listen 192.0.2.1:443 ssl;\n"));
+
+        let (_, redirect) = upgraded.split_once("# BEGIN qicert").unwrap();
+
+        assert!(
+            redirect.contains("listen [::]:80 default_server;\n"),
+            "{redirect}"
+        );
+        assert!(redirect.contains("listen 192.0.2.1:80;\n"), "{redirect}");
+        assert!(!redirect.contains("listen 80;"), "{redirect}");
+        assert!(redirect.contains("return 301 https://www.example.com$request_uri;"));
+    }
+
+    #[test]
+    fn the_challenge_location_goes_inside_the_block() {
+        let domain = domain();
+
+        let proxy = SITE.replace("    root /srv/www/example;\n\n", "");
+
+        let block = HttpBlock::find(&proxy, &domain).unwrap();
+
+        assert_eq!(block.webroot(), None);
+
+        let challenged = block.with_challenge(&proxy, &domain);
+
+        assert!(ConfigFile::find_challenge_in_str(&challenged, &domain));
+
+        let challenged = HttpBlock::find(&challenged, &domain).unwrap();
+
+        assert_eq!(
+            challenged.webroot(),
+            Some(Path::new("/var/www/.well-known/challenge"))
+        );
+
+        let upgraded = block.upgraded(&proxy, &domain, true);
+
+        assert!(!ConfigFile::find_challenge_in_str(&upgraded, &domain));
+        assert!(upgraded.contains(
+            "    }
+    location ^~ /.well-known/acme-challenge/ {
+        root /var/www/.well-known/challenge;
+        allow all;
+        default_type \"text/plain\";
+    }
+}"
+        ));
+    }
+}
@@ -0,0 +1,460 @@
+use std::{
+    env,
+    error::Error,
+    ffi::CString,
+    fmt::Display,
+    fs,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    os::unix::ffi::OsStrExt,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    caddy,
+    certer::{Certer, Challenge},
+    haproxy,
+    layout::{Enable, Layout, Server},
+    lock::Lock,
+    service_manager,
+    settings::Settings,
+};
+
+/// Something that would make the run fail half way, found before anything is changed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Problem {
+    NotRoot {
+        uid: u32,
+    },
+    NotWritable(PathBuf),
+    MissingUser(String),
+    MissingGroup(String),
+    MissingProgram {
+        program: PathBuf,
+        needed_for: &'static str,
+    },
+    NothingOnPort(u16),
+    PortTaken(SocketAddr),
+}
+
+impl Display for Problem {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::NotRoot { uid } => write!(
+                f,
+                "Running as uid {uid}, qicert writes server configuration and reloads services: run it as root or with sudo"
+            ),
+            Self::NotWritable(path) => write!(
+                f,
+                "{} is not writable, fix its owner or mode or point the matching setting elsewhere",
+                path.display()
+            ),
+            Self::MissingUser(user) => write!(
+                f,
+                "The web user {user} does not exist, create it or set www_owner to the user the server runs as"
+            ),
+            Self::MissingGroup(group) => write!(
+                f,
+                "The web group {group} does not exist, create it or set www_owner to the group the server runs as"
+            ),
+            Self::MissingProgram {
+                program,
+                needed_for,
+            } => write!(
+                f,
+                "{} is needed for {needed_for} but was not found, install it or set its path",
+                program.display()
+            ),
+            Self::NothingOnPort(port) => write!(
+                f,
+                "Nothing listens on port {port} to answer the http-01 challenge, start the web server or use --standalone"
+            ),
+            Self::PortTaken(address) => write!(
+                f,
+                "{address} is already in use, stop what listens there or pick another --listen address"
+            ),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct PreflightFailed(pub Vec<Problem>);
+
+impl Display for PreflightFailed {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Preflight checks failed, nothing was changed")?;
+
+        for problem in &self.0 {
+            write!(f, "\n  {problem}")?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Error for PreflightFailed {}
+
+/// Checks privileges, target directories, the web user, the programs qicert calls and
+/// port 80 for a set of servers, and reports every problem at once.
+#[derive(Debug, Clone, Default)]
+pub struct Preflight {
+    servers: Vec<(Server, bool)>,
+}
+
+impl Preflight {
+    const HTTP_PORT: u16 = 80;
+    const LETSENCRYPT_DIR: &str = "/etc/letsencrypt";
+    const TCP_TABLES: [&str; 2] = ["/proc/net/tcp", "/proc/net/tcp6"];
+    // st column of a socket in the LISTEN state.
+    const LISTEN: &str = "0A";
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a server the run configures, `certbot` when it obtains certificates through it.
+    pub fn server(mut self, server: Server, certbot: bool) -> Self {
+        if !self.servers.contains(&(server, certbot)) {
+            self.servers.push((server, certbot));
+        }
+
+        self
+    }
+
+    pub fn verify(&self) -> Result<(), PreflightFailed> {
+        let problems = self.problems();
+
+        if !problems.is_empty() {
+            return Err(PreflightFailed(problems));
+        }
+
+        Ok(())
+    }
+
+    pub fn problems(&self) -> Vec<Problem> {
+        let mut problems = Vec::new();
+
+        // SAFETY: geteuid has no preconditions and cannot fail.
+        let uid = unsafe { libc::geteuid() };
+
+        if uid != 0 {
+            problems.push(Problem::NotRoot { uid });
+        }
+
+        for dir in self.directories() {
+            if !Self::is_writable(&dir) {
+                problems.push(Problem::NotWritable(dir));
+            }
+        }
+
+        for (server, certbot) in &self.servers {
+            problems.extend(Self::owner_problems(&Layout::current(*server)));
+            problems.extend(Self::program_problems(*server, *certbot));
+            problems.extend(Self::port_problems(*server, *certbot));
+        }
+
+        Self::dedup(problems)
+    }
+
+    /// Keeps the first of equal items, in order.
+    fn dedup<T: PartialEq>(items: Vec<T>) -> Vec<T> {
+        let mut unique = Vec::new();
+
+        for item in items {
+            if !unique.contains(&item) {
+                unique.push(item);
+            }
+        }
+
+        unique
+    }
+
+    fn directories(&self) -> Vec<PathBuf> {
+        let settings = Settings::current();
+
+        let mut dirs = vec![
+            settings.backup_dir().to_path_buf(),
+            PathBuf::from(Lock::DIR),
+        ];
+
+        dirs.extend(settings.audit_log().parent().map(Path::to_path_buf));
+
+        for (server, certbot) in &self.servers {
+            let layout = Layout::current(*server);
+
+            dirs.push(layout.config_dir().to_path_buf());
+
+            match layout.enable() {
+                Enable::Symlink(enabled) => dirs.push(enabled.clone()),
+                Enable::Include(main) => dirs.extend(main.parent().map(Path::to_path_buf)),
+                _ => {}
+            }
+
+            match server {
+                Server::Haproxy => {
+                    dirs.push(haproxy::config_file::ConfigFile::certs_path());
+                }
+                Server::Caddy => {
+                    let caddyfile = Path::new(caddy::config_file::ConfigFile::caddyfile_path());
+
+                    dirs.extend(caddyfile.parent().map(Path::to_path_buf));
+                    dirs.push(settings.webroot().to_path_buf());
+                }
+                _ => dirs.push(settings.webroot().to_path_buf()),
+            }
+
+            if *certbot {
+                dirs.push(PathBuf::from(Self::LETSENCRYPT_DIR));
+
+                if *server != Server::Haproxy && Certer::challenge() == Challenge::Webroot {
+                    dirs.push(settings.challenge_root().to_path_buf());
+                }
+            }
+        }
+
+        Self::dedup(dirs)
+    }
+
+    /// A directory qicert creates on the way is writable if its closest existing
+    /// ancestor is.
+    fn is_writable(dir: &Path) -> bool {
+        let Some(existing) = dir.ancestors().find(|a| a.exists()) else {
+            return false;
+        };
+
+        let Ok(path) = CString::new(existing.as_os_str().as_bytes()) else {
+            return false;
+        };
+
+        // SAFETY: path is a valid NUL terminated string for the duration of the call.
+        unsafe { libc::faccessat(libc::AT_FDCWD, path.as_ptr(), libc::W_OK, libc::AT_EACCESS) == 0 }
+    }
+
+    fn owner_problems(layout: &Layout) -> Vec<Problem> {
+        let owner = layout.owner();
+        let (user, group) = owner.split_once(':').unwrap_or((&owner, &owner));
+
+        let mut problems = Vec::new();
+
+        if Self::user_id(user).is_none() {
+            problems.push(Problem::MissingUser(user.to_string()));
+        }
+
+        if Self::group_id(group).is_none() {
+            problems.push(Problem::MissingGroup(group.to_string()));
+        }
+
+        problems
+    }
+
+    pub(crate) fn user_id(user: &str) -> Option<u32> {
+        let name = CString::new(user).ok()?;
+
+        // SAFETY: name is a valid NUL terminated string and the entry is read right away,
+        // before another lookup can overwrite it.
+        unsafe {
+            let entry = libc::getpwnam(name.as_ptr());
+
+            (!entry.is_null()).then(|| (*entry).pw_uid)
+        }
+    }
+
+    pub(crate) fn group_id(group: &str) -> Option<u32> {
+        let name = CString::new(group).ok()?;
+
+        // SAFETY: as in `user_id`.
+        unsafe {
+            let entry = libc::getgrnam(name.as_ptr());
+
+            (!entry.is_null()).then(|| (*entry).gr_gid)
+        }
+    }
+
+    fn program_problems(server: Server, certbot: bool) -> Vec<Problem> {
+        let layout = Layout::current(server);
+
+        let mut programs = vec![
+            (layout.binary().to_path_buf(), "the web server"),
+            (
+                service_manager::for_layout(&layout).program(&layout),
+                "reloading the web server",
+            ),
+        ];
+
+        if let Enable::Command(command) = layout.enable() {
+            programs.push((PathBuf::from(command), "enabling sites"));
+        }
+
+        if certbot {
+            programs.push((
+                Settings::current().certbot().to_path_buf(),
+                "obtaining certificates",
+            ));
+        }
+
+        programs
+            .into_iter()
+            .filter(|(program, _)| !Self::is_installed(program))
+            .map(|(program, needed_for)| Problem::MissingProgram {
+                program,
+                needed_for,
+            })
+            .collect()
+    }
+
+    /// A bare name is looked up in PATH and in the sbin directories sudo may leave out.
+    pub(crate) fn is_installed(program: &Path) -> bool {
+        if program.components().count() > 1 {
+            return program.is_file();
+        }
+
+        let path = env::var_os("PATH").unwrap_or_default();
+
+        env::split_paths(&path)
+            .chain(Layout::SBIN_DIRS.iter().map(PathBuf::from))
+            .any(|dir| dir.join(program).is_file())
+    }
+
+    fn port_problems(server: Server, certbot: bool) -> Vec<Problem> {
+        if !certbot {
+            return Vec::new();
+        }
+
+        let listening = Self::TCP_TABLES
+            .iter()
+            .filter_map(|table| fs::read_to_string(table).ok())
+            .flat_map(|content| Self::listening(&content))
+            .collect::<Vec<_>>();
+
+        match Certer::challenge() {
+            // HAProxy hands the challenge to the responder it starts itself.
+            Challenge::Webroot if server == Server::Haproxy => Vec::new(),
+            Challenge::Webroot => {
+                let served = listening
+                    .iter()
+                    .any(|address| address.port() == Self::HTTP_PORT);
+
+                match served {
+                    true => Vec::new(),
+                    false => vec![Problem::NothingOnPort(Self::HTTP_PORT)],
+                }
+            }
+            Challenge::Standalone(address) => listening
+                .into_iter()
+                .find(|listener| Self::overlaps(*listener, address))
+                .map(|_| vec![Problem::PortTaken(address)])
+                .unwrap_or_default(),
+        }
+    }
+
+    fn overlaps(listener: SocketAddr, address: SocketAddr) -> bool {
+        listener.port() == address.port()
+            && (listener.ip() == address.ip()
+                || listener.ip().is_unspecified()
+                || address.ip().is_unspecified())
+    }
+
+    /// Sockets in the LISTEN state of a `/proc/net/tcp` or `/proc/net/tcp6` table,
+    /// whose addresses are printed as host order 32 bit words.
+    fn listening(table: &str) -> Vec<SocketAddr> {
+        table
+            .lines()
+            .skip(1)
+            .filter_map(|line| {
+                let fields = line.split_whitespace().collect::<Vec<_>>();
+
+                if fields.get(3) != Some(&Self::LISTEN) {
+                    return None;
+                }
+
+                let (ip, port) = fields.get(1)?.split_once(':')?;
+
+                let port = u16::from_str_radix(port, 16).ok()?;
+
+                let words = (0..ip.len() / 8)
+                    .map(|i| u32::from_str_radix(ip.get(i * 8..i * 8 + 8)?, 16).ok())
+                    .collect::<Option<Vec<_>>>()?;
+
+                let bytes = words
+                    .iter()
+                    .flat_map(|word| word.to_ne_bytes())
+                    .collect::<Vec<_>>();
+
+                let ip = match bytes.len() {
+                    4 => IpAddr::V4(Ipv4Addr::from(<[u8; 4]>::try_from(bytes).ok()?)),
+                    16 => IpAddr::V6(Ipv6Addr::from(<[u8; 16]>::try_from(bytes).ok()?)),
+                    _ => return None,
+                };
+
+                Some(SocketAddr::new(ip, port))
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const TCP: &str = "  sl  local_address rem_address   st tx_queue rx_queue tr tm->when retrnsmt   uid  timeout inode
+   0: 00000000:0050 00000000:0000 0A 00000000:00000000 00:00000000 00000000     0        0 21337 1 0000000000000000 100 0 0 10 0
+   1: 0100007F:0CEA 00000000:0000 0A 00000000:00000000 00:00000000 00000000   109        0 21900 1 0000000000000000 100 0 0 10 0
+   2: 0F02000A:0016 0202000A:C5A2 01 00000000:00000000 02:00087F4D 00000000     0        0 30542 4 0000000000000000 20 4 30 10 -1
+";
+
+    const TCP6: &str = "  sl  local_address                         remote_address                        st tx_queue rx_queue tr tm->when retrnsmt   uid  timeout inode
+   0: 00000000000000000000000001000000:01BB 00000000000000000000000000000000:0000 0A 00000000:00000000 00:00000000 00000000     0        0 21340 1 0000000000000000 100 0 0 10 0
+";
+
+    #[test]
+    #[cfg(target_endian = "little")]
+    fn reads_listening_sockets() {
+        assert_eq!(
+            Preflight::listening(TCP),
+            vec![
+                "0.0.0.0:80".parse::<SocketAddr>().unwrap(),
+                "127.0.0.1:3306".parse().unwrap(),
+            ]
+        );
+
+        assert_eq!(
+            Preflight::listening(TCP6),
+            vec!["[::1]:443".parse::<SocketAddr>().unwrap()]
+        );
+    }
+
+    #[test]
+    fn wildcard_listeners_take_every_address() {
+        let any = "0.0.0.0:80".parse().unwrap();
+        let local = "127.0.0.1:80".parse().unwrap();
+        let other = "127.0.0.1:8080".parse().unwrap();
+
+        assert!(Preflight::overlaps(any, local));
+        assert!(Preflight::overlaps(local, any));
+        assert!(!Preflight::overlaps(local, other));
+    }
+
+    #[test]
+    fn reports_each_problem_once() {
+        let problems = Preflight::dedup(vec![
+            Problem::MissingUser("www-data".to_string()),
+            Problem::NothingOnPort(80),
+            Problem::MissingUser("www-data".to_string()),
+        ]);
+
+        assert_eq!(
+            PreflightFailed(problems).to_string(),
+            "Preflight checks failed, nothing was changed
+  The web user www-data does not exist, create it or set www_owner to the user the server runs as
+  Nothing listens on port 80 to answer the http-01 challenge, start the web server or use --standalone"
+        );
+    }
+
+    #[test]
+    fn finds_programs_in_path() {
+        assert!(Preflight::is_installed(Path::new("sh")));
+        assert!(Preflight::is_installed(Path::new("/bin/sh")));
+        assert!(!Preflight::is_installed(Path::new(
+            "qicert-no-such-program"
+        )));
+    }
+}
@@ -0,0 +1,25 @@
+use std::{net::SocketAddr, path::PathBuf};
+
+use crate::{domain::Domain, webroot::WebRoot};
+
+/// What the https site does once the certificate is in place.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Profile {
+    /// Serves the files in the given document root.
+    Static(PathBuf),
+    /// Forwards every request to an application listening on the given address.
+    Proxy(SocketAddr),
+}
+
+impl Profile {
+    /// Static site served from the webroot qicert creates under `/var/www`.
+    pub fn default_for(domain: &Domain) -> Self {
+        Self::Static(WebRoot::build_pathbuf(domain))
+    }
+
+    /// Only the default webroot is created and owned by qicert, any other root is
+    /// left to whoever deploys the site.
+    pub fn uses_default_webroot(&self, domain: &Domain) -> bool {
+        *self == Self::default_for(domain)
+    }
+}
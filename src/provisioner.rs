@@ -0,0 +1,322 @@
+use std::{net::SocketAddr, path::PathBuf, time::Duration};
+
+use crate::{
+    apache,
+    caddy::{self, CaddyReload, CaddyTls},
+    certer::Challenge,
+    configuration_file::ConfigurationFile,
+    dns::{DnsCheck, Resolver},
+    domain::Domain,
+    error::QicertError,
+    haproxy,
+    layout::{Layout, Server},
+    lighttpd,
+    listen::Listen,
+    lock::{Lock, Mode},
+    nginx::{
+        self,
+        upgrade::{Site, UpgradeError},
+    },
+    preflight::Preflight,
+    profile::Profile,
+    report::{Output, Report, Step},
+    run::RunOptions,
+    service_manager::ServiceManagers,
+    settings::Settings,
+};
+
+/// Obtains the certificate of one domain and writes its https configuration for one
+/// web server, with a backup and a rollback if anything fails on the way.
+///
+/// ```no_run
+/// use qicert::{domain::Domain, layout::Server, provisioner::Provisioner};
+///
+/// let domain = Domain::new("example", "com", Some("www"))?;
+///
+/// let report = Provisioner::new(domain, Server::Nginx).run();
+///
+/// for step in report.steps() {
+///     println!("{:?} {:?}", step.action(), step.result());
+/// }
+///
+/// report.into_result()?;
+/// # Ok::<(), qicert::error::QicertError>(())
+/// ```
+#[derive(Debug, Clone)]
+pub struct Provisioner {
+    domain: Domain,
+    server: Server,
+    profile: Option<Profile>,
+    listen: Listen,
+    layout: Option<Layout>,
+    dns: Option<Resolver>,
+    preflight: bool,
+    upgrade: bool,
+    caddy_tls: CaddyTls,
+    caddy_reload: CaddyReload,
+    options: RunOptions,
+}
+
+impl Provisioner {
+    pub fn new(domain: Domain, server: Server) -> Self {
+        Self {
+            domain,
+            server,
+            profile: None,
+            listen: Listen::default(),
+            layout: None,
+            dns: None,
+            preflight: false,
+            upgrade: false,
+            caddy_tls: CaddyTls::default(),
+            caddy_reload: CaddyReload::default(),
+            options: RunOptions::default(),
+        }
+    }
+
+    /// What the https site does, a static site in the default webroot if not given.
+    /// HAProxy ignores it, every hostname goes to the same upstream.
+    pub fn profile(mut self, profile: Profile) -> Self {
+        self.profile = Some(profile);
+        self
+    }
+
+    /// Addresses, ports and protocols of the generated blocks, every address on 80
+    /// and 443 if not given. Only Nginx and Apache take it.
+    pub fn listen(mut self, listen: Listen) -> Self {
+        self.listen = listen;
+        self
+    }
+
+    /// Where the server keeps its configuration, detected from the host if not given.
+    /// It only holds for this run.
+    pub fn layout(mut self, layout: Layout) -> Self {
+        self.layout = Some(layout);
+        self
+    }
+
+    /// Checks the A/AAAA and CAA records of the domain with `resolver` before starting.
+    pub fn dns_check(mut self, resolver: Resolver) -> Self {
+        self.dns = Some(resolver);
+        self
+    }
+
+    /// Checks privileges, target directories, the web user, programs and port 80
+    /// before starting, and fails with every problem found at once.
+    pub fn preflight(mut self) -> Self {
+        self.preflight = true;
+        self
+    }
+
+    /// Moves an existing http-only site of the hostname to https in place instead of
+    /// leaving it alone, keeping what was written by hand. Nginx only.
+    pub fn upgrade(mut self) -> Self {
+        self.upgrade = true;
+        self
+    }
+
+    pub fn caddy_tls(mut self, tls: CaddyTls) -> Self {
+        self.caddy_tls = tls;
+        self
+    }
+
+    pub fn caddy_reload(mut self, reload: CaddyReload) -> Self {
+        self.caddy_reload = reload;
+        self
+    }
+
+    /// Every option of `RunOptions` at once, replacing the ones set before.
+    pub fn options(mut self, options: RunOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    /// How the http-01 challenge is answered, by the web server if not given.
+    pub fn challenge(mut self, challenge: Challenge) -> Self {
+        self.options = self.options.with_challenge(challenge);
+        self
+    }
+
+    /// Goes straight to certbot without fetching a test token first.
+    pub fn skip_reachability_check(mut self) -> Self {
+        self.options = self.options.with_skip_reachability_check(true);
+        self
+    }
+
+    /// Fetches the test token from `address` instead of what DNS currently says.
+    pub fn resolve_reachability_check(mut self, address: SocketAddr) -> Self {
+        self.options = self.options.with_resolve(address);
+        self
+    }
+
+    /// Reloads through `kind` instead of the detected service manager.
+    pub fn service_manager(mut self, kind: ServiceManagers) -> Self {
+        self.options = self.options.with_service_manager(kind);
+        self
+    }
+
+    /// Waits up to `wait` for busy locks instead of failing right away.
+    pub fn wait(mut self, wait: Duration) -> Self {
+        self.options = self.options.with_wait(wait);
+        self
+    }
+
+    /// Prints the progress while the run goes, besides recording it in the report.
+    pub fn output(mut self, output: Output) -> Self {
+        self.options = self.options.with_output(output);
+        self
+    }
+
+    pub fn run(self) -> ProvisionReport {
+        let mark = Report::mark();
+
+        let options = self.options.clone();
+        let result = options.scope(|| self.provision());
+
+        let (steps, messages) = Report::since(mark);
+
+        ProvisionReport {
+            steps,
+            messages,
+            result,
+        }
+    }
+
+    fn provision(self) -> Result<(), QicertError> {
+        let Self {
+            domain,
+            server,
+            profile,
+            listen,
+            layout,
+            dns,
+            preflight,
+            upgrade,
+            caddy_tls,
+            caddy_reload,
+            options: _,
+        } = self;
+
+        if upgrade && server != Server::Nginx {
+            return Err(UpgradeError::Unsupported(server))?;
+        }
+
+        listen.supported_by(server)?;
+
+        let _layout = layout.map(|layout| Layout::for_run(server, layout));
+
+        if preflight {
+            let certbot = server != Server::Caddy || caddy_tls == CaddyTls::Certbot;
+
+            Preflight::new().server(server, certbot).verify()?;
+        }
+
+        if let Some(resolver) = dns {
+            let check = DnsCheck::new(resolver).for_acme_server(Settings::current().acme_server());
+
+            for warning in check.verify(&domain)? {
+                Report::say(warning);
+            }
+        }
+
+        let profile = profile.unwrap_or_else(|| Profile::default_for(&domain));
+
+        let _global = Lock::global(Mode::Shared)?;
+
+        if upgrade {
+            return match Site::locate(&domain)? {
+                Site::Http(file) => {
+                    let _file = Lock::config_file(&file)?;
+
+                    nginx::configurator::Configurator::upgrade(&domain, &file)
+                }
+                Site::Https(file) => {
+                    Report::say(format!(
+                        "{} is already served over https by {}",
+                        domain.unicode(),
+                        file.display()
+                    ));
+
+                    Ok(())
+                }
+            };
+        }
+
+        let _files = Self::locked_files(server, &domain)
+            .iter()
+            .map(|file| Lock::config_file(file))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        match server {
+            Server::Apache => {
+                apache::configurator::Configurator::append_or_create(&domain, &profile, &listen)
+            }
+            Server::Caddy => caddy::configurator::Configurator::append_or_create(
+                &domain,
+                &profile,
+                caddy_tls,
+                &caddy_reload,
+            ),
+            Server::Haproxy => haproxy::configurator::Configurator::append_or_create(&domain),
+            Server::Lighttpd => {
+                lighttpd::configurator::Configurator::append_or_create(&domain, &profile)
+            }
+            Server::Nginx => {
+                nginx::configurator::Configurator::append_or_create(&domain, &profile, &listen)
+            }
+        }
+    }
+
+    /// Files the run rewrites, shared ones included, sorted so that two runs take
+    /// their locks in the same order.
+    pub(crate) fn locked_files(server: Server, domain: &Domain) -> Vec<PathBuf> {
+        let mut files = match server {
+            Server::Apache => vec![apache::config_file::ConfigFile::file_path(domain)],
+            Server::Caddy => vec![
+                caddy::config_file::ConfigFile::file_path(domain),
+                PathBuf::from(caddy::config_file::ConfigFile::caddyfile_path()),
+            ],
+            Server::Haproxy => vec![
+                haproxy::config_file::ConfigFile::file_path(domain),
+                haproxy::config_file::ConfigFile::crt_list_path(),
+            ],
+            Server::Lighttpd => vec![lighttpd::config_file::ConfigFile::file_path(domain)],
+            Server::Nginx => vec![nginx::config_file::ConfigFile::file_path(domain)],
+        };
+
+        files.sort();
+
+        files
+    }
+}
+
+/// What a `Provisioner` run did, step by step, and how it ended.
+#[derive(Debug)]
+pub struct ProvisionReport {
+    steps: Vec<Step>,
+    messages: Vec<String>,
+    result: Result<(), QicertError>,
+}
+
+impl ProvisionReport {
+    pub fn steps(&self) -> &[Step] {
+        &self.steps
+    }
+
+    /// The progress messages the command line prints.
+    pub fn messages(&self) -> &[String] {
+        &self.messages
+    }
+
+    pub fn is_success(&self) -> bool {
+        self.result.is_ok()
+    }
+
+    pub fn error(&self) -> Option<&QicertError> {
+        self.result.as_ref().err()
+    }
+
+    pub fn into_result(self) -> Result<(), QicertError> {
+        self.result
+    }
+}
@@ -0,0 +1,430 @@
+use std::{
+    collections::HashMap,
+    error::Error,
+    fmt::Display,
+    fs,
+    io::{self, Read, Write},
+    net::{SocketAddr, TcpStream, ToSocketAddrs},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use crate::{domain::Domain, error::QicertError, report::Report, responder::Responder};
+
+#[derive(Debug)]
+pub enum ReachabilityError {
+    TokenNotWritten,
+    Unresolvable(String),
+    Unreachable {
+        url: String,
+        address: SocketAddr,
+        reason: String,
+    },
+    NotServed {
+        url: String,
+        address: SocketAddr,
+        status: u16,
+    },
+    Redirected {
+        url: String,
+        address: SocketAddr,
+        location: String,
+    },
+    WrongContent {
+        url: String,
+        address: SocketAddr,
+    },
+}
+
+impl Display for ReachabilityError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::TokenNotWritten => write!(f, "The test token could not be written"),
+            Self::Unresolvable(host) => write!(
+                f,
+                "{host} does not resolve to any address, check its A/AAAA records"
+            ),
+            Self::Unreachable { url, address, reason } => write!(
+                f,
+                "{url} could not be fetched from {address} ({reason}), is port 80 open and does DNS point at this host?"
+            ),
+            Self::NotServed { url, address, status } => write!(
+                f,
+                "{url} answered {status} from {address}, the challenge location is not served from the challenge root"
+            ),
+            Self::Redirected { url, address, location } => write!(
+                f,
+                "{url} was redirected to {location} by {address}, the challenge location must be served over plain http"
+            ),
+            Self::WrongContent { url, address } => write!(
+                f,
+                "{url} answered from {address} with something else than the test token, another server is answering for this host"
+            ),
+        }
+    }
+}
+
+impl Error for ReachabilityError {}
+
+struct Response {
+    status: u16,
+    location: Option<String>,
+    body: String,
+}
+
+/// Fetches a random token through the same path the ACME server will use, so a broken
+/// DNS record or challenge location is caught before spending a rate-limited attempt.
+pub struct Reachability {
+    challenge_root: PathBuf,
+    port: u16,
+    overrides: HashMap<String, SocketAddr>,
+}
+
+impl Reachability {
+    const CHALLENGE_PATH: &'static str = ".well-known/acme-challenge";
+    const MAX_REDIRECTS: usize = 5;
+    const URANDOM: &'static str = "/dev/urandom";
+
+    /// `challenge_root` is the directory given to certbot with `--webroot -w`.
+    pub fn new<P: AsRef<Path>>(challenge_root: P) -> Self {
+        Self {
+            challenge_root: challenge_root.as_ref().to_path_buf(),
+            port: 80,
+            overrides: HashMap::new(),
+        }
+    }
+
+    /// Answers `host` with `address` instead of asking the resolver, like curl's `--resolve`.
+    pub fn resolve<S: AsRef<str>>(mut self, host: S, address: SocketAddr) -> Self {
+        self.overrides.insert(host.as_ref().to_lowercase(), address);
+        self
+    }
+
+    pub fn random_token() -> io::Result<String> {
+        let mut bytes = [0u8; 16];
+
+        fs::File::open(Self::URANDOM)?.read_exact(&mut bytes)?;
+
+        Ok(bytes.iter().map(|b| format!("{b:02x}")).collect())
+    }
+
+    fn token_path(&self, token: &str) -> PathBuf {
+        self.challenge_root.join(Self::CHALLENGE_PATH).join(token)
+    }
+
+    /// Check for the webroot flow: the token is written where certbot would write it.
+    pub fn check_webroot(&self, domain: &Domain) -> Result<(), QicertError> {
+        let token = Self::random_token()
+            .map_err(|e| QicertError::io(ReachabilityError::TokenNotWritten, Self::URANDOM, e))?;
+
+        let path = self.token_path(&token);
+
+        path.parent()
+            .map(fs::create_dir_all)
+            .transpose()
+            .and_then(|_| fs::write(&path, &token))
+            .map_err(|e| QicertError::io(ReachabilityError::TokenNotWritten, &path, e))?;
+
+        let result = self.fetch_everywhere(domain, &token);
+
+        let _ = fs::remove_file(&path);
+
+        Ok(result?)
+    }
+
+    /// Check for the built-in responder, which gets the token from memory.
+    pub fn check_responder(
+        &self,
+        responder: &Responder,
+        domain: &Domain,
+    ) -> Result<(), QicertError> {
+        let token = Self::random_token()
+            .map_err(|e| QicertError::io(ReachabilityError::TokenNotWritten, Self::URANDOM, e))?;
+
+        responder.add(&token, &token);
+
+        let result = self.fetch_everywhere(domain, &token);
+
+        responder.remove(&token);
+
+        Ok(result?)
+    }
+
+    fn resolve_host(&self, host: &str) -> Result<Vec<SocketAddr>, ReachabilityError> {
+        if let Some(address) = self.overrides.get(host) {
+            return Ok(vec![*address]);
+        }
+
+        let addresses: Vec<SocketAddr> = (host, self.port)
+            .to_socket_addrs()
+            .map(Iterator::collect)
+            .unwrap_or_default();
+
+        if addresses.is_empty() {
+            return Err(ReachabilityError::Unresolvable(host.to_string()));
+        }
+
+        Ok(addresses)
+    }
+
+    /// The CA may pick any of the published addresses, every one of them has to work.
+    fn fetch_everywhere(&self, domain: &Domain, token: &str) -> Result<(), ReachabilityError> {
+        let host = domain.to_string();
+        let path = format!("/{}/{token}", Self::CHALLENGE_PATH);
+
+        for address in self.resolve_host(&host)? {
+            self.fetch_token(&host, address, &path, token)?;
+        }
+
+        Ok(())
+    }
+
+    fn fetch_token(
+        &self,
+        host: &str,
+        address: SocketAddr,
+        path: &str,
+        token: &str,
+    ) -> Result<(), ReachabilityError> {
+        let mut host = host.to_string();
+        let mut address = address;
+        let mut path = path.to_string();
+
+        for _ in 0..=Self::MAX_REDIRECTS {
+            let url = format!("http://{host}{path}");
+
+            let response =
+                Self::get(address, &host, &path).map_err(|e| ReachabilityError::Unreachable {
+                    url: url.clone(),
+                    address,
+                    reason: e.to_string(),
+                })?;
+
+            match (response.status, response.location) {
+                (200, _) if response.body.trim() == token => return Ok(()),
+                (200, _) => return Err(ReachabilityError::WrongContent { url, address }),
+                (301 | 302 | 303 | 307 | 308, Some(location)) => {
+                    let Some((next_host, next_path)) = Self::split_http_url(&location) else {
+                        if location.starts_with("https://") {
+                            Report::say(format!(
+                                "{url} redirects to {location}, which cannot be checked before issuance"
+                            ));
+
+                            return Ok(());
+                        }
+
+                        return Err(ReachabilityError::Redirected {
+                            url,
+                            address,
+                            location,
+                        });
+                    };
+
+                    address = self.resolve_host(&next_host)?[0];
+                    host = next_host;
+                    path = next_path;
+                }
+                (status, _) => {
+                    return Err(ReachabilityError::NotServed {
+                        url,
+                        address,
+                        status,
+                    })
+                }
+            }
+        }
+
+        Err(ReachabilityError::Redirected {
+            url: format!("http://{host}{path}"),
+            address,
+            location: String::from("too many redirects"),
+        })
+    }
+
+    fn split_http_url(url: &str) -> Option<(String, String)> {
+        let rest = url.strip_prefix("http://")?;
+
+        let (host, path) = match rest.find('/') {
+            Some(i) => (&rest[..i], &rest[i..]),
+            None => (rest, "/"),
+        };
+
+        Some((host.to_lowercase(), path.to_string()))
+    }
+
+    fn get(address: SocketAddr, host: &str, path: &str) -> io::Result<Response> {
+        let timeout = Duration::from_secs(10);
+
+        let mut stream = TcpStream::connect_timeout(&address, timeout)?;
+        stream.set_read_timeout(Some(timeout))?;
+
+        write!(
+            stream,
+            "GET {path} HTTP/1.1\r\nHost: {host}\r\nUser-Agent: qicert\r\nConnection: close\r\n\r\n"
+        )?;
+
+        let mut raw = Vec::new();
+        stream.take(64 * 1024).read_to_end(&mut raw)?;
+
+        Self::parse_response(&String::from_utf8_lossy(&raw))
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "malformed http response"))
+    }
+
+    fn parse_response(raw: &str) -> Option<Response> {
+        let (head, body) = raw.split_once("\r\n\r\n")?;
+        let mut lines = head.lines();
+
+        let status = lines.next()?.split_whitespace().nth(1)?.parse().ok()?;
+
+        let location = lines
+            .filter_map(|l| l.split_once(':'))
+            .find(|(name, _)| name.eq_ignore_ascii_case("location"))
+            .map(|(_, value)| value.trim().to_string());
+
+        Some(Response {
+            status,
+            location,
+            body: body.to_string(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_support::scratch;
+    use std::{io::BufRead, io::BufReader, net::TcpListener, thread};
+
+    /// Serves files from `root` like the challenge location of a configured web server.
+    fn fake_webserver(root: PathBuf, requests: usize) -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+
+        thread::spawn(move || {
+            for stream in listener.incoming().take(requests) {
+                let mut stream = BufReader::new(stream.unwrap());
+
+                let mut request_line = String::new();
+                stream.read_line(&mut request_line).unwrap();
+
+                // Drain the headers, closing with unread data would reset the connection.
+                let mut header = String::new();
+                while stream.read_line(&mut header).unwrap() > 2 {
+                    header.clear();
+                }
+
+                let path = request_line.split_whitespace().nth(1).unwrap().to_string();
+
+                let response = match fs::read_to_string(root.join(path.trim_start_matches('/'))) {
+                    Ok(body) => format!(
+                        "HTTP/1.1 200 OK\r\nContent-Length: {}\r\n\r\n{body}",
+                        body.len()
+                    ),
+                    Err(_) => String::from("HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\n\r\n"),
+                };
+
+                stream.get_mut().write_all(response.as_bytes()).unwrap();
+            }
+        });
+
+        address
+    }
+
+    #[test]
+    fn token_served_from_challenge_root() {
+        let root = scratch("reachable");
+        let address = fake_webserver(root.to_path_buf(), 1);
+
+        let domain = Domain::new("example", "com", Some("www")).unwrap();
+
+        let result = Reachability::new(&root)
+            .resolve("www.example.com", address)
+            .check_webroot(&domain);
+
+        assert!(result.is_ok(), "{result:?}");
+
+        assert_eq!(
+            fs::read_dir(root.join(".well-known/acme-challenge"))
+                .unwrap()
+                .count(),
+            0
+        );
+    }
+
+    #[test]
+    fn challenge_location_not_served() {
+        let root = scratch("not-served");
+        let elsewhere = scratch("not-served-elsewhere");
+        let address = fake_webserver(elsewhere.to_path_buf(), 1);
+
+        let domain = Domain::new("example", "com", None).unwrap();
+
+        let result = Reachability::new(&root)
+            .resolve("example.com", address)
+            .check_webroot(&domain);
+
+        assert!(matches!(
+            result.unwrap_err().downcast_ref(),
+            Some(ReachabilityError::NotServed { status: 404, .. })
+        ));
+    }
+
+    #[test]
+    fn nothing_listening() {
+        let root = scratch("unreachable");
+
+        let address = {
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            listener.local_addr().unwrap()
+        };
+
+        let domain = Domain::new("example", "com", None).unwrap();
+
+        let result = Reachability::new(&root)
+            .resolve("example.com", address)
+            .check_webroot(&domain);
+
+        assert!(matches!(
+            result.unwrap_err().downcast_ref(),
+            Some(ReachabilityError::Unreachable { .. })
+        ));
+    }
+
+    #[test]
+    fn token_served_by_responder() {
+        let responder = Responder::start("127.0.0.1:0".parse().unwrap()).unwrap();
+
+        let domain = Domain::new("example", "com", None).unwrap();
+
+        let result = Reachability::new("/nonexistent")
+            .resolve("example.com", responder.address())
+            .check_responder(&responder, &domain);
+
+        assert!(result.is_ok(), "{result:?}");
+
+        responder.shutdown();
+    }
+
+    #[test]
+    fn parse_redirect_response() {
+        let raw = "HTTP/1.1 301 Moved Permanently\r\nServer: nginx\r\nLocation: https://example.com/x\r\n\r\n";
+
+        let response = Reachability::parse_response(raw).unwrap();
+
+        assert_eq!(response.status, 301);
+        assert_eq!(response.location.as_deref(), Some("https://example.com/x"));
+    }
+
+    #[test]
+    fn split_http_urls() {
+        assert_eq!(
+            Reachability::split_http_url("http://Example.com/.well-known/acme-challenge/x"),
+            Some((
+                String::from("example.com"),
+                String::from("/.well-known/acme-challenge/x")
+            ))
+        );
+
+        assert_eq!(Reachability::split_http_url("https://example.com/"), None);
+    }
+}
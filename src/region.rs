@@ -0,0 +1,204 @@
+use std::{error::Error, fmt::Display, ops::Range, path::PathBuf};
+
+use crate::{audit::Audit, domain::Domain};
+
+#[derive(Debug)]
+pub enum RegionError {
+    Edited { path: PathBuf, hostname: String },
+}
+
+impl Display for RegionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Edited { path, hostname } => write!(
+                f,
+                "The qicert block of {hostname} in {} was edited by hand and was left alone, \
+                move the changes out of it or delete its BEGIN and END lines to keep them",
+                path.display()
+            ),
+        }
+    }
+}
+
+impl Error for RegionError {}
+
+/// Blocks qicert wrote for one hostname, between
+/// `# BEGIN qicert <hostname> <version> <sha256>` and `# END qicert`.
+///
+/// The hash covers what qicert wrote, so a region whose content no longer matches it
+/// was changed by someone else and is not rewritten or removed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Region<'a> {
+    hostname: &'a str,
+    version: &'a str,
+    hash: &'a str,
+    body: &'a str,
+    range: Range<usize>,
+}
+
+impl<'a> Region<'a> {
+    const BEGIN: &'static str = "# BEGIN qicert";
+    const END: &'static str = "# END qicert";
+
+    pub(crate) fn wrap(domain: &Domain, body: &str) -> String {
+        format!(
+            "{} {domain} {} {}\n{body}\n{}",
+            Self::BEGIN,
+            env!("CARGO_PKG_VERSION"),
+            Self::hash(body),
+            Self::END
+        )
+    }
+
+    /// Trailing whitespace does not count, editors strip it from lines nobody touched.
+    fn hash(body: &str) -> String {
+        let normalized = body.lines().map(str::trim_end).collect::<Vec<_>>();
+
+        Audit::sha256(normalized.join("\n").trim().as_bytes())
+    }
+
+    /// `<hostname> <version> <sha256>` of a BEGIN line.
+    fn begin(line: &str) -> Option<[&str; 3]> {
+        let mut words = line
+            .strip_prefix(Self::BEGIN)?
+            .strip_prefix(' ')?
+            .split_whitespace();
+
+        let fields = [words.next()?, words.next()?, words.next()?];
+
+        words.next().is_none().then_some(fields)
+    }
+
+    /// The regions of `haystack`, in order. A BEGIN line without its END is ignored.
+    pub(crate) fn all(haystack: &'a str) -> Vec<Self> {
+        let mut regions = Vec::new();
+        let mut open = None;
+        let mut offset = 0;
+
+        for line in haystack.split_inclusive('\n') {
+            let trimmed = line.trim();
+
+            if let Some(fields) = Self::begin(trimmed) {
+                open = Some((offset, offset + line.len(), fields));
+            } else if trimmed == Self::END {
+                if let Some((start, body_start, [hostname, version, hash])) = open.take() {
+                    let body = &haystack[body_start.min(offset)..offset];
+
+                    regions.push(Self {
+                        hostname,
+                        version,
+                        hash,
+                        body: body.strip_suffix('\n').unwrap_or(body),
+                        range: start..offset + line.len(),
+                    });
+                }
+            }
+
+            offset += line.len();
+        }
+
+        regions
+    }
+
+    pub(crate) fn find(haystack: &'a str, domain: &Domain) -> Option<Self> {
+        let hostname = domain.to_string();
+
+        Self::all(haystack)
+            .into_iter()
+            .find(|region| region.hostname == hostname)
+    }
+
+    pub(crate) fn hostname(&self) -> &str {
+        self.hostname
+    }
+
+    pub(crate) fn version(&self) -> &str {
+        self.version
+    }
+
+    pub(crate) fn is_edited(&self) -> bool {
+        Self::hash(self.body) != self.hash
+    }
+
+    /// `haystack`, the text the region was found in, without it.
+    pub(crate) fn remove_from(&self, haystack: &str) -> String {
+        format!(
+            "{}{}",
+            &haystack[..self.range.start],
+            &haystack[self.range.end..]
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn domain() -> Domain {
+        Domain::new("example", "com", Some("www")).unwrap()
+    }
+
+    const BODY: &str = "server {
+    listen 80;
+    server_name www.example.com;
+    return 301 https://www.example.com$request_uri;
+}";
+
+    #[test]
+    fn wraps_and_finds_a_region() {
+        let domain = domain();
+
+        let region = Region::wrap(&domain, BODY);
+
+        assert!(region.starts_with(&format!(
+            "# BEGIN qicert www.example.com {} ",
+            env!("CARGO_PKG_VERSION")
+        )));
+        assert!(region.ends_with("}\n# END qicert"));
+
+        let haystack = format!("server {{\n    server_name example.com;\n}}\n{region}\n");
+
+        let found = Region::find(&haystack, &domain).unwrap();
+
+        assert_eq!(found.body, BODY);
+        assert_eq!(found.version(), env!("CARGO_PKG_VERSION"));
+        assert!(!found.is_edited());
+
+        assert_eq!(
+            found.remove_from(&haystack),
+            "server {\n    server_name example.com;\n}\n"
+        );
+
+        assert!(Region::find(&haystack, &Domain::new("example", "com", None).unwrap()).is_none());
+    }
+
+    #[test]
+    fn edits_inside_a_region_are_noticed() {
+        let domain = domain();
+
+        let region = Region::wrap(&domain, &BODY.replace("listen 80;", "listen 80;   "));
+
+        // Stripped trailing whitespace is not an edit.
+        let stripped = region.replace("listen 80;   ", "listen 80;");
+
+        assert_ne!(stripped, region);
+
+        assert!(!Region::find(&stripped, &domain).unwrap().is_edited());
+
+        let edited = region.replace("return 301", "return 302");
+
+        assert!(Region::find(&edited, &domain).unwrap().is_edited());
+    }
+
+    #[test]
+    fn challenge_markers_and_unfinished_regions_are_not_regions() {
+        let haystack = "# BEGIN qicert-challenge www.example.com
+server {}
+# END qicert-challenge www.example.com
+# BEGIN qicert www.example.com 0.3.1 abc
+server {}
+";
+
+        assert!(Region::all(haystack).is_empty());
+    }
+}
@@ -0,0 +1,452 @@
+use std::{
+    cell::RefCell,
+    error::Error,
+    fmt::Display,
+    path::{Path, PathBuf},
+    process::ExitCode,
+};
+
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    apache::ApacheError, caddy::CaddyError, certer::CertBotError, dns::DnsCheckFailed,
+    domain::DomainError, error::QicertError, haproxy::HaProxyError, lighttpd::LighttpdError,
+    lock::LockError, nginx::NginxError, preflight::PreflightFailed,
+    reachability::ReachabilityError, run::RunOptions,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Output {
+    Text,
+    Json,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Action {
+    Backup,
+    CreateConfig,
+    WriteConfig,
+    Link,
+    Webroot,
+    Reload,
+    Certificate,
+    CrtList,
+    Rollback,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StepResult {
+    Done,
+    Skipped,
+    Failed,
+}
+
+/// Failure classes, each one exits with its own code so wrappers do not have to parse
+/// messages. Command line usage errors exit with 2, as clap does.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Failure {
+    Other,
+    DomainInvalid,
+    ServerMissing,
+    ConfigTestFailed,
+    AcmeFailed,
+    RollbackFailed,
+    Locked,
+    PreflightFailed,
+}
+
+impl Failure {
+    pub fn exit_code(self) -> u8 {
+        match self {
+            Self::Other => 1,
+            Self::DomainInvalid => 3,
+            Self::ServerMissing => 4,
+            Self::ConfigTestFailed => 5,
+            Self::AcmeFailed => 6,
+            Self::RollbackFailed => 7,
+            Self::Locked => 8,
+            Self::PreflightFailed => 9,
+        }
+    }
+
+    pub fn classify(error: &(dyn Error + 'static)) -> Self {
+        if let Some(e) = error.downcast_ref::<QicertError>() {
+            return match e {
+                QicertError::Rollback { .. } => Self::RollbackFailed,
+                _ => Self::classify(e.kind()),
+            };
+        }
+
+        if let Some(LockError::Busy { .. }) = error.downcast_ref::<LockError>() {
+            return Self::Locked;
+        }
+
+        if error.is::<PreflightFailed>() {
+            return Self::PreflightFailed;
+        }
+
+        if error.is::<DomainError>() || error.is::<DnsCheckFailed>() {
+            return Self::DomainInvalid;
+        }
+
+        // The CA would not have reached the challenge either.
+        if error.is::<ReachabilityError>() {
+            return Self::AcmeFailed;
+        }
+
+        if let Some(e) = error.downcast_ref::<CertBotError>() {
+            return match e {
+                CertBotError::NotInstalled => Self::ServerMissing,
+                _ => Self::AcmeFailed,
+            };
+        }
+
+        let server = [
+            error.downcast_ref::<NginxError>().map(|e| match e {
+                NginxError::NotInstalled => Self::ServerMissing,
+                NginxError::BadConfiguration => Self::ConfigTestFailed,
+                _ => Self::Other,
+            }),
+            error.downcast_ref::<ApacheError>().map(|e| match e {
+                ApacheError::NotInstalled => Self::ServerMissing,
+                ApacheError::BadConfiguration => Self::ConfigTestFailed,
+                _ => Self::Other,
+            }),
+            error.downcast_ref::<CaddyError>().map(|e| match e {
+                CaddyError::NotInstalled => Self::ServerMissing,
+                CaddyError::BadConfiguration => Self::ConfigTestFailed,
+                _ => Self::Other,
+            }),
+            error.downcast_ref::<HaProxyError>().map(|e| match e {
+                HaProxyError::NotInstalled => Self::ServerMissing,
+                HaProxyError::BadConfiguration => Self::ConfigTestFailed,
+                _ => Self::Other,
+            }),
+            error.downcast_ref::<LighttpdError>().map(|e| match e {
+                LighttpdError::NotInstalled => Self::ServerMissing,
+                LighttpdError::BadConfiguration => Self::ConfigTestFailed,
+                _ => Self::Other,
+            }),
+        ];
+
+        server.into_iter().flatten().next().unwrap_or(Self::Other)
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct StepError {
+    kind: Failure,
+    message: String,
+    causes: Vec<String>,
+}
+
+impl StepError {
+    pub fn kind(&self) -> Failure {
+        self.kind
+    }
+
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    /// The errors that led to this one, outermost first.
+    pub fn causes(&self) -> &[String] {
+        &self.causes
+    }
+
+    fn new(error: &(dyn Error + 'static)) -> Self {
+        Self {
+            kind: Failure::classify(error),
+            message: error.to_string(),
+            causes: std::iter::successors(error.source(), |&e| e.source())
+                .map(ToString::to_string)
+                .collect(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Step {
+    action: Action,
+    path: Option<PathBuf>,
+    result: StepResult,
+    error: Option<StepError>,
+}
+
+impl Step {
+    pub fn action(&self) -> Action {
+        self.action
+    }
+
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+
+    pub fn result(&self) -> StepResult {
+        self.result
+    }
+
+    pub fn error(&self) -> Option<&StepError> {
+        self.error.as_ref()
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct Summary<'a> {
+    status: &'static str,
+    exit_code: u8,
+    error: Option<StepError>,
+    steps: &'a [Step],
+    messages: &'a [String],
+}
+
+thread_local! {
+    static STEPS: RefCell<Vec<Step>> = const { RefCell::new(Vec::new()) };
+    static MESSAGES: RefCell<Vec<String>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Where the steps and messages recorded on this thread stood at some point.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Mark {
+    steps: usize,
+    messages: usize,
+}
+
+/// Collects what a run did, per thread. Text output prints the messages as they come,
+/// JSON output prints everything once the run is over.
+pub struct Report;
+
+impl Report {
+    /// Nothing is printed unless the run sets an output, library callers read the
+    /// messages from the `ProvisionReport` instead.
+    fn output() -> Option<Output> {
+        RunOptions::current().output()
+    }
+
+    fn record(action: Action, path: Option<&Path>, result: StepResult, error: Option<StepError>) {
+        STEPS.with_borrow_mut(|steps| {
+            steps.push(Step {
+                action,
+                path: path.map(Path::to_path_buf),
+                result,
+                error,
+            })
+        });
+    }
+
+    pub(crate) fn mark() -> Mark {
+        Mark {
+            steps: STEPS.with_borrow(Vec::len),
+            messages: MESSAGES.with_borrow(Vec::len),
+        }
+    }
+
+    /// Steps and messages recorded on this thread after `mark`.
+    pub(crate) fn since(mark: Mark) -> (Vec<Step>, Vec<String>) {
+        let steps = STEPS.with_borrow(|s| s.get(mark.steps..).unwrap_or_default().to_vec());
+        let messages =
+            MESSAGES.with_borrow(|m| m.get(mark.messages..).unwrap_or_default().to_vec());
+
+        (steps, messages)
+    }
+
+    /// Forgets what this thread recorded, for long running callers.
+    pub fn clear() {
+        STEPS.with_borrow_mut(Vec::clear);
+        MESSAGES.with_borrow_mut(Vec::clear);
+    }
+
+    pub fn done(action: Action, path: Option<&Path>) {
+        Self::record(action, path, StepResult::Done, None);
+    }
+
+    pub fn skipped(action: Action, path: Option<&Path>) {
+        Self::record(action, path, StepResult::Skipped, None);
+    }
+
+    pub fn failed(action: Action, path: Option<&Path>, error: &(dyn Error + 'static)) {
+        Self::record(
+            action,
+            path,
+            StepResult::Failed,
+            Some(StepError::new(error)),
+        );
+    }
+
+    /// Records the outcome of a step and hands it back untouched.
+    pub fn step<T, E: Error + 'static>(
+        action: Action,
+        path: Option<&Path>,
+        result: Result<T, E>,
+    ) -> Result<T, E> {
+        match &result {
+            Ok(_) => Self::done(action, path),
+            Err(e) => Self::failed(action, path, e),
+        }
+
+        result
+    }
+
+    /// Human readable progress, kept out of stdout when it has to stay valid JSON.
+    pub fn say<M: Display>(message: M) {
+        if Self::output() == Some(Output::Text) {
+            println!("{message}");
+        }
+
+        MESSAGES.with_borrow_mut(|messages| messages.push(message.to_string()));
+    }
+
+    fn render(steps: &[Step], messages: &[String], error: Option<StepError>) -> String {
+        let exit_code = error.as_ref().map_or(0, |e| e.kind.exit_code());
+
+        let summary = Summary {
+            status: if error.is_some() { "failed" } else { "ok" },
+            exit_code,
+            error,
+            steps,
+            messages,
+        };
+
+        serde_json::to_string_pretty(&summary).unwrap_or_default()
+    }
+
+    /// Prints the outcome of the run in `output` and turns it into the process exit code.
+    pub fn finish(output: Output, result: Result<(), QicertError>) -> ExitCode {
+        let error = result.as_ref().err().map(|e| StepError::new(e));
+        let exit_code = error.as_ref().map_or(0, |e| e.kind.exit_code());
+
+        match output {
+            Output::Text => {
+                if let Err(e) = &result {
+                    eprintln!("Error: {}", e.chain());
+                }
+            }
+            Output::Json => {
+                let steps = STEPS.with_borrow(Vec::clone);
+                let messages = MESSAGES.with_borrow(Vec::clone);
+
+                println!("{}", Self::render(&steps, &messages, error));
+            }
+        }
+
+        ExitCode::from(exit_code)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::preflight::Problem;
+
+    use super::*;
+
+    #[test]
+    fn failures_map_to_their_exit_code() {
+        let cases: Vec<(Box<dyn Error>, u8)> = vec![
+            (Box::new(DomainError::InvalidName), 3),
+            (Box::new(NginxError::NotInstalled), 4),
+            (Box::new(CertBotError::NotInstalled), 4),
+            (Box::new(LighttpdError::BadConfiguration), 5),
+            (Box::new(CertBotError::ProcessFailure), 6),
+            (
+                Box::new(QicertError::rollback(
+                    CertBotError::ProcessFailure.into(),
+                    QicertError::io(
+                        NginxError::CannotReload,
+                        "/etc/nginx/sites-available/example.com.conf",
+                        std::io::Error::other("read-only file system"),
+                    ),
+                )),
+                7,
+            ),
+            (
+                Box::new(QicertError::io(
+                    CertBotError::ProcessFailure,
+                    "/usr/bin/certbot",
+                    std::io::Error::other("exec format error"),
+                )),
+                6,
+            ),
+            (
+                Box::new(LockError::Busy {
+                    path: PathBuf::from("/run/qicert/qicert.lock"),
+                    holder: Some(4242),
+                }),
+                8,
+            ),
+            (
+                Box::new(PreflightFailed(vec![Problem::NothingOnPort(80)])),
+                9,
+            ),
+            (Box::new(NginxError::CannotReload), 1),
+            (Box::new(std::io::Error::other("anything else")), 1),
+        ];
+
+        for (error, expected) in cases {
+            assert_eq!(
+                Failure::classify(error.as_ref()).exit_code(),
+                expected,
+                "{error}"
+            );
+        }
+    }
+
+    #[test]
+    fn json_summary() {
+        let steps = vec![
+            Step {
+                action: Action::CreateConfig,
+                path: Some(PathBuf::from("/etc/nginx/sites-available/example.com.conf")),
+                result: StepResult::Done,
+                error: None,
+            },
+            Step {
+                action: Action::Certificate,
+                path: None,
+                result: StepResult::Failed,
+                error: Some(StepError::new(&CertBotError::ProcessFailure)),
+            },
+        ];
+
+        let error = Some(StepError::new(&CertBotError::ProcessFailure));
+
+        let rendered = Report::render(&steps, &[String::from("Link created")], error);
+
+        let json: serde_json::Value = serde_json::from_str(&rendered).unwrap();
+
+        assert_eq!(json["status"], "failed");
+        assert_eq!(json["exit_code"], 6);
+        assert_eq!(json["error"]["kind"], "acme_failed");
+        assert_eq!(json["steps"][0]["action"], "create_config");
+        assert_eq!(
+            json["steps"][0]["path"],
+            "/etc/nginx/sites-available/example.com.conf"
+        );
+        assert_eq!(json["steps"][0]["error"], serde_json::Value::Null);
+        assert_eq!(json["steps"][1]["result"], "failed");
+        assert_eq!(json["steps"][1]["error"]["kind"], "acme_failed");
+        assert_eq!(json["messages"][0], "Link created");
+    }
+
+    #[test]
+    fn steps_are_kept_per_thread() {
+        let mark = Report::mark();
+
+        Report::done(Action::Backup, None);
+        Report::say("Backup done");
+
+        std::thread::spawn(|| Report::skipped(Action::Link, None))
+            .join()
+            .unwrap();
+
+        let (steps, messages) = Report::since(mark);
+
+        assert_eq!(steps.len(), 1);
+        assert_eq!(steps[0].action(), Action::Backup);
+        assert_eq!(steps[0].result(), StepResult::Done);
+        assert_eq!(messages, vec![String::from("Backup done")]);
+    }
+}
@@ -0,0 +1,357 @@
+use std::{
+    collections::HashMap,
+    error::Error,
+    fmt::Display,
+    io::{BufRead, BufReader, Write},
+    net::{SocketAddr, TcpListener, TcpStream},
+    os::unix::net::{UnixListener, UnixStream},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+use crate::error::QicertError;
+
+#[derive(Debug)]
+pub enum ResponderError {
+    Bind,
+    ControlSocket,
+    MissingHookEnvironment,
+}
+
+impl Display for ResponderError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Bind => write!(f, "The challenge responder could not bind its address"),
+            Self::ControlSocket => write!(f, "The challenge responder could not be reached"),
+            Self::MissingHookEnvironment => write!(
+                f,
+                "CERTBOT_TOKEN, CERTBOT_VALIDATION or QICERT_RESPONDER is not set"
+            ),
+        }
+    }
+}
+
+impl Error for ResponderError {}
+
+type Tokens = Arc<Mutex<HashMap<String, String>>>;
+
+/// Minimal http server answering `/.well-known/acme-challenge/<token>` from memory and
+/// nothing else. It lives only for the duration of an issuance.
+pub struct Responder {
+    address: SocketAddr,
+    tokens: Tokens,
+    stop: Arc<AtomicBool>,
+    threads: Vec<JoinHandle<()>>,
+    control: Option<PathBuf>,
+}
+
+impl Responder {
+    const CHALLENGE_PREFIX: &'static str = "/.well-known/acme-challenge/";
+
+    pub fn start(address: SocketAddr) -> Result<Self, QicertError> {
+        let listener = TcpListener::bind(address)
+            .map_err(|e| QicertError::io(ResponderError::Bind, address.to_string(), e))?;
+        let address = listener
+            .local_addr()
+            .map_err(|e| QicertError::io(ResponderError::Bind, address.to_string(), e))?;
+
+        let tokens = Tokens::default();
+        let stop = Arc::new(AtomicBool::new(false));
+
+        let thread = {
+            let tokens = tokens.clone();
+            let stop = stop.clone();
+
+            thread::spawn(move || {
+                for stream in listener.incoming() {
+                    if stop.load(Ordering::SeqCst) {
+                        break;
+                    }
+
+                    if let Ok(stream) = stream {
+                        let _ = Self::serve(stream, &tokens);
+                    }
+                }
+            })
+        };
+
+        Ok(Self {
+            address,
+            tokens,
+            stop,
+            threads: vec![thread],
+            control: None,
+        })
+    }
+
+    pub fn address(&self) -> SocketAddr {
+        self.address
+    }
+
+    /// In-process counterpart of the `auth` hook, for clients living in qicert itself.
+    pub fn add(&self, token: &str, key_authorization: &str) {
+        if let Ok(mut tokens) = self.tokens.lock() {
+            tokens.insert(token.to_string(), key_authorization.to_string());
+        }
+    }
+
+    pub fn remove(&self, token: &str) {
+        if let Ok(mut tokens) = self.tokens.lock() {
+            tokens.remove(token);
+        }
+    }
+
+    fn serve(stream: TcpStream, tokens: &Tokens) -> std::io::Result<()> {
+        stream.set_read_timeout(Some(Duration::from_secs(10)))?;
+
+        let mut reader = BufReader::new(stream);
+        let mut request_line = String::new();
+
+        reader.read_line(&mut request_line)?;
+
+        let mut header = String::new();
+
+        while reader.read_line(&mut header)? > 2 {
+            header.clear();
+        }
+
+        let mut parts = request_line.split_whitespace();
+
+        let key_authorization = match (parts.next(), parts.next()) {
+            (Some("GET" | "HEAD"), Some(path)) => path
+                .strip_prefix(Self::CHALLENGE_PREFIX)
+                .and_then(|token| tokens.lock().ok()?.get(token).cloned()),
+            _ => None,
+        };
+
+        let response = match key_authorization {
+            Some(body) => format!(
+                "HTTP/1.1 200 OK\r\nContent-Type: text/plain\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+                body.len()
+            ),
+            None => String::from(
+                "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+            ),
+        };
+
+        reader.get_mut().write_all(response.as_bytes())
+    }
+
+    /// Accepts `auth <token> <validation>` and `cleanup <token>` lines on a unix socket,
+    /// so certbot's manual hooks, which run in their own process, can feed the responder.
+    pub fn listen_for_hooks(&mut self, socket: &Path) -> Result<(), QicertError> {
+        let _ = std::fs::remove_file(socket);
+
+        if let Some(parent) = socket.parent() {
+            std::fs::create_dir_all(parent)
+                .map_err(|e| QicertError::io(ResponderError::ControlSocket, parent, e))?;
+        }
+
+        let listener = UnixListener::bind(socket)
+            .map_err(|e| QicertError::io(ResponderError::ControlSocket, socket, e))?;
+
+        let tokens = self.tokens.clone();
+        let stop = self.stop.clone();
+
+        let thread = thread::spawn(move || {
+            for stream in listener.incoming() {
+                if stop.load(Ordering::SeqCst) {
+                    break;
+                }
+
+                if let Ok(stream) = stream {
+                    let _ = Self::control(stream, &tokens);
+                }
+            }
+        });
+
+        self.threads.push(thread);
+        self.control = Some(socket.to_path_buf());
+
+        Ok(())
+    }
+
+    fn control(stream: UnixStream, tokens: &Tokens) -> std::io::Result<()> {
+        let mut reader = BufReader::new(stream);
+        let mut line = String::new();
+
+        reader.read_line(&mut line)?;
+
+        let words: Vec<&str> = line.split_whitespace().collect();
+
+        let done = match (words.as_slice(), tokens.lock()) {
+            (["auth", token, validation], Ok(mut tokens)) => {
+                tokens.insert(token.to_string(), validation.to_string());
+                true
+            }
+            (["cleanup", token], Ok(mut tokens)) => {
+                tokens.remove(*token);
+                true
+            }
+            _ => false,
+        };
+
+        let reply = if done { "ok\n" } else { "error\n" };
+
+        reader.get_mut().write_all(reply.as_bytes())
+    }
+
+    /// Stops accepting connections and waits for the serving threads.
+    pub fn shutdown(mut self) {
+        self.stop_threads();
+    }
+
+    fn stop_threads(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+
+        let _ = TcpStream::connect(self.address);
+
+        if let Some(control) = self.control.take() {
+            let _ = UnixStream::connect(&control);
+            let _ = std::fs::remove_file(control);
+        }
+
+        for thread in self.threads.drain(..) {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl Drop for Responder {
+    fn drop(&mut self) {
+        if !self.threads.is_empty() {
+            self.stop_threads();
+        }
+    }
+}
+
+/// Hook side of `listen_for_hooks`, run by certbot as `qicert hook auth|cleanup`.
+pub struct Hook;
+
+impl Hook {
+    pub const SOCKET_ENV: &'static str = "QICERT_RESPONDER";
+
+    fn env(name: &str) -> Result<String, ResponderError> {
+        std::env::var(name).map_err(|_| ResponderError::MissingHookEnvironment)
+    }
+
+    pub fn send(socket: &Path, line: &str) -> Result<(), QicertError> {
+        let mut reply = String::new();
+
+        UnixStream::connect(socket)
+            .and_then(|mut stream| {
+                writeln!(stream, "{line}")?;
+                BufReader::new(stream).read_line(&mut reply)
+            })
+            .map_err(|e| QicertError::io(ResponderError::ControlSocket, socket, e))?;
+
+        if reply.trim() != "ok" {
+            return Err(ResponderError::ControlSocket)?;
+        }
+
+        Ok(())
+    }
+
+    pub fn auth() -> Result<(), QicertError> {
+        let socket = PathBuf::from(Self::env(Self::SOCKET_ENV)?);
+        let token = Self::env("CERTBOT_TOKEN")?;
+        let validation = Self::env("CERTBOT_VALIDATION")?;
+
+        Self::send(&socket, &format!("auth {token} {validation}"))
+    }
+
+    pub fn cleanup() -> Result<(), QicertError> {
+        let socket = PathBuf::from(Self::env(Self::SOCKET_ENV)?);
+        let token = Self::env("CERTBOT_TOKEN")?;
+
+        Self::send(&socket, &format!("cleanup {token}"))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::Read;
+
+    fn get(address: SocketAddr, path: &str) -> String {
+        let mut stream = TcpStream::connect(address).unwrap();
+
+        write!(stream, "GET {path} HTTP/1.1\r\nHost: example.com\r\n\r\n").unwrap();
+
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+
+        response
+    }
+
+    fn local() -> SocketAddr {
+        "127.0.0.1:0".parse().unwrap()
+    }
+
+    #[test]
+    fn serves_known_token() {
+        let responder = Responder::start(local()).unwrap();
+
+        responder.add("abc123", "abc123.thumbprint");
+
+        let response = get(responder.address(), "/.well-known/acme-challenge/abc123");
+
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.ends_with("\r\n\r\nabc123.thumbprint"));
+
+        responder.shutdown();
+    }
+
+    #[test]
+    fn unknown_paths_are_not_found() {
+        let responder = Responder::start(local()).unwrap();
+
+        responder.add("abc123", "abc123.thumbprint");
+        responder.remove("abc123");
+
+        for path in ["/.well-known/acme-challenge/abc123", "/", "/index.html"] {
+            let response = get(responder.address(), path);
+
+            assert!(response.starts_with("HTTP/1.1 404 Not Found\r\n"), "{path}");
+        }
+    }
+
+    #[test]
+    fn stops_listening_after_shutdown() {
+        let responder = Responder::start(local()).unwrap();
+        let address = responder.address();
+
+        responder.shutdown();
+
+        assert!(TcpStream::connect(address).is_err());
+    }
+
+    #[test]
+    fn hooks_feed_the_responder() {
+        let socket = std::env::temp_dir().join(format!("qicert-hook-{}.sock", std::process::id()));
+
+        let mut responder = Responder::start(local()).unwrap();
+        responder.listen_for_hooks(&socket).unwrap();
+
+        Hook::send(&socket, "auth tok3n tok3n.key").unwrap();
+
+        let response = get(responder.address(), "/.well-known/acme-challenge/tok3n");
+        assert!(response.ends_with("tok3n.key"));
+
+        Hook::send(&socket, "cleanup tok3n").unwrap();
+
+        let response = get(responder.address(), "/.well-known/acme-challenge/tok3n");
+        assert!(response.starts_with("HTTP/1.1 404"));
+
+        assert!(Hook::send(&socket, "bogus").is_err());
+
+        responder.shutdown();
+
+        assert!(!socket.exists());
+    }
+}
@@ -0,0 +1,153 @@
+use std::{cell::RefCell, net::SocketAddr, time::Duration};
+
+use crate::{certer::Challenge, report::Output, service_manager::ServiceManagers};
+
+thread_local! {
+    static SCOPES: RefCell<Vec<RunOptions>> = const { RefCell::new(Vec::new()) };
+}
+
+/// How a run answers the challenge, reloads the server, waits for locks and prints its
+/// progress. The options only hold inside `scope`, on its thread, so that two runs of
+/// one process can use different ones.
+///
+/// ```no_run
+/// use std::time::Duration;
+///
+/// use qicert::{domain::Domain, layout::Server, provisioner::Provisioner, run::RunOptions};
+///
+/// let options = RunOptions::default().with_wait(Duration::from_secs(30));
+///
+/// let domain = Domain::new("example", "com", None)?;
+///
+/// Provisioner::new(domain, Server::Nginx)
+///     .options(options)
+///     .run()
+///     .into_result()?;
+/// # Ok::<(), qicert::error::QicertError>(())
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RunOptions {
+    challenge: Challenge,
+    skip_reachability_check: bool,
+    resolve: Option<SocketAddr>,
+    service_manager: Option<ServiceManagers>,
+    wait: Duration,
+    output: Option<Output>,
+}
+
+/// Puts the options around back when a scope ends, also when it panics.
+struct Scope;
+
+impl Drop for Scope {
+    fn drop(&mut self) {
+        SCOPES.with_borrow_mut(Vec::pop);
+    }
+}
+
+impl RunOptions {
+    /// The web server serves the challenge from the challenge root if not given.
+    pub fn with_challenge(mut self, challenge: Challenge) -> Self {
+        self.challenge = challenge;
+        self
+    }
+
+    /// Goes straight to certbot without fetching a test token first.
+    pub fn with_skip_reachability_check(mut self, skip: bool) -> Self {
+        self.skip_reachability_check = skip;
+        self
+    }
+
+    /// Fetches the test token from `address` instead of what DNS currently says.
+    pub fn with_resolve(mut self, address: SocketAddr) -> Self {
+        self.resolve = Some(address);
+        self
+    }
+
+    /// Forces a service manager instead of detecting one.
+    pub fn with_service_manager(mut self, kind: ServiceManagers) -> Self {
+        self.service_manager = Some(kind);
+        self
+    }
+
+    /// How long to wait for a busy lock before giving up, not at all by default.
+    pub fn with_wait(mut self, wait: Duration) -> Self {
+        self.wait = wait;
+        self
+    }
+
+    /// Prints the progress as it comes, nothing is printed if not given.
+    pub fn with_output(mut self, output: Output) -> Self {
+        self.output = Some(output);
+        self
+    }
+
+    /// Runs `f` with these options on the calling thread, the ones that held before
+    /// are back once it returns.
+    pub fn scope<T, F: FnOnce() -> T>(&self, f: F) -> T {
+        SCOPES.with_borrow_mut(|scopes| scopes.push(self.clone()));
+
+        let _scope = Scope;
+
+        f()
+    }
+
+    /// The options of the innermost scope of this thread, the defaults outside of one.
+    pub(crate) fn current() -> Self {
+        SCOPES.with_borrow(|scopes| scopes.last().cloned().unwrap_or_default())
+    }
+
+    pub fn challenge(&self) -> Challenge {
+        self.challenge
+    }
+
+    pub fn checks_reachability(&self) -> bool {
+        !self.skip_reachability_check
+    }
+
+    pub fn resolve(&self) -> Option<SocketAddr> {
+        self.resolve
+    }
+
+    pub fn service_manager(&self) -> Option<ServiceManagers> {
+        self.service_manager
+    }
+
+    pub fn wait(&self) -> Duration {
+        self.wait
+    }
+
+    pub fn output(&self) -> Option<Output> {
+        self.output
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn options_hold_inside_their_scope_and_thread() {
+        let outer = RunOptions::default().with_wait(Duration::from_secs(5));
+        let inner = RunOptions::default().with_skip_reachability_check(true);
+
+        assert_eq!(RunOptions::current(), RunOptions::default());
+
+        outer.scope(|| {
+            assert_eq!(RunOptions::current(), outer);
+
+            inner.scope(|| {
+                assert!(!RunOptions::current().checks_reachability());
+                assert_eq!(RunOptions::current().wait(), Duration::ZERO);
+            });
+
+            assert!(RunOptions::current().checks_reachability());
+
+            let elsewhere = thread::spawn(RunOptions::current).join().unwrap();
+
+            assert_eq!(elsewhere, RunOptions::default());
+        });
+
+        assert_eq!(RunOptions::current(), RunOptions::default());
+    }
+}
@@ -0,0 +1,278 @@
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+};
+
+use crate::{layout::Layout, run::RunOptions};
+
+/// Something able to tell a running web server to reload its configuration.
+pub(crate) trait ServiceManager {
+    /// Shell command reloading the service, also handed to certbot as a deploy hook.
+    fn reload_command(&self, layout: &Layout) -> String;
+
+    /// The program `reload_command` runs, for checking it is there before starting.
+    fn program(&self, layout: &Layout) -> PathBuf;
+
+    fn reload(&self, layout: &Layout) -> io::Result<()> {
+        let output = Command::new("sh")
+            .arg("-c")
+            .arg(self.reload_command(layout))
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped())
+            .output()?;
+
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+
+            return Err(io::Error::other(format!(
+                "reload exited with {}: {}",
+                output.status,
+                stderr.trim()
+            )));
+        }
+
+        Ok(())
+    }
+}
+
+pub struct Systemd;
+
+impl ServiceManager for Systemd {
+    fn program(&self, _layout: &Layout) -> PathBuf {
+        PathBuf::from("systemctl")
+    }
+
+    fn reload_command(&self, layout: &Layout) -> String {
+        format!("systemctl reload {}", layout.service())
+    }
+}
+
+pub struct OpenRc;
+
+impl ServiceManager for OpenRc {
+    fn program(&self, _layout: &Layout) -> PathBuf {
+        PathBuf::from("rc-service")
+    }
+
+    fn reload_command(&self, layout: &Layout) -> String {
+        format!("rc-service {} reload", layout.service())
+    }
+}
+
+pub struct Runit;
+
+impl ServiceManager for Runit {
+    fn program(&self, _layout: &Layout) -> PathBuf {
+        PathBuf::from("sv")
+    }
+
+    fn reload_command(&self, layout: &Layout) -> String {
+        format!("sv reload {}", layout.service())
+    }
+}
+
+pub struct SysVinit;
+
+impl ServiceManager for SysVinit {
+    fn program(&self, layout: &Layout) -> PathBuf {
+        Path::new("/etc/init.d").join(layout.service())
+    }
+
+    fn reload_command(&self, layout: &Layout) -> String {
+        format!("/etc/init.d/{} reload", layout.service())
+    }
+}
+
+/// Signals the master process directly, for containers and supervisors qicert does not
+/// know about (s6, supervisord, a bare `nginx -g 'daemon off;'`...).
+pub struct Signal;
+
+impl Signal {
+    fn master_pid(pid_file: &Path) -> io::Result<u32> {
+        let pid: u32 = fs::read_to_string(pid_file)?
+            .trim()
+            .parse()
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "malformed pid file"))?;
+
+        if !Path::new("/proc").join(pid.to_string()).is_dir() {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("no process with pid {pid}, stale pid file"),
+            ));
+        }
+
+        Ok(pid)
+    }
+}
+
+impl ServiceManager for Signal {
+    fn program(&self, _layout: &Layout) -> PathBuf {
+        PathBuf::from("kill")
+    }
+
+    fn reload_command(&self, layout: &Layout) -> String {
+        format!(
+            "kill -s {} $(cat {})",
+            layout.reload_signal().unwrap_or("HUP"),
+            layout.pid_file().display()
+        )
+    }
+
+    fn reload(&self, layout: &Layout) -> io::Result<()> {
+        let Some(signal) = layout.reload_signal() else {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "this server cannot be reloaded with a signal",
+            ));
+        };
+
+        let pid = Self::master_pid(layout.pid_file())?;
+
+        let status = Command::new("kill")
+            .arg("-s")
+            .arg(signal)
+            .arg(pid.to_string())
+            .status()?;
+
+        if !status.success() {
+            return Err(io::Error::other(format!("kill exited with {status}")));
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum ServiceManagers {
+    Systemd,
+    Openrc,
+    Runit,
+    Sysvinit,
+    Signal,
+}
+
+impl ServiceManagers {
+    /// Looks at what is running under `root` to decide how `service` is supervised.
+    pub fn detect(root: &Path, service: &str) -> Self {
+        if root.join("run/systemd/system").is_dir() {
+            return Self::Systemd;
+        }
+
+        if root.join("run/openrc").is_dir() {
+            return Self::Openrc;
+        }
+
+        let runit_service = ["etc/service", "var/service", "etc/sv"]
+            .iter()
+            .any(|dir| root.join(dir).join(service).is_dir());
+
+        if runit_service {
+            return Self::Runit;
+        }
+
+        if root.join("etc/init.d").join(service).is_file() {
+            return Self::Sysvinit;
+        }
+
+        Self::Signal
+    }
+
+    fn manager(self) -> Box<dyn ServiceManager> {
+        match self {
+            Self::Systemd => Box::new(Systemd),
+            Self::Openrc => Box::new(OpenRc),
+            Self::Runit => Box::new(Runit),
+            Self::Sysvinit => Box::new(SysVinit),
+            Self::Signal => Box::new(Signal),
+        }
+    }
+}
+
+/// The service manager the run forces, see `RunOptions`, or the detected one.
+pub(crate) fn for_layout(layout: &Layout) -> Box<dyn ServiceManager> {
+    let kind = RunOptions::current()
+        .service_manager()
+        .unwrap_or_else(|| ServiceManagers::detect(Path::new("/"), layout.service()));
+
+    kind.manager()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        layout::{Family, Server},
+        test_support::scratch,
+    };
+
+    #[test]
+    fn reload_commands() {
+        let layout = Layout::defaults(Family::RedHat, Server::Apache);
+
+        let managers: Vec<(Box<dyn ServiceManager>, &str)> = vec![
+            (Box::new(Systemd), "systemctl reload httpd"),
+            (Box::new(OpenRc), "rc-service httpd reload"),
+            (Box::new(Runit), "sv reload httpd"),
+            (Box::new(SysVinit), "/etc/init.d/httpd reload"),
+            (Box::new(Signal), "kill -s USR1 $(cat /run/httpd/httpd.pid)"),
+        ];
+
+        for (manager, expected) in managers {
+            assert_eq!(manager.reload_command(&layout), expected);
+        }
+    }
+
+    #[test]
+    fn detect_service_manager() {
+        let cases = vec![
+            ("run/systemd/system", ServiceManagers::Systemd),
+            ("run/openrc", ServiceManagers::Openrc),
+            ("etc/service/nginx", ServiceManagers::Runit),
+            ("", ServiceManagers::Signal),
+        ];
+
+        for (dir, expected) in cases {
+            let root = scratch("detect");
+
+            fs::create_dir_all(root.join(dir)).unwrap();
+
+            assert_eq!(ServiceManagers::detect(&root, "nginx"), expected, "{dir}");
+        }
+    }
+
+    #[test]
+    fn detect_sysvinit_script() {
+        let root = scratch("sysvinit");
+
+        fs::create_dir_all(root.join("etc/init.d")).unwrap();
+        fs::write(root.join("etc/init.d/lighttpd"), "#!/bin/sh\n").unwrap();
+
+        assert_eq!(
+            ServiceManagers::detect(&root, "lighttpd"),
+            ServiceManagers::Sysvinit
+        );
+    }
+
+    #[test]
+    fn stale_pid_file_is_rejected() {
+        let root = scratch("stale-pid");
+        let pid_file = root.join("nginx.pid");
+
+        fs::write(&pid_file, "4194304\n").unwrap();
+
+        let err = Signal::master_pid(&pid_file).unwrap_err();
+
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+    }
+
+    #[test]
+    fn running_master_pid_is_found() {
+        let root = scratch("running-pid");
+        let pid_file = root.join("nginx.pid");
+
+        fs::write(&pid_file, format!("{}\n", std::process::id())).unwrap();
+
+        assert_eq!(Signal::master_pid(&pid_file).unwrap(), std::process::id());
+    }
+}
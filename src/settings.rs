@@ -0,0 +1,542 @@
+use std::{
+    error::Error,
+    fmt::Display,
+    fs,
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::OnceLock,
+};
+
+use serde::Deserialize;
+
+#[derive(Debug)]
+pub enum SettingsError {
+    Unreadable(PathBuf),
+    Invalid {
+        path: PathBuf,
+        reason: String,
+    },
+    InvalidVariable {
+        name: String,
+        value: String,
+        reason: String,
+    },
+    NotAbsolute {
+        key: &'static str,
+        value: PathBuf,
+    },
+    EmptyOwner,
+    NoBackupKept,
+}
+
+impl Display for SettingsError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Unreadable(path) => write!(f, "{} could not be read", path.display()),
+            Self::Invalid { path, reason } => {
+                write!(f, "{} is not valid: {reason}", path.display())
+            }
+            Self::InvalidVariable {
+                name,
+                value,
+                reason,
+            } => write!(f, "{name}={value} is not valid: {reason}"),
+            Self::NotAbsolute { key, value } => {
+                write!(
+                    f,
+                    "{key} has to be an absolute path, got {}",
+                    value.display()
+                )
+            }
+            Self::EmptyOwner => write!(f, "www_owner can not be empty"),
+            Self::NoBackupKept => write!(f, "backup_keep has to be at least 1"),
+        }
+    }
+}
+
+impl Error for SettingsError {}
+
+/// Where the effective value of a setting comes from, later layers win.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Source {
+    Default,
+    File(PathBuf),
+    Environment,
+    CommandLine,
+}
+
+#[derive(Debug, Clone)]
+pub struct Setting<T> {
+    value: T,
+    source: Source,
+}
+
+impl<T> Setting<T> {
+    fn new(value: T) -> Self {
+        Self {
+            value,
+            source: Source::Default,
+        }
+    }
+
+    fn set(&mut self, value: Option<T>, source: &Source) {
+        if let Some(value) = value {
+            self.value = value;
+            self.source = source.clone();
+        }
+    }
+
+    pub fn source(&self) -> &Source {
+        &self.source
+    }
+}
+
+/// One layer of overrides: the config file, the environment or the command line.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Layer {
+    pub www_owner: Option<String>,
+    pub webroot: Option<PathBuf>,
+    pub challenge_root: Option<PathBuf>,
+    pub certbot: Option<PathBuf>,
+    pub acme_server: Option<String>,
+    pub backup_dir: Option<PathBuf>,
+    pub backup_keep: Option<usize>,
+    pub audit_log: Option<PathBuf>,
+    pub audit_syslog: Option<bool>,
+}
+
+impl Layer {
+    const ENV_PREFIX: &str = "QICERT_";
+
+    pub fn env_name(key: &str) -> String {
+        format!("{}{}", Self::ENV_PREFIX, key.to_uppercase())
+    }
+
+    /// Reads `QICERT_<KEY>` variables through `var`, empty ones are ignored. A value
+    /// that does not parse is an error, as it is in the config file.
+    pub fn from_env<F: Fn(&str) -> Option<String>>(var: F) -> Result<Self, SettingsError> {
+        let get = |key: &str| var(&Self::env_name(key)).filter(|v| !v.is_empty());
+
+        Ok(Self {
+            www_owner: get("www_owner"),
+            webroot: get("webroot").map(PathBuf::from),
+            challenge_root: get("challenge_root").map(PathBuf::from),
+            certbot: get("certbot").map(PathBuf::from),
+            acme_server: get("acme_server"),
+            backup_dir: get("backup_dir").map(PathBuf::from),
+            backup_keep: Self::parsed("backup_keep", get("backup_keep"))?,
+            audit_log: get("audit_log").map(PathBuf::from),
+            audit_syslog: Self::parsed("audit_syslog", get("audit_syslog"))?,
+        })
+    }
+
+    fn parsed<T: FromStr>(key: &str, value: Option<String>) -> Result<Option<T>, SettingsError>
+    where
+        T::Err: Display,
+    {
+        value
+            .map(|value| {
+                value
+                    .parse()
+                    .map_err(|e: T::Err| SettingsError::InvalidVariable {
+                        name: Self::env_name(key),
+                        value,
+                        reason: e.to_string(),
+                    })
+            })
+            .transpose()
+    }
+
+    fn parse(content: &str, path: &Path) -> Result<Self, SettingsError> {
+        toml::from_str(content).map_err(|e| SettingsError::Invalid {
+            path: path.to_path_buf(),
+            reason: e.message().to_string(),
+        })
+    }
+}
+
+/// Values that used to be hard-coded, layered as defaults, `/etc/qicert/config.toml`,
+/// `QICERT_*` environment variables and command line flags.
+#[derive(Debug, Clone)]
+pub struct Settings {
+    www_owner: Setting<Option<String>>,
+    webroot: Setting<PathBuf>,
+    challenge_root: Setting<PathBuf>,
+    certbot: Setting<PathBuf>,
+    acme_server: Setting<Option<String>>,
+    backup_dir: Setting<PathBuf>,
+    backup_keep: Setting<usize>,
+    audit_log: Setting<PathBuf>,
+    audit_syslog: Setting<bool>,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            www_owner: Setting::new(None),
+            webroot: Setting::new(PathBuf::from("/var/www")),
+            challenge_root: Setting::new(PathBuf::from("/var/www/.well-known/challenge")),
+            certbot: Setting::new(PathBuf::from("/usr/bin/certbot")),
+            acme_server: Setting::new(None),
+            backup_dir: Setting::new(PathBuf::from("/var/lib/qicert/backups")),
+            backup_keep: Setting::new(10),
+            audit_log: Setting::new(PathBuf::from("/var/log/qicert/audit.jsonl")),
+            audit_syslog: Setting::new(false),
+        }
+    }
+}
+
+static CURRENT: OnceLock<Settings> = OnceLock::new();
+
+impl Settings {
+    pub const PATH: &str = "/etc/qicert/config.toml";
+
+    /// The default file may be missing, a file given with `--config` may not.
+    pub fn load(config: Option<&Path>, cli: Layer) -> Result<Self, SettingsError> {
+        let mut settings = Self::default();
+
+        let path = config.unwrap_or(Path::new(Self::PATH));
+
+        match fs::read_to_string(path) {
+            Ok(content) => {
+                let source = Source::File(path.to_path_buf());
+                settings.apply(Layer::parse(&content, path)?, &source);
+            }
+            Err(_) if config.is_none() => {}
+            Err(_) => return Err(SettingsError::Unreadable(path.to_path_buf())),
+        }
+
+        settings.apply(
+            Layer::from_env(|name| std::env::var(name).ok())?,
+            &Source::Environment,
+        );
+
+        settings.apply(cli, &Source::CommandLine);
+
+        settings.validate()?;
+
+        Ok(settings)
+    }
+
+    fn apply(&mut self, layer: Layer, source: &Source) {
+        self.www_owner.set(layer.www_owner.map(Some), source);
+        self.webroot.set(layer.webroot, source);
+        self.challenge_root.set(layer.challenge_root, source);
+        self.certbot.set(layer.certbot, source);
+        self.acme_server.set(layer.acme_server.map(Some), source);
+        self.backup_dir.set(layer.backup_dir, source);
+        self.backup_keep.set(layer.backup_keep, source);
+        self.audit_log.set(layer.audit_log, source);
+        self.audit_syslog.set(layer.audit_syslog, source);
+    }
+
+    fn validate(&self) -> Result<(), SettingsError> {
+        if self.www_owner.value.as_ref().is_some_and(|o| o.is_empty()) {
+            return Err(SettingsError::EmptyOwner);
+        }
+
+        if self.backup_keep.value == 0 {
+            return Err(SettingsError::NoBackupKept);
+        }
+
+        let paths = [
+            ("webroot", &self.webroot.value),
+            ("challenge_root", &self.challenge_root.value),
+            ("certbot", &self.certbot.value),
+            ("backup_dir", &self.backup_dir.value),
+            ("audit_log", &self.audit_log.value),
+        ];
+
+        for (key, value) in paths {
+            if !value.is_absolute() {
+                return Err(SettingsError::NotAbsolute {
+                    key,
+                    value: value.clone(),
+                });
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Makes these the settings every module reads through `current`.
+    pub fn install(self) {
+        let _ = CURRENT.set(self);
+    }
+
+    /// The installed settings, the defaults when nothing was installed as in tests.
+    pub fn current() -> &'static Self {
+        CURRENT.get_or_init(Self::default)
+    }
+
+    /// `user:group` to chown files to, `None` leaves it to the web server layout.
+    pub fn www_owner(&self) -> Option<&str> {
+        self.www_owner.value.as_deref()
+    }
+
+    pub fn webroot(&self) -> &Path {
+        &self.webroot.value
+    }
+
+    pub fn challenge_root(&self) -> &Path {
+        &self.challenge_root.value
+    }
+
+    pub fn certbot(&self) -> &Path {
+        &self.certbot.value
+    }
+
+    /// ACME directory handed to certbot with `--server`, certbot's own default if `None`.
+    pub fn acme_server(&self) -> Option<&str> {
+        self.acme_server.value.as_deref()
+    }
+
+    /// Where copies of the configuration files are kept before qicert changes them.
+    pub fn backup_dir(&self) -> &Path {
+        &self.backup_dir.value
+    }
+
+    /// How many copies of each configuration file are kept.
+    pub fn backup_keep(&self) -> usize {
+        self.backup_keep.value
+    }
+
+    /// JSON lines file every change is recorded in.
+    pub fn audit_log(&self) -> &Path {
+        &self.audit_log.value
+    }
+
+    /// Whether audit entries also go to syslog through /dev/log.
+    pub fn audit_syslog(&self) -> bool {
+        self.audit_syslog.value
+    }
+}
+
+impl Display for Settings {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let rows = [
+            (
+                "www_owner",
+                self.www_owner().unwrap_or("per web server").to_string(),
+                self.www_owner.source(),
+            ),
+            (
+                "webroot",
+                self.webroot().display().to_string(),
+                self.webroot.source(),
+            ),
+            (
+                "challenge_root",
+                self.challenge_root().display().to_string(),
+                self.challenge_root.source(),
+            ),
+            (
+                "certbot",
+                self.certbot().display().to_string(),
+                self.certbot.source(),
+            ),
+            (
+                "acme_server",
+                self.acme_server().unwrap_or("certbot default").to_string(),
+                self.acme_server.source(),
+            ),
+            (
+                "backup_dir",
+                self.backup_dir().display().to_string(),
+                self.backup_dir.source(),
+            ),
+            (
+                "backup_keep",
+                self.backup_keep().to_string(),
+                self.backup_keep.source(),
+            ),
+            (
+                "audit_log",
+                self.audit_log().display().to_string(),
+                self.audit_log.source(),
+            ),
+            (
+                "audit_syslog",
+                self.audit_syslog().to_string(),
+                self.audit_syslog.source(),
+            ),
+        ];
+
+        for (key, value, source) in rows {
+            let source = match source {
+                Source::Default => String::from("default"),
+                Source::File(path) => path.display().to_string(),
+                Source::Environment => Layer::env_name(key),
+                Source::CommandLine => format!("--{}", key.replace('_', "-")),
+            };
+
+            writeln!(f, "{key:<16}{value:<48}{source}")?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_support::scratch;
+
+    #[test]
+    fn layers_override_in_order() {
+        let dir = scratch("settings-layers");
+        let path = dir.join("qicert.toml");
+
+        fs::write(
+            &path,
+            "webroot = \"/srv/www\"\ncertbot = \"/opt/certbot/bin/certbot\"",
+        )
+        .unwrap();
+
+        let mut settings = Settings::default();
+
+        settings.apply(
+            Layer::parse(&fs::read_to_string(&path).unwrap(), &path).unwrap(),
+            &Source::File(path.clone()),
+        );
+
+        settings.apply(
+            Layer::from_env(|name| {
+                (name == "QICERT_CERTBOT").then(|| String::from("/usr/local/bin/certbot"))
+            })
+            .unwrap(),
+            &Source::Environment,
+        );
+
+        settings.apply(
+            Layer {
+                acme_server: Some(String::from("https://acme.example/directory")),
+                ..Default::default()
+            },
+            &Source::CommandLine,
+        );
+
+        assert_eq!(settings.webroot(), Path::new("/srv/www"));
+        assert_eq!(settings.webroot.source(), &Source::File(path.clone()));
+
+        assert_eq!(settings.certbot(), Path::new("/usr/local/bin/certbot"));
+        assert_eq!(settings.certbot.source(), &Source::Environment);
+
+        assert_eq!(
+            settings.acme_server(),
+            Some("https://acme.example/directory")
+        );
+        assert_eq!(settings.acme_server.source(), &Source::CommandLine);
+
+        assert_eq!(settings.www_owner(), None);
+        assert_eq!(settings.www_owner.source(), &Source::Default);
+    }
+
+    #[test]
+    fn unparsable_variables_are_errors() {
+        let error =
+            Layer::from_env(|name| (name == "QICERT_BACKUP_KEEP").then(|| String::from("ten")))
+                .unwrap_err();
+
+        assert_eq!(
+            error.to_string(),
+            "QICERT_BACKUP_KEEP=ten is not valid: invalid digit found in string"
+        );
+
+        assert!(matches!(
+            Layer::from_env(|name| (name == "QICERT_AUDIT_SYSLOG").then(|| String::from("yes"))),
+            Err(SettingsError::InvalidVariable { .. })
+        ));
+
+        let layer = Layer::from_env(|name| match name {
+            "QICERT_BACKUP_KEEP" => Some(String::from("3")),
+            "QICERT_AUDIT_SYSLOG" => Some(String::from("true")),
+            _ => None,
+        })
+        .unwrap();
+
+        assert_eq!(layer.backup_keep, Some(3));
+        assert_eq!(layer.audit_syslog, Some(true));
+    }
+
+    #[test]
+    fn explicit_config_has_to_exist() {
+        let missing = std::env::temp_dir().join("qicert-settings-missing.toml");
+
+        assert!(matches!(
+            Settings::load(Some(&missing), Layer::default()),
+            Err(SettingsError::Unreadable(_))
+        ));
+    }
+
+    #[test]
+    fn rejects_unknown_keys_and_relative_paths() {
+        let dir = scratch("settings-unknown");
+        let path = dir.join("qicert.toml");
+
+        fs::write(&path, "web_root = \"/srv/www\"").unwrap();
+
+        assert!(matches!(
+            Settings::load(Some(&path), Layer::default()),
+            Err(SettingsError::Invalid { .. })
+        ));
+
+        let relative = Layer {
+            challenge_root: Some(PathBuf::from("challenge")),
+            ..Default::default()
+        };
+
+        assert!(matches!(
+            Settings::load(Some(&path), relative),
+            Err(SettingsError::Invalid { .. })
+        ));
+
+        fs::write(&path, "").unwrap();
+
+        let relative = Layer {
+            challenge_root: Some(PathBuf::from("challenge")),
+            ..Default::default()
+        };
+
+        assert!(matches!(
+            Settings::load(Some(&path), relative),
+            Err(SettingsError::NotAbsolute {
+                key: "challenge_root",
+                ..
+            })
+        ));
+
+        let none_kept = Layer {
+            backup_keep: Some(0),
+            ..Default::default()
+        };
+
+        assert!(matches!(
+            Settings::load(Some(&path), none_kept),
+            Err(SettingsError::NoBackupKept)
+        ));
+    }
+
+    #[test]
+    fn show_names_the_source() {
+        let mut settings = Settings::default();
+
+        settings.apply(
+            Layer {
+                webroot: Some(PathBuf::from("/srv/www")),
+                ..Default::default()
+            },
+            &Source::Environment,
+        );
+
+        let shown = settings.to_string();
+
+        assert!(shown
+            .lines()
+            .any(|l| l.starts_with("webroot") && l.ends_with("QICERT_WEBROOT")));
+        assert!(shown
+            .lines()
+            .any(|l| l.starts_with("certbot") && l.ends_with("default")));
+    }
+}
@@ -0,0 +1,50 @@
+//! Helpers shared by the unit tests.
+use std::{
+    fs,
+    ops::Deref,
+    path::{Path, PathBuf},
+};
+
+use crate::layout::{Family, Layout, Server};
+
+/// Makes `server` use the Debian layout, so that expected paths do not depend on the
+/// machine running the tests.
+pub(crate) fn debian_layout(server: Server) {
+    Layout::set_override(server, Layout::defaults(Family::Debian, server));
+}
+
+/// An empty directory of its own under the temporary directory, removed with
+/// everything in it when dropped, also when the test fails.
+pub(crate) struct Scratch {
+    dir: PathBuf,
+}
+
+/// Creates the scratch directory for `name`, which has to be unique across the tests.
+pub(crate) fn scratch(name: &str) -> Scratch {
+    let dir = std::env::temp_dir().join(format!("qicert-{name}-{}", std::process::id()));
+
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+
+    Scratch { dir }
+}
+
+impl Deref for Scratch {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.dir
+    }
+}
+
+impl AsRef<Path> for Scratch {
+    fn as_ref(&self) -> &Path {
+        &self.dir
+    }
+}
+
+impl Drop for Scratch {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
@@ -0,0 +1,84 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// UTC calendar time of a `SystemTime`, to the millisecond.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Timestamp {
+    year: u64,
+    month: u64,
+    day: u64,
+    hour: u64,
+    minute: u64,
+    second: u64,
+    millis: u32,
+}
+
+impl Timestamp {
+    pub(crate) fn new(time: SystemTime) -> Self {
+        let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+
+        let secs = since_epoch.as_secs();
+        let (days, secs_of_day) = (secs / 86_400, secs % 86_400);
+
+        // Civil date from days since the epoch, as in Howard Hinnant's `civil_from_days`.
+        let z = days + 719_468;
+        let era = z / 146_097;
+        let doe = z % 146_097;
+        let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+        let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+        let mp = (5 * doy + 2) / 153;
+        let day = doy - (153 * mp + 2) / 5 + 1;
+        let month = if mp < 10 { mp + 3 } else { mp - 9 };
+        let year = yoe + era * 400 + u64::from(month <= 2);
+
+        Self {
+            year,
+            month,
+            day,
+            hour: secs_of_day / 3_600,
+            minute: secs_of_day % 3_600 / 60,
+            second: secs_of_day % 60,
+            millis: since_epoch.subsec_millis(),
+        }
+    }
+
+    pub(crate) fn now() -> Self {
+        Self::new(SystemTime::now())
+    }
+
+    /// `20261018T115501.123Z`, usable in file names and sorting by time.
+    pub(crate) fn compact(&self) -> String {
+        format!(
+            "{:04}{:02}{:02}T{:02}{:02}{:02}.{:03}Z",
+            self.year, self.month, self.day, self.hour, self.minute, self.second, self.millis
+        )
+    }
+
+    /// `2026-10-18T11:55:01.123Z`
+    pub(crate) fn rfc3339(&self) -> String {
+        format!(
+            "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+            self.year, self.month, self.day, self.hour, self.minute, self.second, self.millis
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn formats_utc_dates() {
+        let time = Timestamp::new(UNIX_EPOCH + Duration::from_millis(1_792_324_501_123));
+
+        assert_eq!(time.compact(), "20261018T115501.123Z");
+        assert_eq!(time.rfc3339(), "2026-10-18T11:55:01.123Z");
+
+        assert_eq!(Timestamp::new(UNIX_EPOCH).compact(), "19700101T000000.000Z");
+
+        let leap_day = Timestamp::new(UNIX_EPOCH + Duration::from_secs(951_782_400));
+
+        assert_eq!(leap_day.rfc3339(), "2000-02-29T00:00:00.000Z");
+    }
+}
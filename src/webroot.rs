@@ -1,4 +1,6 @@
 use crate::domain::Domain;
+use crate::error::{run_command, QicertError};
+use crate::settings::Settings;
 use std::fs;
 use std::path::PathBuf;
 use std::process::Command;
@@ -28,46 +30,47 @@ impl Error for WebRootError {}
 pub struct WebRoot;
 
 impl WebRoot {
-    const BASE_PATH: &str = "/var/www";
-
-    fn create(domain: &Domain) -> Result<(), WebRootError> {
+    fn create(domain: &Domain) -> Result<(), QicertError> {
         if Self::exists(domain) {
-            return Err(WebRootError::AlreadyExists);
+            return Err(WebRootError::AlreadyExists)?;
         }
 
-        let mut dir = fs::DirBuilder::new();
+        let path = Self::build_pathbuf(domain);
 
-        dir.recursive(true)
-            .create(Self::build_pathbuf(domain))
-            .map_err(|_| WebRootError::CreationFailure)?;
+        fs::DirBuilder::new()
+            .recursive(true)
+            .create(&path)
+            .map_err(|e| QicertError::io(WebRootError::CreationFailure, path, e))?;
 
         Ok(())
     }
 
-    fn create_dummy_html(domain: &Domain) -> Result<(), WebRootError> {
+    fn create_dummy_html(domain: &Domain) -> Result<(), QicertError> {
         if !Self::exists(domain) {
-            return Err(WebRootError::CreationFailure);
+            return Err(WebRootError::CreationFailure)?;
         }
 
         let mut root_path = Self::build_pathbuf(domain);
 
         root_path.push("index.html");
 
-        fs::write(root_path, "<p>hello</p>").map_err(|_| WebRootError::CreationFailure)?;
+        fs::write(&root_path, "<p>hello</p>")
+            .map_err(|e| QicertError::io(WebRootError::CreationFailure, root_path, e))?;
 
         Ok(())
     }
 
-    pub fn create_and_set_chown(domain: &Domain) -> Result<(), WebRootError> {
-        if Self::has_files(domain) {
-            return Err(WebRootError::AlreadyExists);
+    /// `owner` is the `user:group` the web server runs as in the current layout.
+    pub fn create_and_set_chown(domain: &Domain, owner: &str) -> Result<(), QicertError> {
+        if Self::has_files(domain)? {
+            return Err(WebRootError::AlreadyExists)?;
         }
 
         Self::create(domain)?;
 
         Self::create_dummy_html(domain)?;
 
-        Self::chown_to_www(domain)?;
+        Self::chown_to_www(domain, owner)?;
 
         Ok(())
     }
@@ -78,9 +81,9 @@ impl WebRoot {
         webroot_path.is_dir()
     }
 
-    fn has_files(domain: &Domain) -> bool {
+    fn has_files(domain: &Domain) -> Result<bool, QicertError> {
         if !Self::exists(domain) {
-            return false;
+            return Ok(false);
         }
 
         let webroot_path = Self::build_pathbuf(domain);
@@ -88,7 +91,7 @@ impl WebRoot {
         webroot_path
             .read_dir()
             .map(|e| e.count() > 0)
-            .expect("FIXME: could not read webroot directory")
+            .map_err(|e| QicertError::io(WebRootError::Permissions, webroot_path, e))
     }
 
     pub fn build_path_string(domain: &Domain) -> String {
@@ -99,7 +102,7 @@ impl WebRoot {
     }
 
     pub fn build_pathbuf(domain: &Domain) -> PathBuf {
-        let mut path = PathBuf::from(Self::BASE_PATH);
+        let mut path = Settings::current().webroot().to_path_buf();
 
         path.push(domain.to_string());
 
@@ -108,20 +111,20 @@ impl WebRoot {
         path
     }
 
-    fn chown_to_www(domain: &Domain) -> Result<(), WebRootError> {
+    fn chown_to_www(domain: &Domain, owner: &str) -> Result<(), QicertError> {
         if !Self::exists(domain) {
-            return Err(WebRootError::DoesNotExist);
+            return Err(WebRootError::DoesNotExist)?;
         }
 
         let path = Self::build_path_string(domain);
 
-        let _ = Command::new("chown")
-            .arg("-R")
-            .arg("www-data:www-data")
-            .arg(path.as_str())
-            .spawn()
-            .and_then(|mut p| p.wait())
-            .map_err(|_| WebRootError::Permissions);
+        run_command(
+            Command::new("chown")
+                .arg("-R")
+                .arg(owner)
+                .arg(path.as_str()),
+            WebRootError::Permissions,
+        )?;
 
         Ok(())
     }
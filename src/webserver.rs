@@ -1,29 +1,34 @@
-use std::{
-    error::Error,
-    path::PathBuf,
-    process::{Child, Command},
+use std::error::Error;
+
+use crate::{
+    error::QicertError,
+    layout::{Layout, Server},
+    service_manager,
 };
 
-pub(crate) trait WebServer<'a> {
-    const WEBSERVER_SBIN_PATH: &'a str;
-    const BINARY_NAME: &'a str;
+pub(crate) trait WebServer {
+    const SERVER: Server;
+
+    fn layout() -> Layout {
+        Layout::current(Self::SERVER)
+    }
 
-    fn _reload<E: Error + Copy>(reload_err: E) -> Result<(), E> {
-        let output = Command::new("systemctl")
-            .arg("reload")
-            .arg(Self::BINARY_NAME)
-            .spawn()
-            .and_then(Child::wait_with_output)
-            .map_err(|_| reload_err)?;
+    fn _reload<E: Error + Send + Sync + 'static>(reload_err: E) -> Result<(), QicertError> {
+        let layout = Self::layout();
+
+        service_manager::for_layout(&layout)
+            .reload(&layout)
+            .map_err(|e| QicertError::command(reload_err, Self::reload_command(), e))
+    }
 
-        if !output.status.success() {
-            return Err(reload_err);
-        }
+    /// Shell command that reloads the server, for hooks run outside of qicert.
+    fn reload_command() -> String {
+        let layout = Self::layout();
 
-        Ok(())
+        service_manager::for_layout(&layout).reload_command(&layout)
     }
 
     fn is_installed() -> bool {
-        PathBuf::from(Self::WEBSERVER_SBIN_PATH).is_file()
+        Self::layout().binary().is_file()
     }
 }